@@ -0,0 +1,1039 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+
+use crate::decoder::Instruction;
+use crate::encoder::encode;
+use crate::symbols::Symbols;
+use crate::system::{csr_name, FP_REGISTER_NAMES, REGISTER_NAMES};
+
+/// Machine code assembled from source text, with the labels it defines
+pub struct Program {
+    pub code: Vec<u8>,
+    pub symbols: Symbols,
+}
+
+/// Assembles RV32IMAFD assembly into machine code placed at `origin`.
+///
+/// Besides the instructions the usual pseudo-instructions (`li`, `la`, `call`, `beqz`, ...)
+/// and data directives (`.word`, `.byte`, `.ascii`, `.align`, ...) are understood. Every
+/// instruction is emitted in its 32-bit form. Branches and jumps to a plain number take it
+/// as an offset, which is how the disassembler prints them without an address.
+pub fn assemble(source: &str, origin: u32) -> anyhow::Result<Program> {
+    let mut assembler = Assembler {
+        origin,
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        code: Vec::new(),
+        final_pass: false,
+    };
+    /* The first pass only learns the addresses of the labels, the sizes don't depend on them */
+    for final_pass in [false, true] {
+        assembler.final_pass = final_pass;
+        assembler.code.clear();
+        for (number, line) in source.lines().enumerate() {
+            assembler
+                .line(line)
+                .map_err(|error| anyhow!("Line {}: {error}", number + 1))?;
+        }
+    }
+    let symbols = assembler
+        .labels
+        .into_iter()
+        .map(|(name, addr)| (addr, name))
+        .collect();
+    Ok(Program {
+        code: assembler.code,
+        symbols,
+    })
+}
+
+struct Assembler {
+    origin: u32,
+    labels: HashMap<String, u32>,
+    /* Values of `.equ` and `.set` */
+    constants: HashMap<String, i64>,
+    code: Vec<u8>,
+    /* Before the final pass, symbols defined further down are still unknown */
+    final_pass: bool,
+}
+
+type Result<T> = std::result::Result<T, String>;
+
+/// Removes a `#` or `//` comment, unless it is part of a string or character
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match (quote, c) {
+            (Some(_), _) if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..index],
+            (None, '/') if line[index..].starts_with("//") => return &line[..index],
+            (None, _) => {}
+        }
+    }
+    line
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$')
+}
+
+/// Splits operands at the commas, `0(sp)` and the like stay one operand
+fn split_operands(operands: &str) -> Vec<&str> {
+    if operands.is_empty() {
+        return Vec::new();
+    }
+    operands.split(',').map(str::trim).collect()
+}
+
+fn register(name: &str) -> Result<usize> {
+    if let Some(index) = REGISTER_NAMES.iter().position(|&abi| abi == name) {
+        return Ok(index);
+    }
+    match name {
+        "fp" => Ok(8),
+        _ => name
+            .strip_prefix('x')
+            .and_then(|number| number.parse().ok())
+            .filter(|&index| index < 32)
+            .ok_or(format!("Invalid register: {name}")),
+    }
+}
+
+fn fp_register(name: &str) -> Result<usize> {
+    if let Some(index) = FP_REGISTER_NAMES.iter().position(|&abi| abi == name) {
+        return Ok(index);
+    }
+    name.strip_prefix('f')
+        .and_then(|number| number.parse().ok())
+        .filter(|&index| index < 32)
+        .ok_or(format!("Invalid floating-point register: {name}"))
+}
+
+fn rounding_mode(name: &str) -> Result<u32> {
+    match name {
+        "rne" => Ok(0b000),
+        "rtz" => Ok(0b001),
+        "rdn" => Ok(0b010),
+        "rup" => Ok(0b011),
+        "rmm" => Ok(0b100),
+        "dyn" => Ok(0b111),
+        _ => Err(format!("Invalid rounding mode: {name}")),
+    }
+}
+
+/// Predecessor or successor set of a fence, `0` is the empty set
+fn fence_set(set: &str) -> Result<u32> {
+    if set == "0" {
+        return Ok(0);
+    }
+    set.chars().try_fold(0, |bits, c| match c {
+        'i' => Ok(bits | 0b1000),
+        'o' => Ok(bits | 0b0100),
+        'r' => Ok(bits | 0b0010),
+        'w' => Ok(bits | 0b0001),
+        _ => Err(format!("Invalid fence set: {set}")),
+    })
+}
+
+/// Splits `value` into the upper immediate and the signed 12-bit rest added to it
+fn split_upper(value: u32) -> (u32, u32) {
+    let upper = value.wrapping_add(0x800) & 0xFFFF_F000;
+    (upper, value.wrapping_sub(upper) & 0xFFF)
+}
+
+/// Parses a number, as decimal, `0x` hex, `0b` binary or a character like `'a'`
+fn number(text: &str) -> Option<i64> {
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        return i64::from_str_radix(hex, 16).ok();
+    }
+    if let Some(binary) = text.strip_prefix("0b").or_else(|| text.strip_prefix("0B")) {
+        return i64::from_str_radix(binary, 2).ok();
+    }
+    if let Some(c) = text.strip_prefix('\'').and_then(|c| c.strip_suffix('\'')) {
+        let bytes = unescape(c).ok()?;
+        return match bytes.as_slice() {
+            [byte] => Some(i64::from(*byte)),
+            _ => None,
+        };
+    }
+    text.parse().ok()
+}
+
+/// Resolves the backslash escapes of a string or character literal
+fn unescape(text: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+        let byte = match chars.next() {
+            Some('n') => b'\n',
+            Some('r') => b'\r',
+            Some('t') => b'\t',
+            Some('0') => 0,
+            Some('\\') => b'\\',
+            Some('"') => b'"',
+            Some('\'') => b'\'',
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                u8::from_str_radix(&hex, 16).map_err(|_| format!("Invalid escape: \\x{hex}"))?
+            }
+            other => return Err(format!("Invalid escape: \\{}", other.unwrap_or(' '))),
+        };
+        bytes.push(byte);
+    }
+    Ok(bytes)
+}
+
+/// Parses the comma separated string literals of `.ascii` and the like
+fn strings(operands: &str) -> Result<Vec<Vec<u8>>> {
+    let mut strings = Vec::new();
+    let mut rest = operands.trim();
+    while !rest.is_empty() {
+        let body = rest
+            .strip_prefix('"')
+            .ok_or(format!("Expected a string: {rest}"))?;
+        let mut escaped = false;
+        let end = body
+            .char_indices()
+            .find(|&(_, c)| {
+                let end = c == '"' && !escaped;
+                escaped = c == '\\' && !escaped;
+                end
+            })
+            .map(|(index, _)| index)
+            .ok_or("Unterminated string".to_string())?;
+        strings.push(unescape(&body[..end])?);
+        rest = body[end + 1..].trim_start();
+        if let Some(next) = rest.strip_prefix(',') {
+            rest = next.trim_start();
+        } else if !rest.is_empty() {
+            return Err(format!("Expected a comma: {rest}"));
+        }
+    }
+    Ok(strings)
+}
+
+/* Constructors of instructions by their operands, the last one may be an immediate, a
+ * rounding mode or the ordering bits of an atomic */
+type RegRegReg = fn(usize, usize, usize) -> Instruction;
+type RegRegImm = fn(usize, usize, u32) -> Instruction;
+type RegRegRegImm = fn(usize, usize, usize, u32) -> Instruction;
+type RegRegRegRegImm = fn(usize, usize, usize, usize, u32) -> Instruction;
+
+fn r_type(mnemonic: &str) -> Option<RegRegReg> {
+    use Instruction::*;
+    let constructor: RegRegReg = match mnemonic {
+        "add" => ADD,
+        "sub" => SUB,
+        "sll" => SLL,
+        "slt" => SLT,
+        "sltu" => SLTU,
+        "xor" => XOR,
+        "srl" => SRL,
+        "sra" => SRA,
+        "or" => OR,
+        "and" => AND,
+        "mul" => MUL,
+        "mulh" => MULH,
+        "mulhsu" => MULHSU,
+        "mulhu" => MULHU,
+        "div" => DIV,
+        "divu" => DIVU,
+        "rem" => REM,
+        "remu" => REMU,
+        _ => return None,
+    };
+    Some(constructor)
+}
+
+/// Instructions with a register and a 12-bit immediate operand, loads and stores included
+fn i_type(mnemonic: &str) -> Option<RegRegImm> {
+    use Instruction::*;
+    let constructor: RegRegImm = match mnemonic {
+        "addi" => ADDI,
+        "slti" => SLTI,
+        "sltiu" => SLTIU,
+        "xori" => XORI,
+        "ori" => ORI,
+        "andi" => ANDI,
+        "lb" => LB,
+        "lh" => LH,
+        "lw" => LW,
+        "lbu" => LBU,
+        "lhu" => LHU,
+        "sb" => SB,
+        "sh" => SH,
+        "sw" => SW,
+        "flw" => FLW,
+        "fld" => FLD,
+        "fsw" => FSW,
+        "fsd" => FSD,
+        "jalr" => JALR,
+        _ => return None,
+    };
+    Some(constructor)
+}
+
+fn branch(mnemonic: &str) -> Option<RegRegImm> {
+    use Instruction::*;
+    let constructor: RegRegImm = match mnemonic {
+        "beq" => BEQ,
+        "bne" => BNE,
+        "blt" => BLT,
+        "bge" => BGE,
+        "bltu" => BLTU,
+        "bgeu" => BGEU,
+        _ => return None,
+    };
+    Some(constructor)
+}
+
+fn atomic(mnemonic: &str) -> Option<RegRegRegImm> {
+    use Instruction::*;
+    let constructor: RegRegRegImm = match mnemonic {
+        "sc.w" => SCW,
+        "amoswap.w" => AMOSWAPW,
+        "amoadd.w" => AMOADDW,
+        "amoxor.w" => AMOXORW,
+        "amoand.w" => AMOANDW,
+        "amoor.w" => AMOORW,
+        "amomin.w" => AMOMINW,
+        "amomax.w" => AMOMAXW,
+        "amominu.w" => AMOMINUW,
+        "amomaxu.w" => AMOMAXUW,
+        _ => return None,
+    };
+    Some(constructor)
+}
+
+fn fp_fused(mnemonic: &str) -> Option<RegRegRegRegImm> {
+    use Instruction::*;
+    let constructor: RegRegRegRegImm = match mnemonic {
+        "fmadd.s" => FMADDS,
+        "fmsub.s" => FMSUBS,
+        "fnmsub.s" => FNMSUBS,
+        "fnmadd.s" => FNMADDS,
+        "fmadd.d" => FMADDD,
+        "fmsub.d" => FMSUBD,
+        "fnmsub.d" => FNMSUBD,
+        "fnmadd.d" => FNMADDD,
+        _ => return None,
+    };
+    Some(constructor)
+}
+
+fn fp_arithmetic(mnemonic: &str) -> Option<RegRegRegImm> {
+    use Instruction::*;
+    let constructor: RegRegRegImm = match mnemonic {
+        "fadd.s" => FADDS,
+        "fsub.s" => FSUBS,
+        "fmul.s" => FMULS,
+        "fdiv.s" => FDIVS,
+        "fadd.d" => FADDD,
+        "fsub.d" => FSUBD,
+        "fmul.d" => FMULD,
+        "fdiv.d" => FDIVD,
+        _ => return None,
+    };
+    Some(constructor)
+}
+
+/// Instructions on three registers without a rounding mode, `x` means the result is an
+/// integer register
+fn fp_binary(mnemonic: &str) -> Option<(RegRegReg, bool)> {
+    use Instruction::*;
+    let operation: (RegRegReg, bool) = match mnemonic {
+        "fsgnj.s" => (FSGNJS, false),
+        "fsgnjn.s" => (FSGNJNS, false),
+        "fsgnjx.s" => (FSGNJXS, false),
+        "fmin.s" => (FMINS, false),
+        "fmax.s" => (FMAXS, false),
+        "feq.s" => (FEQS, true),
+        "flt.s" => (FLTS, true),
+        "fle.s" => (FLES, true),
+        "fsgnj.d" => (FSGNJD, false),
+        "fsgnjn.d" => (FSGNJND, false),
+        "fsgnjx.d" => (FSGNJXD, false),
+        "fmin.d" => (FMIND, false),
+        "fmax.d" => (FMAXD, false),
+        "feq.d" => (FEQD, true),
+        "flt.d" => (FLTD, true),
+        "fle.d" => (FLED, true),
+        _ => return None,
+    };
+    Some(operation)
+}
+
+/// Whether a register is an integer or floating-point one
+#[derive(Clone, Copy)]
+enum File {
+    X,
+    F,
+}
+
+/// Conversions and moves between two registers, with the files of the destination and the
+/// source and the default rounding mode, if the instruction has one
+type Unary = (RegRegImm, File, File, Option<u32>);
+
+fn fp_unary(mnemonic: &str) -> Option<Unary> {
+    use File::{F, X};
+    use Instruction::*;
+    /* Conversions to double are exact, assemblers encode them with round to nearest */
+    let operation: Unary = match mnemonic {
+        "fsqrt.s" => (FSQRTS, F, F, Some(0b111)),
+        "fsqrt.d" => (FSQRTD, F, F, Some(0b111)),
+        "fcvt.s.d" => (FCVTSD, F, F, Some(0b111)),
+        "fcvt.d.s" => (FCVTDS, F, F, Some(0b000)),
+        "fcvt.w.s" => (FCVTWS, X, F, Some(0b111)),
+        "fcvt.wu.s" => (FCVTWUS, X, F, Some(0b111)),
+        "fcvt.w.d" => (FCVTWD, X, F, Some(0b111)),
+        "fcvt.wu.d" => (FCVTWUD, X, F, Some(0b111)),
+        "fcvt.s.w" => (FCVTSW, F, X, Some(0b111)),
+        "fcvt.s.wu" => (FCVTSWU, F, X, Some(0b111)),
+        "fcvt.d.w" => (FCVTDW, F, X, Some(0b000)),
+        "fcvt.d.wu" => (FCVTDWU, F, X, Some(0b000)),
+        "fmv.x.w" => (|rd, rs1, _| FMVXW(rd, rs1), X, F, None),
+        "fclass.s" => (|rd, rs1, _| FCLASSS(rd, rs1), X, F, None),
+        "fclass.d" => (|rd, rs1, _| FCLASSD(rd, rs1), X, F, None),
+        "fmv.w.x" => (|rd, rs1, _| FMVWX(rd, rs1), F, X, None),
+        _ => return None,
+    };
+    Some(operation)
+}
+
+impl Assembler {
+    /// Address the next byte is placed at
+    fn pc(&self) -> u32 {
+        self.origin.wrapping_add(self.code.len() as u32)
+    }
+
+    fn define(&mut self, name: &str, addr: u32) -> Result<()> {
+        if !self.final_pass && self.labels.insert(name.to_string(), addr).is_some() {
+            return Err(format!("Label defined twice: {name}"));
+        }
+        Ok(())
+    }
+
+    fn line(&mut self, line: &str) -> Result<()> {
+        let mut rest = strip_comment(line).trim();
+        /* Any number of labels may precede the statement */
+        loop {
+            let end = rest.find(|c| !is_symbol_char(c)).unwrap_or(rest.len());
+            match rest[end..].strip_prefix(':') {
+                Some(after) if end > 0 => {
+                    self.define(&rest[..end], self.pc())?;
+                    rest = after.trim_start();
+                }
+                _ => break,
+            }
+        }
+        if rest.is_empty() {
+            return Ok(());
+        }
+        let (head, operands) = rest
+            .split_once(char::is_whitespace)
+            .map_or((rest, ""), |(head, operands)| (head, operands.trim()));
+        if head.starts_with('.') {
+            return self.directive(head, operands);
+        }
+        let mnemonic = head.to_ascii_lowercase();
+        for instruction in self.instruction(&mnemonic, &split_operands(operands))? {
+            self.code
+                .extend_from_slice(&encode(&instruction).to_le_bytes());
+        }
+        Ok(())
+    }
+
+    /// Value of a symbol, before the final pass unknown ones are taken to be here
+    fn symbol(&self, name: &str) -> Result<i64> {
+        if let Some(&addr) = self.labels.get(name) {
+            return Ok(i64::from(addr));
+        }
+        match self.constants.get(name) {
+            Some(&value) => Ok(value),
+            None if !self.final_pass => Ok(i64::from(self.pc())),
+            None => Err(format!("Unknown symbol: {name}")),
+        }
+    }
+
+    /// Evaluates a sum of numbers and symbols, optionally wrapped in `%hi()` or `%lo()`
+    fn value(&self, expression: &str) -> Result<i64> {
+        let expression = expression.trim();
+        for (function, hi) in [("%hi(", true), ("%lo(", false)] {
+            if let Some(inner) = expression
+                .strip_prefix(function)
+                .and_then(|inner| inner.strip_suffix(')'))
+            {
+                let (upper, lower) = split_upper(self.value(inner)? as u32);
+                return Ok(if hi {
+                    i64::from(upper >> 12)
+                } else {
+                    i64::from((lower << 20) as i32 >> 20)
+                });
+            }
+        }
+        let mut total = 0;
+        let mut rest = expression;
+        let mut negative = false;
+        loop {
+            if let Some(after) = rest.strip_prefix('-') {
+                negative = !negative;
+                rest = after.trim_start();
+                continue;
+            }
+            /* The term ends at the next operator, characters like '-' are terms of their own */
+            let end = match rest.strip_prefix('\'') {
+                Some(character) => character.find('\'').map_or(rest.len(), |index| index + 2),
+                None => rest.find(['+', '-']).unwrap_or(rest.len()),
+            };
+            let term = rest[..end].trim();
+            let value = match number(term) {
+                Some(value) => value,
+                None if term.starts_with(|c: char| is_symbol_char(c) && !c.is_ascii_digit()) => {
+                    self.symbol(term)?
+                }
+                None => return Err(format!("Invalid expression: {expression}")),
+            };
+            total += if negative { -value } else { value };
+            rest = rest[end..].trim_start();
+            match rest.chars().next() {
+                None => return Ok(total),
+                Some('+') => negative = false,
+                Some(_) => negative = true,
+            }
+            rest = rest[1..].trim_start();
+        }
+    }
+
+    /// A value that must fit into `bits` bits, either as a signed or an unsigned number
+    fn bits(&self, expression: &str, bits: u32) -> Result<u32> {
+        let value = self.value(expression)?;
+        if value < -(1 << (bits - 1)) || value >= 1 << bits {
+            return Err(format!("{expression} doesn't fit into {bits} bits"));
+        }
+        Ok((value & ((1 << bits) - 1)) as u32)
+    }
+
+    /// A signed 12-bit immediate
+    fn immediate(&self, expression: &str) -> Result<u32> {
+        let value = self.value(expression)?;
+        if !(-2048..=2047).contains(&value) {
+            return Err(format!(
+                "{expression} is out of range for a 12-bit immediate"
+            ));
+        }
+        Ok(value as u32 & 0xFFF)
+    }
+
+    /// An unsigned immediate of `bits` bits, like shift amounts and CSR immediates
+    fn unsigned(&self, expression: &str, bits: u32) -> Result<u32> {
+        let value = self.value(expression)?;
+        if !(0..1 << bits).contains(&value) {
+            return Err(format!(
+                "{expression} is out of range for a {bits}-bit immediate"
+            ));
+        }
+        Ok(value as u32)
+    }
+
+    /// Offset to a branch or jump target that must fit into `bits` bits
+    fn target(&self, expression: &str, bits: u32) -> Result<u32> {
+        let offset = match number(expression.trim()) {
+            Some(offset) => offset,
+            None => self.value(expression)? - i64::from(self.pc()),
+        };
+        let limit = 1 << (bits - 1);
+        if !(-limit..limit).contains(&offset) || offset % 2 != 0 {
+            return Err(format!("{expression} is out of reach"));
+        }
+        Ok((offset & ((1 << bits) - 1)) as u32)
+    }
+
+    /// An `offset(register)` operand, the offset may be left out
+    fn memory(&self, operand: &str) -> Result<(u32, usize)> {
+        let (offset, base) = operand
+            .strip_suffix(')')
+            .and_then(|operand| operand.rsplit_once('('))
+            .ok_or(format!("Expected offset(register): {operand}"))?;
+        let offset = match offset.trim() {
+            "" => 0,
+            offset => self.immediate(offset)?,
+        };
+        Ok((offset, register(base.trim())?))
+    }
+
+    /// The address operand of an atomic instruction, `(a0)` or `0(a0)`
+    fn address(&self, operand: &str) -> Result<usize> {
+        match self.memory(operand)? {
+            (0, base) => Ok(base),
+            _ => Err(format!("Atomic instructions take no offset: {operand}")),
+        }
+    }
+
+    fn csr(&self, operand: &str) -> Result<u32> {
+        match (0..0x1000).find(|&index| csr_name(index).as_deref() == Some(operand)) {
+            Some(index) => Ok(index),
+            None if number(operand).is_some() || self.constants.contains_key(operand) => {
+                self.unsigned(operand, 12)
+            }
+            None => Err(format!("Unknown CSR: {operand}")),
+        }
+    }
+
+    /// `auipc` and the instruction adding the rest of the offset to `target`
+    fn pc_relative(&self, target: &str) -> Result<(u32, u32)> {
+        let offset = self.value(target)?.wrapping_sub(i64::from(self.pc()));
+        Ok(split_upper(offset as u32))
+    }
+
+    fn instruction(&self, mnemonic: &str, operands: &[&str]) -> Result<Vec<Instruction>> {
+        use Instruction::*;
+        let arity = |count: usize| {
+            if operands.len() == count {
+                Ok(())
+            } else {
+                Err(format!("{mnemonic} takes {count} operands"))
+            }
+        };
+        let op = |index: usize| operands[index];
+        let x = |index: usize| register(operands[index]);
+        let f = |index: usize| fp_register(operands[index]);
+
+        /* Atomics may carry an ordering suffix */
+        let (base, aqrl) = [(".aqrl", 0b11), (".aq", 0b10), (".rl", 0b01)]
+            .into_iter()
+            .find_map(|(suffix, aqrl)| Some((mnemonic.strip_suffix(suffix)?, aqrl)))
+            .filter(|(base, _)| {
+                base.starts_with("amo") || base.starts_with("lr.") || base.starts_with("sc.")
+            })
+            .unwrap_or((mnemonic, 0));
+
+        if let Some(constructor) = r_type(mnemonic) {
+            arity(3)?;
+            return Ok(vec![constructor(x(0)?, x(1)?, x(2)?)]);
+        }
+        if let Some(constructor) = i_type(mnemonic) {
+            let instruction = match mnemonic {
+                "sb" | "sh" | "sw" => {
+                    arity(2)?;
+                    let (offset, base) = self.memory(op(1))?;
+                    constructor(base, x(0)?, offset)
+                }
+                "fsw" | "fsd" => {
+                    arity(2)?;
+                    let (offset, base) = self.memory(op(1))?;
+                    constructor(base, f(0)?, offset)
+                }
+                "flw" | "fld" => {
+                    arity(2)?;
+                    let (offset, base) = self.memory(op(1))?;
+                    constructor(f(0)?, base, offset)
+                }
+                "jalr" if operands.len() == 1 => constructor(1, x(0)?, 0),
+                "jalr" if operands.len() == 3 => constructor(x(0)?, x(1)?, self.immediate(op(2))?),
+                "lb" | "lh" | "lw" | "lbu" | "lhu" | "jalr" => {
+                    arity(2)?;
+                    let (offset, base) = self.memory(op(1))?;
+                    constructor(x(0)?, base, offset)
+                }
+                _ => {
+                    arity(3)?;
+                    constructor(x(0)?, x(1)?, self.immediate(op(2))?)
+                }
+            };
+            return Ok(vec![instruction]);
+        }
+        if let Some(constructor) = branch(mnemonic) {
+            arity(3)?;
+            return Ok(vec![constructor(x(0)?, x(1)?, self.target(op(2), 13)?)]);
+        }
+        if let Some(constructor) = atomic(base) {
+            arity(3)?;
+            return Ok(vec![constructor(x(0)?, self.address(op(2))?, x(1)?, aqrl)]);
+        }
+        if let Some(constructor) = fp_fused(mnemonic) {
+            let rm = match operands.len() {
+                4 => 0b111,
+                _ => {
+                    arity(5)?;
+                    rounding_mode(op(4))?
+                }
+            };
+            return Ok(vec![constructor(f(0)?, f(1)?, f(2)?, f(3)?, rm)]);
+        }
+        if let Some(constructor) = fp_arithmetic(mnemonic) {
+            let rm = match operands.len() {
+                3 => 0b111,
+                _ => {
+                    arity(4)?;
+                    rounding_mode(op(3))?
+                }
+            };
+            return Ok(vec![constructor(f(0)?, f(1)?, f(2)?, rm)]);
+        }
+        if let Some((constructor, integer)) = fp_binary(mnemonic) {
+            arity(3)?;
+            let rd = if integer { x(0)? } else { f(0)? };
+            return Ok(vec![constructor(rd, f(1)?, f(2)?)]);
+        }
+        if let Some((constructor, rd_file, rs1_file, default)) = fp_unary(mnemonic) {
+            let rm = match (default, operands.len()) {
+                (Some(default), 2) => default,
+                (Some(_), _) => {
+                    arity(3)?;
+                    rounding_mode(op(2))?
+                }
+                (None, _) => {
+                    arity(2)?;
+                    0
+                }
+            };
+            let register = |index, file| match file {
+                File::X => x(index),
+                File::F => f(index),
+            };
+            return Ok(vec![constructor(
+                register(0, rd_file)?,
+                register(1, rs1_file)?,
+                rm,
+            )]);
+        }
+
+        let instructions = match (base, operands.len()) {
+            ("lui", 2) => vec![LUI(x(0)?, self.bits(op(1), 20)? << 12)],
+            ("auipc", 2) => vec![AUIPC(x(0)?, self.bits(op(1), 20)? << 12)],
+            ("jal", 1) => vec![JAL(1, self.target(op(0), 21)?)],
+            ("jal", 2) => vec![JAL(x(0)?, self.target(op(1), 21)?)],
+            ("j", 1) => vec![JAL(0, self.target(op(0), 21)?)],
+            ("jr", 1) => vec![JALR(0, x(0)?, 0)],
+            ("ret", 0) => vec![JALR(0, 1, 0)],
+            ("call" | "tail", 1) => {
+                let (link, scratch) = if base == "call" { (1, 1) } else { (0, 6) };
+                let (upper, lower) = self.pc_relative(op(0))?;
+                vec![AUIPC(scratch, upper), JALR(link, scratch, lower)]
+            }
+            ("slli", 3) => vec![SLLI(x(0)?, x(1)?, self.unsigned(op(2), 5)?)],
+            ("srli", 3) => vec![SRLI(x(0)?, x(1)?, self.unsigned(op(2), 5)?)],
+            ("srai", 3) => vec![SRAI(x(0)?, x(1)?, 0x400 | self.unsigned(op(2), 5)?)],
+            ("nop", 0) => vec![ADDI(0, 0, 0)],
+            ("li", 2) => {
+                let rd = x(0)?;
+                let value = self.bits(op(1), 32)?;
+                /* Only literals get the short forms, symbols may change value between passes */
+                let (upper, lower) = split_upper(value);
+                match number(op(1)) {
+                    Some(_) if upper == 0 => vec![ADDI(rd, 0, lower)],
+                    Some(_) if lower == 0 => vec![LUI(rd, upper)],
+                    _ => vec![LUI(rd, upper), ADDI(rd, rd, lower)],
+                }
+            }
+            ("la" | "lla", 2) => {
+                let rd = x(0)?;
+                let (upper, lower) = self.pc_relative(op(1))?;
+                vec![AUIPC(rd, upper), ADDI(rd, rd, lower)]
+            }
+            ("mv", 2) => vec![ADDI(x(0)?, x(1)?, 0)],
+            ("not", 2) => vec![XORI(x(0)?, x(1)?, 0xFFF)],
+            ("neg", 2) => vec![SUB(x(0)?, 0, x(1)?)],
+            ("seqz", 2) => vec![SLTIU(x(0)?, x(1)?, 1)],
+            ("snez", 2) => vec![SLTU(x(0)?, 0, x(1)?)],
+            ("sltz", 2) => vec![SLT(x(0)?, x(1)?, 0)],
+            ("sgtz", 2) => vec![SLT(x(0)?, 0, x(1)?)],
+            ("beqz", 2) => vec![BEQ(x(0)?, 0, self.target(op(1), 13)?)],
+            ("bnez", 2) => vec![BNE(x(0)?, 0, self.target(op(1), 13)?)],
+            ("bltz", 2) => vec![BLT(x(0)?, 0, self.target(op(1), 13)?)],
+            ("bgez", 2) => vec![BGE(x(0)?, 0, self.target(op(1), 13)?)],
+            ("blez", 2) => vec![BGE(0, x(0)?, self.target(op(1), 13)?)],
+            ("bgtz", 2) => vec![BLT(0, x(0)?, self.target(op(1), 13)?)],
+            /* The swapped comparisons exchange their operands */
+            ("bgt", 3) => vec![BLT(x(1)?, x(0)?, self.target(op(2), 13)?)],
+            ("ble", 3) => vec![BGE(x(1)?, x(0)?, self.target(op(2), 13)?)],
+            ("bgtu", 3) => vec![BLTU(x(1)?, x(0)?, self.target(op(2), 13)?)],
+            ("bleu", 3) => vec![BGEU(x(1)?, x(0)?, self.target(op(2), 13)?)],
+            ("fence", 0) => vec![FENCE(0, 0, 0b1111_1111)],
+            ("fence", 2) => {
+                let (pred, succ) = (fence_set(op(0))?, fence_set(op(1))?);
+                vec![FENCE(0, 0, (pred << 4) | succ)]
+            }
+            ("fence.tso", 0) => vec![FENCE(0, 0, 0b1000_0011_0011)],
+            ("ecall", 0) => vec![ECALL()],
+            ("ebreak", 0) => vec![EBREAK()],
+            ("sret", 0) => vec![SRET()],
+            ("mret", 0) => vec![MRET()],
+            ("wfi", 0) => vec![WFI()],
+            ("sfence.vma", 0) => vec![SFENCEVMA(0, 0)],
+            ("sfence.vma", 1) => vec![SFENCEVMA(x(0)?, 0)],
+            ("sfence.vma", 2) => vec![SFENCEVMA(x(0)?, x(1)?)],
+            ("csrrw", 3) => vec![CSRRW(x(0)?, x(2)?, self.csr(op(1))?)],
+            ("csrrs", 3) => vec![CSRRS(x(0)?, x(2)?, self.csr(op(1))?)],
+            ("csrrc", 3) => vec![CSRRC(x(0)?, x(2)?, self.csr(op(1))?)],
+            ("csrrwi", 3) => vec![CSRRWI(
+                x(0)?,
+                self.unsigned(op(2), 5)? as usize,
+                self.csr(op(1))?,
+            )],
+            ("csrrsi", 3) => vec![CSRRSI(
+                x(0)?,
+                self.unsigned(op(2), 5)? as usize,
+                self.csr(op(1))?,
+            )],
+            ("csrrci", 3) => vec![CSRRCI(
+                x(0)?,
+                self.unsigned(op(2), 5)? as usize,
+                self.csr(op(1))?,
+            )],
+            ("csrr", 2) => vec![CSRRS(x(0)?, 0, self.csr(op(1))?)],
+            ("csrw", 2) => vec![CSRRW(0, x(1)?, self.csr(op(0))?)],
+            ("csrs", 2) => vec![CSRRS(0, x(1)?, self.csr(op(0))?)],
+            ("csrc", 2) => vec![CSRRC(0, x(1)?, self.csr(op(0))?)],
+            ("csrwi", 2) => vec![CSRRWI(
+                0,
+                self.unsigned(op(1), 5)? as usize,
+                self.csr(op(0))?,
+            )],
+            ("csrsi", 2) => vec![CSRRSI(
+                0,
+                self.unsigned(op(1), 5)? as usize,
+                self.csr(op(0))?,
+            )],
+            ("csrci", 2) => vec![CSRRCI(
+                0,
+                self.unsigned(op(1), 5)? as usize,
+                self.csr(op(0))?,
+            )],
+            ("rdcycle" | "rdtime" | "rdinstret" | "rdcycleh" | "rdtimeh" | "rdinstreth", 1) => {
+                vec![CSRRS(x(0)?, 0, self.csr(&base[2..])?)]
+            }
+            ("lr.w", 2) => vec![LRW(x(0)?, self.address(op(1))?, aqrl)],
+            ("fmv.s", 2) => vec![FSGNJS(f(0)?, f(1)?, f(1)?)],
+            ("fneg.s", 2) => vec![FSGNJNS(f(0)?, f(1)?, f(1)?)],
+            ("fabs.s", 2) => vec![FSGNJXS(f(0)?, f(1)?, f(1)?)],
+            ("fmv.d", 2) => vec![FSGNJD(f(0)?, f(1)?, f(1)?)],
+            ("fneg.d", 2) => vec![FSGNJND(f(0)?, f(1)?, f(1)?)],
+            ("fabs.d", 2) => vec![FSGNJXD(f(0)?, f(1)?, f(1)?)],
+            _ => return Err(format!("Unknown instruction or wrong operands: {mnemonic}")),
+        };
+        Ok(instructions)
+    }
+
+    fn directive(&mut self, directive: &str, operands: &str) -> Result<()> {
+        let values = split_operands(operands);
+        match directive {
+            ".byte" | ".half" | ".short" | ".word" | ".long" => {
+                let size = match directive {
+                    ".byte" => 1,
+                    ".half" | ".short" => 2,
+                    _ => 4,
+                };
+                for value in values {
+                    let value = self.bits(value, size * 8)?;
+                    self.code
+                        .extend_from_slice(&value.to_le_bytes()[..size as usize]);
+                }
+            }
+            ".ascii" | ".asciz" | ".string" => {
+                for string in strings(operands)? {
+                    self.code.extend_from_slice(&string);
+                    if directive != ".ascii" {
+                        self.code.push(0);
+                    }
+                }
+            }
+            ".zero" | ".space" | ".skip" => {
+                let (size, fill) = match values.as_slice() {
+                    [size] => (self.unsigned(size, 24)?, 0),
+                    [size, fill] => (self.unsigned(size, 24)?, self.bits(fill, 8)?),
+                    _ => return Err(format!("{directive} takes a size and a fill value")),
+                };
+                self.code
+                    .resize(self.code.len() + size as usize, fill as u8);
+            }
+            /* .align counts in powers of two, like GNU as does for RISC-V */
+            ".align" | ".p2align" | ".balign" => {
+                let [alignment] = values.as_slice() else {
+                    return Err(format!("{directive} takes an alignment"));
+                };
+                let alignment = match directive {
+                    ".balign" => self.unsigned(alignment, 16)?,
+                    _ => 1 << self.unsigned(alignment, 4)?,
+                };
+                if alignment == 0 || !alignment.is_power_of_two() {
+                    return Err(format!("Invalid alignment: {alignment}"));
+                }
+                while !self.pc().is_multiple_of(alignment) {
+                    self.code.push(0);
+                }
+            }
+            ".equ" | ".set" => {
+                let [name, value] = values.as_slice() else {
+                    return Err(format!("{directive} takes a name and a value"));
+                };
+                let value = self.value(value)?;
+                self.constants.insert(name.to_string(), value);
+            }
+            /* There is a single section, the rest only matters to linkers */
+            ".text" | ".data" | ".rodata" | ".bss" | ".section" | ".globl" | ".global"
+            | ".local" | ".type" | ".size" | ".file" | ".option" | ".attribute" => {}
+            _ => return Err(format!("Unknown directive: {directive}")),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::decode;
+    use Instruction::*;
+
+    /// Instructions at the start of `source` assembled at `origin`, up to the first data
+    fn instructions(source: &str, origin: u32) -> Vec<Instruction> {
+        assemble(source, origin)
+            .unwrap()
+            .code
+            .chunks(4)
+            .map_while(|word| decode(u32::from_le_bytes(word.try_into().ok()?)).ok())
+            .collect()
+    }
+
+    /// Message of the error assembling `source` fails with
+    fn error(source: &str) -> String {
+        match assemble(source, 0) {
+            Ok(_) => panic!("{source} assembled"),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    #[test]
+    fn labels_resolve_forward_and_backward() {
+        let source = "start: j end
+                      nop
+                      end: beq a0, a1, start
+                      loop: bnez a0, loop";
+        assert_eq!(
+            instructions(source, 0x1000),
+            [JAL(0, 8), ADDI(0, 0, 0), BEQ(10, 11, 0x1FF8), BNE(10, 0, 0)]
+        );
+        let symbols = assemble(source, 0x1000).unwrap().symbols;
+        assert_eq!(symbols.at(0x1000), Some("start"));
+        assert_eq!(symbols.at(0x1008), Some("end"));
+        assert_eq!(symbols.address("loop"), Some(0x100C));
+    }
+
+    #[test]
+    fn branches_and_jumps_must_reach_their_target() {
+        assert_eq!(
+            instructions("beq a0, a1, far\n.zero 4090\nfar:", 0)[0],
+            BEQ(10, 11, 0xFFE)
+        );
+        assert_eq!(
+            error("beq a0, a1, far\n.zero 4092\nfar:"),
+            "Line 1: far is out of reach"
+        );
+        assert_eq!(
+            error("back:\n.zero 4100\nbltu a0, a1, back"),
+            "Line 3: back is out of reach"
+        );
+        assert_eq!(error("j 3"), "Line 1: 3 is out of reach");
+        assert_eq!(error("j 0x100000"), "Line 1: 0x100000 is out of reach");
+        assert_eq!(instructions("j 0xffffe", 0), [JAL(0, 0xF_FFFE)]);
+    }
+
+    #[test]
+    fn data_directives() {
+        let source = ".byte 1, -1
+                      .align 2
+                      .half 0x1234
+                      .word label
+                      .ascii \"hi\\n\"
+                      .asciz \"a\"
+                      label:
+                      .balign 4
+                      .word -1";
+        let program = assemble(source, 0).unwrap();
+        assert_eq!(
+            program.code,
+            [
+                1, 0xFF, 0, 0, 0x34, 0x12, 15, 0, 0, 0, b'h', b'i', b'\n', b'a', 0, 0, 0xFF, 0xFF,
+                0xFF, 0xFF
+            ]
+        );
+        assert_eq!(program.symbols.address("label"), Some(15));
+        /* .align counts in powers of two */
+        assert_eq!(assemble(".byte 1\n.align 3", 0).unwrap().code.len(), 8);
+    }
+
+    #[test]
+    fn pseudo_instructions() {
+        let cases: &[(&str, &[Instruction])] = &[
+            ("li a0, 42", &[ADDI(10, 0, 42)]),
+            ("li a0, -1", &[ADDI(10, 0, 0xFFF)]),
+            ("li a0, 0x10000", &[LUI(10, 0x1_0000)]),
+            (
+                "li a0, 0x12345678",
+                &[LUI(10, 0x1234_5000), ADDI(10, 10, 0x678)],
+            ),
+            /* A negative lower part is added to the next upper value */
+            (
+                "li a1, 0xdeadbeef",
+                &[LUI(11, 0xDEAD_C000), ADDI(11, 11, 0xEEF)],
+            ),
+            ("ret", &[JALR(0, 1, 0)]),
+            ("j 16", &[JAL(0, 16)]),
+            ("mv a0, a1", &[ADDI(10, 11, 0)]),
+        ];
+        for &(source, expected) in cases {
+            assert_eq!(instructions(source, 0), expected, "{source}");
+        }
+        /* Symbols are PC-relative, they split into auipc and a 12-bit lower part */
+        let source = "la a0, data
+                      call function
+                      function: ret
+                      .zero 0x1800
+                      data:";
+        assert_eq!(
+            instructions(source, 0x8000_0000)[..4],
+            [
+                AUIPC(10, 0x2000),
+                ADDI(10, 10, 0x814),
+                AUIPC(1, 0),
+                JALR(1, 1, 8)
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_name_the_line_and_the_problem() {
+        let cases = [
+            (
+                "nop\nfoo a0",
+                "Line 2: Unknown instruction or wrong operands: foo",
+            ),
+            ("addi a0, a1", "Line 1: addi takes 3 operands"),
+            (
+                "addi a0, a1, 2048",
+                "Line 1: 2048 is out of range for a 12-bit immediate",
+            ),
+            ("add a0, a1, q9", "Line 1: Invalid register: q9"),
+            ("lw a0, a1", "Line 1: Expected offset(register): a1"),
+            ("la a0, missing", "Line 1: Unknown symbol: missing"),
+            ("a:\na:", "Line 2: Label defined twice: a"),
+            (".byte 256", "Line 1: 256 doesn't fit into 8 bits"),
+            (".balign 3", "Line 1: Invalid alignment: 3"),
+            (".foo", "Line 1: Unknown directive: .foo"),
+        ];
+        for (source, message) in cases {
+            assert_eq!(error(source), message, "{source}");
+        }
+    }
+}
@@ -0,0 +1,403 @@
+use anyhow::{anyhow, ensure};
+
+use crate::assembler::assemble;
+use crate::clint::{CLINT_LEN, WALL_CLOCK_HZ};
+use crate::fdt::Fdt;
+use crate::plic::PLIC_LEN;
+use crate::symbols::Symbols;
+use crate::system::{Image, RAM_BASE};
+use crate::uart::UART_LEN;
+
+/// Memory taken by the firmware at the start of RAM, a power of two to fit a PMP entry
+const FIRMWARE_SIZE: usize = 0x1_0000;
+/// Offset of the kernel in RAM, RV32 Linux has to be loaded 4 MiB aligned
+const KERNEL_OFFSET: usize = 0x40_0000;
+/// Space for the device tree at the end of RAM
+const DTB_SIZE: usize = 0x1_0000;
+
+/* Fields of the header of a RISC-V Linux `Image` */
+const IMAGE_SIZE: usize = 0x10;
+const IMAGE_MAGIC2: usize = 0x38;
+
+/* Phandles of the interrupt controllers */
+const CPU_INTC: u32 = 1;
+const PLIC: u32 = 2;
+
+/// Base clock of the UART, the one of the crystal of a PC serial port
+const UART_CLOCK_HZ: u32 = 1_843_200;
+
+/// The machine a kernel boots on, described to it in the device tree
+pub struct Machine {
+    pub ram_size: usize,
+    pub clint_base: usize,
+    pub plic_base: usize,
+    pub plic_sources: usize,
+    pub plic_contexts: usize,
+    pub uart_base: usize,
+    pub uart_irq: usize,
+}
+
+/// A kernel to boot, with its initial ramdisk and command line
+pub struct Kernel<'a> {
+    pub image: &'a [u8],
+    pub initrd: Option<&'a [u8]>,
+    pub bootargs: &'a str,
+}
+
+/// Lays out RAM for booting a kernel `Image`, a flat binary, following the boot protocol
+/// of RISC-V Linux.
+///
+/// The built-in SBI firmware at the start of RAM runs first, in machine mode, and enters
+/// the kernel in supervisor mode at 4 MiB into RAM. The device tree goes to the end of RAM
+/// and the initrd right below it. The symbols are the ones of the firmware.
+///
+/// Only kernels written for the tests have been booted this way, no Linux kernel.
+pub fn linux(machine: &Machine, kernel: &Kernel) -> anyhow::Result<(Image, Symbols)> {
+    let ram_size = machine.ram_size;
+    let dtb_offset = ram_size - DTB_SIZE;
+    let initrd = kernel.initrd.unwrap_or_default();
+    let too_large = || {
+        anyhow!(
+            "Kernel and initrd don't fit into {} MiB of RAM",
+            ram_size >> 20
+        )
+    };
+    let initrd_offset = dtb_offset.checked_sub(initrd.len()).ok_or_else(too_large)? & !0xFFF;
+
+    /* The header tells the size including .bss, which must not overlap the initrd */
+    let image_size = match kernel.image.get(IMAGE_MAGIC2..IMAGE_MAGIC2 + 4) {
+        Some(b"RSC\x05") => {
+            let size = kernel.image[IMAGE_SIZE..IMAGE_SIZE + 8].try_into()?;
+            usize::try_from(u64::from_le_bytes(size))?
+        }
+        _ => kernel.image.len(),
+    };
+    let kernel_end = KERNEL_OFFSET
+        .checked_add(image_size.max(kernel.image.len()))
+        .ok_or_else(too_large)?;
+    if kernel_end > initrd_offset {
+        return Err(too_large());
+    }
+
+    let dtb_addr = RAM_BASE + dtb_offset;
+    let initrd_range = kernel.initrd.map(|initrd| {
+        (
+            RAM_BASE + initrd_offset,
+            RAM_BASE + initrd_offset + initrd.len(),
+        )
+    });
+    let dtb = device_tree(machine, kernel.bootargs, initrd_range);
+    ensure!(dtb.len() <= DTB_SIZE, "Device tree is too large");
+
+    let source = format!(
+        ".equ KERNEL_ENTRY, 0x{:X}\n\
+         .equ DTB_ADDR, 0x{dtb_addr:X}\n\
+         .equ FIRMWARE_PMPADDR, 0x{:X}\n\
+         .equ CLINT_BASE, 0x{:X}\n\
+         .equ UART_BASE, 0x{:X}\n\
+         {}",
+        RAM_BASE + KERNEL_OFFSET,
+        /* NAPOT, the trailing ones encode the size */
+        (RAM_BASE >> 2) | ((FIRMWARE_SIZE >> 3) - 1),
+        machine.clint_base,
+        machine.uart_base,
+        include_str!("sbi.s")
+    );
+    let firmware = assemble(&source, RAM_BASE as u32)?;
+    ensure!(
+        firmware.code.len() <= FIRMWARE_SIZE,
+        "Firmware is too large"
+    );
+
+    let mut ram = vec![0; ram_size];
+    ram[..firmware.code.len()].copy_from_slice(&firmware.code);
+    ram[KERNEL_OFFSET..KERNEL_OFFSET + kernel.image.len()].copy_from_slice(kernel.image);
+    ram[initrd_offset..initrd_offset + initrd.len()].copy_from_slice(initrd);
+    ram[dtb_offset..dtb_offset + dtb.len()].copy_from_slice(&dtb);
+    Ok((Image::flat(ram), firmware.symbols))
+}
+
+/// Device tree of the machine, the kernel gets its address in `a1`
+fn device_tree(machine: &Machine, bootargs: &str, initrd: Option<(usize, usize)>) -> Vec<u8> {
+    let uart = format!("serial@{:x}", machine.uart_base);
+    let mut fdt = Fdt::default();
+    fdt.begin_node("");
+    fdt.property_u32("#address-cells", 1);
+    fdt.property_u32("#size-cells", 1);
+    fdt.property_string("compatible", "rv");
+    fdt.property_string("model", "rv");
+
+    fdt.begin_node("chosen");
+    fdt.property_string("bootargs", bootargs);
+    fdt.property_string("stdout-path", &format!("/soc/{uart}"));
+    if let Some((start, end)) = initrd {
+        fdt.property_u32("linux,initrd-start", start as u32);
+        fdt.property_u32("linux,initrd-end", end as u32);
+    }
+    fdt.end_node();
+
+    fdt.begin_node(&format!("memory@{RAM_BASE:x}"));
+    fdt.property_string("device_type", "memory");
+    fdt.property_cells("reg", &[RAM_BASE as u32, machine.ram_size as u32]);
+    fdt.end_node();
+
+    /* The PMP keeps the kernel out of the firmware, it must not use that memory */
+    fdt.begin_node("reserved-memory");
+    fdt.property_u32("#address-cells", 1);
+    fdt.property_u32("#size-cells", 1);
+    fdt.property_empty("ranges");
+    fdt.begin_node(&format!("firmware@{RAM_BASE:x}"));
+    fdt.property_cells("reg", &[RAM_BASE as u32, FIRMWARE_SIZE as u32]);
+    fdt.property_empty("no-map");
+    fdt.end_node();
+    fdt.end_node();
+
+    fdt.begin_node("cpus");
+    fdt.property_u32("#address-cells", 1);
+    fdt.property_u32("#size-cells", 0);
+    /* Kernels always run with mtime following the wall clock */
+    fdt.property_u32("timebase-frequency", WALL_CLOCK_HZ as u32);
+    fdt.begin_node("cpu@0");
+    fdt.property_string("device_type", "cpu");
+    fdt.property_u32("reg", 0);
+    fdt.property_string("status", "okay");
+    fdt.property_string("compatible", "riscv");
+    fdt.property_string("riscv,isa", "rv32imafdc_zicsr_zifencei");
+    fdt.property_string("riscv,isa-base", "rv32i");
+    fdt.property_strings(
+        "riscv,isa-extensions",
+        &["i", "m", "a", "f", "d", "c", "zicntr", "zicsr", "zifencei"],
+    );
+    fdt.property_string("mmu-type", "riscv,sv32");
+    fdt.begin_node("interrupt-controller");
+    fdt.property_u32("#interrupt-cells", 1);
+    fdt.property_empty("interrupt-controller");
+    fdt.property_string("compatible", "riscv,cpu-intc");
+    fdt.property_u32("phandle", CPU_INTC);
+    fdt.end_node();
+    fdt.end_node();
+    fdt.end_node();
+
+    fdt.begin_node("soc");
+    fdt.property_u32("#address-cells", 1);
+    fdt.property_u32("#size-cells", 1);
+    fdt.property_string("compatible", "simple-bus");
+    fdt.property_empty("ranges");
+
+    fdt.begin_node(&format!("clint@{:x}", machine.clint_base));
+    fdt.property_strings("compatible", &["sifive,clint0", "riscv,clint0"]);
+    fdt.property_cells("reg", &[machine.clint_base as u32, CLINT_LEN as u32]);
+    /* Machine software and timer interrupt */
+    fdt.property_cells("interrupts-extended", &[CPU_INTC, 3, CPU_INTC, 7]);
+    fdt.end_node();
+
+    /* Context 0 raises the machine external interrupt, context 1 the supervisor one */
+    let contexts: Vec<u32> = [11, 9]
+        .iter()
+        .take(machine.plic_contexts)
+        .flat_map(|&interrupt| [CPU_INTC, interrupt])
+        .collect();
+    fdt.begin_node(&format!("plic@{:x}", machine.plic_base));
+    fdt.property_strings("compatible", &["sifive,plic-1.0.0", "riscv,plic0"]);
+    fdt.property_cells("reg", &[machine.plic_base as u32, PLIC_LEN as u32]);
+    fdt.property_cells("interrupts-extended", &contexts);
+    fdt.property_empty("interrupt-controller");
+    fdt.property_u32("#interrupt-cells", 1);
+    fdt.property_u32("#address-cells", 0);
+    fdt.property_u32("riscv,ndev", machine.plic_sources as u32);
+    fdt.property_u32("phandle", PLIC);
+    fdt.end_node();
+
+    fdt.begin_node(&uart);
+    fdt.property_string("compatible", "ns16550a");
+    fdt.property_cells("reg", &[machine.uart_base as u32, UART_LEN as u32]);
+    fdt.property_u32("clock-frequency", UART_CLOCK_HZ);
+    fdt.property_u32("interrupt-parent", PLIC);
+    fdt.property_u32("interrupts", machine.uart_irq as u32);
+    fdt.end_node();
+
+    fdt.end_node();
+    fdt.end_node();
+    fdt.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+    use crate::debugger::{Debugger, Stop};
+    use crate::fdt::tests::{parse, Node};
+    use crate::system::Privilege;
+
+    const MACHINE: Machine = Machine {
+        ram_size: 8 << 20,
+        clint_base: 0x0200_0000,
+        plic_base: 0x0C00_0000,
+        plic_sources: 31,
+        plic_contexts: 2,
+        uart_base: 0x1000_0000,
+        uart_irq: 10,
+    };
+
+    fn device_tree_of(machine: &Machine, initrd: Option<(usize, usize)>) -> Node {
+        parse(&device_tree(machine, "console=ttyS0", initrd))
+    }
+
+    #[test]
+    fn device_tree_describes_memory_and_chosen() {
+        let root = device_tree_of(&MACHINE, Some((0x8070_0000, 0x8070_1234)));
+        let memory = root.child("memory@80000000");
+        assert_eq!(memory.string("device_type"), "memory");
+        assert_eq!(memory.cells("reg"), [0x8000_0000, 8 << 20]);
+        let firmware = root.child("reserved-memory").child("firmware@80000000");
+        assert_eq!(firmware.cells("reg"), [0x8000_0000, FIRMWARE_SIZE as u32]);
+        assert!(firmware.get("no-map").is_some());
+
+        let chosen = root.child("chosen");
+        assert_eq!(chosen.string("bootargs"), "console=ttyS0");
+        assert_eq!(chosen.string("stdout-path"), "/soc/serial@10000000");
+        assert_eq!(chosen.cells("linux,initrd-start"), [0x8070_0000]);
+        assert_eq!(chosen.cells("linux,initrd-end"), [0x8070_1234]);
+
+        let chosen = device_tree_of(&MACHINE, None);
+        assert!(chosen.child("chosen").get("linux,initrd-start").is_none());
+    }
+
+    #[test]
+    fn interrupts_reference_the_controllers_by_phandle() {
+        let root = device_tree_of(&MACHINE, None);
+        let intc = root
+            .child("cpus")
+            .child("cpu@0")
+            .child("interrupt-controller");
+        assert_eq!(intc.cells("phandle"), [CPU_INTC]);
+        assert!(intc.get("interrupt-controller").is_some());
+
+        let soc = root.child("soc");
+        let clint = soc.child("clint@2000000");
+        assert_eq!(clint.cells("reg"), [0x0200_0000, CLINT_LEN as u32]);
+        assert_eq!(
+            clint.cells("interrupts-extended"),
+            [CPU_INTC, 3, CPU_INTC, 7]
+        );
+        let plic = soc.child("plic@c000000");
+        assert_eq!(plic.cells("phandle"), [PLIC]);
+        assert_eq!(plic.cells("riscv,ndev"), [31]);
+        assert_eq!(
+            plic.cells("interrupts-extended"),
+            [CPU_INTC, 11, CPU_INTC, 9]
+        );
+        let uart = soc.child("serial@10000000");
+        assert_eq!(uart.cells("interrupt-parent"), [PLIC]);
+        assert_eq!(uart.cells("interrupts"), [10]);
+
+        /* A single PLIC context only raises the machine external interrupt */
+        let machine = Machine {
+            plic_contexts: 1,
+            ..MACHINE
+        };
+        let root = device_tree_of(&machine, None);
+        let plic = root.child("soc").child("plic@c000000");
+        assert_eq!(plic.cells("interrupts-extended"), [CPU_INTC, 11]);
+    }
+
+    #[test]
+    fn ram_holds_firmware_kernel_initrd_and_device_tree() {
+        let initrd = [0xAB; 5000];
+        let kernel = Kernel {
+            image: &[1, 2, 3, 4],
+            initrd: Some(&initrd),
+            bootargs: "rdinit=/init",
+        };
+        let (image, symbols) = linux(&MACHINE, &kernel).unwrap();
+        assert_eq!(image.entry, RAM_BASE as u32);
+        assert_eq!(image.ram.len(), MACHINE.ram_size);
+        assert_eq!(symbols.address("_start"), Some(RAM_BASE as u32));
+        assert_eq!(image.ram[KERNEL_OFFSET..KERNEL_OFFSET + 4], [1, 2, 3, 4]);
+
+        let dtb_offset = MACHINE.ram_size - DTB_SIZE;
+        let dtb = &image.ram[dtb_offset..];
+        let total = u32::from_be_bytes(dtb[4..8].try_into().unwrap());
+        let root = parse(&dtb[..total as usize]);
+        let chosen = root.child("chosen");
+        assert_eq!(chosen.string("bootargs"), "rdinit=/init");
+        let start = chosen.cells("linux,initrd-start")[0] as usize - RAM_BASE;
+        let end = chosen.cells("linux,initrd-end")[0] as usize - RAM_BASE;
+        /* Page aligned, right below the device tree */
+        assert_eq!(start % 0x1000, 0);
+        assert!(end <= dtb_offset && dtb_offset - start < 0x1000 + initrd.len());
+        assert_eq!(image.ram[start..end], initrd);
+    }
+
+    #[test]
+    fn kernel_and_initrd_must_fit() {
+        let initrd = vec![0; MACHINE.ram_size - KERNEL_OFFSET];
+        let kernel = Kernel {
+            image: &[0; 4],
+            initrd: Some(&initrd),
+            bootargs: "",
+        };
+        let error = linux(&MACHINE, &kernel).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Kernel and initrd don't fit into 8 MiB of RAM"
+        );
+
+        /* The header of an Image tells the size of the kernel including .bss */
+        let mut header = vec![0; 0x40];
+        header[IMAGE_SIZE..IMAGE_SIZE + 8].copy_from_slice(&(8u64 << 20).to_le_bytes());
+        header[IMAGE_MAGIC2..IMAGE_MAGIC2 + 4].copy_from_slice(b"RSC\x05");
+        let kernel = Kernel {
+            image: &header,
+            initrd: None,
+            bootargs: "",
+        };
+        assert!(linux(&MACHINE, &kernel).is_err());
+    }
+
+    #[test]
+    fn firmware_hands_over_to_the_kernel() {
+        /* Keeps what it was handed, asks the firmware for the SBI version and shuts down */
+        let kernel = assemble(
+            "mv s0, a0
+             mv s1, a1
+             lw s2, 0(a1)
+             li a7, 0x10
+             li a6, 0
+             ecall
+             mv s4, a1
+             li a7, 0x53525354
+             li a6, 0
+             li a0, 0
+             li a1, 0
+             ecall",
+            (RAM_BASE + KERNEL_OFFSET) as u32,
+        )
+        .unwrap();
+        let kernel = Kernel {
+            image: &kernel.code,
+            initrd: None,
+            bootargs: "",
+        };
+        let (image, _) = linux(&MACHINE, &kernel).unwrap();
+        let mut rf = crate::system::RegisterFile::default();
+        rf.pc = image.entry;
+        let mut bus = image.into_bus(Box::new(io::sink())).unwrap();
+        let mut debugger = Debugger::default();
+        debugger.ebreak = Some(Privilege::Machine);
+        /* Traps of the kernel are served by the firmware, only its EBREAK stops */
+        let stopped =
+            (0..10_000).any(|_| matches!(debugger.step(&mut rf, &mut bus), Some(Stop::Ebreak(_))));
+
+        assert!(stopped, "No EBREAK at 0x{:08X}", rf.pc);
+        /* Shut down by an ECALL from supervisor mode */
+        assert_eq!(rf.read(17), 93);
+        assert_eq!(rf.csr.mcause, 9);
+        assert_eq!(rf.read(8), 0);
+        assert_eq!(rf.read(9), (RAM_BASE + MACHINE.ram_size - DTB_SIZE) as u32);
+        /* The magic of the device tree, big-endian */
+        assert_eq!(rf.read(18), 0xEDFE_0DD0);
+        assert_eq!(rf.read(20), 0x0200_0000);
+    }
+}
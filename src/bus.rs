@@ -0,0 +1,418 @@
+use std::any::Any;
+use std::fmt;
+
+use anyhow::{anyhow, ensure};
+
+use crate::plic::Plic;
+use crate::trap::Exception;
+use crate::watch::{Access, WatchHit, Watchpoint};
+
+/// Width of a bus access
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Size {
+    Byte = 1,
+    Halfword = 2,
+    Word = 4,
+}
+
+impl Size {
+    pub fn bytes(self) -> usize {
+        self as usize
+    }
+}
+
+/// Error of a device that can't perform an access, the bus turns it into an access fault
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessFault;
+
+/// A memory-mapped device, offsets are relative to the address it is mapped at.
+pub trait Device: Any {
+    fn read(&mut self, offset: usize, size: Size) -> Result<u32, AccessFault>;
+
+    fn write(&mut self, offset: usize, size: Size, value: u32) -> Result<(), AccessFault>;
+
+    /// Reads without side effects, for instruction fetch and the debugger.
+    ///
+    /// Devices whose reads have side effects keep the default, nothing can be fetched
+    /// from them.
+    fn peek(&self, _offset: usize, _size: Size) -> Option<u32> {
+        None
+    }
+
+    /// Advances the device after every step, `retired` tells whether an instruction retired
+    fn tick(&mut self, _retired: bool) {}
+
+    /// Interrupts the device raises directly at the hart, as `mip` bits
+    fn interrupts(&self) -> u32 {
+        0
+    }
+
+    /// Level of the interrupt line of the device, which the bus routes to the PLIC
+    fn irq(&self) -> bool {
+        false
+    }
+}
+
+struct Mapping {
+    name: &'static str,
+    base: usize,
+    len: usize,
+    /* PLIC source the interrupt line of the device is connected to */
+    irq: Option<usize>,
+    device: Box<dyn Device>,
+}
+
+impl Mapping {
+    fn contains(&self, addr: usize, size: Size) -> bool {
+        self.base <= addr && addr + size.bytes() <= self.base + self.len
+    }
+}
+
+/// The system bus, an ordered list of address ranges each mapped to a device.
+#[derive(Default)]
+pub struct Bus {
+    mappings: Vec<Mapping>,
+    /* Index of the PLIC in the mappings and (mapping index, PLIC source) of every routed
+     * interrupt line, kept up to date by attach and route_irq so tick doesn't search */
+    plic: Option<usize>,
+    routes: Vec<(usize, usize)>,
+    watchpoints: Vec<Watchpoint>,
+    /* First watchpoint hit since the debugger last looked */
+    watch_hit: Option<WatchHit>,
+}
+
+impl Bus {
+    /// Maps `device` at `base`, the range must not overlap any already mapped device
+    pub fn attach(
+        &mut self,
+        name: &'static str,
+        base: usize,
+        len: usize,
+        device: impl Device,
+    ) -> anyhow::Result<()> {
+        ensure!(len > 0, "Device {name} has no size");
+        let end = base
+            .checked_add(len)
+            .ok_or(anyhow!("Device {name} exceeds the address space"))?;
+        if let Some(other) = self
+            .mappings
+            .iter()
+            .find(|other| base < other.base + other.len && other.base < end)
+        {
+            return Err(anyhow!(
+                "Device {name} at 0x{base:08X} overlaps {} at 0x{:08X}",
+                other.name,
+                other.base
+            ));
+        }
+
+        let index = self.mappings.partition_point(|other| other.base < base);
+        self.mappings.insert(
+            index,
+            Mapping {
+                name,
+                base,
+                len,
+                irq: None,
+                device: Box::new(device),
+            },
+        );
+        self.update_routes();
+        Ok(())
+    }
+
+    /// Connects the interrupt line of the device called `name` to PLIC source `source`
+    pub fn route_irq(&mut self, name: &str, source: usize) -> anyhow::Result<()> {
+        let mapping = self
+            .mappings
+            .iter_mut()
+            .find(|mapping| mapping.name == name)
+            .ok_or(anyhow!("No device called {name}"))?;
+        mapping.irq = Some(source);
+        self.update_routes();
+        Ok(())
+    }
+
+    /// Finds the PLIC and the routed interrupt lines again, after the mappings changed
+    fn update_routes(&mut self) {
+        self.plic = self
+            .mappings
+            .iter()
+            .position(|mapping| (mapping.device.as_ref() as &dyn Any).is::<Plic>());
+        self.routes = self
+            .mappings
+            .iter()
+            .enumerate()
+            .filter_map(|(index, mapping)| Some((index, mapping.irq?)))
+            .collect();
+    }
+
+    /// The first device of type `T`
+    pub fn device<T: Device>(&self) -> Option<&T> {
+        self.mappings
+            .iter()
+            .find_map(|mapping| (mapping.device.as_ref() as &dyn Any).downcast_ref())
+    }
+
+    fn mapping(&self, addr: usize, size: Size) -> Option<&Mapping> {
+        self.mappings
+            .iter()
+            .find(|mapping| mapping.contains(addr, size))
+    }
+
+    fn mapping_mut(&mut self, addr: usize, size: Size) -> Option<&mut Mapping> {
+        self.mappings
+            .iter_mut()
+            .find(|mapping| mapping.contains(addr, size))
+    }
+
+    /// Whether a device is mapped at all `size` bytes from `addr`
+    pub fn maps(&self, addr: usize, size: Size) -> bool {
+        self.mapping(addr, size).is_some()
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn watch(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn unwatch(&mut self, index: usize) -> Option<Watchpoint> {
+        (index < self.watchpoints.len()).then(|| self.watchpoints.remove(index))
+    }
+
+    pub fn unwatch_all(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    /// The watchpoint hit since the last call, if any
+    pub fn take_watch_hit(&mut self) -> Option<WatchHit> {
+        self.watch_hit.take()
+    }
+
+    /// Value a watchpoint sees, that of the watched range or else the one of the access
+    fn watched_value(&self, watchpoint: &Watchpoint, addr: usize, size: Size) -> Option<u32> {
+        match watchpoint.size() {
+            Some(watched) => self.peek(watchpoint.addr, watched),
+            None => self.peek(addr, size),
+        }
+    }
+
+    /// Records the first watchpoint the access hits, `before` holds the values every
+    /// watchpoint saw before a write and is `None` for reads
+    fn check_watchpoints(
+        &mut self,
+        addr: usize,
+        size: Size,
+        value: u32,
+        before: Option<&[Option<u32>]>,
+    ) {
+        if self.watch_hit.is_some() {
+            return;
+        }
+        for (index, watchpoint) in self.watchpoints.iter().enumerate() {
+            if !watchpoint.overlaps(addr, size) {
+                continue;
+            }
+            let access = match before {
+                Some(before) => Access::Write { old: before[index] },
+                None => Access::Read,
+            };
+            let value = match watchpoint.size() {
+                Some(_) => self.watched_value(watchpoint, addr, size).unwrap_or(value),
+                None => value,
+            };
+            if watchpoint.hits(access, value) {
+                self.watch_hit = Some(WatchHit {
+                    watchpoint: *watchpoint,
+                    addr,
+                    access,
+                    value,
+                    pc: 0,
+                    instruction: None,
+                });
+                return;
+            }
+        }
+    }
+
+    pub fn read(&mut self, addr: usize, size: Size) -> Result<u32, Exception> {
+        let fault = Exception::LoadAccessFault(addr as u32);
+        let mapping = self.mapping_mut(addr, size).ok_or(fault)?;
+        let value = mapping
+            .device
+            .read(addr - mapping.base, size)
+            .map_err(|_| fault)?;
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(addr, size, value, None);
+        }
+        Ok(value)
+    }
+
+    pub fn write(&mut self, addr: usize, size: Size, value: u32) -> Result<(), Exception> {
+        let fault = Exception::StoreAccessFault(addr as u32);
+        let before: Vec<Option<u32>> = self
+            .watchpoints
+            .iter()
+            .map(|watchpoint| self.watched_value(watchpoint, addr, size))
+            .collect();
+        let mapping = self.mapping_mut(addr, size).ok_or(fault)?;
+        mapping
+            .device
+            .write(addr - mapping.base, size, value)
+            .map_err(|_| fault)?;
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(addr, size, value, Some(&before));
+        }
+        Ok(())
+    }
+
+    /// Reads without side effects, `None` if nothing that can be peeked at is mapped there
+    pub fn peek(&self, addr: usize, size: Size) -> Option<u32> {
+        let mapping = self.mapping(addr, size)?;
+        mapping.device.peek(addr - mapping.base, size)
+    }
+
+    pub fn read_word(&mut self, addr: usize) -> Result<u32, Exception> {
+        self.read(addr, Size::Word)
+    }
+    pub fn write_word(&mut self, addr: usize, value: u32) -> Result<(), Exception> {
+        self.write(addr, Size::Word, value)
+    }
+
+    /// Advances all devices and forwards their interrupt lines to the PLIC
+    pub fn tick(&mut self, retired: bool) {
+        for mapping in &mut self.mappings {
+            mapping.device.tick(retired);
+        }
+        let Some(plic) = self.plic else {
+            return;
+        };
+        for &(index, source) in &self.routes {
+            let level = self.mappings[index].device.irq();
+            let device = self.mappings[plic].device.as_mut() as &mut dyn Any;
+            if let Some(plic) = device.downcast_mut::<Plic>() {
+                plic.set_level(source, level);
+            }
+        }
+    }
+
+    /// Interrupts raised by all devices, as `mip` bits
+    pub fn interrupts(&self) -> u32 {
+        self.mappings
+            .iter()
+            .fold(0, |pending, mapping| pending | mapping.device.interrupts())
+    }
+}
+
+impl fmt::Display for Bus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for mapping in &self.mappings {
+            write!(
+                f,
+                "0x{:08X}-0x{:08X} {}",
+                mapping.base,
+                mapping.base + mapping.len - 1,
+                mapping.name
+            )?;
+            if let Some(irq) = mapping.irq {
+                write!(f, " (irq {irq})")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plic::PLIC_LEN;
+    use crate::trap::Interrupt;
+
+    /// A device with a single register, its interrupt line is high while it isn't zero
+    #[derive(Default)]
+    struct Register(u32);
+
+    impl Device for Register {
+        fn read(&mut self, offset: usize, _size: Size) -> Result<u32, AccessFault> {
+            (offset == 0).then_some(self.0).ok_or(AccessFault)
+        }
+
+        fn write(&mut self, offset: usize, _size: Size, value: u32) -> Result<(), AccessFault> {
+            (offset == 0).then(|| self.0 = value).ok_or(AccessFault)
+        }
+
+        fn irq(&self) -> bool {
+            self.0 != 0
+        }
+    }
+
+    #[test]
+    fn attach_rejects_overlapping_ranges() {
+        let mut bus = Bus::default();
+        bus.attach("a", 0x1000, 0x100, Register::default()).unwrap();
+        assert!(bus.attach("b", 0x10FF, 0x10, Register::default()).is_err());
+        assert!(bus.attach("b", 0x0F00, 0x101, Register::default()).is_err());
+        assert!(bus.attach("b", 0x0, 0, Register::default()).is_err());
+        assert!(bus.attach("b", usize::MAX, 2, Register::default()).is_err());
+        /* Adjacent ranges are fine */
+        bus.attach("b", 0x0F00, 0x100, Register::default()).unwrap();
+        bus.attach("c", 0x1100, 0x100, Register::default()).unwrap();
+        assert_eq!(
+            bus.to_string(),
+            "0x00000F00-0x00000FFF b\n0x00001000-0x000010FF a\n0x00001100-0x000011FF c\n"
+        );
+    }
+
+    #[test]
+    fn accesses_go_to_the_device_at_their_offset() {
+        let mut bus = Bus::default();
+        bus.attach("a", 0x1000, 0x100, Register::default()).unwrap();
+        bus.attach("b", 0x2000, 0x100, Register::default()).unwrap();
+        bus.write_word(0x2000, 42).unwrap();
+        assert_eq!(bus.read_word(0x2000), Ok(42));
+        assert_eq!(bus.read_word(0x1000), Ok(0));
+        /* The device faults, nothing is mapped, or the access leaves the mapping */
+        assert_eq!(
+            bus.read_word(0x1004),
+            Err(Exception::LoadAccessFault(0x1004))
+        );
+        assert_eq!(
+            bus.write_word(0x3000, 0),
+            Err(Exception::StoreAccessFault(0x3000))
+        );
+        assert_eq!(
+            bus.read_word(0x10FE),
+            Err(Exception::LoadAccessFault(0x10FE))
+        );
+    }
+
+    #[test]
+    fn routed_interrupt_lines_reach_the_plic() {
+        let mut bus = Bus::default();
+        bus.attach("a", 0x4000_0000, 0x100, Register::default())
+            .unwrap();
+        bus.route_irq("a", 2).unwrap();
+        assert!(bus.route_irq("missing", 1).is_err());
+        /* The PLIC is attached below the device, which moves the device in the mappings */
+        bus.attach("plic", 0x0C00_0000, PLIC_LEN, Plic::new(3, 1))
+            .unwrap();
+        bus.write_word(0x0C00_0008, 1).unwrap();
+        bus.write_word(0x0C00_2000, 0b100).unwrap();
+
+        bus.tick(true);
+        assert_eq!(bus.interrupts(), 0);
+        bus.write_word(0x4000_0000, 1).unwrap();
+        bus.tick(true);
+        assert_eq!(bus.interrupts(), Interrupt::MachineExternal.mask());
+        assert_eq!(bus.read_word(0x0C20_0004), Ok(2));
+        assert_eq!(bus.interrupts(), 0);
+        assert_eq!(
+            bus.to_string(),
+            "0x0C000000-0x0FFFFFFF plic\n0x40000000-0x400000FF a (irq 2)\n"
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use std::time::{Duration, Instant};
+
+use crate::bus::{AccessFault, Device, Size};
+use crate::trap::Interrupt;
+
+/* Register offsets within the CLINT, as laid out by SiFive */
+const MSIP: usize = 0x0000;
+const MTIMECMP: usize = 0x4000;
+const MTIME: usize = 0xBFF8;
+
+/// Size of the address range taken by the CLINT
+pub const CLINT_LEN: usize = 0x1_0000;
+
+/// Frequency of `mtime` when it follows the host clock
+pub const WALL_CLOCK_HZ: u64 = 10_000_000;
+
+/// What makes `mtime` advance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TimerSource {
+    /// One tick per retired instruction, runs are fully deterministic
+    Instret,
+    /// Ticks at 10 MHz of host wall clock time
+    WallClock,
+}
+
+/// Core-local interruptor of a single hart, providing the timer and software interrupt.
+pub struct Clint {
+    timer: TimerSource,
+    msip: u32,
+    mtimecmp: u64,
+    mtime: u64,
+    /* Host time up to which mtime has been advanced in wall clock mode */
+    last_tick: Instant,
+}
+
+impl Clint {
+    pub fn new(timer: TimerSource) -> Self {
+        Self {
+            timer,
+            msip: 0,
+            /* No timer interrupt until software programs the compare register */
+            mtimecmp: u64::MAX,
+            mtime: 0,
+            last_tick: Instant::now(),
+        }
+    }
+
+    pub fn mtime(&self) -> u64 {
+        self.mtime
+    }
+
+    /// Advances `mtime`, `retired` tells whether the last step retired an instruction
+    fn advance(&mut self, retired: bool) {
+        match self.timer {
+            TimerSource::Instret => {
+                if retired {
+                    self.mtime = self.mtime.wrapping_add(1);
+                }
+            }
+            TimerSource::WallClock => {
+                let elapsed = self.last_tick.elapsed().as_nanos();
+                let ticks = (elapsed * u128::from(WALL_CLOCK_HZ) / 1_000_000_000) as u64;
+                if ticks > 0 {
+                    self.mtime = self.mtime.wrapping_add(ticks);
+                    self.last_tick += Duration::from_nanos(ticks * 1_000_000_000 / WALL_CLOCK_HZ);
+                }
+            }
+        }
+    }
+
+    /// Interrupts currently raised by the CLINT, as `mip` bits
+    fn pending(&self) -> u32 {
+        let mut pending = 0;
+        if self.msip & 1 != 0 {
+            pending |= Interrupt::MachineSoftware.mask();
+        }
+        if self.mtime >= self.mtimecmp {
+            pending |= Interrupt::MachineTimer.mask();
+        }
+        pending
+    }
+
+    fn read_byte(&self, offset: usize) -> Option<u32> {
+        let (value, byte) = match offset {
+            MSIP..=0x0003 => (u64::from(self.msip), offset - MSIP),
+            MTIMECMP..=0x4007 => (self.mtimecmp, offset - MTIMECMP),
+            MTIME..=0xBFFF => (self.mtime, offset - MTIME),
+            _ => return None,
+        };
+        Some(((value >> (8 * byte)) & 0xFF) as u32)
+    }
+
+    fn write_byte(&mut self, offset: usize, value: u32) -> Option<()> {
+        fn set_byte(register: u64, byte: usize, value: u32) -> u64 {
+            let shift = 8 * byte;
+            (register & !(0xFF << shift)) | (u64::from(value & 0xFF) << shift)
+        }
+        match offset {
+            MSIP..=0x0003 => {
+                /* Only bit 0 of msip is implemented */
+                self.msip = set_byte(u64::from(self.msip), offset - MSIP, value) as u32 & 1;
+            }
+            MTIMECMP..=0x4007 => self.mtimecmp = set_byte(self.mtimecmp, offset - MTIMECMP, value),
+            MTIME..=0xBFFF => self.mtime = set_byte(self.mtime, offset - MTIME, value),
+            _ => return None,
+        }
+        Some(())
+    }
+}
+
+impl Device for Clint {
+    fn read(&mut self, offset: usize, size: Size) -> Result<u32, AccessFault> {
+        self.peek(offset, size).ok_or(AccessFault)
+    }
+
+    fn write(&mut self, offset: usize, size: Size, value: u32) -> Result<(), AccessFault> {
+        for byte in 0..size.bytes() {
+            self.write_byte(offset + byte, value >> (8 * byte))
+                .ok_or(AccessFault)?;
+        }
+        Ok(())
+    }
+
+    fn peek(&self, offset: usize, size: Size) -> Option<u32> {
+        (0..size.bytes()).try_fold(0, |value, byte| {
+            Some(value | (self.read_byte(offset + byte)? << (8 * byte)))
+        })
+    }
+
+    fn tick(&mut self, retired: bool) {
+        self.advance(retired);
+    }
+
+    fn interrupts(&self) -> u32 {
+        self.pending()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timer_interrupt_is_pending_from_mtimecmp_on() {
+        let mut clint = Clint::new(TimerSource::Instret);
+        assert_eq!(clint.interrupts(), 0);
+
+        clint.write(MTIMECMP, Size::Word, 2).unwrap();
+        clint.write(MTIMECMP + 4, Size::Word, 0).unwrap();
+        clint.tick(true);
+        /* A step that retires nothing doesn't advance mtime */
+        clint.tick(false);
+        assert_eq!(clint.mtime(), 1);
+        assert_eq!(clint.interrupts(), 0);
+        clint.tick(true);
+        assert_eq!(clint.interrupts(), Interrupt::MachineTimer.mask());
+
+        /* Writing a later compare value clears MTIP */
+        clint.write(MTIMECMP, Size::Word, 3).unwrap();
+        assert_eq!(clint.interrupts(), 0);
+    }
+
+    #[test]
+    fn mtime_and_mtimecmp_are_accessible_in_halves() {
+        let mut clint = Clint::new(TimerSource::Instret);
+        clint.write(MTIME, Size::Word, 0xFFFF_FFFF).unwrap();
+        clint.write(MTIME + 4, Size::Word, 1).unwrap();
+        clint.tick(true);
+        assert_eq!(clint.mtime(), 0x2_0000_0000);
+        assert_eq!(clint.read(MTIME, Size::Word), Ok(0));
+        assert_eq!(clint.read(MTIME + 4, Size::Word), Ok(2));
+        assert_eq!(clint.read(MTIMECMP + 4, Size::Word), Ok(u32::MAX));
+        assert_eq!(clint.read(MTIME + 4, Size::Byte), Ok(2));
+    }
+
+    #[test]
+    fn msip_raises_the_software_interrupt() {
+        let mut clint = Clint::new(TimerSource::Instret);
+        /* Only bit 0 is implemented */
+        clint.write(MSIP, Size::Word, 0xFFFF_FFFF).unwrap();
+        assert_eq!(clint.read(MSIP, Size::Word), Ok(1));
+        assert_eq!(clint.interrupts(), Interrupt::MachineSoftware.mask());
+        clint.write(MSIP, Size::Word, 0).unwrap();
+        assert_eq!(clint.interrupts(), 0);
+    }
+
+    #[test]
+    fn unimplemented_registers_fault() {
+        let mut clint = Clint::new(TimerSource::Instret);
+        assert_eq!(clint.read(0x0004, Size::Word), Err(AccessFault));
+        assert_eq!(clint.write(0x4008, Size::Word, 0), Err(AccessFault));
+    }
+}
@@ -0,0 +1,163 @@
+use std::cell::{Ref, RefCell};
+use std::io::{self, Write};
+use std::rc::Rc;
+
+/// Lines kept in the scrollback, older ones are dropped
+const MAX_LINES: usize = 10_000;
+
+/// Scrollback buffer of guest console output.
+///
+/// Clones share the same buffer, the devices write to it and the TUI renders it.
+#[derive(Clone)]
+pub struct Console {
+    buffer: Rc<RefCell<Buffer>>,
+}
+
+struct Buffer {
+    lines: Vec<String>,
+    /// Start of a UTF-8 sequence whose remaining bytes haven't been written yet
+    partial: Vec<u8>,
+}
+
+impl Console {
+    pub fn new() -> Self {
+        Self {
+            buffer: Rc::new(RefCell::new(Buffer {
+                lines: vec![String::new()],
+                partial: Vec::new(),
+            })),
+        }
+    }
+
+    /// All lines, the last one is the line currently being written
+    pub fn lines(&self) -> Ref<'_, Vec<String>> {
+        Ref::map(self.buffer.borrow(), |buffer| &buffer.lines)
+    }
+
+    fn push(&self, byte: u8) {
+        let mut buffer = self.buffer.borrow_mut();
+        if !byte.is_ascii() {
+            buffer.partial.push(byte);
+            buffer.decode();
+            return;
+        }
+        /* An ASCII byte ends a sequence that isn't complete, it shows as U+FFFD */
+        if !buffer.partial.is_empty() {
+            let partial = std::mem::take(&mut buffer.partial);
+            buffer
+                .current()
+                .push_str(&String::from_utf8_lossy(&partial));
+        }
+        match byte {
+            b'\n' => {
+                let lines = &mut buffer.lines;
+                lines.push(String::new());
+                if lines.len() > MAX_LINES {
+                    lines.drain(..MAX_LINES / 10);
+                }
+            }
+            /* Backspace, as sent by line editors to erase the character before the cursor */
+            0x08 => {
+                buffer.current().pop();
+            }
+            b'\t' => buffer.current().push_str("    "),
+            /* Carriage returns and other control characters have nothing to draw */
+            0x00..=0x1F | 0x7F => {}
+            _ => buffer.current().push(char::from(byte)),
+        }
+    }
+}
+
+impl Buffer {
+    fn current(&mut self) -> &mut String {
+        self.lines.last_mut().unwrap()
+    }
+
+    /// Appends the complete characters of `partial`, invalid bytes show as U+FFFD
+    fn decode(&mut self) {
+        while !self.partial.is_empty() {
+            let (end, incomplete) = match std::str::from_utf8(&self.partial) {
+                Ok(_) => (self.partial.len(), false),
+                Err(error) => match error.error_len() {
+                    Some(len) => (error.valid_up_to() + len, false),
+                    None => (error.valid_up_to(), true),
+                },
+            };
+            let text = String::from_utf8_lossy(&self.partial[..end]).into_owned();
+            self.current().push_str(&text);
+            self.partial.drain(..end);
+            if incomplete {
+                break;
+            }
+        }
+    }
+}
+
+impl Write for Console {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.push(byte);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lines of a console after the writes in `chunks`
+    fn lines(chunks: &[&[u8]]) -> Vec<String> {
+        let mut console = Console::new();
+        for chunk in chunks {
+            console.write_all(chunk).unwrap();
+        }
+        let lines = console.lines().clone();
+        lines
+    }
+
+    #[test]
+    fn splits_lines_and_handles_control_characters() {
+        assert_eq!(lines(&[b"one\r\ntwo\n"]), ["one", "two", ""]);
+        assert_eq!(lines(&[b"a\tb\x07"]), ["a    b"]);
+        assert_eq!(lines(&[b"abc\x08\x08d"]), ["ad"]);
+        /* Clones share the buffer */
+        let console = Console::new();
+        console.clone().write_all(b"x\ny").unwrap();
+        assert_eq!(*console.lines(), ["x", "y"]);
+    }
+
+    #[test]
+    fn scrollback_drops_the_oldest_lines() {
+        let mut console = Console::new();
+        for line in 0..MAX_LINES {
+            writeln!(console, "{line}").unwrap();
+        }
+        let lines = console.lines();
+        assert_eq!(lines.len(), MAX_LINES - MAX_LINES / 10 + 1);
+        assert_eq!(lines[0], (MAX_LINES / 10).to_string());
+        assert_eq!(lines[lines.len() - 2], (MAX_LINES - 1).to_string());
+    }
+
+    #[test]
+    fn decodes_utf8_split_across_writes() {
+        assert_eq!(lines(&["grüße €".as_bytes()]), ["grüße €"]);
+        assert_eq!(
+            lines(&[b"\xC3", b"\xA9", b"\xF0\x9F", b"\xA6\x80"]),
+            ["é🦀"]
+        );
+        /* Backspace erases the whole character */
+        assert_eq!(lines(&["aé".as_bytes(), b"\x08"]), ["a"]);
+    }
+
+    #[test]
+    fn replaces_invalid_utf8() {
+        assert_eq!(lines(&[b"\xFFa"]), ["\u{FFFD}a"]);
+        assert_eq!(lines(&[b"\xE2\x82\nb"]), ["\u{FFFD}", "b"]);
+        assert_eq!(lines(&[b"\xC3\xC3\xA9"]), ["\u{FFFD}é"]);
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::bus::Bus;
+use crate::decoder::decode;
+use crate::executer::{at_ebreak, fetch, step};
+use crate::parse_address;
+use crate::symbols::Symbols;
+use crate::system::{Privilege, RegisterFile};
+use crate::trap::Exception;
+use crate::watch::{parse_watchpoint, Access, WatchHit};
+
+/// Steps executed between two looks at the clock while running
+const STEPS_PER_CHECK: usize = 1024;
+
+/// Why the guest stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// `pc` reached a breakpoint, the instruction there has not executed yet
+    Breakpoint(u32),
+    /// The guest reached an `EBREAK` that halts it, `pc` stays on it
+    Ebreak(u32),
+    /// The guest raised an exception, `pc` is at the start of its handler
+    Trap(Exception),
+    /// An access hit a watchpoint, the instruction that made it has completed
+    Watchpoint(WatchHit),
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Breakpoint(addr) => write!(f, "Breakpoint at 0x{addr:08X}"),
+            Self::Ebreak(addr) => write!(f, "EBREAK at 0x{addr:08X}"),
+            Self::Trap(exception) => write!(f, "Trap: {exception:?}"),
+            Self::Watchpoint(hit) => {
+                write!(f, "Watchpoint {}: ", hit.watchpoint)?;
+                match hit.access {
+                    Access::Read => write!(f, "read 0x{:08X}", hit.value)?,
+                    Access::Write { old: Some(old) } => {
+                        write!(f, "0x{old:08X} -> 0x{:08X}", hit.value)?;
+                    }
+                    Access::Write { old: None } => write!(f, "wrote 0x{:08X}", hit.value)?,
+                }
+                write!(f, " by ")?;
+                match hit.instruction.map(|raw| (raw, decode(raw))) {
+                    Some((_, Ok(instruction))) => write!(f, "{instruction}")?,
+                    Some((raw, Err(_))) => write!(f, "{raw:08X}")?,
+                    None => write!(f, "????????")?,
+                }
+                write!(f, " at 0x{:08X}, accessing 0x{:08X}", hit.pc, hit.addr)
+            }
+        }
+    }
+}
+
+/// Runs the guest under control of the user, stopping at breakpoints.
+///
+/// Watchpoints live on the bus, which checks them on every access.
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: BTreeSet<u32>,
+    /// Lowest privilege level whose `EBREAK`s halt the guest, like `dcsr.ebreakm/s/u` of
+    /// a debug module. Below it and with `None`, the guest takes the breakpoint trap.
+    pub ebreak: Option<Privilege>,
+}
+
+impl Debugger {
+    pub fn has_breakpoint(&self, addr: u32) -> bool {
+        self.breakpoints.contains(&addr)
+    }
+
+    pub fn set_breakpoint(&mut self, addr: u32) {
+        self.breakpoints.insert(addr);
+    }
+
+    /// Removes the breakpoint at `addr`, false if there was none
+    pub fn clear_breakpoint(&mut self, addr: u32) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    /// Executes a single step, breakpoints don't apply to it but watchpoints do
+    pub fn step(&self, rf: &mut RegisterFile, bus: &mut Bus) -> Option<Stop> {
+        let pc = rf.pc;
+        if self.ebreak.is_some_and(|lowest| rf.privilege >= lowest) && at_ebreak(rf, bus) {
+            return Some(Stop::Ebreak(pc));
+        }
+        let result = step(rf, bus);
+        if let Some(mut hit) = bus.take_watch_hit() {
+            hit.pc = pc;
+            hit.instruction = fetch(rf, bus, pc).ok();
+            return Some(Stop::Watchpoint(hit));
+        }
+        match result {
+            Ok(()) => None,
+            Err(exception) => Some(Stop::Trap(exception)),
+        }
+    }
+
+    /// Runs for about `duration`, `None` if the guest is still running after it
+    pub fn run(&self, rf: &mut RegisterFile, bus: &mut Bus, duration: Duration) -> Option<Stop> {
+        let start = Instant::now();
+        while start.elapsed() < duration {
+            for _ in 0..STEPS_PER_CHECK {
+                if self.has_breakpoint(rf.pc) {
+                    return Some(Stop::Breakpoint(rf.pc));
+                }
+                if let Some(stop) = self.step(rf, bus) {
+                    return Some(stop);
+                }
+            }
+        }
+        None
+    }
+
+    /// Executes a command line and returns the message to show for it.
+    ///
+    /// Breakpoints can be given as an address or as the name of one of the `symbols`.
+    pub fn command(&mut self, line: &str, bus: &mut Bus, symbols: &Symbols) -> String {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("");
+        if let "w" | "watch" = command {
+            let spec = line.trim_start()[command.len()..].trim();
+            return match parse_watchpoint(spec) {
+                Ok(watchpoint) => {
+                    bus.watch(watchpoint);
+                    format!("Watchpoint {} set: {watchpoint}", bus.watchpoints().len())
+                }
+                Err(error) => error,
+            };
+        }
+        let breakpoint = matches!(command, "b" | "break" | "d" | "delete");
+        let addr = words.next().map(|word| match symbols.address(word) {
+            Some(addr) if breakpoint => Ok(addr as usize),
+            _ => parse_address(word),
+        });
+        match (command, addr) {
+            ("b" | "break", Some(Ok(addr))) => {
+                self.set_breakpoint(addr as u32);
+                format!("Breakpoint set at 0x{addr:08X}")
+            }
+            ("d" | "delete", Some(Ok(addr))) => {
+                if self.clear_breakpoint(addr as u32) {
+                    format!("Breakpoint at 0x{addr:08X} deleted")
+                } else {
+                    format!("No breakpoint at 0x{addr:08X}")
+                }
+            }
+            ("d" | "delete", None) => {
+                self.breakpoints.clear();
+                "All breakpoints deleted".to_string()
+            }
+            /* Watchpoints are numbered from 1 in the order they were set */
+            ("u" | "unwatch", Some(Ok(number))) => match bus.unwatch(number.wrapping_sub(1)) {
+                Some(watchpoint) => format!("Watchpoint {number} deleted: {watchpoint}"),
+                None => format!("No watchpoint {number}"),
+            },
+            ("u" | "unwatch", None) => {
+                bus.unwatch_all();
+                "All watchpoints deleted".to_string()
+            }
+            ("l" | "list", None) => {
+                let breakpoints: Vec<String> = self
+                    .breakpoints
+                    .iter()
+                    .map(|addr| format!("0x{addr:08X}"))
+                    .collect();
+                let watchpoints: Vec<String> = bus
+                    .watchpoints()
+                    .iter()
+                    .enumerate()
+                    .map(|(index, watchpoint)| format!("{}: {watchpoint}", index + 1))
+                    .collect();
+                match (breakpoints.is_empty(), watchpoints.is_empty()) {
+                    (true, true) => "No breakpoints or watchpoints".to_string(),
+                    (false, true) => format!("Breakpoints: {}", breakpoints.join(", ")),
+                    (true, false) => format!("Watchpoints: {}", watchpoints.join(", ")),
+                    (false, false) => format!(
+                        "Breakpoints: {}; Watchpoints: {}",
+                        breakpoints.join(", "),
+                        watchpoints.join(", ")
+                    ),
+                }
+            }
+            (_, Some(Err(_))) => format!("Invalid address in: {line}"),
+            _ => format!("Unknown command: {line}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::system::{Image, RAM_BASE};
+
+    const PROGRAM: &str = "li a0, 1
+                           second:
+                           li a0, 2
+                           spin:
+                           j spin";
+
+    /// Loads `source` at the start of RAM, lower privilege levels may access all of it
+    fn load(source: &str) -> (RegisterFile, Bus, Symbols) {
+        let program = assemble(source, RAM_BASE as u32).unwrap();
+        let mut ram = program.code;
+        ram.resize(0x1000, 0);
+        let bus = Image::flat(ram).into_bus(Box::new(io::sink())).unwrap();
+        let mut rf = RegisterFile::default();
+        rf.pc = RAM_BASE as u32;
+        rf.csr.write(0x3B0, u32::MAX).unwrap();
+        rf.csr.write(0x3A0, 0x1F).unwrap();
+        (rf, bus, program.symbols)
+    }
+
+    #[test]
+    fn breakpoints_stop_before_the_instruction() {
+        let (mut rf, mut bus, symbols) = load(PROGRAM);
+        let mut debugger = Debugger::default();
+        debugger.command("b second", &mut bus, &symbols);
+        let stop = debugger.run(&mut rf, &mut bus, Duration::from_secs(10));
+        assert_eq!(stop, Some(Stop::Breakpoint(RAM_BASE as u32 + 4)));
+        assert_eq!(rf.read(10), 1);
+        /* Stepping leaves the breakpoint, running again stops at the next one */
+        assert_eq!(debugger.step(&mut rf, &mut bus), None);
+        assert_eq!(rf.read(10), 2);
+        debugger.command("break spin", &mut bus, &symbols);
+        let stop = debugger.run(&mut rf, &mut bus, Duration::from_secs(10));
+        assert_eq!(stop, Some(Stop::Breakpoint(RAM_BASE as u32 + 8)));
+    }
+
+    #[test]
+    fn break_and_delete_take_addresses_and_symbols() {
+        let (_, mut bus, symbols) = load(PROGRAM);
+        let mut debugger = Debugger::default();
+        let cases = [
+            ("b 0x80000004", "Breakpoint set at 0x80000004"),
+            ("break 2147483656", "Breakpoint set at 0x80000008"),
+            ("b second", "Breakpoint set at 0x80000004"),
+            ("l", "Breakpoints: 0x80000004, 0x80000008"),
+            ("d second", "Breakpoint at 0x80000004 deleted"),
+            ("delete 0x80000004", "No breakpoint at 0x80000004"),
+            ("b nowhere", "Invalid address in: b nowhere"),
+            ("delete", "All breakpoints deleted"),
+            ("list", "No breakpoints or watchpoints"),
+            ("x", "Unknown command: x"),
+        ];
+        for (line, message) in cases {
+            assert_eq!(debugger.command(line, &mut bus, &symbols), message);
+        }
+    }
+
+    #[test]
+    fn run_returns_after_its_time_slice() {
+        let (mut rf, mut bus, _) = load(PROGRAM);
+        let debugger = Debugger::default();
+        let start = Instant::now();
+        assert_eq!(
+            debugger.run(&mut rf, &mut bus, Duration::from_millis(20)),
+            None
+        );
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(20));
+        assert!(elapsed < Duration::from_secs(5), "{elapsed:?}");
+        assert_eq!(rf.pc, RAM_BASE as u32 + 8);
+    }
+
+    #[test]
+    fn ebreak_halts_from_the_lowest_privilege_up() {
+        let cases = [
+            (None, Privilege::Machine, false),
+            (Some(Privilege::Machine), Privilege::Machine, true),
+            (Some(Privilege::Machine), Privilege::Supervisor, false),
+            (Some(Privilege::Supervisor), Privilege::Supervisor, true),
+            (Some(Privilege::Supervisor), Privilege::User, false),
+            (Some(Privilege::User), Privilege::User, true),
+        ];
+        for (ebreak, privilege, halts) in cases {
+            let (mut rf, mut bus, _) = load("ebreak");
+            rf.privilege = privilege;
+            let debugger = Debugger {
+                ebreak,
+                ..Debugger::default()
+            };
+            let pc = rf.pc;
+            let expected = if halts {
+                Stop::Ebreak(pc)
+            } else {
+                Stop::Trap(Exception::Breakpoint(pc))
+            };
+            let stop = debugger.run(&mut rf, &mut bus, Duration::from_secs(10));
+            assert_eq!(stop, Some(expected), "{ebreak:?} {privilege:?}");
+            assert_eq!(rf.pc == pc, halts);
+        }
+    }
+}
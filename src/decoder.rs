@@ -1,7 +1,10 @@
+use crate::trap::Exception;
+
 pub type Rindex = usize;
 pub type RDindex = Rindex;
 pub type RS1index = Rindex;
 pub type RS2index = Rindex;
+pub type RS3index = Rindex;
 
 pub type RS1value = u32;
 pub type RS2value = u32;
@@ -13,8 +16,15 @@ type Uimmediate = u32;
 type Jimmediate = u32;
 
 type Funct3 = u32;
+type Funct5 = u32;
 type Funct7 = u32;
 
+/* The acquire and release bits of atomic instructions */
+type Aqrl = u32;
+
+/* The rounding mode field of floating-point instructions */
+type Rm = u32;
+
 fn immediate_i(instruction: u32) -> Iimmediate {
     (instruction >> 20) as Iimmediate
 }
@@ -59,6 +69,18 @@ fn funct3(instruction: u32) -> Funct3 {
     ((instruction >> 12) & 0b111) as Funct3
 }
 
+fn rs3(instruction: u32) -> RS3index {
+    ((instruction >> 27) & 0b1_1111) as RS3index
+}
+
+fn funct5(instruction: u32) -> Funct5 {
+    ((instruction >> 27) & 0b1_1111) as Funct5
+}
+
+fn aqrl(instruction: u32) -> Aqrl {
+    ((instruction >> 25) & 0b11) as Aqrl
+}
+
 fn funct7(instruction: u32) -> Funct7 {
     ((instruction >> 25) & 0b111_1111) as Funct7
 }
@@ -117,7 +139,7 @@ pub enum OpCode {
     LEN80,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Instruction {
     /* RV32I */
     LUI(RDindex, Uimmediate),
@@ -160,7 +182,10 @@ pub enum Instruction {
     FENCE(RDindex, RS1index, Iimmediate),
     ECALL(),
     EBREAK(),
+    SRET(),
     MRET(),
+    WFI(),
+    SFENCEVMA(RS1index, RS2index),
     /* Zicsr */
     CSRRW(RDindex, RS1index, Iimmediate),
     CSRRS(RDindex, RS1index, Iimmediate),
@@ -177,6 +202,72 @@ pub enum Instruction {
     DIVU(RDindex, RS1index, RS2index),
     REM(RDindex, RS1index, RS2index),
     REMU(RDindex, RS1index, RS2index),
+    /* A */
+    LRW(RDindex, RS1index, Aqrl),
+    SCW(RDindex, RS1index, RS2index, Aqrl),
+    AMOSWAPW(RDindex, RS1index, RS2index, Aqrl),
+    AMOADDW(RDindex, RS1index, RS2index, Aqrl),
+    AMOXORW(RDindex, RS1index, RS2index, Aqrl),
+    AMOANDW(RDindex, RS1index, RS2index, Aqrl),
+    AMOORW(RDindex, RS1index, RS2index, Aqrl),
+    AMOMINW(RDindex, RS1index, RS2index, Aqrl),
+    AMOMAXW(RDindex, RS1index, RS2index, Aqrl),
+    AMOMINUW(RDindex, RS1index, RS2index, Aqrl),
+    AMOMAXUW(RDindex, RS1index, RS2index, Aqrl),
+    /* F */
+    FLW(RDindex, RS1index, Iimmediate),
+    FSW(RS1index, RS2index, Simmediate),
+    FMADDS(RDindex, RS1index, RS2index, RS3index, Rm),
+    FMSUBS(RDindex, RS1index, RS2index, RS3index, Rm),
+    FNMSUBS(RDindex, RS1index, RS2index, RS3index, Rm),
+    FNMADDS(RDindex, RS1index, RS2index, RS3index, Rm),
+    FADDS(RDindex, RS1index, RS2index, Rm),
+    FSUBS(RDindex, RS1index, RS2index, Rm),
+    FMULS(RDindex, RS1index, RS2index, Rm),
+    FDIVS(RDindex, RS1index, RS2index, Rm),
+    FSQRTS(RDindex, RS1index, Rm),
+    FSGNJS(RDindex, RS1index, RS2index),
+    FSGNJNS(RDindex, RS1index, RS2index),
+    FSGNJXS(RDindex, RS1index, RS2index),
+    FMINS(RDindex, RS1index, RS2index),
+    FMAXS(RDindex, RS1index, RS2index),
+    FCVTWS(RDindex, RS1index, Rm),
+    FCVTWUS(RDindex, RS1index, Rm),
+    FMVXW(RDindex, RS1index),
+    FEQS(RDindex, RS1index, RS2index),
+    FLTS(RDindex, RS1index, RS2index),
+    FLES(RDindex, RS1index, RS2index),
+    FCLASSS(RDindex, RS1index),
+    FCVTSW(RDindex, RS1index, Rm),
+    FCVTSWU(RDindex, RS1index, Rm),
+    FMVWX(RDindex, RS1index),
+    /* D */
+    FLD(RDindex, RS1index, Iimmediate),
+    FSD(RS1index, RS2index, Simmediate),
+    FMADDD(RDindex, RS1index, RS2index, RS3index, Rm),
+    FMSUBD(RDindex, RS1index, RS2index, RS3index, Rm),
+    FNMSUBD(RDindex, RS1index, RS2index, RS3index, Rm),
+    FNMADDD(RDindex, RS1index, RS2index, RS3index, Rm),
+    FADDD(RDindex, RS1index, RS2index, Rm),
+    FSUBD(RDindex, RS1index, RS2index, Rm),
+    FMULD(RDindex, RS1index, RS2index, Rm),
+    FDIVD(RDindex, RS1index, RS2index, Rm),
+    FSQRTD(RDindex, RS1index, Rm),
+    FSGNJD(RDindex, RS1index, RS2index),
+    FSGNJND(RDindex, RS1index, RS2index),
+    FSGNJXD(RDindex, RS1index, RS2index),
+    FMIND(RDindex, RS1index, RS2index),
+    FMAXD(RDindex, RS1index, RS2index),
+    FCVTSD(RDindex, RS1index, Rm),
+    FCVTDS(RDindex, RS1index, Rm),
+    FEQD(RDindex, RS1index, RS2index),
+    FLTD(RDindex, RS1index, RS2index),
+    FLED(RDindex, RS1index, RS2index),
+    FCLASSD(RDindex, RS1index),
+    FCVTWD(RDindex, RS1index, Rm),
+    FCVTWUD(RDindex, RS1index, Rm),
+    FCVTDW(RDindex, RS1index, Rm),
+    FCVTDWU(RDindex, RS1index, Rm),
 }
 
 impl Instruction {
@@ -204,6 +295,84 @@ impl Instruction {
                 | Self::REMU(..)
         )
     }
+    pub fn is_a(&self) -> bool {
+        matches!(
+            self,
+            Self::LRW(..)
+                | Self::SCW(..)
+                | Self::AMOSWAPW(..)
+                | Self::AMOADDW(..)
+                | Self::AMOXORW(..)
+                | Self::AMOANDW(..)
+                | Self::AMOORW(..)
+                | Self::AMOMINW(..)
+                | Self::AMOMAXW(..)
+                | Self::AMOMINUW(..)
+                | Self::AMOMAXUW(..)
+        )
+    }
+    pub fn is_f(&self) -> bool {
+        matches!(
+            self,
+            Self::FLW(..)
+                | Self::FSW(..)
+                | Self::FMADDS(..)
+                | Self::FMSUBS(..)
+                | Self::FNMSUBS(..)
+                | Self::FNMADDS(..)
+                | Self::FADDS(..)
+                | Self::FSUBS(..)
+                | Self::FMULS(..)
+                | Self::FDIVS(..)
+                | Self::FSQRTS(..)
+                | Self::FSGNJS(..)
+                | Self::FSGNJNS(..)
+                | Self::FSGNJXS(..)
+                | Self::FMINS(..)
+                | Self::FMAXS(..)
+                | Self::FCVTWS(..)
+                | Self::FCVTWUS(..)
+                | Self::FMVXW(..)
+                | Self::FEQS(..)
+                | Self::FLTS(..)
+                | Self::FLES(..)
+                | Self::FCLASSS(..)
+                | Self::FCVTSW(..)
+                | Self::FCVTSWU(..)
+                | Self::FMVWX(..)
+        )
+    }
+    pub fn is_d(&self) -> bool {
+        matches!(
+            self,
+            Self::FLD(..)
+                | Self::FSD(..)
+                | Self::FMADDD(..)
+                | Self::FMSUBD(..)
+                | Self::FNMSUBD(..)
+                | Self::FNMADDD(..)
+                | Self::FADDD(..)
+                | Self::FSUBD(..)
+                | Self::FMULD(..)
+                | Self::FDIVD(..)
+                | Self::FSQRTD(..)
+                | Self::FSGNJD(..)
+                | Self::FSGNJND(..)
+                | Self::FSGNJXD(..)
+                | Self::FMIND(..)
+                | Self::FMAXD(..)
+                | Self::FCVTSD(..)
+                | Self::FCVTDS(..)
+                | Self::FEQD(..)
+                | Self::FLTD(..)
+                | Self::FLED(..)
+                | Self::FCLASSD(..)
+                | Self::FCVTWD(..)
+                | Self::FCVTWUD(..)
+                | Self::FCVTDW(..)
+                | Self::FCVTDWU(..)
+        )
+    }
 }
 
 fn get_opcode(instruction: u32) -> Result<OpCode, &'static str> {
@@ -257,11 +426,12 @@ fn get_opcode(instruction: u32) -> Result<OpCode, &'static str> {
 }
 
 #[allow(clippy::too_many_lines)]
-pub fn decode(instruction: u32) -> Result<Instruction, &'static str> {
+pub fn decode(instruction: u32) -> Result<Instruction, Exception> {
     if !isBaseInstructionSet!(instruction) {
-        return Err("Invalid base instruction type");
+        return decode_compressed(instruction & 0xFFFF);
     }
-    let op = get_opcode(instruction)?;
+    let illegal = Exception::IllegalInstruction(instruction);
+    let op = get_opcode(instruction).map_err(|_| illegal)?;
 
     match op {
         OpCode::LOAD => {
@@ -275,11 +445,21 @@ pub fn decode(instruction: u32) -> Result<Instruction, &'static str> {
                 0b010 => Ok(Instruction::LW(rd_index, rs1, i_imm)),
                 0b100 => Ok(Instruction::LBU(rd_index, rs1, i_imm)),
                 0b101 => Ok(Instruction::LHU(rd_index, rs1, i_imm)),
-                _ => Err("Invalid funct3 I-Type"),
+                _ => Err(illegal),
+            }
+        }
+        OpCode::LOADFP => {
+            /* I-Type, rd is a floating-point register */
+            let rd_index: RDindex = rd(instruction);
+            let rs1: RS1index = rs1(instruction);
+            let i_imm: Iimmediate = immediate_i(instruction);
+            match funct3(instruction) {
+                0b010 => Ok(Instruction::FLW(rd_index, rs1, i_imm)),
+                0b011 => Ok(Instruction::FLD(rd_index, rs1, i_imm)),
+                _ => Err(illegal),
             }
         }
-        OpCode::LOADFP => todo!(),
-        OpCode::CUSTOM0 => todo!(),
+        OpCode::CUSTOM0 => Err(illegal),
         OpCode::MISCMEM => {
             let rd_index: RDindex = rd(instruction);
             let rs1: RS1index = rs1(instruction);
@@ -298,15 +478,13 @@ pub fn decode(instruction: u32) -> Result<Instruction, &'static str> {
                 0b100 => Ok(Instruction::XORI(rd_index, rs1, i_imm)),
                 0b110 => Ok(Instruction::ORI(rd_index, rs1, i_imm)),
                 0b111 => Ok(Instruction::ANDI(rd_index, rs1, i_imm)),
-                0b001 => Ok(Instruction::SLLI(rd_index, rs1, i_imm)),
-                0b101 => {
-                    if i_imm == 0 {
-                        Ok(Instruction::SRLI(rd_index, rs1, i_imm))
-                    } else {
-                        Ok(Instruction::SRAI(rd_index, rs1, i_imm))
-                    }
+                /* shamt[5] is reserved on RV32, only funct7 selects the shift */
+                0b001 if funct7(instruction) == 0 => Ok(Instruction::SLLI(rd_index, rs1, i_imm)),
+                0b101 if funct7(instruction) == 0 => Ok(Instruction::SRLI(rd_index, rs1, i_imm)),
+                0b101 if funct7(instruction) == 0b010_0000 => {
+                    Ok(Instruction::SRAI(rd_index, rs1, i_imm))
                 }
-                _ => Err("Invalid funct3 I-Type"),
+                _ => Err(illegal),
             }
         }
         OpCode::AUIPC => {
@@ -315,8 +493,8 @@ pub fn decode(instruction: u32) -> Result<Instruction, &'static str> {
             let u_imm: Uimmediate = immediate_u(instruction);
             Ok(Instruction::AUIPC(rd_index, u_imm))
         }
-        OpCode::OPIMM32 => todo!(),
-        OpCode::LEN48 => todo!(),
+        OpCode::OPIMM32 => Err(illegal),
+        OpCode::LEN48 => Err(illegal),
         OpCode::STORE => {
             /* STOREs are S-Type */
             let rs1: RS1index = rs1(instruction);
@@ -326,77 +504,73 @@ pub fn decode(instruction: u32) -> Result<Instruction, &'static str> {
                 0b000 => Ok(Instruction::SB(rs1, rs2, s_imm)),
                 0b001 => Ok(Instruction::SH(rs1, rs2, s_imm)),
                 0b010 => Ok(Instruction::SW(rs1, rs2, s_imm)),
-                _ => Err("Invalid funct3 S-Type"),
+                _ => Err(illegal),
+            }
+        }
+        OpCode::STOREFP => {
+            /* S-Type, rs2 is a floating-point register */
+            let rs1: RS1index = rs1(instruction);
+            let rs2: RS2index = rs2(instruction);
+            let s_imm: Simmediate = immediate_s(instruction);
+            match funct3(instruction) {
+                0b010 => Ok(Instruction::FSW(rs1, rs2, s_imm)),
+                0b011 => Ok(Instruction::FSD(rs1, rs2, s_imm)),
+                _ => Err(illegal),
+            }
+        }
+        OpCode::CUSTOM1 => Err(illegal),
+        OpCode::AMO => {
+            /* R-Type instructions, the upper bits of funct7 select the operation */
+            let rd_index: RDindex = rd(instruction);
+            let rs1: RS1index = rs1(instruction);
+            let rs2: RS2index = rs2(instruction);
+            let aqrl: Aqrl = aqrl(instruction);
+            if funct3(instruction) != 0b010 {
+                /* Only word sized atomics exist in RV32 */
+                return Err(illegal);
+            }
+            match funct5(instruction) {
+                0b00010 if rs2 == 0 => Ok(Instruction::LRW(rd_index, rs1, aqrl)),
+                0b00011 => Ok(Instruction::SCW(rd_index, rs1, rs2, aqrl)),
+                0b00001 => Ok(Instruction::AMOSWAPW(rd_index, rs1, rs2, aqrl)),
+                0b00000 => Ok(Instruction::AMOADDW(rd_index, rs1, rs2, aqrl)),
+                0b00100 => Ok(Instruction::AMOXORW(rd_index, rs1, rs2, aqrl)),
+                0b01100 => Ok(Instruction::AMOANDW(rd_index, rs1, rs2, aqrl)),
+                0b01000 => Ok(Instruction::AMOORW(rd_index, rs1, rs2, aqrl)),
+                0b10000 => Ok(Instruction::AMOMINW(rd_index, rs1, rs2, aqrl)),
+                0b10100 => Ok(Instruction::AMOMAXW(rd_index, rs1, rs2, aqrl)),
+                0b11000 => Ok(Instruction::AMOMINUW(rd_index, rs1, rs2, aqrl)),
+                0b11100 => Ok(Instruction::AMOMAXUW(rd_index, rs1, rs2, aqrl)),
+                _ => Err(illegal),
             }
         }
-        OpCode::STOREFP => todo!(),
-        OpCode::CUSTOM1 => todo!(),
-        OpCode::AMO => todo!(),
         OpCode::OP => {
             /* All OP are R-Type instructions */
             let rd_index: RDindex = rd(instruction);
             let rs1: RS1index = rs1(instruction);
             let rs2: RS2index = rs2(instruction);
 
-            let is_m_extension = funct7(instruction) & 0b1 == 1;
-            match funct3(instruction) {
-                0b000 => {
-                    if is_m_extension {
-                        return Ok(Instruction::MUL(rd_index, rs1, rs2));
-                    }
-                    if funct7(instruction) == 0 {
-                        Ok(Instruction::ADD(rd_index, rs1, rs2))
-                    } else {
-                        Ok(Instruction::SUB(rd_index, rs1, rs2))
-                    }
-                }
-                0b001 => {
-                    if is_m_extension {
-                        return Ok(Instruction::MULH(rd_index, rs1, rs2));
-                    }
-                    Ok(Instruction::SLL(rd_index, rs1, rs2))
-                }
-                0b010 => {
-                    if is_m_extension {
-                        return Ok(Instruction::MULHSU(rd_index, rs1, rs2));
-                    }
-                    Ok(Instruction::SLT(rd_index, rs1, rs2))
-                }
-                0b011 => {
-                    if is_m_extension {
-                        return Ok(Instruction::MULHU(rd_index, rs1, rs2));
-                    }
-                    Ok(Instruction::SLTU(rd_index, rs1, rs2))
-                }
-                0b100 => {
-                    if is_m_extension {
-                        return Ok(Instruction::DIV(rd_index, rs1, rs2));
-                    }
-                    Ok(Instruction::XOR(rd_index, rs1, rs2))
-                }
-                0b101 => {
-                    if is_m_extension {
-                        return Ok(Instruction::DIVU(rd_index, rs1, rs2));
-                    }
-                    if funct7(instruction) == 0 {
-                        Ok(Instruction::SRL(rd_index, rs1, rs2))
-                    } else {
-                        Ok(Instruction::SRA(rd_index, rs1, rs2))
-                    }
-                }
-                0b110 => {
-                    if is_m_extension {
-                        return Ok(Instruction::REM(rd_index, rs1, rs2));
-                    }
-                    Ok(Instruction::OR(rd_index, rs1, rs2))
-                }
-                0b111 => {
-                    if is_m_extension {
-                        return Ok(Instruction::REMU(rd_index, rs1, rs2));
-                    }
-                    Ok(Instruction::AND(rd_index, rs1, rs2))
-                }
-                _ => Err("Invalid funct3 R-Type"),
+            match (funct7(instruction), funct3(instruction)) {
+                (0b000_0000, 0b000) => Ok(Instruction::ADD(rd_index, rs1, rs2)),
+                (0b010_0000, 0b000) => Ok(Instruction::SUB(rd_index, rs1, rs2)),
+                (0b000_0000, 0b001) => Ok(Instruction::SLL(rd_index, rs1, rs2)),
+                (0b000_0000, 0b010) => Ok(Instruction::SLT(rd_index, rs1, rs2)),
+                (0b000_0000, 0b011) => Ok(Instruction::SLTU(rd_index, rs1, rs2)),
+                (0b000_0000, 0b100) => Ok(Instruction::XOR(rd_index, rs1, rs2)),
+                (0b000_0000, 0b101) => Ok(Instruction::SRL(rd_index, rs1, rs2)),
+                (0b010_0000, 0b101) => Ok(Instruction::SRA(rd_index, rs1, rs2)),
+                (0b000_0000, 0b110) => Ok(Instruction::OR(rd_index, rs1, rs2)),
+                (0b000_0000, 0b111) => Ok(Instruction::AND(rd_index, rs1, rs2)),
+                /* M extension */
+                (0b000_0001, 0b000) => Ok(Instruction::MUL(rd_index, rs1, rs2)),
+                (0b000_0001, 0b001) => Ok(Instruction::MULH(rd_index, rs1, rs2)),
+                (0b000_0001, 0b010) => Ok(Instruction::MULHSU(rd_index, rs1, rs2)),
+                (0b000_0001, 0b011) => Ok(Instruction::MULHU(rd_index, rs1, rs2)),
+                (0b000_0001, 0b100) => Ok(Instruction::DIV(rd_index, rs1, rs2)),
+                (0b000_0001, 0b101) => Ok(Instruction::DIVU(rd_index, rs1, rs2)),
+                (0b000_0001, 0b110) => Ok(Instruction::REM(rd_index, rs1, rs2)),
+                (0b000_0001, 0b111) => Ok(Instruction::REMU(rd_index, rs1, rs2)),
+                _ => Err(illegal),
             }
         }
         OpCode::LUI => {
@@ -405,16 +579,31 @@ pub fn decode(instruction: u32) -> Result<Instruction, &'static str> {
             let u_imm: Uimmediate = immediate_u(instruction);
             Ok(Instruction::LUI(rd_index, u_imm))
         }
-        OpCode::OP32 => todo!(),
-        OpCode::LEN64 => todo!(),
-        OpCode::MADD => todo!(),
-        OpCode::MSUB => todo!(),
-        OpCode::NMSUB => todo!(),
-        OpCode::NMADD => todo!(),
-        OpCode::OPFP => todo!(),
-        OpCode::RESERVED1 => todo!(),
-        OpCode::CUSTOM2 => todo!(),
-        OpCode::LEN482 => todo!(),
+        OpCode::OP32 => Err(illegal),
+        OpCode::LEN64 => Err(illegal),
+        OpCode::MADD | OpCode::MSUB | OpCode::NMSUB | OpCode::NMADD => {
+            /* R4-Type, bits 26:25 select the format */
+            let rd_index: RDindex = rd(instruction);
+            let rs1: RS1index = rs1(instruction);
+            let rs2: RS2index = rs2(instruction);
+            let rs3: RS3index = rs3(instruction);
+            let rm: Rm = funct3(instruction);
+            match (op, (instruction >> 25) & 0b11) {
+                (OpCode::MADD, 0b00) => Ok(Instruction::FMADDS(rd_index, rs1, rs2, rs3, rm)),
+                (OpCode::MSUB, 0b00) => Ok(Instruction::FMSUBS(rd_index, rs1, rs2, rs3, rm)),
+                (OpCode::NMSUB, 0b00) => Ok(Instruction::FNMSUBS(rd_index, rs1, rs2, rs3, rm)),
+                (OpCode::NMADD, 0b00) => Ok(Instruction::FNMADDS(rd_index, rs1, rs2, rs3, rm)),
+                (OpCode::MADD, 0b01) => Ok(Instruction::FMADDD(rd_index, rs1, rs2, rs3, rm)),
+                (OpCode::MSUB, 0b01) => Ok(Instruction::FMSUBD(rd_index, rs1, rs2, rs3, rm)),
+                (OpCode::NMSUB, 0b01) => Ok(Instruction::FNMSUBD(rd_index, rs1, rs2, rs3, rm)),
+                (OpCode::NMADD, 0b01) => Ok(Instruction::FNMADDD(rd_index, rs1, rs2, rs3, rm)),
+                _ => Err(illegal),
+            }
+        }
+        OpCode::OPFP => decode_opfp(instruction),
+        OpCode::RESERVED1 => Err(illegal),
+        OpCode::CUSTOM2 => Err(illegal),
+        OpCode::LEN482 => Err(illegal),
         OpCode::BRANCH => {
             /* B-Type instructions */
             let rs1: RS1index = rs1(instruction);
@@ -427,7 +616,7 @@ pub fn decode(instruction: u32) -> Result<Instruction, &'static str> {
                 0b101 => Ok(Instruction::BGE(rs1, rs2, b_imm)),
                 0b110 => Ok(Instruction::BLTU(rs1, rs2, b_imm)),
                 0b111 => Ok(Instruction::BGEU(rs1, rs2, b_imm)),
-                _ => Err("Invalid funct3 B-Type"),
+                _ => Err(illegal),
             }
         }
         OpCode::JALR => {
@@ -435,9 +624,12 @@ pub fn decode(instruction: u32) -> Result<Instruction, &'static str> {
             let rd_index: RDindex = rd(instruction);
             let rs1: RS1index = rs1(instruction);
             let i_imm: Iimmediate = immediate_i(instruction);
+            if funct3(instruction) != 0 {
+                return Err(illegal);
+            }
             Ok(Instruction::JALR(rd_index, rs1, i_imm))
         }
-        OpCode::RESERVED2 => todo!(),
+        OpCode::RESERVED2 => Err(illegal),
         OpCode::JAL => {
             let rd_index: RDindex = rd(instruction);
             let j_imm: Jimmediate = immediate_j(instruction);
@@ -449,11 +641,19 @@ pub fn decode(instruction: u32) -> Result<Instruction, &'static str> {
             let rs1: RS1index = rs1(instruction);
             let i_imm: Iimmediate = immediate_i(instruction);
             match funct3(instruction) {
+                /* SFENCE.VMA is R-Type, the virtual address and the ASID to flush */
+                0b000 if funct7(instruction) == 0b000_1001 && rd_index == 0 => {
+                    Ok(Instruction::SFENCEVMA(rs1, rs2(instruction)))
+                }
+                /* The other privileged instructions have no operands */
+                0b000 if rd_index != 0 || rs1 != 0 => Err(illegal),
                 0b000 => match i_imm {
                     0b0000_0000_0000 => Ok(Instruction::ECALL()),
                     0b0000_0000_0001 => Ok(Instruction::EBREAK()),
+                    0b0001_0000_0010 => Ok(Instruction::SRET()),
                     0b0011_0000_0010 => Ok(Instruction::MRET()),
-                    _ => Err("Invalid SYSTEM instruction immediate"),
+                    0b0001_0000_0101 => Ok(Instruction::WFI()),
+                    _ => Err(illegal),
                 },
                 0b001 => Ok(Instruction::CSRRW(rd_index, rs1, i_imm)),
                 0b010 => Ok(Instruction::CSRRS(rd_index, rs1, i_imm)),
@@ -470,11 +670,673 @@ pub fn decode(instruction: u32) -> Result<Instruction, &'static str> {
                     /* This instruction repurposes rs1 as immediate */
                     Ok(Instruction::CSRRCI(rd_index, rs1, i_imm))
                 }
-                _ => Err("Invalid funct3 I-Type"),
+                _ => Err(illegal),
+            }
+        }
+        OpCode::RESERVED3 => Err(illegal),
+        OpCode::CUSTOM3 => Err(illegal),
+        OpCode::LEN80 => Err(illegal),
+    }
+}
+
+fn decode_opfp(instruction: u32) -> Result<Instruction, Exception> {
+    /* R-Type, funct7 selects the operation, rs2 and funct3 may select a variant */
+    let illegal = Exception::IllegalInstruction(instruction);
+    let rd_index: RDindex = rd(instruction);
+    let rs1: RS1index = rs1(instruction);
+    let rs2: RS2index = rs2(instruction);
+    let rm: Rm = funct3(instruction);
+    match (funct7(instruction), rs2, funct3(instruction)) {
+        (0b000_0000, _, _) => Ok(Instruction::FADDS(rd_index, rs1, rs2, rm)),
+        (0b000_0100, _, _) => Ok(Instruction::FSUBS(rd_index, rs1, rs2, rm)),
+        (0b000_1000, _, _) => Ok(Instruction::FMULS(rd_index, rs1, rs2, rm)),
+        (0b000_1100, _, _) => Ok(Instruction::FDIVS(rd_index, rs1, rs2, rm)),
+        (0b010_1100, 0, _) => Ok(Instruction::FSQRTS(rd_index, rs1, rm)),
+        (0b001_0000, _, 0b000) => Ok(Instruction::FSGNJS(rd_index, rs1, rs2)),
+        (0b001_0000, _, 0b001) => Ok(Instruction::FSGNJNS(rd_index, rs1, rs2)),
+        (0b001_0000, _, 0b010) => Ok(Instruction::FSGNJXS(rd_index, rs1, rs2)),
+        (0b001_0100, _, 0b000) => Ok(Instruction::FMINS(rd_index, rs1, rs2)),
+        (0b001_0100, _, 0b001) => Ok(Instruction::FMAXS(rd_index, rs1, rs2)),
+        (0b110_0000, 0, _) => Ok(Instruction::FCVTWS(rd_index, rs1, rm)),
+        (0b110_0000, 1, _) => Ok(Instruction::FCVTWUS(rd_index, rs1, rm)),
+        (0b111_0000, 0, 0b000) => Ok(Instruction::FMVXW(rd_index, rs1)),
+        (0b111_0000, 0, 0b001) => Ok(Instruction::FCLASSS(rd_index, rs1)),
+        (0b101_0000, _, 0b010) => Ok(Instruction::FEQS(rd_index, rs1, rs2)),
+        (0b101_0000, _, 0b001) => Ok(Instruction::FLTS(rd_index, rs1, rs2)),
+        (0b101_0000, _, 0b000) => Ok(Instruction::FLES(rd_index, rs1, rs2)),
+        (0b110_1000, 0, _) => Ok(Instruction::FCVTSW(rd_index, rs1, rm)),
+        (0b110_1000, 1, _) => Ok(Instruction::FCVTSWU(rd_index, rs1, rm)),
+        (0b111_1000, 0, 0b000) => Ok(Instruction::FMVWX(rd_index, rs1)),
+        (0b000_0001, _, _) => Ok(Instruction::FADDD(rd_index, rs1, rs2, rm)),
+        (0b000_0101, _, _) => Ok(Instruction::FSUBD(rd_index, rs1, rs2, rm)),
+        (0b000_1001, _, _) => Ok(Instruction::FMULD(rd_index, rs1, rs2, rm)),
+        (0b000_1101, _, _) => Ok(Instruction::FDIVD(rd_index, rs1, rs2, rm)),
+        (0b010_1101, 0, _) => Ok(Instruction::FSQRTD(rd_index, rs1, rm)),
+        (0b001_0001, _, 0b000) => Ok(Instruction::FSGNJD(rd_index, rs1, rs2)),
+        (0b001_0001, _, 0b001) => Ok(Instruction::FSGNJND(rd_index, rs1, rs2)),
+        (0b001_0001, _, 0b010) => Ok(Instruction::FSGNJXD(rd_index, rs1, rs2)),
+        (0b001_0101, _, 0b000) => Ok(Instruction::FMIND(rd_index, rs1, rs2)),
+        (0b001_0101, _, 0b001) => Ok(Instruction::FMAXD(rd_index, rs1, rs2)),
+        (0b010_0000, 1, _) => Ok(Instruction::FCVTSD(rd_index, rs1, rm)),
+        (0b010_0001, 0, _) => Ok(Instruction::FCVTDS(rd_index, rs1, rm)),
+        (0b101_0001, _, 0b010) => Ok(Instruction::FEQD(rd_index, rs1, rs2)),
+        (0b101_0001, _, 0b001) => Ok(Instruction::FLTD(rd_index, rs1, rs2)),
+        (0b101_0001, _, 0b000) => Ok(Instruction::FLED(rd_index, rs1, rs2)),
+        (0b111_0001, 0, 0b001) => Ok(Instruction::FCLASSD(rd_index, rs1)),
+        (0b110_0001, 0, _) => Ok(Instruction::FCVTWD(rd_index, rs1, rm)),
+        (0b110_0001, 1, _) => Ok(Instruction::FCVTWUD(rd_index, rs1, rm)),
+        (0b110_1001, 0, _) => Ok(Instruction::FCVTDW(rd_index, rs1, rm)),
+        (0b110_1001, 1, _) => Ok(Instruction::FCVTDWU(rd_index, rs1, rm)),
+        _ => Err(illegal),
+    }
+}
+
+/// Extracts `instruction[hi:lo]` and moves it to bit position `at`
+fn c_field(instruction: u32, hi: u32, lo: u32, at: u32) -> u32 {
+    ((instruction >> lo) & ((1 << (hi - lo + 1)) - 1)) << at
+}
+
+/// Sign extends the `bits` wide immediate of a compressed instruction
+fn c_sign_extend(immediate: u32, bits: u32) -> u32 {
+    let shift = 32 - bits;
+    (((immediate << shift) as i32) >> shift) as u32
+}
+
+/* The three bit register fields of compressed instructions address x8 - x15 */
+fn rd_c(instruction: u32) -> RDindex {
+    c_field(instruction, 4, 2, 0) as RDindex + 8
+}
+
+fn rs1_c(instruction: u32) -> RS1index {
+    c_field(instruction, 9, 7, 0) as RS1index + 8
+}
+
+fn rs2_c(instruction: u32) -> RS2index {
+    rd_c(instruction)
+}
+
+fn immediate_ci(instruction: u32) -> Iimmediate {
+    let imm = c_field(instruction, 12, 12, 5) | c_field(instruction, 6, 2, 0);
+    c_sign_extend(imm, 6) & 0xFFF
+}
+
+fn immediate_cl_word(instruction: u32) -> Iimmediate {
+    c_field(instruction, 12, 10, 3) | c_field(instruction, 6, 6, 2) | c_field(instruction, 5, 5, 6)
+}
+
+fn immediate_cl_double(instruction: u32) -> Iimmediate {
+    c_field(instruction, 12, 10, 3) | c_field(instruction, 6, 5, 6)
+}
+
+fn immediate_cj(instruction: u32) -> Jimmediate {
+    let imm = c_field(instruction, 12, 12, 11)
+        | c_field(instruction, 11, 11, 4)
+        | c_field(instruction, 10, 9, 8)
+        | c_field(instruction, 8, 8, 10)
+        | c_field(instruction, 7, 7, 6)
+        | c_field(instruction, 6, 6, 7)
+        | c_field(instruction, 5, 3, 1)
+        | c_field(instruction, 2, 2, 5);
+    c_sign_extend(imm, 12) & 0x1F_FFFF
+}
+
+fn immediate_cb(instruction: u32) -> Bimmediate {
+    let imm = c_field(instruction, 12, 12, 8)
+        | c_field(instruction, 11, 10, 3)
+        | c_field(instruction, 6, 5, 6)
+        | c_field(instruction, 4, 3, 1)
+        | c_field(instruction, 2, 2, 5);
+    c_sign_extend(imm, 9) & 0x1FFF
+}
+
+fn shamt_c(instruction: u32) -> Iimmediate {
+    c_field(instruction, 12, 12, 5) | c_field(instruction, 6, 2, 0)
+}
+
+/// Length of the instruction in bytes, judged by the lowest bits of its first halfword
+pub fn instruction_length(instruction: u32) -> u32 {
+    if isBaseInstructionSet!(instruction) {
+        4
+    } else {
+        2
+    }
+}
+
+/// Expands a 16-bit RV32C instruction into its 32-bit equivalent
+#[allow(clippy::too_many_lines)]
+fn decode_compressed(instruction: u32) -> Result<Instruction, Exception> {
+    let illegal = Exception::IllegalInstruction(instruction);
+    let rd_index: RDindex = rd(instruction);
+    let rs2: RS2index = c_field(instruction, 6, 2, 0) as RS2index;
+
+    match (instruction & 0b11, c_field(instruction, 15, 13, 0)) {
+        /* Quadrant 0 */
+        (0b00, 0b000) => {
+            /* C.ADDI4SPN */
+            let nzuimm = c_field(instruction, 12, 11, 4)
+                | c_field(instruction, 10, 7, 6)
+                | c_field(instruction, 6, 6, 2)
+                | c_field(instruction, 5, 5, 3);
+            if nzuimm == 0 {
+                return Err(illegal);
+            }
+            Ok(Instruction::ADDI(rd_c(instruction), 2, nzuimm))
+        }
+        (0b00, 0b001) => {
+            /* C.FLD */
+            let uimm = immediate_cl_double(instruction);
+            Ok(Instruction::FLD(
+                rd_c(instruction),
+                rs1_c(instruction),
+                uimm,
+            ))
+        }
+        (0b00, 0b010) => {
+            /* C.LW */
+            let uimm = immediate_cl_word(instruction);
+            Ok(Instruction::LW(rd_c(instruction), rs1_c(instruction), uimm))
+        }
+        (0b00, 0b011) => {
+            /* C.FLW */
+            let uimm = immediate_cl_word(instruction);
+            Ok(Instruction::FLW(
+                rd_c(instruction),
+                rs1_c(instruction),
+                uimm,
+            ))
+        }
+        (0b00, 0b101) => {
+            /* C.FSD */
+            let uimm = immediate_cl_double(instruction);
+            Ok(Instruction::FSD(
+                rs1_c(instruction),
+                rs2_c(instruction),
+                uimm,
+            ))
+        }
+        (0b00, 0b110) => {
+            /* C.SW */
+            let uimm = immediate_cl_word(instruction);
+            Ok(Instruction::SW(
+                rs1_c(instruction),
+                rs2_c(instruction),
+                uimm,
+            ))
+        }
+        (0b00, 0b111) => {
+            /* C.FSW */
+            let uimm = immediate_cl_word(instruction);
+            Ok(Instruction::FSW(
+                rs1_c(instruction),
+                rs2_c(instruction),
+                uimm,
+            ))
+        }
+        /* Quadrant 1 */
+        (0b01, 0b000) => {
+            /* C.ADDI, C.NOP */
+            Ok(Instruction::ADDI(
+                rd_index,
+                rd_index,
+                immediate_ci(instruction),
+            ))
+        }
+        (0b01, 0b001) => {
+            /* C.JAL */
+            Ok(Instruction::JAL(1, immediate_cj(instruction)))
+        }
+        (0b01, 0b010) => {
+            /* C.LI */
+            Ok(Instruction::ADDI(rd_index, 0, immediate_ci(instruction)))
+        }
+        (0b01, 0b011) => {
+            if rd_index == 2 {
+                /* C.ADDI16SP */
+                let nzimm = c_field(instruction, 12, 12, 9)
+                    | c_field(instruction, 6, 6, 4)
+                    | c_field(instruction, 5, 5, 6)
+                    | c_field(instruction, 4, 3, 7)
+                    | c_field(instruction, 2, 2, 5);
+                if nzimm == 0 {
+                    return Err(illegal);
+                }
+                Ok(Instruction::ADDI(2, 2, c_sign_extend(nzimm, 10) & 0xFFF))
+            } else {
+                /* C.LUI */
+                let nzimm = c_field(instruction, 12, 12, 17) | c_field(instruction, 6, 2, 12);
+                if nzimm == 0 {
+                    return Err(illegal);
+                }
+                Ok(Instruction::LUI(rd_index, c_sign_extend(nzimm, 18)))
+            }
+        }
+        (0b01, 0b100) => {
+            let rd_index = rs1_c(instruction);
+            match c_field(instruction, 11, 10, 0) {
+                0b00 | 0b01 if c_field(instruction, 12, 12, 0) != 0 => Err(illegal),
+                0b00 => Ok(Instruction::SRLI(rd_index, rd_index, shamt_c(instruction))),
+                0b01 => Ok(Instruction::SRAI(
+                    rd_index,
+                    rd_index,
+                    0b0100_0000_0000 | shamt_c(instruction),
+                )),
+                0b10 => Ok(Instruction::ANDI(
+                    rd_index,
+                    rd_index,
+                    immediate_ci(instruction),
+                )),
+                _ => {
+                    let rs2 = rs2_c(instruction);
+                    match (
+                        c_field(instruction, 12, 12, 0),
+                        c_field(instruction, 6, 5, 0),
+                    ) {
+                        (0, 0b00) => Ok(Instruction::SUB(rd_index, rd_index, rs2)),
+                        (0, 0b01) => Ok(Instruction::XOR(rd_index, rd_index, rs2)),
+                        (0, 0b10) => Ok(Instruction::OR(rd_index, rd_index, rs2)),
+                        (0, 0b11) => Ok(Instruction::AND(rd_index, rd_index, rs2)),
+                        _ => Err(illegal),
+                    }
+                }
+            }
+        }
+        (0b01, 0b101) => {
+            /* C.J */
+            Ok(Instruction::JAL(0, immediate_cj(instruction)))
+        }
+        (0b01, 0b110) => {
+            /* C.BEQZ */
+            Ok(Instruction::BEQ(
+                rs1_c(instruction),
+                0,
+                immediate_cb(instruction),
+            ))
+        }
+        (0b01, 0b111) => {
+            /* C.BNEZ */
+            Ok(Instruction::BNE(
+                rs1_c(instruction),
+                0,
+                immediate_cb(instruction),
+            ))
+        }
+        /* Quadrant 2 */
+        (0b10, 0b000) => {
+            /* C.SLLI */
+            if c_field(instruction, 12, 12, 0) != 0 {
+                return Err(illegal);
+            }
+            Ok(Instruction::SLLI(rd_index, rd_index, shamt_c(instruction)))
+        }
+        (0b10, 0b001) => {
+            /* C.FLDSP */
+            let uimm = c_field(instruction, 12, 12, 5)
+                | c_field(instruction, 6, 5, 3)
+                | c_field(instruction, 4, 2, 6);
+            Ok(Instruction::FLD(rd_index, 2, uimm))
+        }
+        (0b10, 0b010) => {
+            /* C.LWSP */
+            if rd_index == 0 {
+                return Err(illegal);
             }
+            let uimm = c_field(instruction, 12, 12, 5)
+                | c_field(instruction, 6, 4, 2)
+                | c_field(instruction, 3, 2, 6);
+            Ok(Instruction::LW(rd_index, 2, uimm))
+        }
+        (0b10, 0b011) => {
+            /* C.FLWSP */
+            let uimm = c_field(instruction, 12, 12, 5)
+                | c_field(instruction, 6, 4, 2)
+                | c_field(instruction, 3, 2, 6);
+            Ok(Instruction::FLW(rd_index, 2, uimm))
+        }
+        (0b10, 0b100) => match (c_field(instruction, 12, 12, 0), rd_index, rs2) {
+            /* C.JR */
+            (0, 0, 0) => Err(illegal),
+            (0, rs1, 0) => Ok(Instruction::JALR(0, rs1, 0)),
+            /* C.MV */
+            (0, rd_index, rs2) => Ok(Instruction::ADD(rd_index, 0, rs2)),
+            /* C.EBREAK */
+            (_, 0, 0) => Ok(Instruction::EBREAK()),
+            /* C.JALR */
+            (_, rs1, 0) => Ok(Instruction::JALR(1, rs1, 0)),
+            /* C.ADD */
+            (_, rd_index, rs2) => Ok(Instruction::ADD(rd_index, rd_index, rs2)),
+        },
+        (0b10, 0b101) => {
+            /* C.FSDSP */
+            let uimm = c_field(instruction, 12, 10, 3) | c_field(instruction, 9, 7, 6);
+            Ok(Instruction::FSD(2, rs2, uimm))
+        }
+        (0b10, 0b110) => {
+            /* C.SWSP */
+            let uimm = c_field(instruction, 12, 9, 2) | c_field(instruction, 8, 7, 6);
+            Ok(Instruction::SW(2, rs2, uimm))
+        }
+        (0b10, 0b111) => {
+            /* C.FSWSP */
+            let uimm = c_field(instruction, 12, 9, 2) | c_field(instruction, 8, 7, 6);
+            Ok(Instruction::FSW(2, rs2, uimm))
+        }
+        _ => Err(illegal),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::encoder::encode;
+    use crate::system::RAM_BASE;
+    use Instruction::*;
+
+    /* One encoding of every instruction with distinct registers, as assembled by llvm-mc */
+    const INSTRUCTIONS: &[(u32, &str, Instruction)] = &[
+        (0xFFFF_F537, "lui a0, 0xfffff", LUI(10, 0xFFFF_F000)),
+        (0x1234_5597, "auipc a1, 0x12345", AUIPC(11, 0x1234_5000)),
+        (0x801F_F0EF, "jal ra, -2048", JAL(1, 0x1F_F800)),
+        (0xFFC7_06E7, "jalr a3, -4(a4)", JALR(13, 14, 0xFFC)),
+        (0xFEB5_0CE3, "beq a0, a1, -8", BEQ(10, 11, 0x1FF8)),
+        (0x00D6_1863, "bne a2, a3, 16", BNE(12, 13, 16)),
+        (0x80F7_4063, "blt a4, a5, -4096", BLT(14, 15, 0x1000)),
+        (0x7F39_5FE3, "bge s2, s3, 4094", BGE(18, 19, 0xFFE)),
+        (0x0462_E063, "bltu t0, t1, 64", BLTU(5, 6, 64)),
+        (0xFFC3_FFE3, "bgeu t2, t3, -2", BGEU(7, 28, 0x1FFE)),
+        (0xFFF1_0503, "lb a0, -1(sp)", LB(10, 2, 0xFFF)),
+        (0x0024_1583, "lh a1, 2(s0)", LH(11, 8, 2)),
+        (0x8004_A603, "lw a2, -2048(s1)", LW(12, 9, 0x800)),
+        (0x7FF7_4683, "lbu a3, 2047(a4)", LBU(13, 14, 0x7FF)),
+        (0x0061_D783, "lhu a5, 6(gp)", LHU(15, 3, 6)),
+        (0xFEA1_0FA3, "sb a0, -1(sp)", SB(2, 10, 0xFFF)),
+        (0x00B4_1123, "sh a1, 2(s0)", SH(8, 11, 2)),
+        (0x80C4_A023, "sw a2, -2048(s1)", SW(9, 12, 0x800)),
+        (0xFF95_8513, "addi a0, a1, -7", ADDI(10, 11, 0xFF9)),
+        (0x8006_A613, "slti a2, a3, -2048", SLTI(12, 13, 0x800)),
+        (0x7FF7_B713, "sltiu a4, a5, 2047", SLTIU(14, 15, 0x7FF)),
+        (0xFFF9_C913, "xori s2, s3, -1", XORI(18, 19, 0xFFF)),
+        (0x555A_EA13, "ori s4, s5, 0x555", ORI(20, 21, 0x555)),
+        (0x0FFB_FB13, "andi s6, s7, 0xff", ANDI(22, 23, 255)),
+        (0x01F3_1293, "slli t0, t1, 31", SLLI(5, 6, 31)),
+        (0x001E_5393, "srli t2, t3, 1", SRLI(7, 28, 1)),
+        (0x400F_5E93, "srai t4, t5, 0", SRAI(29, 30, 0x400)),
+        (0x00C5_8533, "add a0, a1, a2", ADD(10, 11, 12)),
+        (0x40F7_06B3, "sub a3, a4, a5", SUB(13, 14, 15)),
+        (0x0149_9933, "sll s2, s3, s4", SLL(18, 19, 20)),
+        (0x017B_2AB3, "slt s5, s6, s7", SLT(21, 22, 23)),
+        (0x01AC_BC33, "sltu s8, s9, s10", SLTU(24, 25, 26)),
+        (0x01DE_4DB3, "xor s11, t3, t4", XOR(27, 28, 29)),
+        (0x001F_DF33, "srl t5, t6, ra", SRL(30, 31, 1)),
+        (0x4041_D133, "sra sp, gp, tp", SRA(2, 3, 4)),
+        (0x0073_62B3, "or t0, t1, t2", OR(5, 6, 7)),
+        (0x00A4_F433, "and s0, s1, a0", AND(8, 9, 10)),
+        (0x0310_000F, "fence rw, w", FENCE(0, 0, 49)),
+        (0x0000_0073, "ecall", ECALL()),
+        (0x0010_0073, "ebreak", EBREAK()),
+        (0x1020_0073, "sret", SRET()),
+        (0x3020_0073, "mret", MRET()),
+        (0x1050_0073, "wfi", WFI()),
+        (0x1200_0073, "sfence.vma", SFENCEVMA(0, 0)),
+        (0x1205_0073, "sfence.vma a0", SFENCEVMA(10, 0)),
+        (0x12B5_0073, "sfence.vma a0, a1", SFENCEVMA(10, 11)),
+        (0x12B0_0073, "sfence.vma zero, a1", SFENCEVMA(0, 11)),
+        (0x3005_9573, "csrrw a0, mstatus, a1", CSRRW(10, 11, 0x300)),
+        (0x3046_A673, "csrrs a2, mie, a3", CSRRS(12, 13, 0x304)),
+        (0x3447_B773, "csrrc a4, mip, a5", CSRRC(14, 15, 0x344)),
+        (0x305F_D973, "csrrwi s2, mtvec, 31", CSRRWI(18, 31, 0x305)),
+        (0x3400_E9F3, "csrrsi s3, mscratch, 1", CSRRSI(19, 1, 0x340)),
+        (0xC008_7A73, "csrrci s4, 0xc00, 16", CSRRCI(20, 16, 0xC00)),
+        (0x02C5_8533, "mul a0, a1, a2", MUL(10, 11, 12)),
+        (0x02F7_16B3, "mulh a3, a4, a5", MULH(13, 14, 15)),
+        (0x0349_A933, "mulhsu s2, s3, s4", MULHSU(18, 19, 20)),
+        (0x037B_3AB3, "mulhu s5, s6, s7", MULHU(21, 22, 23)),
+        (0x03AC_CC33, "div s8, s9, s10", DIV(24, 25, 26)),
+        (0x03DE_5DB3, "divu s11, t3, t4", DIVU(27, 28, 29)),
+        (0x021F_EF33, "rem t5, t6, ra", REM(30, 31, 1)),
+        (0x0241_F133, "remu sp, gp, tp", REMU(2, 3, 4)),
+        (0x1005_A52F, "lr.w a0, (a1)", LRW(10, 11, 0)),
+        (0x1CD7_262F, "sc.w.aq a2, a3, (a4)", SCW(12, 14, 13, 2)),
+        (
+            0x0B29_A7AF,
+            "amoswap.w.rl a5, s2, (s3)",
+            AMOSWAPW(15, 19, 18, 1),
+        ),
+        (
+            0x075B_2A2F,
+            "amoadd.w.aqrl s4, s5, (s6)",
+            AMOADDW(20, 22, 21, 3),
+        ),
+        (0x218C_ABAF, "amoxor.w s7, s8, (s9)", AMOXORW(23, 25, 24, 0)),
+        (
+            0x61BE_2D2F,
+            "amoand.w s10, s11, (t3)",
+            AMOANDW(26, 28, 27, 0),
+        ),
+        (0x41EF_AEAF, "amoor.w t4, t5, (t6)", AMOORW(29, 31, 30, 0)),
+        (0x80B6_252F, "amomin.w a0, a1, (a2)", AMOMINW(10, 12, 11, 0)),
+        (0xA0E7_A6AF, "amomax.w a3, a4, (a5)", AMOMAXW(13, 15, 14, 0)),
+        (
+            0xC13A_292F,
+            "amominu.w s2, s3, (s4)",
+            AMOMINUW(18, 20, 19, 0),
+        ),
+        (
+            0xE16B_AAAF,
+            "amomaxu.w s5, s6, (s7)",
+            AMOMAXUW(21, 23, 22, 0),
+        ),
+        (0xFFC1_2507, "flw fa0, -4(sp)", FLW(10, 2, 0xFFC)),
+        (0x00B4_2427, "fsw fa1, 8(s0)", FSW(8, 11, 8)),
+        (
+            0x68C5_8543,
+            "fmadd.s fa0, fa1, fa2, fa3, rne",
+            FMADDS(10, 11, 12, 13, 0),
+        ),
+        (
+            0x8907_9747,
+            "fmsub.s fa4, fa5, fa6, fa7, rtz",
+            FMSUBS(14, 15, 16, 17, 1),
+        ),
+        (
+            0x9924_A44B,
+            "fnmsub.s fs0, fs1, fs2, fs3, rdn",
+            FNMSUBS(8, 9, 18, 19, 2),
+        ),
+        (
+            0xB96A_BA4F,
+            "fnmadd.s fs4, fs5, fs6, fs7, rup",
+            FNMADDS(20, 21, 22, 23, 3),
+        ),
+        (0x0020_C053, "fadd.s ft0, ft1, ft2, rmm", FADDS(0, 1, 2, 4)),
+        (0x0852_71D3, "fsub.s ft3, ft4, ft5, dyn", FSUBS(3, 4, 5, 7)),
+        (0x11C3_8353, "fmul.s ft6, ft7, ft8, rne", FMULS(6, 7, 28, 0)),
+        (
+            0x19FF_1ED3,
+            "fdiv.s ft9, ft10, ft11, rtz",
+            FDIVS(29, 30, 31, 1),
+        ),
+        (0x5805_A553, "fsqrt.s fa0, fa1, rdn", FSQRTS(10, 11, 2)),
+        (0x20E6_8653, "fsgnj.s fa2, fa3, fa4", FSGNJS(12, 13, 14)),
+        (0x2118_17D3, "fsgnjn.s fa5, fa6, fa7", FSGNJNS(15, 16, 17)),
+        (0x2124_A453, "fsgnjx.s fs0, fs1, fs2", FSGNJXS(8, 9, 18)),
+        (0x295A_09D3, "fmin.s fs3, fs4, fs5", FMINS(19, 20, 21)),
+        (0x298B_9B53, "fmax.s fs6, fs7, fs8", FMAXS(22, 23, 24)),
+        (0xC005_9553, "fcvt.w.s a0, fa1, rtz", FCVTWS(10, 11, 1)),
+        (0xC016_B653, "fcvt.wu.s a2, fa3, rup", FCVTWUS(12, 13, 3)),
+        (0xE007_8753, "fmv.x.w a4, fa5", FMVXW(14, 15)),
+        (0xA088_A853, "feq.s a6, fa7, fs0", FEQS(16, 17, 8)),
+        (0xA149_9953, "flt.s s2, fs3, fs4", FLTS(18, 19, 20)),
+        (0xA17B_0AD3, "fle.s s5, fs6, fs7", FLES(21, 22, 23)),
+        (0xE00C_9C53, "fclass.s s8, fs9", FCLASSS(24, 25)),
+        (0xD005_8553, "fcvt.s.w fa0, a1, rne", FCVTSW(10, 11, 0)),
+        (0xD016_C653, "fcvt.s.wu fa2, a3, rmm", FCVTSWU(12, 13, 4)),
+        (0xF007_8753, "fmv.w.x fa4, a5", FMVWX(14, 15)),
+        (0xFF81_3507, "fld fa0, -8(sp)", FLD(10, 2, 0xFF8)),
+        (0x00B4_3827, "fsd fa1, 16(s0)", FSD(8, 11, 16)),
+        (
+            0x6AC5_8543,
+            "fmadd.d fa0, fa1, fa2, fa3, rne",
+            FMADDD(10, 11, 12, 13, 0),
+        ),
+        (
+            0x8B07_9747,
+            "fmsub.d fa4, fa5, fa6, fa7, rtz",
+            FMSUBD(14, 15, 16, 17, 1),
+        ),
+        (
+            0x9B24_A44B,
+            "fnmsub.d fs0, fs1, fs2, fs3, rdn",
+            FNMSUBD(8, 9, 18, 19, 2),
+        ),
+        (
+            0xBB6A_BA4F,
+            "fnmadd.d fs4, fs5, fs6, fs7, rup",
+            FNMADDD(20, 21, 22, 23, 3),
+        ),
+        (0x0220_C053, "fadd.d ft0, ft1, ft2, rmm", FADDD(0, 1, 2, 4)),
+        (0x0A52_71D3, "fsub.d ft3, ft4, ft5, dyn", FSUBD(3, 4, 5, 7)),
+        (0x13C3_8353, "fmul.d ft6, ft7, ft8, rne", FMULD(6, 7, 28, 0)),
+        (
+            0x1BFF_1ED3,
+            "fdiv.d ft9, ft10, ft11, rtz",
+            FDIVD(29, 30, 31, 1),
+        ),
+        (0x5A05_A553, "fsqrt.d fa0, fa1, rdn", FSQRTD(10, 11, 2)),
+        (0x22E6_8653, "fsgnj.d fa2, fa3, fa4", FSGNJD(12, 13, 14)),
+        (0x2318_17D3, "fsgnjn.d fa5, fa6, fa7", FSGNJND(15, 16, 17)),
+        (0x2324_A453, "fsgnjx.d fs0, fs1, fs2", FSGNJXD(8, 9, 18)),
+        (0x2B5A_09D3, "fmin.d fs3, fs4, fs5", FMIND(19, 20, 21)),
+        (0x2B8B_9B53, "fmax.d fs6, fs7, fs8", FMAXD(22, 23, 24)),
+        (0x4015_B553, "fcvt.s.d fa0, fa1, rup", FCVTSD(10, 11, 3)),
+        (0x4206_8653, "fcvt.d.s fa2, fa3", FCVTDS(12, 13, 0)),
+        (0xA288_A853, "feq.d a6, fa7, fs0", FEQD(16, 17, 8)),
+        (0xA349_9953, "flt.d s2, fs3, fs4", FLTD(18, 19, 20)),
+        (0xA37B_0AD3, "fle.d s5, fs6, fs7", FLED(21, 22, 23)),
+        (0xE20C_9C53, "fclass.d s8, fs9", FCLASSD(24, 25)),
+        (0xC205_9553, "fcvt.w.d a0, fa1, rtz", FCVTWD(10, 11, 1)),
+        (0xC216_A653, "fcvt.wu.d a2, fa3, rdn", FCVTWUD(12, 13, 2)),
+        (0xD205_8553, "fcvt.d.w fa0, a1", FCVTDW(10, 11, 0)),
+        (0xD216_8653, "fcvt.d.wu fa2, a3", FCVTDWU(12, 13, 0)),
+    ];
+
+    /* Compressed instructions and the instructions they expand to */
+    const COMPRESSED: &[(u32, &str, Instruction)] = &[
+        (0x1FE0, "c.addi4spn s0, sp, 1020", ADDI(8, 2, 0x3FC)),
+        (0x3D64, "c.fld fs1, 248(a0)", FLD(9, 10, 248)),
+        (0x5EF0, "c.lw a2, 124(a3)", LW(12, 13, 124)),
+        (0x63B8, "c.flw fa4, 64(a5)", FLW(14, 15, 64)),
+        (0xA480, "c.fsd fs0, 8(s1)", FSD(9, 8, 8)),
+        (0xC1C8, "c.sw a0, 4(a1)", SW(11, 10, 4)),
+        (0xE290, "c.fsw fa2, 0(a3)", FSW(13, 12, 0)),
+        (0x0001, "c.nop", ADDI(0, 0, 0)),
+        (0x1501, "c.addi a0, -32", ADDI(10, 10, 0xFE0)),
+        (0x3001, "c.jal -2048", JAL(1, 0x1F_F800)),
+        (0x42FD, "c.li t0, 31", ADDI(5, 0, 31)),
+        (0x7101, "c.addi16sp sp, -512", ADDI(2, 2, 0xE00)),
+        (0x7905, "c.lui s2, 0xfffe1", LUI(18, 0xFFFE_1000)),
+        (0x817D, "c.srli a0, 31", SRLI(10, 10, 31)),
+        (0x8585, "c.srai a1, 1", SRAI(11, 11, 0x401)),
+        (0x9A7D, "c.andi a2, -1", ANDI(12, 12, 0xFFF)),
+        (0x8C05, "c.sub s0, s1", SUB(8, 8, 9)),
+        (0x8EB9, "c.xor a3, a4", XOR(13, 13, 14)),
+        (0x8FC1, "c.or a5, s0", OR(15, 15, 8)),
+        (0x8CE9, "c.and s1, a0", AND(9, 9, 10)),
+        (0xAFFD, "c.j 2046", JAL(0, 0x7FE)),
+        (0xD101, "c.beqz a0, -256", BEQ(10, 0, 0x1F00)),
+        (0xECFD, "c.bnez s1, 254", BNE(9, 0, 254)),
+        (0x0332, "c.slli t1, 12", SLLI(6, 6, 12)),
+        (0x307E, "c.fldsp ft0, 504(sp)", FLD(0, 2, 0x1F8)),
+        (0x50FE, "c.lwsp ra, 252(sp)", LW(1, 2, 252)),
+        (0x6D82, "c.flwsp fs11, 0(sp)", FLW(27, 2, 0)),
+        (0x8282, "c.jr t0", JALR(0, 5, 0)),
+        (0x857E, "c.mv a0, t6", ADD(10, 0, 31)),
+        (0x9002, "c.ebreak", EBREAK()),
+        (0x9782, "c.jalr a5", JALR(1, 15, 0)),
+        (0x994E, "c.add s2, s3", ADD(18, 18, 19)),
+        (0xA02A, "c.fsdsp fa0, 0(sp)", FSD(2, 10, 0)),
+        (0xC622, "c.swsp s0, 12(sp)", SW(2, 8, 12)),
+        (0xFFAE, "c.fswsp fa1, 252(sp)", FSW(2, 11, 252)),
+    ];
+
+    #[test]
+    fn decodes_every_instruction() {
+        for &(raw, source, instruction) in INSTRUCTIONS {
+            assert_eq!(decode(raw), Ok(instruction), "{source}");
+            assert_eq!(instruction_length(raw), 4, "{source}");
+        }
+    }
+
+    #[test]
+    fn encodes_every_instruction() {
+        for &(raw, source, instruction) in INSTRUCTIONS {
+            assert_eq!(encode(&instruction), raw, "{source}");
+        }
+    }
+
+    #[test]
+    fn assembles_every_instruction() {
+        for &(raw, source, _) in INSTRUCTIONS {
+            let program = assemble(source, RAM_BASE as u32).unwrap();
+            assert_eq!(program.code, raw.to_le_bytes(), "{source}");
+        }
+    }
+
+    #[test]
+    fn disassembly_assembles_back() {
+        for &(raw, source, instruction) in INSTRUCTIONS {
+            let text = instruction.to_string();
+            let program = assemble(&text, RAM_BASE as u32).unwrap();
+            assert_eq!(program.code, raw.to_le_bytes(), "{source} => {text}");
+        }
+    }
+
+    #[test]
+    fn expands_compressed_instructions() {
+        for &(raw, source, instruction) in COMPRESSED {
+            assert_eq!(decode(raw), Ok(instruction), "{source}");
+            assert_eq!(instruction_length(raw), 2, "{source}");
+        }
+    }
+
+    #[test]
+    fn compressed_upper_halfword_is_ignored() {
+        /* The fetch of a compressed instruction may or may not include the next halfword */
+        assert_eq!(decode(0x1234_0001), Ok(ADDI(0, 0, 0)));
+    }
+
+    #[test]
+    fn rejects_illegal_encodings() {
+        let illegal = [
+            0x0000,      /* c.unimp, all zero */
+            0x0004,      /* c.addi4spn with a zero immediate */
+            0x6101,      /* c.addi16sp with a zero immediate */
+            0x6501,      /* c.lui with a zero immediate */
+            0x4002,      /* c.lwsp into x0 */
+            0x8002,      /* c.jr x0 */
+            0x0205_1513, /* slli with shamt[5] set */
+            0x4000_1033, /* sll with funct7 of sub */
+            0x2000_0033, /* add with an unassigned funct7 */
+            0x0000_3003, /* ld */
+            0x0000_3023, /* sd */
+            0x0000_2063, /* branch with funct3 2 */
+            0x0000_1067, /* jalr with funct3 1 */
+            0x1010_252F, /* lr.w with rs2 set */
+            0x2800_252F, /* amo with an unassigned funct5 */
+            0x0020_0073, /* uret */
+            0x0000_0573, /* ecall with rd set */
+            0x1200_00F3, /* sfence.vma with rd set */
+            0x0000_4073, /* system with funct3 4 */
+            0x5810_0553, /* fsqrt.s with rs2 set */
+            0x0000_007B, /* custom-3 */
+            0xFFFF_FFFF, /* longer than 32 bits */
+        ];
+        for raw in illegal {
+            assert_eq!(
+                decode(raw),
+                Err(Exception::IllegalInstruction(raw)),
+                "{raw:#010x}"
+            );
         }
-        OpCode::RESERVED3 => todo!(),
-        OpCode::CUSTOM3 => todo!(),
-        OpCode::LEN80 => todo!(),
     }
 }
@@ -0,0 +1,525 @@
+use std::fmt;
+use std::io::{self, Write};
+
+use anyhow::{anyhow, ensure};
+use elf::{abi, endian::LittleEndian, file::Class, ElfBytes};
+
+use crate::decoder::{decode, instruction_length, Instruction};
+use crate::symbols::Symbols;
+use crate::system::{csr_name, FP_REGISTER_NAMES, RAM_BASE, REGISTER_NAMES};
+
+/// An instruction formatted as assembly, see [`Instruction::at`]
+pub struct Disassembly<'a> {
+    instruction: &'a Instruction,
+    /* Without the address of the instruction, branch and jump targets are printed as offsets */
+    pc: Option<u32>,
+    symbols: Option<&'a Symbols>,
+}
+
+impl Instruction {
+    /// Disassembly of the instruction placed at `pc`, branch and jump targets become
+    /// addresses labelled with the nearest symbol before them
+    pub fn at<'a>(&'a self, pc: u32, symbols: &'a Symbols) -> Disassembly<'a> {
+        Disassembly {
+            instruction: self,
+            pc: Some(pc),
+            symbols: Some(symbols),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Disassembly {
+            instruction: self,
+            pc: None,
+            symbols: None,
+        }
+        .fmt(f)
+    }
+}
+
+/// Sign extends the `bits` wide immediate of an instruction
+fn signed(immediate: u32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    ((immediate << shift) as i32) >> shift
+}
+
+fn x(index: usize) -> &'static str {
+    REGISTER_NAMES[index]
+}
+
+fn fp(index: usize) -> &'static str {
+    FP_REGISTER_NAMES[index]
+}
+
+fn csr(index: u32) -> String {
+    csr_name(index).unwrap_or_else(|| format!("0x{index:03x}"))
+}
+
+/// Suffix of an atomic instruction for its acquire and release bits
+fn ordering(aqrl: u32) -> &'static str {
+    match aqrl & 0b11 {
+        0b00 => "",
+        0b01 => ".rl",
+        0b10 => ".aq",
+        _ => ".aqrl",
+    }
+}
+
+/// Rounding mode operand, left out for the dynamic mode like assemblers do
+fn rounding(rm: u32) -> String {
+    match rm {
+        0b000 => ", rne".to_string(),
+        0b001 => ", rtz".to_string(),
+        0b010 => ", rdn".to_string(),
+        0b011 => ", rup".to_string(),
+        0b100 => ", rmm".to_string(),
+        0b111 => String::new(),
+        _ => format!(", {rm}"),
+    }
+}
+
+/// Predecessor or successor set of a fence, as `iorw` letters
+fn fence_set(bits: u32) -> String {
+    let set: String = "iorw"
+        .chars()
+        .enumerate()
+        .filter(|(n, _)| bits & (0b1000 >> n) != 0)
+        .map(|(_, c)| c)
+        .collect();
+    if set.is_empty() {
+        "0".to_string()
+    } else {
+        set
+    }
+}
+
+fn mnemonic(instruction: &Instruction) -> &'static str {
+    use Instruction::*;
+    match instruction {
+        LUI(..) => "lui",
+        AUIPC(..) => "auipc",
+        JAL(..) => "jal",
+        JALR(..) => "jalr",
+        BEQ(..) => "beq",
+        BNE(..) => "bne",
+        BLT(..) => "blt",
+        BGE(..) => "bge",
+        BLTU(..) => "bltu",
+        BGEU(..) => "bgeu",
+        LB(..) => "lb",
+        LH(..) => "lh",
+        LW(..) => "lw",
+        LBU(..) => "lbu",
+        LHU(..) => "lhu",
+        SB(..) => "sb",
+        SH(..) => "sh",
+        SW(..) => "sw",
+        ADDI(..) => "addi",
+        SLTI(..) => "slti",
+        SLTIU(..) => "sltiu",
+        XORI(..) => "xori",
+        ORI(..) => "ori",
+        ANDI(..) => "andi",
+        SLLI(..) => "slli",
+        SRLI(..) => "srli",
+        SRAI(..) => "srai",
+        ADD(..) => "add",
+        SUB(..) => "sub",
+        SLL(..) => "sll",
+        SLT(..) => "slt",
+        SLTU(..) => "sltu",
+        XOR(..) => "xor",
+        SRL(..) => "srl",
+        SRA(..) => "sra",
+        OR(..) => "or",
+        AND(..) => "and",
+        FENCE(..) => "fence",
+        ECALL() => "ecall",
+        EBREAK() => "ebreak",
+        SRET() => "sret",
+        MRET() => "mret",
+        WFI() => "wfi",
+        SFENCEVMA(..) => "sfence.vma",
+        CSRRW(..) => "csrrw",
+        CSRRS(..) => "csrrs",
+        CSRRC(..) => "csrrc",
+        CSRRWI(..) => "csrrwi",
+        CSRRSI(..) => "csrrsi",
+        CSRRCI(..) => "csrrci",
+        MUL(..) => "mul",
+        MULH(..) => "mulh",
+        MULHSU(..) => "mulhsu",
+        MULHU(..) => "mulhu",
+        DIV(..) => "div",
+        DIVU(..) => "divu",
+        REM(..) => "rem",
+        REMU(..) => "remu",
+        LRW(..) => "lr.w",
+        SCW(..) => "sc.w",
+        AMOSWAPW(..) => "amoswap.w",
+        AMOADDW(..) => "amoadd.w",
+        AMOXORW(..) => "amoxor.w",
+        AMOANDW(..) => "amoand.w",
+        AMOORW(..) => "amoor.w",
+        AMOMINW(..) => "amomin.w",
+        AMOMAXW(..) => "amomax.w",
+        AMOMINUW(..) => "amominu.w",
+        AMOMAXUW(..) => "amomaxu.w",
+        FLW(..) => "flw",
+        FSW(..) => "fsw",
+        FMADDS(..) => "fmadd.s",
+        FMSUBS(..) => "fmsub.s",
+        FNMSUBS(..) => "fnmsub.s",
+        FNMADDS(..) => "fnmadd.s",
+        FADDS(..) => "fadd.s",
+        FSUBS(..) => "fsub.s",
+        FMULS(..) => "fmul.s",
+        FDIVS(..) => "fdiv.s",
+        FSQRTS(..) => "fsqrt.s",
+        FSGNJS(..) => "fsgnj.s",
+        FSGNJNS(..) => "fsgnjn.s",
+        FSGNJXS(..) => "fsgnjx.s",
+        FMINS(..) => "fmin.s",
+        FMAXS(..) => "fmax.s",
+        FCVTWS(..) => "fcvt.w.s",
+        FCVTWUS(..) => "fcvt.wu.s",
+        FMVXW(..) => "fmv.x.w",
+        FEQS(..) => "feq.s",
+        FLTS(..) => "flt.s",
+        FLES(..) => "fle.s",
+        FCLASSS(..) => "fclass.s",
+        FCVTSW(..) => "fcvt.s.w",
+        FCVTSWU(..) => "fcvt.s.wu",
+        FMVWX(..) => "fmv.w.x",
+        FLD(..) => "fld",
+        FSD(..) => "fsd",
+        FMADDD(..) => "fmadd.d",
+        FMSUBD(..) => "fmsub.d",
+        FNMSUBD(..) => "fnmsub.d",
+        FNMADDD(..) => "fnmadd.d",
+        FADDD(..) => "fadd.d",
+        FSUBD(..) => "fsub.d",
+        FMULD(..) => "fmul.d",
+        FDIVD(..) => "fdiv.d",
+        FSQRTD(..) => "fsqrt.d",
+        FSGNJD(..) => "fsgnj.d",
+        FSGNJND(..) => "fsgnjn.d",
+        FSGNJXD(..) => "fsgnjx.d",
+        FMIND(..) => "fmin.d",
+        FMAXD(..) => "fmax.d",
+        FCVTSD(..) => "fcvt.s.d",
+        FCVTDS(..) => "fcvt.d.s",
+        FEQD(..) => "feq.d",
+        FLTD(..) => "flt.d",
+        FLED(..) => "fle.d",
+        FCLASSD(..) => "fclass.d",
+        FCVTWD(..) => "fcvt.w.d",
+        FCVTWUD(..) => "fcvt.wu.d",
+        FCVTDW(..) => "fcvt.d.w",
+        FCVTDWU(..) => "fcvt.d.wu",
+    }
+}
+
+impl Disassembly<'_> {
+    /// Writes the target of a branch or jump `offset` bytes away from the instruction
+    fn target(&self, f: &mut fmt::Formatter<'_>, offset: i32) -> fmt::Result {
+        let Some(pc) = self.pc else {
+            return write!(f, "{offset}");
+        };
+        let addr = pc.wrapping_add(offset as u32);
+        write!(f, "0x{addr:08X}")?;
+        match self.symbols.and_then(|symbols| symbols.label(addr)) {
+            Some(label) => write!(f, " {label}"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Disassembly<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Instruction::*;
+        let name = mnemonic(self.instruction);
+        match *self.instruction {
+            LUI(rd, imm) | AUIPC(rd, imm) => write!(f, "{name} {}, 0x{:x}", x(rd), imm >> 12),
+            JAL(rd, imm) => {
+                match rd {
+                    0 => write!(f, "j ")?,
+                    1 => write!(f, "jal ")?,
+                    _ => write!(f, "jal {}, ", x(rd))?,
+                }
+                self.target(f, signed(imm, 21))
+            }
+            JALR(0, 1, 0) => write!(f, "ret"),
+            JALR(0, rs1, 0) => write!(f, "jr {}", x(rs1)),
+            JALR(1, rs1, 0) => write!(f, "jalr {}", x(rs1)),
+            JALR(rd, rs1, imm) => write!(f, "jalr {}, {}({})", x(rd), signed(imm, 12), x(rs1)),
+            BEQ(rs1, rs2, imm)
+            | BNE(rs1, rs2, imm)
+            | BLT(rs1, rs2, imm)
+            | BGE(rs1, rs2, imm)
+            | BLTU(rs1, rs2, imm)
+            | BGEU(rs1, rs2, imm) => {
+                /* Comparisons against zero have a single operand form */
+                match (self.instruction, rs1, rs2) {
+                    (BEQ(..), _, 0) => write!(f, "beqz {}, ", x(rs1))?,
+                    (BNE(..), _, 0) => write!(f, "bnez {}, ", x(rs1))?,
+                    (BLT(..), _, 0) => write!(f, "bltz {}, ", x(rs1))?,
+                    (BLT(..), 0, _) => write!(f, "bgtz {}, ", x(rs2))?,
+                    (BGE(..), _, 0) => write!(f, "bgez {}, ", x(rs1))?,
+                    (BGE(..), 0, _) => write!(f, "blez {}, ", x(rs2))?,
+                    _ => write!(f, "{name} {}, {}, ", x(rs1), x(rs2))?,
+                }
+                self.target(f, signed(imm, 13))
+            }
+            LB(rd, rs1, imm)
+            | LH(rd, rs1, imm)
+            | LW(rd, rs1, imm)
+            | LBU(rd, rs1, imm)
+            | LHU(rd, rs1, imm) => {
+                write!(f, "{name} {}, {}({})", x(rd), signed(imm, 12), x(rs1))
+            }
+            SB(rs1, rs2, imm) | SH(rs1, rs2, imm) | SW(rs1, rs2, imm) => {
+                write!(f, "{name} {}, {}({})", x(rs2), signed(imm, 12), x(rs1))
+            }
+            ADDI(0, 0, 0) => write!(f, "nop"),
+            ADDI(rd, 0, imm) => write!(f, "li {}, {}", x(rd), signed(imm, 12)),
+            ADDI(rd, rs1, 0) => write!(f, "mv {}, {}", x(rd), x(rs1)),
+            SLTIU(rd, rs1, 1) => write!(f, "seqz {}, {}", x(rd), x(rs1)),
+            XORI(rd, rs1, 0xFFF) => write!(f, "not {}, {}", x(rd), x(rs1)),
+            ADDI(rd, rs1, imm)
+            | SLTI(rd, rs1, imm)
+            | SLTIU(rd, rs1, imm)
+            | XORI(rd, rs1, imm)
+            | ORI(rd, rs1, imm)
+            | ANDI(rd, rs1, imm) => write!(f, "{name} {}, {}, {}", x(rd), x(rs1), signed(imm, 12)),
+            /* The immediate of shifts also holds the bits telling SRLI and SRAI apart */
+            SLLI(rd, rs1, imm) | SRLI(rd, rs1, imm) | SRAI(rd, rs1, imm) => {
+                write!(f, "{name} {}, {}, {}", x(rd), x(rs1), imm & 0x1F)
+            }
+            ADD(rd, 0, rs2) => write!(f, "mv {}, {}", x(rd), x(rs2)),
+            SUB(rd, 0, rs2) => write!(f, "neg {}, {}", x(rd), x(rs2)),
+            SLTU(rd, 0, rs2) => write!(f, "snez {}, {}", x(rd), x(rs2)),
+            SLT(rd, rs1, 0) => write!(f, "sltz {}, {}", x(rd), x(rs1)),
+            SLT(rd, 0, rs2) => write!(f, "sgtz {}, {}", x(rd), x(rs2)),
+            ADD(rd, rs1, rs2)
+            | SUB(rd, rs1, rs2)
+            | SLL(rd, rs1, rs2)
+            | SLT(rd, rs1, rs2)
+            | SLTU(rd, rs1, rs2)
+            | XOR(rd, rs1, rs2)
+            | SRL(rd, rs1, rs2)
+            | SRA(rd, rs1, rs2)
+            | OR(rd, rs1, rs2)
+            | AND(rd, rs1, rs2)
+            | MUL(rd, rs1, rs2)
+            | MULH(rd, rs1, rs2)
+            | MULHSU(rd, rs1, rs2)
+            | MULHU(rd, rs1, rs2)
+            | DIV(rd, rs1, rs2)
+            | DIVU(rd, rs1, rs2)
+            | REM(rd, rs1, rs2)
+            | REMU(rd, rs1, rs2) => write!(f, "{name} {}, {}, {}", x(rd), x(rs1), x(rs2)),
+            FENCE(_, _, imm) => {
+                let (fm, pred, succ) = (imm >> 8, (imm >> 4) & 0xF, imm & 0xF);
+                match (fm, pred, succ) {
+                    (0b0000, 0xF, 0xF) => write!(f, "fence"),
+                    (0b1000, 0b0011, 0b0011) => write!(f, "fence.tso"),
+                    _ => write!(f, "fence {}, {}", fence_set(pred), fence_set(succ)),
+                }
+            }
+            ECALL() | EBREAK() | SRET() | MRET() | WFI() => write!(f, "{name}"),
+            /* Both operands are optional, trailing zero ones are left out */
+            SFENCEVMA(0, 0) => write!(f, "{name}"),
+            SFENCEVMA(rs1, 0) => write!(f, "{name} {}", x(rs1)),
+            SFENCEVMA(rs1, rs2) => write!(f, "{name} {}, {}", x(rs1), x(rs2)),
+            /* Writes that discard the old value and reads that change nothing are shortened */
+            CSRRS(rd, 0, index) => write!(f, "csrr {}, {}", x(rd), csr(index)),
+            CSRRW(0, rs1, index) => write!(f, "csrw {}, {}", csr(index), x(rs1)),
+            CSRRS(0, rs1, index) => write!(f, "csrs {}, {}", csr(index), x(rs1)),
+            CSRRC(0, rs1, index) => write!(f, "csrc {}, {}", csr(index), x(rs1)),
+            CSRRWI(0, uimm, index) => write!(f, "csrwi {}, {uimm}", csr(index)),
+            CSRRSI(0, uimm, index) => write!(f, "csrsi {}, {uimm}", csr(index)),
+            CSRRCI(0, uimm, index) => write!(f, "csrci {}, {uimm}", csr(index)),
+            CSRRW(rd, rs1, index) | CSRRS(rd, rs1, index) | CSRRC(rd, rs1, index) => {
+                write!(f, "{name} {}, {}, {}", x(rd), csr(index), x(rs1))
+            }
+            CSRRWI(rd, uimm, index) | CSRRSI(rd, uimm, index) | CSRRCI(rd, uimm, index) => {
+                write!(f, "{name} {}, {}, {uimm}", x(rd), csr(index))
+            }
+            LRW(rd, rs1, aqrl) => write!(f, "{name}{} {}, ({})", ordering(aqrl), x(rd), x(rs1)),
+            SCW(rd, rs1, rs2, aqrl)
+            | AMOSWAPW(rd, rs1, rs2, aqrl)
+            | AMOADDW(rd, rs1, rs2, aqrl)
+            | AMOXORW(rd, rs1, rs2, aqrl)
+            | AMOANDW(rd, rs1, rs2, aqrl)
+            | AMOORW(rd, rs1, rs2, aqrl)
+            | AMOMINW(rd, rs1, rs2, aqrl)
+            | AMOMAXW(rd, rs1, rs2, aqrl)
+            | AMOMINUW(rd, rs1, rs2, aqrl)
+            | AMOMAXUW(rd, rs1, rs2, aqrl) => write!(
+                f,
+                "{name}{} {}, {}, ({})",
+                ordering(aqrl),
+                x(rd),
+                x(rs2),
+                x(rs1)
+            ),
+            FLW(rd, rs1, imm) | FLD(rd, rs1, imm) => {
+                write!(f, "{name} {}, {}({})", fp(rd), signed(imm, 12), x(rs1))
+            }
+            FSW(rs1, rs2, imm) | FSD(rs1, rs2, imm) => {
+                write!(f, "{name} {}, {}({})", fp(rs2), signed(imm, 12), x(rs1))
+            }
+            FMADDS(rd, rs1, rs2, rs3, rm)
+            | FMSUBS(rd, rs1, rs2, rs3, rm)
+            | FNMSUBS(rd, rs1, rs2, rs3, rm)
+            | FNMADDS(rd, rs1, rs2, rs3, rm)
+            | FMADDD(rd, rs1, rs2, rs3, rm)
+            | FMSUBD(rd, rs1, rs2, rs3, rm)
+            | FNMSUBD(rd, rs1, rs2, rs3, rm)
+            | FNMADDD(rd, rs1, rs2, rs3, rm) => write!(
+                f,
+                "{name} {}, {}, {}, {}{}",
+                fp(rd),
+                fp(rs1),
+                fp(rs2),
+                fp(rs3),
+                rounding(rm)
+            ),
+            FADDS(rd, rs1, rs2, rm)
+            | FSUBS(rd, rs1, rs2, rm)
+            | FMULS(rd, rs1, rs2, rm)
+            | FDIVS(rd, rs1, rs2, rm)
+            | FADDD(rd, rs1, rs2, rm)
+            | FSUBD(rd, rs1, rs2, rm)
+            | FMULD(rd, rs1, rs2, rm)
+            | FDIVD(rd, rs1, rs2, rm) => write!(
+                f,
+                "{name} {}, {}, {}{}",
+                fp(rd),
+                fp(rs1),
+                fp(rs2),
+                rounding(rm)
+            ),
+            /* Conversions to double are exact, their rounding mode doesn't matter */
+            FCVTDS(rd, rs1, _) => write!(f, "{name} {}, {}", fp(rd), fp(rs1)),
+            FCVTDW(rd, rs1, _) | FCVTDWU(rd, rs1, _) => write!(f, "{name} {}, {}", fp(rd), x(rs1)),
+            FSQRTS(rd, rs1, rm) | FSQRTD(rd, rs1, rm) | FCVTSD(rd, rs1, rm) => {
+                write!(f, "{name} {}, {}{}", fp(rd), fp(rs1), rounding(rm))
+            }
+            /* Sign injection from a register into itself moves, negates or takes the magnitude */
+            FSGNJS(rd, rs1, rs2) | FSGNJD(rd, rs1, rs2) if rs1 == rs2 => {
+                write!(f, "fmv{} {}, {}", &name[5..], fp(rd), fp(rs1))
+            }
+            FSGNJNS(rd, rs1, rs2) | FSGNJND(rd, rs1, rs2) if rs1 == rs2 => {
+                write!(f, "fneg{} {}, {}", &name[6..], fp(rd), fp(rs1))
+            }
+            FSGNJXS(rd, rs1, rs2) | FSGNJXD(rd, rs1, rs2) if rs1 == rs2 => {
+                write!(f, "fabs{} {}, {}", &name[6..], fp(rd), fp(rs1))
+            }
+            FSGNJS(rd, rs1, rs2)
+            | FSGNJNS(rd, rs1, rs2)
+            | FSGNJXS(rd, rs1, rs2)
+            | FMINS(rd, rs1, rs2)
+            | FMAXS(rd, rs1, rs2)
+            | FSGNJD(rd, rs1, rs2)
+            | FSGNJND(rd, rs1, rs2)
+            | FSGNJXD(rd, rs1, rs2)
+            | FMIND(rd, rs1, rs2)
+            | FMAXD(rd, rs1, rs2) => write!(f, "{name} {}, {}, {}", fp(rd), fp(rs1), fp(rs2)),
+            FEQS(rd, rs1, rs2)
+            | FLTS(rd, rs1, rs2)
+            | FLES(rd, rs1, rs2)
+            | FEQD(rd, rs1, rs2)
+            | FLTD(rd, rs1, rs2)
+            | FLED(rd, rs1, rs2) => write!(f, "{name} {}, {}, {}", x(rd), fp(rs1), fp(rs2)),
+            FCVTWS(rd, rs1, rm)
+            | FCVTWUS(rd, rs1, rm)
+            | FCVTWD(rd, rs1, rm)
+            | FCVTWUD(rd, rs1, rm) => write!(f, "{name} {}, {}{}", x(rd), fp(rs1), rounding(rm)),
+            FCVTSW(rd, rs1, rm) | FCVTSWU(rd, rs1, rm) => {
+                write!(f, "{name} {}, {}{}", fp(rd), x(rs1), rounding(rm))
+            }
+            FMVXW(rd, rs1) | FCLASSS(rd, rs1) | FCLASSD(rd, rs1) => {
+                write!(f, "{name} {}, {}", x(rd), fp(rs1))
+            }
+            FMVWX(rd, rs1) => write!(f, "{name} {}, {}", fp(rd), x(rs1)),
+        }
+    }
+}
+
+/// Writes an objdump-like listing of the executable sections of an ELF file, or of a
+/// whole flat binary as loaded to the start of RAM
+pub fn print_listing(data: &[u8], out: &mut dyn Write) -> anyhow::Result<()> {
+    if !data.starts_with(b"\x7fELF") {
+        listing(out, data, RAM_BASE as u32, &Symbols::default())?;
+        return Ok(());
+    }
+    let file = ElfBytes::<LittleEndian>::minimal_parse(data)?;
+    ensure!(file.ehdr.class == Class::ELF32, "Not an ELF32 file");
+    ensure!(
+        file.ehdr.e_machine == abi::EM_RISCV,
+        "Not a RISC-V ELF file"
+    );
+    let symbols = Symbols::from_elf(data)?;
+    let (sections, names) = file.section_headers_with_strtab()?;
+    let (sections, names) = sections
+        .zip(names)
+        .ok_or(anyhow!("ELF file has no section headers"))?;
+    let code = sections.iter().filter(|section| {
+        section.sh_type == abi::SHT_PROGBITS
+            && section.sh_flags & u64::from(abi::SHF_EXECINSTR) != 0
+    });
+    for section in code {
+        let name = names.get(section.sh_name as usize)?;
+        let (data, _) = file.section_data(&section)?;
+        writeln!(out, "\nDisassembly of section {name}:")?;
+        listing(out, data, u32::try_from(section.sh_addr)?, &symbols)?;
+    }
+    Ok(())
+}
+
+/// Writes a line for every instruction in `code` placed at `base`, words that don't
+/// decode are listed as data
+fn listing(out: &mut dyn Write, code: &[u8], base: u32, symbols: &Symbols) -> io::Result<()> {
+    let mut offset = 0;
+    while offset < code.len() {
+        let addr = base.wrapping_add(offset as u32);
+        if let Some(name) = symbols.at(addr) {
+            writeln!(out, "\n{addr:08X} <{name}>:")?;
+        }
+        let rest = &code[offset..];
+        let lower = match rest {
+            [low, high, ..] => u32::from(u16::from_le_bytes([*low, *high])),
+            [byte] => {
+                writeln!(out, "{addr:08X}: {byte:02X}        .byte 0x{byte:02x}")?;
+                break;
+            }
+            [] => break,
+        };
+        /* A 32-bit instruction cut off by the end of the code is left as a halfword */
+        let (raw, hex) = match rest {
+            [_, _, low, high, ..] if instruction_length(lower) == 4 => {
+                let raw = (u32::from(u16::from_le_bytes([*low, *high])) << 16) | lower;
+                (raw, format!("{raw:08X}"))
+            }
+            _ => (lower, format!("{lower:04X}")),
+        };
+        let length = hex.len() / 2;
+        match decode(raw) {
+            Ok(instruction) if length == instruction_length(raw) as usize => {
+                writeln!(
+                    out,
+                    "{addr:08X}: {hex:<8}  {}",
+                    instruction.at(addr, symbols)
+                )?;
+            }
+            _ if length == 2 => writeln!(out, "{addr:08X}: {hex:<8}  .half 0x{raw:04x}")?,
+            _ => writeln!(out, "{addr:08X}: {hex:<8}  .word 0x{raw:08x}")?,
+        }
+        offset += length;
+    }
+    Ok(())
+}
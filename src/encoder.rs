@@ -0,0 +1,224 @@
+use crate::decoder::Instruction;
+
+/// Major opcodes of the 32-bit instructions
+mod opcode {
+    pub const LOAD: u32 = 0b000_0011;
+    pub const LOAD_FP: u32 = 0b000_0111;
+    pub const MISC_MEM: u32 = 0b000_1111;
+    pub const OP_IMM: u32 = 0b001_0011;
+    pub const AUIPC: u32 = 0b001_0111;
+    pub const STORE: u32 = 0b010_0011;
+    pub const STORE_FP: u32 = 0b010_0111;
+    pub const AMO: u32 = 0b010_1111;
+    pub const OP: u32 = 0b011_0011;
+    pub const LUI: u32 = 0b011_0111;
+    pub const MADD: u32 = 0b100_0011;
+    pub const MSUB: u32 = 0b100_0111;
+    pub const NMSUB: u32 = 0b100_1011;
+    pub const NMADD: u32 = 0b100_1111;
+    pub const OP_FP: u32 = 0b101_0011;
+    pub const BRANCH: u32 = 0b110_0011;
+    pub const JALR: u32 = 0b110_0111;
+    pub const JAL: u32 = 0b110_1111;
+    pub const SYSTEM: u32 = 0b111_0011;
+}
+
+/* Immediates are taken as the raw fields the decoder produces, so none are sign extended */
+
+fn r_type(opcode: u32, rd: usize, funct3: u32, rs1: usize, rs2: usize, funct7: u32) -> u32 {
+    (funct7 << 25)
+        | ((rs2 as u32) << 20)
+        | ((rs1 as u32) << 15)
+        | (funct3 << 12)
+        | ((rd as u32) << 7)
+        | opcode
+}
+
+fn r4_type(opcode: u32, rd: usize, rm: u32, rs1: usize, rs2: usize, rs3: usize, fmt: u32) -> u32 {
+    r_type(opcode, rd, rm, rs1, rs2, ((rs3 as u32) << 2) | fmt)
+}
+
+fn i_type(opcode: u32, rd: usize, funct3: u32, rs1: usize, imm: u32) -> u32 {
+    ((imm & 0xFFF) << 20) | ((rs1 as u32) << 15) | (funct3 << 12) | ((rd as u32) << 7) | opcode
+}
+
+fn s_type(opcode: u32, funct3: u32, rs1: usize, rs2: usize, imm: u32) -> u32 {
+    (((imm >> 5) & 0x7F) << 25)
+        | ((rs2 as u32) << 20)
+        | ((rs1 as u32) << 15)
+        | (funct3 << 12)
+        | ((imm & 0x1F) << 7)
+        | opcode
+}
+
+fn b_type(funct3: u32, rs1: usize, rs2: usize, imm: u32) -> u32 {
+    (((imm >> 12) & 0b1) << 31)
+        | (((imm >> 5) & 0b11_1111) << 25)
+        | ((rs2 as u32) << 20)
+        | ((rs1 as u32) << 15)
+        | (funct3 << 12)
+        | (((imm >> 1) & 0b1111) << 8)
+        | (((imm >> 11) & 0b1) << 7)
+        | opcode::BRANCH
+}
+
+fn u_type(opcode: u32, rd: usize, imm: u32) -> u32 {
+    (imm & 0xFFFF_F000) | ((rd as u32) << 7) | opcode
+}
+
+fn j_type(rd: usize, imm: u32) -> u32 {
+    (((imm >> 20) & 0b1) << 31)
+        | (((imm >> 1) & 0b11_1111_1111) << 21)
+        | (((imm >> 11) & 0b1) << 20)
+        | (((imm >> 12) & 0b1111_1111) << 12)
+        | ((rd as u32) << 7)
+        | opcode::JAL
+}
+
+fn amo(funct5: u32, rd: usize, rs1: usize, rs2: usize, aqrl: u32) -> u32 {
+    r_type(
+        opcode::AMO,
+        rd,
+        0b010,
+        rs1,
+        rs2,
+        (funct5 << 2) | (aqrl & 0b11),
+    )
+}
+
+/// Encodes an instruction as its 32-bit form, the inverse of [`crate::decoder::decode`].
+///
+/// Compressed instructions decode to their 32-bit equivalents, so those are what comes out.
+pub fn encode(instruction: &Instruction) -> u32 {
+    use Instruction::*;
+    match *instruction {
+        /* RV32I */
+        LUI(rd, imm) => u_type(opcode::LUI, rd, imm),
+        AUIPC(rd, imm) => u_type(opcode::AUIPC, rd, imm),
+        JAL(rd, imm) => j_type(rd, imm),
+        JALR(rd, rs1, imm) => i_type(opcode::JALR, rd, 0b000, rs1, imm),
+        BEQ(rs1, rs2, imm) => b_type(0b000, rs1, rs2, imm),
+        BNE(rs1, rs2, imm) => b_type(0b001, rs1, rs2, imm),
+        BLT(rs1, rs2, imm) => b_type(0b100, rs1, rs2, imm),
+        BGE(rs1, rs2, imm) => b_type(0b101, rs1, rs2, imm),
+        BLTU(rs1, rs2, imm) => b_type(0b110, rs1, rs2, imm),
+        BGEU(rs1, rs2, imm) => b_type(0b111, rs1, rs2, imm),
+        LB(rd, rs1, imm) => i_type(opcode::LOAD, rd, 0b000, rs1, imm),
+        LH(rd, rs1, imm) => i_type(opcode::LOAD, rd, 0b001, rs1, imm),
+        LW(rd, rs1, imm) => i_type(opcode::LOAD, rd, 0b010, rs1, imm),
+        LBU(rd, rs1, imm) => i_type(opcode::LOAD, rd, 0b100, rs1, imm),
+        LHU(rd, rs1, imm) => i_type(opcode::LOAD, rd, 0b101, rs1, imm),
+        SB(rs1, rs2, imm) => s_type(opcode::STORE, 0b000, rs1, rs2, imm),
+        SH(rs1, rs2, imm) => s_type(opcode::STORE, 0b001, rs1, rs2, imm),
+        SW(rs1, rs2, imm) => s_type(opcode::STORE, 0b010, rs1, rs2, imm),
+        ADDI(rd, rs1, imm) => i_type(opcode::OP_IMM, rd, 0b000, rs1, imm),
+        SLTI(rd, rs1, imm) => i_type(opcode::OP_IMM, rd, 0b010, rs1, imm),
+        SLTIU(rd, rs1, imm) => i_type(opcode::OP_IMM, rd, 0b011, rs1, imm),
+        XORI(rd, rs1, imm) => i_type(opcode::OP_IMM, rd, 0b100, rs1, imm),
+        ORI(rd, rs1, imm) => i_type(opcode::OP_IMM, rd, 0b110, rs1, imm),
+        ANDI(rd, rs1, imm) => i_type(opcode::OP_IMM, rd, 0b111, rs1, imm),
+        /* The immediate of SRAI already holds the bit setting it apart from SRLI */
+        SLLI(rd, rs1, imm) => i_type(opcode::OP_IMM, rd, 0b001, rs1, imm),
+        SRLI(rd, rs1, imm) => i_type(opcode::OP_IMM, rd, 0b101, rs1, imm),
+        SRAI(rd, rs1, imm) => i_type(opcode::OP_IMM, rd, 0b101, rs1, imm),
+        ADD(rd, rs1, rs2) => r_type(opcode::OP, rd, 0b000, rs1, rs2, 0b000_0000),
+        SUB(rd, rs1, rs2) => r_type(opcode::OP, rd, 0b000, rs1, rs2, 0b010_0000),
+        SLL(rd, rs1, rs2) => r_type(opcode::OP, rd, 0b001, rs1, rs2, 0b000_0000),
+        SLT(rd, rs1, rs2) => r_type(opcode::OP, rd, 0b010, rs1, rs2, 0b000_0000),
+        SLTU(rd, rs1, rs2) => r_type(opcode::OP, rd, 0b011, rs1, rs2, 0b000_0000),
+        XOR(rd, rs1, rs2) => r_type(opcode::OP, rd, 0b100, rs1, rs2, 0b000_0000),
+        SRL(rd, rs1, rs2) => r_type(opcode::OP, rd, 0b101, rs1, rs2, 0b000_0000),
+        SRA(rd, rs1, rs2) => r_type(opcode::OP, rd, 0b101, rs1, rs2, 0b010_0000),
+        OR(rd, rs1, rs2) => r_type(opcode::OP, rd, 0b110, rs1, rs2, 0b000_0000),
+        AND(rd, rs1, rs2) => r_type(opcode::OP, rd, 0b111, rs1, rs2, 0b000_0000),
+        FENCE(rd, rs1, imm) => i_type(opcode::MISC_MEM, rd, 0b000, rs1, imm),
+        ECALL() => i_type(opcode::SYSTEM, 0, 0b000, 0, 0b0000_0000_0000),
+        EBREAK() => i_type(opcode::SYSTEM, 0, 0b000, 0, 0b0000_0000_0001),
+        SRET() => i_type(opcode::SYSTEM, 0, 0b000, 0, 0b0001_0000_0010),
+        MRET() => i_type(opcode::SYSTEM, 0, 0b000, 0, 0b0011_0000_0010),
+        WFI() => i_type(opcode::SYSTEM, 0, 0b000, 0, 0b0001_0000_0101),
+        SFENCEVMA(rs1, rs2) => r_type(opcode::SYSTEM, 0, 0b000, rs1, rs2, 0b000_1001),
+        /* Zicsr, the immediate forms carry their immediate in rs1 */
+        CSRRW(rd, rs1, csr) => i_type(opcode::SYSTEM, rd, 0b001, rs1, csr),
+        CSRRS(rd, rs1, csr) => i_type(opcode::SYSTEM, rd, 0b010, rs1, csr),
+        CSRRC(rd, rs1, csr) => i_type(opcode::SYSTEM, rd, 0b011, rs1, csr),
+        CSRRWI(rd, uimm, csr) => i_type(opcode::SYSTEM, rd, 0b101, uimm, csr),
+        CSRRSI(rd, uimm, csr) => i_type(opcode::SYSTEM, rd, 0b110, uimm, csr),
+        CSRRCI(rd, uimm, csr) => i_type(opcode::SYSTEM, rd, 0b111, uimm, csr),
+        /* M */
+        MUL(rd, rs1, rs2) => r_type(opcode::OP, rd, 0b000, rs1, rs2, 0b000_0001),
+        MULH(rd, rs1, rs2) => r_type(opcode::OP, rd, 0b001, rs1, rs2, 0b000_0001),
+        MULHSU(rd, rs1, rs2) => r_type(opcode::OP, rd, 0b010, rs1, rs2, 0b000_0001),
+        MULHU(rd, rs1, rs2) => r_type(opcode::OP, rd, 0b011, rs1, rs2, 0b000_0001),
+        DIV(rd, rs1, rs2) => r_type(opcode::OP, rd, 0b100, rs1, rs2, 0b000_0001),
+        DIVU(rd, rs1, rs2) => r_type(opcode::OP, rd, 0b101, rs1, rs2, 0b000_0001),
+        REM(rd, rs1, rs2) => r_type(opcode::OP, rd, 0b110, rs1, rs2, 0b000_0001),
+        REMU(rd, rs1, rs2) => r_type(opcode::OP, rd, 0b111, rs1, rs2, 0b000_0001),
+        /* A */
+        LRW(rd, rs1, aqrl) => amo(0b00010, rd, rs1, 0, aqrl),
+        SCW(rd, rs1, rs2, aqrl) => amo(0b00011, rd, rs1, rs2, aqrl),
+        AMOSWAPW(rd, rs1, rs2, aqrl) => amo(0b00001, rd, rs1, rs2, aqrl),
+        AMOADDW(rd, rs1, rs2, aqrl) => amo(0b00000, rd, rs1, rs2, aqrl),
+        AMOXORW(rd, rs1, rs2, aqrl) => amo(0b00100, rd, rs1, rs2, aqrl),
+        AMOANDW(rd, rs1, rs2, aqrl) => amo(0b01100, rd, rs1, rs2, aqrl),
+        AMOORW(rd, rs1, rs2, aqrl) => amo(0b01000, rd, rs1, rs2, aqrl),
+        AMOMINW(rd, rs1, rs2, aqrl) => amo(0b10000, rd, rs1, rs2, aqrl),
+        AMOMAXW(rd, rs1, rs2, aqrl) => amo(0b10100, rd, rs1, rs2, aqrl),
+        AMOMINUW(rd, rs1, rs2, aqrl) => amo(0b11000, rd, rs1, rs2, aqrl),
+        AMOMAXUW(rd, rs1, rs2, aqrl) => amo(0b11100, rd, rs1, rs2, aqrl),
+        /* F */
+        FLW(rd, rs1, imm) => i_type(opcode::LOAD_FP, rd, 0b010, rs1, imm),
+        FSW(rs1, rs2, imm) => s_type(opcode::STORE_FP, 0b010, rs1, rs2, imm),
+        FMADDS(rd, rs1, rs2, rs3, rm) => r4_type(opcode::MADD, rd, rm, rs1, rs2, rs3, 0b00),
+        FMSUBS(rd, rs1, rs2, rs3, rm) => r4_type(opcode::MSUB, rd, rm, rs1, rs2, rs3, 0b00),
+        FNMSUBS(rd, rs1, rs2, rs3, rm) => r4_type(opcode::NMSUB, rd, rm, rs1, rs2, rs3, 0b00),
+        FNMADDS(rd, rs1, rs2, rs3, rm) => r4_type(opcode::NMADD, rd, rm, rs1, rs2, rs3, 0b00),
+        FADDS(rd, rs1, rs2, rm) => r_type(opcode::OP_FP, rd, rm, rs1, rs2, 0b000_0000),
+        FSUBS(rd, rs1, rs2, rm) => r_type(opcode::OP_FP, rd, rm, rs1, rs2, 0b000_0100),
+        FMULS(rd, rs1, rs2, rm) => r_type(opcode::OP_FP, rd, rm, rs1, rs2, 0b000_1000),
+        FDIVS(rd, rs1, rs2, rm) => r_type(opcode::OP_FP, rd, rm, rs1, rs2, 0b000_1100),
+        FSQRTS(rd, rs1, rm) => r_type(opcode::OP_FP, rd, rm, rs1, 0, 0b010_1100),
+        FSGNJS(rd, rs1, rs2) => r_type(opcode::OP_FP, rd, 0b000, rs1, rs2, 0b001_0000),
+        FSGNJNS(rd, rs1, rs2) => r_type(opcode::OP_FP, rd, 0b001, rs1, rs2, 0b001_0000),
+        FSGNJXS(rd, rs1, rs2) => r_type(opcode::OP_FP, rd, 0b010, rs1, rs2, 0b001_0000),
+        FMINS(rd, rs1, rs2) => r_type(opcode::OP_FP, rd, 0b000, rs1, rs2, 0b001_0100),
+        FMAXS(rd, rs1, rs2) => r_type(opcode::OP_FP, rd, 0b001, rs1, rs2, 0b001_0100),
+        FCVTWS(rd, rs1, rm) => r_type(opcode::OP_FP, rd, rm, rs1, 0, 0b110_0000),
+        FCVTWUS(rd, rs1, rm) => r_type(opcode::OP_FP, rd, rm, rs1, 1, 0b110_0000),
+        FMVXW(rd, rs1) => r_type(opcode::OP_FP, rd, 0b000, rs1, 0, 0b111_0000),
+        FEQS(rd, rs1, rs2) => r_type(opcode::OP_FP, rd, 0b010, rs1, rs2, 0b101_0000),
+        FLTS(rd, rs1, rs2) => r_type(opcode::OP_FP, rd, 0b001, rs1, rs2, 0b101_0000),
+        FLES(rd, rs1, rs2) => r_type(opcode::OP_FP, rd, 0b000, rs1, rs2, 0b101_0000),
+        FCLASSS(rd, rs1) => r_type(opcode::OP_FP, rd, 0b001, rs1, 0, 0b111_0000),
+        FCVTSW(rd, rs1, rm) => r_type(opcode::OP_FP, rd, rm, rs1, 0, 0b110_1000),
+        FCVTSWU(rd, rs1, rm) => r_type(opcode::OP_FP, rd, rm, rs1, 1, 0b110_1000),
+        FMVWX(rd, rs1) => r_type(opcode::OP_FP, rd, 0b000, rs1, 0, 0b111_1000),
+        /* D */
+        FLD(rd, rs1, imm) => i_type(opcode::LOAD_FP, rd, 0b011, rs1, imm),
+        FSD(rs1, rs2, imm) => s_type(opcode::STORE_FP, 0b011, rs1, rs2, imm),
+        FMADDD(rd, rs1, rs2, rs3, rm) => r4_type(opcode::MADD, rd, rm, rs1, rs2, rs3, 0b01),
+        FMSUBD(rd, rs1, rs2, rs3, rm) => r4_type(opcode::MSUB, rd, rm, rs1, rs2, rs3, 0b01),
+        FNMSUBD(rd, rs1, rs2, rs3, rm) => r4_type(opcode::NMSUB, rd, rm, rs1, rs2, rs3, 0b01),
+        FNMADDD(rd, rs1, rs2, rs3, rm) => r4_type(opcode::NMADD, rd, rm, rs1, rs2, rs3, 0b01),
+        FADDD(rd, rs1, rs2, rm) => r_type(opcode::OP_FP, rd, rm, rs1, rs2, 0b000_0001),
+        FSUBD(rd, rs1, rs2, rm) => r_type(opcode::OP_FP, rd, rm, rs1, rs2, 0b000_0101),
+        FMULD(rd, rs1, rs2, rm) => r_type(opcode::OP_FP, rd, rm, rs1, rs2, 0b000_1001),
+        FDIVD(rd, rs1, rs2, rm) => r_type(opcode::OP_FP, rd, rm, rs1, rs2, 0b000_1101),
+        FSQRTD(rd, rs1, rm) => r_type(opcode::OP_FP, rd, rm, rs1, 0, 0b010_1101),
+        FSGNJD(rd, rs1, rs2) => r_type(opcode::OP_FP, rd, 0b000, rs1, rs2, 0b001_0001),
+        FSGNJND(rd, rs1, rs2) => r_type(opcode::OP_FP, rd, 0b001, rs1, rs2, 0b001_0001),
+        FSGNJXD(rd, rs1, rs2) => r_type(opcode::OP_FP, rd, 0b010, rs1, rs2, 0b001_0001),
+        FMIND(rd, rs1, rs2) => r_type(opcode::OP_FP, rd, 0b000, rs1, rs2, 0b001_0101),
+        FMAXD(rd, rs1, rs2) => r_type(opcode::OP_FP, rd, 0b001, rs1, rs2, 0b001_0101),
+        FCVTSD(rd, rs1, rm) => r_type(opcode::OP_FP, rd, rm, rs1, 1, 0b010_0000),
+        FCVTDS(rd, rs1, rm) => r_type(opcode::OP_FP, rd, rm, rs1, 0, 0b010_0001),
+        FEQD(rd, rs1, rs2) => r_type(opcode::OP_FP, rd, 0b010, rs1, rs2, 0b101_0001),
+        FLTD(rd, rs1, rs2) => r_type(opcode::OP_FP, rd, 0b001, rs1, rs2, 0b101_0001),
+        FLED(rd, rs1, rs2) => r_type(opcode::OP_FP, rd, 0b000, rs1, rs2, 0b101_0001),
+        FCLASSD(rd, rs1) => r_type(opcode::OP_FP, rd, 0b001, rs1, 0, 0b111_0001),
+        FCVTWD(rd, rs1, rm) => r_type(opcode::OP_FP, rd, rm, rs1, 0, 0b110_0001),
+        FCVTWUD(rd, rs1, rm) => r_type(opcode::OP_FP, rd, rm, rs1, 1, 0b110_0001),
+        FCVTDW(rd, rs1, rm) => r_type(opcode::OP_FP, rd, rm, rs1, 0, 0b110_1001),
+        FCVTDWU(rd, rs1, rm) => r_type(opcode::OP_FP, rd, rm, rs1, 1, 0b110_1001),
+    }
+}
@@ -1,5 +1,13 @@
-use crate::decoder::{Instruction, RS1value, RS2value};
-use crate::system::{Memory, RegisterFile};
+use crate::bus::{Bus, Size};
+use crate::clint::Clint;
+use crate::decoder::{decode, instruction_length, Instruction, RS1value, RS2value};
+use crate::mmu::{check_pmp, crosses_page, peek_translate, translate, AccessType};
+use crate::softfloat::{classify, FloatEnv, RoundingMode, DOUBLE, SINGLE};
+use crate::system::{
+    Event, Privilege, RegisterFile, FS_OFF, MSTATUS_FS, MSTATUS_MIE, MSTATUS_MPIE, MSTATUS_MPP,
+    MSTATUS_MPRV, MSTATUS_SIE, MSTATUS_SPIE, MSTATUS_SPP, MSTATUS_TSR, MSTATUS_TVM, MSTATUS_TW,
+};
+use crate::trap::{Exception, Interrupt};
 
 fn sign_extend(num: u32, bitnum: u32) -> u32 {
     let msb = num >> (bitnum - 1);
@@ -23,22 +31,482 @@ macro_rules! add_signed {
     }};
 }
 
+fn jump(register_file: &mut RegisterFile, target: u32) -> Result<(), Exception> {
+    /* With the C extension, instructions only need to be 2 byte aligned */
+    if !target.is_multiple_of(2) {
+        return Err(Exception::InstructionAddressMisaligned(target));
+    }
+    register_file.pc = target;
+    Ok(())
+}
+
+/// Physical address of an access at virtual `addr`.
+///
+/// `None` for an access that spans two pages which aren't contiguous in physical memory,
+/// it has to be split into bytes. Both pages are translated by then, so the bytes can't
+/// fault anymore and a page fault leaves memory unchanged.
+fn translate_access(
+    register_file: &mut RegisterFile,
+    bus: &mut Bus,
+    addr: u32,
+    size: Size,
+    access: AccessType,
+) -> Result<Option<usize>, Exception> {
+    let start = translate(register_file, bus, addr, access)?;
+    if !crosses_page(addr, size) {
+        return Ok(Some(start));
+    }
+    let last = addr.wrapping_add(size.bytes() as u32 - 1);
+    let contiguous = translate(register_file, bus, last, access)? == start + size.bytes() - 1;
+    Ok(contiguous.then_some(start))
+}
+
+/// Loads `size` bytes from the virtual address `addr`, the PMP has to permit it
+fn load(
+    register_file: &mut RegisterFile,
+    bus: &mut Bus,
+    addr: u32,
+    size: Size,
+) -> Result<u32, Exception> {
+    let fault = Exception::LoadAccessFault(addr);
+    if let Some(paddr) = translate_access(register_file, bus, addr, size, AccessType::Load)? {
+        check_pmp(register_file, addr, paddr, size.bytes(), AccessType::Load)?;
+        return bus.read(paddr, size).map_err(|_| fault);
+    }
+    (0..size.bytes() as u32).try_fold(0, |value, index| {
+        let byte_addr = addr.wrapping_add(index);
+        let paddr = translate(register_file, bus, byte_addr, AccessType::Load)?;
+        check_pmp(register_file, addr, paddr, 1, AccessType::Load)?;
+        let byte = bus.read(paddr, Size::Byte).map_err(|_| fault)?;
+        Ok(value | (byte << (8 * index)))
+    })
+}
+
+/// Physical destination of a store, see `store_target`
+enum StoreTarget {
+    Whole(usize),
+    /// One address per byte, for an access split across pages that aren't contiguous
+    Bytes([usize; 4]),
+}
+
+/// Translates a store of `size` bytes to the virtual address `addr`, the PMP has to
+/// permit it and the bus has to map it.
+///
+/// Every byte is checked before the first one is written, so a store that faults leaves
+/// memory unchanged. FSD checks both of its words this way before writing either.
+fn store_target(
+    register_file: &mut RegisterFile,
+    bus: &mut Bus,
+    addr: u32,
+    size: Size,
+) -> Result<StoreTarget, Exception> {
+    let fault = Exception::StoreAccessFault(addr);
+    if let Some(paddr) = translate_access(register_file, bus, addr, size, AccessType::Store)? {
+        check_pmp(register_file, addr, paddr, size.bytes(), AccessType::Store)?;
+        return bus
+            .maps(paddr, size)
+            .then_some(StoreTarget::Whole(paddr))
+            .ok_or(fault);
+    }
+    let mut paddrs = [0; 4];
+    for (index, paddr) in paddrs.iter_mut().enumerate().take(size.bytes()) {
+        let byte_addr = addr.wrapping_add(index as u32);
+        *paddr = translate(register_file, bus, byte_addr, AccessType::Store)?;
+        check_pmp(register_file, addr, *paddr, 1, AccessType::Store)?;
+        if !bus.maps(*paddr, Size::Byte) {
+            return Err(fault);
+        }
+    }
+    Ok(StoreTarget::Bytes(paddrs))
+}
+
+/// Writes the lower `size` bytes of `value` to a `target` of the store to `addr`
+fn store_to(
+    bus: &mut Bus,
+    addr: u32,
+    target: StoreTarget,
+    size: Size,
+    value: u32,
+) -> Result<(), Exception> {
+    let fault = Exception::StoreAccessFault(addr);
+    match target {
+        StoreTarget::Whole(paddr) => bus.write(paddr, size, value).map_err(|_| fault),
+        StoreTarget::Bytes(paddrs) => {
+            paddrs[..size.bytes()]
+                .iter()
+                .enumerate()
+                .try_for_each(|(index, &paddr)| {
+                    bus.write(paddr, Size::Byte, (value >> (8 * index)) & 0xFF)
+                        .map_err(|_| fault)
+                })
+        }
+    }
+}
+
+/// Stores the lower `size` bytes of `value` to the virtual address `addr`, the PMP has to
+/// permit it
+fn store(
+    register_file: &mut RegisterFile,
+    bus: &mut Bus,
+    addr: u32,
+    size: Size,
+    value: u32,
+) -> Result<(), Exception> {
+    let target = store_target(register_file, bus, addr, size)?;
+    store_to(bus, addr, target, size, value)
+}
+
+/// Atomic read-modify-write of the word at `rs1`, `rd` receives the old value
+fn amo(
+    register_file: &mut RegisterFile,
+    bus: &mut Bus,
+    rdindex: usize,
+    rs1index: usize,
+    rs2index: usize,
+    op: impl Fn(u32, u32) -> u32,
+) -> Result<(), Exception> {
+    let addr: RS1value = register_file.read(rs1index);
+    let rs2: RS2value = register_file.read(rs2index);
+    if !addr.is_multiple_of(4) {
+        return Err(Exception::StoreAddressMisaligned(addr));
+    }
+    /* AMOs report faults of their load part as store faults */
+    let fault = Exception::StoreAccessFault(addr);
+    let paddr = translate(register_file, bus, addr, AccessType::Store)?;
+    check_pmp(register_file, addr, paddr, 4, AccessType::Store)?;
+    let value = bus.read_word(paddr).map_err(|_| fault)?;
+    bus.write_word(paddr, op(value, rs2)).map_err(|_| fault)?;
+    register_file.write(rdindex, value);
+    Ok(())
+}
+
+/// Resolves the rounding mode of an instruction, `DYN` defers to `frm`
+fn rounding_mode(register_file: &RegisterFile, rm: u32) -> Result<RoundingMode, Exception> {
+    let rm = if rm == 0b111 {
+        (register_file.csr.fcsr >> 5) & 0b111
+    } else {
+        rm
+    };
+    RoundingMode::from_bits(rm).ok_or(Exception::IllegalInstruction(0))
+}
+
+/// Runs a floating-point operation and accrues its exception flags into `fflags`
+fn float_op<T>(
+    register_file: &mut RegisterFile,
+    rm: u32,
+    op: impl FnOnce(&mut FloatEnv) -> T,
+) -> Result<T, Exception> {
+    let mut env = FloatEnv::new(rounding_mode(register_file, rm)?);
+    let result = op(&mut env);
+    if env.flags != 0 {
+        register_file.csr.fcsr |= env.flags;
+        register_file.csr.fp_written();
+    }
+    Ok(result)
+}
+
+/* Operations without a rounding mode field still need one to construct a FloatEnv */
+const RM_NONE: u32 = 0b000;
+const SIGN_SINGLE: u64 = 1 << 31;
+const SIGN_DOUBLE: u64 = 1 << 63;
+
+/// Enters the trap handler for the given `cause` and trap value.
+///
+/// Traps from supervisor or user mode whose cause is delegated in `medeleg` or `mideleg`
+/// are taken in supervisor mode at `stvec`, all others in machine mode at `mtvec`.
+/// The interrupt enable of the new mode is stacked into its xPIE and the previous privilege
+/// into xPP, so the handler runs with interrupts disabled until `SRET` or `MRET`.
+/// In Vectored mode interrupts jump to `base + 4 * cause`.
+fn enter_trap(register_file: &mut RegisterFile, cause: u32, value: u32) {
+    let csr = &mut register_file.csr;
+    let interrupt = cause & (1 << 31) != 0;
+    let code = cause & !(1 << 31);
+    let delegation = if interrupt { csr.mideleg } else { csr.medeleg };
+    let previous = register_file.privilege;
+
+    let tvec = if previous <= Privilege::Supervisor && delegation & (1 << code) != 0 {
+        csr.sepc = register_file.pc;
+        csr.scause = cause;
+        csr.stval = value;
+        let sie = csr.mstatus & MSTATUS_SIE != 0;
+        csr.mstatus &= !(MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP);
+        if sie {
+            csr.mstatus |= MSTATUS_SPIE;
+        }
+        if previous == Privilege::Supervisor {
+            csr.mstatus |= MSTATUS_SPP;
+        }
+        register_file.privilege = Privilege::Supervisor;
+        csr.stvec
+    } else {
+        csr.mepc = register_file.pc;
+        csr.mcause = cause;
+        csr.mtval = value;
+        let mie = csr.mstatus & MSTATUS_MIE != 0;
+        csr.mstatus &= !(MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP);
+        if mie {
+            csr.mstatus |= MSTATUS_MPIE;
+        }
+        csr.mstatus |= (previous as u32) << 11;
+        register_file.privilege = Privilege::Machine;
+        csr.mtvec
+    };
+
+    let base = tvec & !0b11;
+    let vectored = tvec & 0b11 == 0b01;
+    register_file.pc = if vectored && interrupt {
+        base.wrapping_add(4 * code)
+    } else {
+        base
+    };
+    register_file.reservation = None;
+}
+
+/// Takes a trap into machine mode or the supervisor mode it's delegated to.
+pub fn trap(register_file: &mut RegisterFile, exception: Exception) {
+    enter_trap(register_file, exception.cause(), exception.value());
+}
+
+/// The interrupt that is taken before the next instruction, if any.
+///
+/// An interrupt needs to be both pending in `mip` and enabled in `mie`. Interrupts that
+/// are not delegated always preempt lower privilege levels and interrupt machine mode
+/// while `mstatus.MIE` is set. Delegated interrupts never interrupt machine mode, they
+/// preempt user mode and interrupt supervisor mode while `mstatus.SIE` is set.
+/// Machine interrupts take priority over supervisor ones.
+pub fn pending_interrupt(register_file: &RegisterFile) -> Option<Interrupt> {
+    let csr = &register_file.csr;
+    let privilege = register_file.privilege;
+    let pending = csr.mip & csr.mie;
+    let machine_enabled = privilege < Privilege::Machine || csr.mstatus & MSTATUS_MIE != 0;
+    let supervisor_enabled = privilege < Privilege::Supervisor
+        || (privilege == Privilege::Supervisor && csr.mstatus & MSTATUS_SIE != 0);
+
+    let machine = if machine_enabled {
+        pending & !csr.mideleg
+    } else {
+        0
+    };
+    let supervisor = if supervisor_enabled {
+        pending & csr.mideleg
+    } else {
+        0
+    };
+    let enabled = if machine != 0 { machine } else { supervisor };
+    Interrupt::PRIORITY
+        .into_iter()
+        .find(|interrupt| enabled & interrupt.mask() != 0)
+}
+
+/// Checks that the current privilege level may access the CSR with the given number.
+///
+/// Bits 9:8 of the number are the lowest privilege level that can access it, `satp` is
+/// reserved to machine mode while `mstatus.TVM` is set. The unprivileged counters are only
+/// accessible to lower levels when enabled in `mcounteren`, and for user mode also
+/// in `scounteren`. The floating-point CSRs are not accessible while `mstatus.FS` is Off.
+fn check_csr_access(register_file: &RegisterFile, index: u32) -> Result<(), Exception> {
+    let csr = &register_file.csr;
+    let privilege = register_file.privilege;
+    let trapped_satp =
+        index == 0x180 && privilege == Privilege::Supervisor && csr.mstatus & MSTATUS_TVM != 0;
+    let counter_enabled = match (index, privilege) {
+        (0xC00..=0xC1F | 0xC80..=0xC9F, Privilege::Supervisor) => {
+            csr.mcounteren & (1 << (index & 0x1F)) != 0
+        }
+        (0xC00..=0xC1F | 0xC80..=0xC9F, Privilege::User) => {
+            csr.mcounteren & csr.scounteren & (1 << (index & 0x1F)) != 0
+        }
+        _ => true,
+    };
+    let fp_off = (0x001..=0x003).contains(&index) && csr.mstatus & MSTATUS_FS == FS_OFF;
+    if (privilege as u32) < (index >> 8) & 0b11 || trapped_satp || !counter_enabled || fp_off {
+        return Err(Exception::IllegalInstruction(0));
+    }
+    Ok(())
+}
+
+/// Reads the instruction at `addr` one halfword at a time, it is either 16 or 32 bit long.
+///
+/// The upper halfword is only read for 32 bit instructions, a compressed
+/// instruction at the very end of memory or of a page can still be fetched.
+fn fetch_from(
+    addr: u32,
+    mut halfword: impl FnMut(u32) -> Result<u32, Exception>,
+) -> Result<u32, Exception> {
+    let lower = halfword(addr)?;
+    if instruction_length(lower) == 2 {
+        return Ok(lower);
+    }
+    let upper = halfword(addr.wrapping_add(2))?;
+    Ok((upper << 16) | lower)
+}
+
+/// Reads the instruction at the virtual address `addr` for the debugger.
+///
+/// Fetches peek at the bus, so code can only run from devices without read side effects
+/// like RAM and ROM. This doesn't change the TLB or the page table either.
+pub fn fetch(register_file: &RegisterFile, bus: &Bus, addr: u32) -> Result<u32, Exception> {
+    fetch_from(addr, |addr| {
+        let paddr = peek_translate(register_file, bus, addr)
+            .ok_or(Exception::InstructionPageFault(addr))?;
+        bus.peek(paddr, Size::Halfword)
+            .ok_or(Exception::InstructionAccessFault(addr))
+    })
+}
+
+/// Whether the next step executes an `EBREAK`, and doesn't take an interrupt or fault first
+pub fn at_ebreak(register_file: &RegisterFile, bus: &Bus) -> bool {
+    let raw = fetch_from(register_file.pc, |addr| {
+        let paddr = peek_translate(register_file, bus, addr)
+            .ok_or(Exception::InstructionPageFault(addr))?;
+        check_pmp(register_file, addr, paddr, 2, AccessType::Fetch)?;
+        bus.peek(paddr, Size::Halfword)
+            .ok_or(Exception::InstructionAccessFault(addr))
+    });
+    pending_interrupt(register_file).is_none() && raw.and_then(decode) == Ok(Instruction::EBREAK())
+}
+
+/// Events of a retired instruction for the hardware performance counters
+fn events(instruction: &Instruction, taken: bool) -> u32 {
+    match instruction {
+        Instruction::LB(..)
+        | Instruction::LH(..)
+        | Instruction::LW(..)
+        | Instruction::LBU(..)
+        | Instruction::LHU(..)
+        | Instruction::LRW(..)
+        | Instruction::FLW(..)
+        | Instruction::FLD(..) => Event::Load.mask(),
+        Instruction::SB(..)
+        | Instruction::SH(..)
+        | Instruction::SW(..)
+        | Instruction::SCW(..)
+        | Instruction::FSW(..)
+        | Instruction::FSD(..) => Event::Store.mask(),
+        Instruction::BEQ(..)
+        | Instruction::BNE(..)
+        | Instruction::BLT(..)
+        | Instruction::BGE(..)
+        | Instruction::BLTU(..)
+        | Instruction::BGEU(..) => {
+            if taken {
+                Event::Branch.mask() | Event::BranchTaken.mask()
+            } else {
+                Event::Branch.mask()
+            }
+        }
+        Instruction::JAL(..) | Instruction::JALR(..) => Event::Jump.mask(),
+        _ if instruction.is_a() => Event::Load.mask() | Event::Store.mask(),
+        _ => 0,
+    }
+}
+
+/// Fetches, decodes and executes the instruction at `pc`.
+///
+/// Any exception raised on the way is taken as trap and then returned to the caller,
+/// including the breakpoint exception of `EBREAK`. A debugger that wants to halt at an
+/// `EBREAK` instead checks `at_ebreak` before the step.
+///
+/// Every other step advances the counters, `instret` only counts instructions that retired.
+/// A pending interrupt is taken instead of executing an instruction, the step then ends
+/// at the start of its handler. Devices advance after every step and raise their
+/// interrupts into `mip` for the next one.
+pub fn step(register_file: &mut RegisterFile, bus: &mut Bus) -> Result<(), Exception> {
+    let result = match pending_interrupt(register_file) {
+        Some(interrupt) => {
+            enter_trap(register_file, (1 << 31) | interrupt.cause(), 0);
+            register_file.csr.count(false, Event::Trap.mask());
+            Ok(false)
+        }
+        None => execute(register_file, bus).map(|()| true),
+    };
+
+    bus.tick(result == Ok(true));
+    if let Some(clint) = bus.device::<Clint>() {
+        register_file.csr.time = clint.mtime();
+    }
+    let device_interrupts = Interrupt::MachineSoftware.mask()
+        | Interrupt::MachineTimer.mask()
+        | Interrupt::MachineExternal.mask()
+        | Interrupt::SupervisorExternal.mask();
+    register_file.csr.mip = (register_file.csr.mip & !device_interrupts) | bus.interrupts();
+
+    result.map(|_| ())
+}
+
+/// Executes the instruction at `pc` and takes the trap of any exception it raises
+fn execute(register_file: &mut RegisterFile, bus: &mut Bus) -> Result<(), Exception> {
+    let pc = register_file.pc;
+    let mut retired_events = 0;
+    let result = fetch_from(pc, |addr| {
+        let paddr = translate(register_file, bus, addr, AccessType::Fetch)?;
+        check_pmp(register_file, addr, paddr, 2, AccessType::Fetch)?;
+        bus.peek(paddr, Size::Halfword)
+            .ok_or(Exception::InstructionAccessFault(addr))
+    })
+    .and_then(|raw| {
+        let instruction = decode(raw)?;
+        let length = instruction_length(raw);
+        exec(register_file, bus, &instruction, length, Extensions::ALL)?;
+        retired_events = events(&instruction, register_file.pc != pc.wrapping_add(length));
+        Ok(())
+    });
+
+    match result {
+        Ok(()) => register_file.csr.count(true, retired_events),
+        Err(exception) => {
+            trap(register_file, exception);
+            register_file.csr.count(false, Event::Trap.mask());
+        }
+    }
+    result
+}
+
+/// Extensions `exec` implements, instructions of the others are illegal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extensions {
+    pub zicsr: bool,
+    pub m: bool,
+    pub a: bool,
+    pub f: bool,
+    pub d: bool,
+}
+
+impl Extensions {
+    pub const ALL: Self = Self {
+        zicsr: true,
+        m: true,
+        a: true,
+        f: true,
+        d: true,
+    };
+
+    /// Whether `instruction` belongs to an enabled extension or the base ISA
+    fn allow(self, instruction: &Instruction) -> bool {
+        (self.zicsr || !instruction.is_zicsr())
+            && (self.m || !instruction.is_m())
+            && (self.a || !instruction.is_a())
+            && (self.f || !instruction.is_f())
+            && (self.d || !instruction.is_d())
+    }
+}
+
 #[allow(clippy::too_many_lines)]
 pub fn exec(
     register_file: &mut RegisterFile,
-    memory: &mut Memory,
+    bus: &mut Bus,
     instruction: &Instruction,
-    zicsr_enabled: bool,
-    m_enabled: bool,
-) -> bool {
-    assert!(
-        !instruction.is_zicsr() || zicsr_enabled,
-        "Zicsr instruction found but zicsr is not enabled."
-    );
-    assert!(
-        !instruction.is_m() || m_enabled,
-        "M instruction found but M is not enabled."
-    );
+    instruction_len: u32,
+    extensions: Extensions,
+) -> Result<(), Exception> {
+    if !extensions.allow(instruction) {
+        return Err(Exception::IllegalInstruction(0));
+    }
+    /* With the FPU off in mstatus.FS, floating-point instructions are illegal */
+    let fs_off = register_file.csr.mstatus & MSTATUS_FS == FS_OFF;
+    if fs_off && (instruction.is_f() || instruction.is_d()) {
+        return Err(Exception::IllegalInstruction(0));
+    }
 
     match *instruction {
         Instruction::LUI(rdindex, uimmediate) => {
@@ -48,158 +516,125 @@ pub fn exec(
             register_file.write(rdindex, register_file.pc.wrapping_add(uimmediate));
         }
         Instruction::JAL(rdindex, jimmediate) => {
-            let sign_imm = sign_extend(jimmediate, 20) as i32;
-            register_file.write(rdindex, register_file.pc + 4);
-            assert!(
-                (add_signed!(register_file.pc, sign_imm) % 4) == 0,
-                "JAL target addr not 4 byte aligned."
-            );
-            register_file.pc = add_signed!(register_file.pc, sign_imm);
-            return true;
+            let sign_imm = sign_extend(jimmediate, 21) as i32;
+            let return_addr = register_file.pc.wrapping_add(instruction_len);
+            jump(register_file, add_signed!(register_file.pc, sign_imm))?;
+            register_file.write(rdindex, return_addr);
+            return Ok(());
         }
         Instruction::JALR(rdindex, rs1index, iimmediate) => {
             let rs1: RS1value = register_file.read(rs1index);
             let sign_imm = sign_extend(iimmediate, 12) as i32;
             let target = add_signed!(rs1, sign_imm) & !0b1;
-            assert!(target % 4 == 0, "JALR target addr not 4 byte aligned.");
-            register_file.write(rdindex, register_file.pc + 4);
-            register_file.pc = target;
-            return true;
+            let return_addr = register_file.pc.wrapping_add(instruction_len);
+            jump(register_file, target)?;
+            register_file.write(rdindex, return_addr);
+            return Ok(());
         }
         Instruction::BEQ(rs1index, rs2index, bimmediate) => {
             let rs1: RS1value = register_file.read(rs1index);
             let rs2: RS2value = register_file.read(rs2index);
-            let sign_imm = sign_extend(bimmediate, 12) as i32;
+            let sign_imm = sign_extend(bimmediate, 13) as i32;
             if rs1 == rs2 {
-                assert!(
-                    (add_signed!(register_file.pc, sign_imm) % 4) == 0,
-                    "Branch target addr not 4 byte aligned."
-                );
-                register_file.pc = add_signed!(register_file.pc, sign_imm);
-                return true;
+                return jump(register_file, add_signed!(register_file.pc, sign_imm));
             }
         }
         Instruction::BNE(rs1index, rs2index, bimmediate) => {
             let rs1: RS1value = register_file.read(rs1index);
             let rs2: RS2value = register_file.read(rs2index);
-            let sign_imm = sign_extend(bimmediate, 12) as i32;
+            let sign_imm = sign_extend(bimmediate, 13) as i32;
             if rs1 != rs2 {
-                assert!(
-                    (add_signed!(register_file.pc, sign_imm) % 4) == 0,
-                    "Branch target addr not 4 byte aligned."
-                );
-                register_file.pc = add_signed!(register_file.pc, sign_imm);
-                return true;
+                return jump(register_file, add_signed!(register_file.pc, sign_imm));
             }
         }
         Instruction::BLT(rs1index, rs2index, bimmediate) => {
             let rs1: RS1value = register_file.read(rs1index);
             let rs2: RS2value = register_file.read(rs2index);
-            let sign_imm = sign_extend(bimmediate, 12) as i32;
+            let sign_imm = sign_extend(bimmediate, 13) as i32;
             if (rs1 as i32) < (rs2 as i32) {
-                assert!(
-                    (add_signed!(register_file.pc, sign_imm) % 4) == 0,
-                    "Branch target addr not 4 byte aligned."
-                );
-                register_file.pc = add_signed!(register_file.pc, sign_imm);
-                return true;
+                return jump(register_file, add_signed!(register_file.pc, sign_imm));
             }
         }
         Instruction::BGE(rs1index, rs2index, bimmediate) => {
             let rs1: RS1value = register_file.read(rs1index);
             let rs2: RS2value = register_file.read(rs2index);
-            let sign_imm = sign_extend(bimmediate, 12) as i32;
+            let sign_imm = sign_extend(bimmediate, 13) as i32;
             if (rs1 as i32) >= (rs2 as i32) {
-                assert!(
-                    (add_signed!(register_file.pc, sign_imm) % 4) == 0,
-                    "Branch target addr not 4 byte aligned."
-                );
-                register_file.pc = add_signed!(register_file.pc, sign_imm);
-                return true;
+                return jump(register_file, add_signed!(register_file.pc, sign_imm));
             }
         }
         Instruction::BLTU(rs1index, rs2index, bimmediate) => {
             let rs1: RS1value = register_file.read(rs1index);
             let rs2: RS2value = register_file.read(rs2index);
-            let sign_imm = sign_extend(bimmediate, 12) as i32;
+            let sign_imm = sign_extend(bimmediate, 13) as i32;
             if rs1 < rs2 {
-                assert!(
-                    (add_signed!(register_file.pc, sign_imm) % 4) == 0,
-                    "Branch target addr not 4 byte aligned."
-                );
-                register_file.pc = add_signed!(register_file.pc, sign_imm);
-                return true;
+                return jump(register_file, add_signed!(register_file.pc, sign_imm));
             }
         }
         Instruction::BGEU(rs1index, rs2index, bimmediate) => {
             let rs1: RS1value = register_file.read(rs1index);
             let rs2: RS2value = register_file.read(rs2index);
-            let sign_imm = sign_extend(bimmediate, 12) as i32;
+            let sign_imm = sign_extend(bimmediate, 13) as i32;
             if rs1 >= rs2 {
-                assert!(
-                    (add_signed!(register_file.pc, sign_imm) % 4) == 0,
-                    "Branch target addr not 4 byte aligned."
-                );
-                register_file.pc = add_signed!(register_file.pc, sign_imm);
-                return true;
+                return jump(register_file, add_signed!(register_file.pc, sign_imm));
             }
         }
         Instruction::LB(rdindex, rs1index, iimmediate) => {
             let rs1: RS1value = register_file.read(rs1index);
             let sign_imm = sign_extend(iimmediate, 12) as i32;
-            let target = add_signed!(rs1, sign_imm) as usize;
-            let value = sign_extend(memory.read_byte(target), 8);
+            let target = add_signed!(rs1, sign_imm);
+            let value = sign_extend(load(register_file, bus, target, Size::Byte)?, 8);
             register_file.write(rdindex, value);
         }
         Instruction::LH(rdindex, rs1index, iimmediate) => {
             let rs1: RS1value = register_file.read(rs1index);
             let sign_imm = sign_extend(iimmediate, 12) as i32;
-            let target = add_signed!(rs1, sign_imm) as usize;
-            let value = sign_extend(memory.read_halfword(target), 16);
+            let target = add_signed!(rs1, sign_imm);
+            let value = sign_extend(load(register_file, bus, target, Size::Halfword)?, 16);
             register_file.write(rdindex, value);
         }
         Instruction::LW(rdindex, rs1index, iimmediate) => {
             let rs1: RS1value = register_file.read(rs1index);
             let sign_imm = sign_extend(iimmediate, 12) as i32;
-            let target = add_signed!(rs1, sign_imm) as usize;
-            let value = memory.read_word(target);
+            let target = add_signed!(rs1, sign_imm);
+            let value = load(register_file, bus, target, Size::Word)?;
             register_file.write(rdindex, value);
         }
         Instruction::LBU(rdindex, rs1index, iimmediate) => {
             let rs1: RS1value = register_file.read(rs1index);
             let sign_imm = sign_extend(iimmediate, 12) as i32;
-            let target = add_signed!(rs1, sign_imm) as usize;
-            let value = memory.read_byte(target);
+            let target = add_signed!(rs1, sign_imm);
+            let value = load(register_file, bus, target, Size::Byte)?;
             register_file.write(rdindex, value);
         }
         Instruction::LHU(rdindex, rs1index, iimmediate) => {
             let rs1: RS1value = register_file.read(rs1index);
             let sign_imm = sign_extend(iimmediate, 12) as i32;
-            let target = add_signed!(rs1, sign_imm) as usize;
-            let value = memory.read_halfword(target);
+            let target = add_signed!(rs1, sign_imm);
+            let value = load(register_file, bus, target, Size::Halfword)?;
             register_file.write(rdindex, value);
         }
         Instruction::SB(rs1index, rs2index, simmediate) => {
             let rs1: RS1value = register_file.read(rs1index);
             let rs2: RS2value = register_file.read(rs2index);
             let sign_imm = sign_extend(simmediate, 12) as i32;
-            let target = add_signed!(rs1, sign_imm) as usize;
-            memory.write_byte(target, rs2);
+            let target = add_signed!(rs1, sign_imm);
+            store(register_file, bus, target, Size::Byte, rs2)?;
         }
         Instruction::SH(rs1index, rs2index, simmediate) => {
             let rs1: RS1value = register_file.read(rs1index);
             let rs2: RS2value = register_file.read(rs2index);
             let sign_imm = sign_extend(simmediate, 12) as i32;
-            let target = add_signed!(rs1, sign_imm) as usize;
-            memory.write_halfword(target, rs2);
+            let target = add_signed!(rs1, sign_imm);
+            store(register_file, bus, target, Size::Halfword, rs2)?;
         }
         Instruction::SW(rs1index, rs2index, simmediate) => {
             let rs1: RS1value = register_file.read(rs1index);
             let rs2: RS2value = register_file.read(rs2index);
             let sign_imm = sign_extend(simmediate, 12) as i32;
-            let target = add_signed!(rs1, sign_imm) as usize;
+            let target = add_signed!(rs1, sign_imm);
             //println!("{:}, {:}, {:}", rs1, rs2, sign_imm);
-            memory.write_word(target, rs2);
+            store(register_file, bus, target, Size::Word, rs2)?;
         }
         Instruction::ADDI(rdindex, rs1index, iimmediate) => {
             let rs1: RS1value = register_file.read(rs1index);
@@ -314,65 +749,144 @@ pub fn exec(
         }
         Instruction::FENCE(_rdindex, _rs1index, _iimmediate) => { /* Nop */ }
         Instruction::ECALL() => {
-            register_file.csr.mepc = register_file.pc;
-            register_file.csr.mcause = 11; /* Environment call from M-Mode */
-            register_file.pc = register_file.csr.mtvec;
+            return Err(match register_file.privilege {
+                Privilege::User => Exception::EnvironmentCallFromUMode,
+                Privilege::Supervisor => Exception::EnvironmentCallFromSMode,
+                Privilege::Machine => Exception::EnvironmentCallFromMMode,
+            });
         }
         Instruction::EBREAK() => {
-            return false;
+            return Err(Exception::Breakpoint(register_file.pc));
+        }
+        Instruction::SRET() => {
+            /* mstatus.TSR traps SRET so machine mode can emulate it */
+            let tsr = register_file.csr.mstatus & MSTATUS_TSR != 0;
+            if register_file.privilege < Privilege::Supervisor
+                || (register_file.privilege == Privilege::Supervisor && tsr)
+            {
+                return Err(Exception::IllegalInstruction(0));
+            }
+            let csr = &mut register_file.csr;
+            let spie = csr.mstatus & MSTATUS_SPIE != 0;
+            register_file.privilege = if csr.mstatus & MSTATUS_SPP != 0 {
+                Privilege::Supervisor
+            } else {
+                Privilege::User
+            };
+            /* SPP is left at the lowest privilege level, user mode. Returning below machine
+             * mode also ends any modified data privilege */
+            csr.mstatus =
+                (csr.mstatus & !(MSTATUS_SIE | MSTATUS_SPP | MSTATUS_MPRV)) | MSTATUS_SPIE;
+            if spie {
+                csr.mstatus |= MSTATUS_SIE;
+            }
+            register_file.pc = csr.sepc;
+            return Ok(());
         }
         Instruction::MRET() => {
-            register_file.pc = register_file.csr.mepc;
+            if register_file.privilege < Privilege::Machine {
+                return Err(Exception::IllegalInstruction(0));
+            }
+            let csr = &mut register_file.csr;
+            let mpie = csr.mstatus & MSTATUS_MPIE != 0;
+            /* MPP only ever holds a valid privilege level */
+            register_file.privilege =
+                Privilege::from_bits((csr.mstatus & MSTATUS_MPP) >> 11).unwrap();
+            /* MPP is left at the lowest privilege level, user mode */
+            csr.mstatus = (csr.mstatus & !(MSTATUS_MIE | MSTATUS_MPP)) | MSTATUS_MPIE;
+            if register_file.privilege != Privilege::Machine {
+                csr.mstatus &= !MSTATUS_MPRV;
+            }
+            if mpie {
+                csr.mstatus |= MSTATUS_MIE;
+            }
+            register_file.pc = csr.mepc;
+            return Ok(());
         }
-        Instruction::CSRRW(rd_index, rs1, i_imm) => {
-            if rd_index != 0 {
-                register_file.write(rd_index, register_file.csr.read(i_imm));
-                register_file.csr.write(i_imm, register_file.read(rs1));
+        Instruction::WFI() => {
+            /* Lower levels may not stall the hart, mstatus.TW extends this to supervisor mode */
+            let tw = register_file.csr.mstatus & MSTATUS_TW != 0;
+            if register_file.privilege == Privilege::User
+                || (register_file.privilege == Privilege::Supervisor && tw)
+            {
+                return Err(Exception::IllegalInstruction(0));
             }
+            /* Nop, interrupts are checked before every instruction anyway */
         }
-        Instruction::CSRRS(rd_index, rs1, i_imm) => {
-            let csr_value = register_file.csr.read(i_imm);
+        Instruction::SFENCEVMA(rs1index, _rs2index) => {
+            /* mstatus.TVM traps it like satp, ASIDs are not implemented so rs2 doesn't matter */
+            let tvm = register_file.csr.mstatus & MSTATUS_TVM != 0;
+            if register_file.privilege == Privilege::User
+                || (register_file.privilege == Privilege::Supervisor && tvm)
+            {
+                return Err(Exception::IllegalInstruction(0));
+            }
+            let addr = (rs1index != 0).then(|| register_file.read(rs1index));
+            register_file.tlb.flush(addr);
+        }
+        Instruction::CSRRW(rd_index, rs1, i_imm) => {
+            check_csr_access(register_file, i_imm)?;
+            let rs1_value = register_file.read(rs1);
+            /* CSRRW with rd = x0 shall not read the CSR */
+            let csr_value = if rd_index == 0 {
+                0
+            } else {
+                register_file.csr.read(i_imm)?
+            };
+            register_file.csr.write(i_imm, rs1_value)?;
             register_file.write(rd_index, csr_value);
+        }
+        Instruction::CSRRS(rd_index, rs1, i_imm) => {
+            check_csr_access(register_file, i_imm)?;
+            let csr_value = register_file.csr.read(i_imm)?;
             if rs1 != 0 {
                 register_file
                     .csr
-                    .write(i_imm, register_file.read(rs1) | csr_value);
+                    .write(i_imm, register_file.read(rs1) | csr_value)?;
             }
+            register_file.write(rd_index, csr_value);
         }
         Instruction::CSRRC(rd_index, rs1, i_imm) => {
-            let csr_value = register_file.csr.read(i_imm);
-            register_file.write(rd_index, csr_value);
+            check_csr_access(register_file, i_imm)?;
+            let csr_value = register_file.csr.read(i_imm)?;
             if rs1 != 0 {
                 register_file
                     .csr
-                    .write(i_imm, !register_file.read(rs1) & csr_value);
+                    .write(i_imm, !register_file.read(rs1) & csr_value)?;
             }
+            register_file.write(rd_index, csr_value);
         }
         Instruction::CSRRWI(rd_index, rs1, i_imm) => {
+            check_csr_access(register_file, i_imm)?;
             /* rs1 is actual an immediate */
             let uimm = u32::try_from(rs1).unwrap();
-            if rd_index != 0 {
-                register_file.write(rd_index, register_file.csr.read(i_imm));
-            }
-            register_file.csr.write(i_imm, uimm);
+            let csr_value = if rd_index == 0 {
+                0
+            } else {
+                register_file.csr.read(i_imm)?
+            };
+            register_file.csr.write(i_imm, uimm)?;
+            register_file.write(rd_index, csr_value);
         }
         Instruction::CSRRSI(rd_index, rs1, i_imm) => {
+            check_csr_access(register_file, i_imm)?;
             /* rs1 is actual an immediate */
             let uimm = u32::try_from(rs1).unwrap();
-            let csr_value = register_file.csr.read(i_imm);
-            register_file.write(rd_index, csr_value);
+            let csr_value = register_file.csr.read(i_imm)?;
             if uimm != 0 {
-                register_file.csr.write(i_imm, uimm | csr_value);
+                register_file.csr.write(i_imm, uimm | csr_value)?;
             }
+            register_file.write(rd_index, csr_value);
         }
         Instruction::CSRRCI(rd_index, rs1, i_imm) => {
+            check_csr_access(register_file, i_imm)?;
             /* rs1 is actual an immediate */
             let uimm = u32::try_from(rs1).unwrap();
-            let csr_value = register_file.csr.read(i_imm);
-            register_file.write(rd_index, csr_value);
+            let csr_value = register_file.csr.read(i_imm)?;
             if uimm != 0 {
-                register_file.csr.write(i_imm, !uimm & csr_value);
+                register_file.csr.write(i_imm, !uimm & csr_value)?;
             }
+            register_file.write(rd_index, csr_value);
         }
         Instruction::MUL(rdindex, rs1index, rs2index) => {
             let _rs1: RS1value = register_file.read(rs1index);
@@ -381,19 +895,21 @@ pub fn exec(
             // For this reason, the multiplication is done on 64-bit numbers and then typecasted.
             let _rs1_64 = _rs1 as u64;
             let _rs2_64 = _rs2 as u64;
-            register_file.write(rdindex, (_rs1_64* _rs2_64) as u32);
+            register_file.write(rdindex, (_rs1_64 * _rs2_64) as u32);
         }
         Instruction::MULH(rdindex, rs1index, rs2index) => {
             let _rs1: RS1value = register_file.read(rs1index);
             let _rs2: RS2value = register_file.read(rs2index);
-            let result: i64 = (i64::from(_rs1)) * (i64::from(_rs2));
+            /* Both operands are signed, the product of two 32-bit numbers always fits 64 bits */
+            let result: i64 = i64::from(_rs1 as i32) * i64::from(_rs2 as i32);
             let high_bytes: u32 = (result >> 32) as u32;
             register_file.write(rdindex, high_bytes);
         }
         Instruction::MULHSU(rdindex, rs1index, rs2index) => {
             let _rs1: RS1value = register_file.read(rs1index);
             let _rs2: RS2value = register_file.read(rs2index);
-            let result = i64::from(_rs1).wrapping_mul(i64::from(_rs2));
+            /* rs1 is signed and rs2 unsigned */
+            let result: i64 = i64::from(_rs1 as i32) * i64::from(_rs2);
             let high_bytes: u32 = (result >> 32) as u32;
             register_file.write(rdindex, high_bytes);
         }
@@ -405,36 +921,33 @@ pub fn exec(
             register_file.write(rdindex, high_bytes);
         }
         Instruction::DIV(rdindex, rs1index, rs2index) => {
-            let _rs1: RS1value = register_file.read(rs1index);
-            let _rs2: RS2value = register_file.read(rs2index);
-            if (_rs2 == 0) {
+            let _rs1 = register_file.read(rs1index) as i32;
+            let _rs2 = register_file.read(rs2index) as i32;
+            if _rs2 == 0 {
                 // The spec defines that -1 should be stored. In 32-bit two's complement, u32::MAX is -1
                 register_file.write(rdindex, u32::MAX);
+            } else {
+                /* The overflowing i32::MIN / -1 results in i32::MIN */
+                register_file.write(rdindex, _rs1.wrapping_div(_rs2) as u32);
             }
-            else {
-                let result: i32 = (_rs1 / _rs2) as i32;
-                register_file.write(rdindex, result as u32);
-            } 
         }
         Instruction::DIVU(rdindex, rs1index, rs2index) => {
             let _rs1: RS1value = register_file.read(rs1index);
             let _rs2: RS2value = register_file.read(rs2index);
-            if (_rs2 == 0) {
+            if let Some(result) = _rs1.checked_div(_rs2) {
+                register_file.write(rdindex, result);
+            } else {
                 register_file.write(rdindex, u32::MAX);
             }
-            else {
-                register_file.write(rdindex, _rs1 / _rs2);
-            }
         }
         Instruction::REM(rdindex, rs1index, rs2index) => {
-            let _rs1: RS1value = register_file.read(rs1index);
-            let _rs2: RS2value = register_file.read(rs2index);
+            let _rs1 = register_file.read(rs1index) as i32;
+            let _rs2 = register_file.read(rs2index) as i32;
             if (_rs2 == 0) {
-                register_file.write(rdindex, _rs1);
-            }
-            else {
-                let result: i32 = (_rs1 % _rs2) as i32;
-                register_file.write(rdindex, result as u32);
+                register_file.write(rdindex, _rs1 as u32);
+            } else {
+                /* The overflowing i32::MIN % -1 results in 0 */
+                register_file.write(rdindex, _rs1.wrapping_rem(_rs2) as u32);
             }
         }
         Instruction::REMU(rdindex, rs1index, rs2index) => {
@@ -442,12 +955,1738 @@ pub fn exec(
             let _rs2: RS2value = register_file.read(rs2index);
             if (_rs2 == 0) {
                 register_file.write(rdindex, _rs1);
-            }
-            else {
+            } else {
                 register_file.write(rdindex, _rs1 % _rs2);
             }
         }
+        Instruction::LRW(rdindex, rs1index, _aqrl) => {
+            let addr: RS1value = register_file.read(rs1index);
+            if !addr.is_multiple_of(4) {
+                return Err(Exception::LoadAddressMisaligned(addr));
+            }
+            let value = load(register_file, bus, addr, Size::Word)?;
+            register_file.reservation = Some(addr);
+            register_file.write(rdindex, value);
+        }
+        Instruction::SCW(rdindex, rs1index, rs2index, _aqrl) => {
+            let addr: RS1value = register_file.read(rs1index);
+            let rs2: RS2value = register_file.read(rs2index);
+            if !addr.is_multiple_of(4) {
+                return Err(Exception::StoreAddressMisaligned(addr));
+            }
+            if register_file.reservation.take() == Some(addr) {
+                store(register_file, bus, addr, Size::Word, rs2)?;
+                register_file.write(rdindex, 0);
+            } else {
+                register_file.write(rdindex, 1);
+            }
+        }
+        Instruction::AMOSWAPW(rdindex, rs1index, rs2index, _aqrl) => {
+            amo(register_file, bus, rdindex, rs1index, rs2index, |_, rs2| {
+                rs2
+            })?;
+        }
+        Instruction::AMOADDW(rdindex, rs1index, rs2index, _aqrl) => {
+            amo(
+                register_file,
+                bus,
+                rdindex,
+                rs1index,
+                rs2index,
+                u32::wrapping_add,
+            )?;
+        }
+        Instruction::AMOXORW(rdindex, rs1index, rs2index, _aqrl) => {
+            amo(register_file, bus, rdindex, rs1index, rs2index, |a, b| {
+                a ^ b
+            })?;
+        }
+        Instruction::AMOANDW(rdindex, rs1index, rs2index, _aqrl) => {
+            amo(register_file, bus, rdindex, rs1index, rs2index, |a, b| {
+                a & b
+            })?;
+        }
+        Instruction::AMOORW(rdindex, rs1index, rs2index, _aqrl) => {
+            amo(register_file, bus, rdindex, rs1index, rs2index, |a, b| {
+                a | b
+            })?;
+        }
+        Instruction::AMOMINW(rdindex, rs1index, rs2index, _aqrl) => {
+            amo(register_file, bus, rdindex, rs1index, rs2index, |a, b| {
+                (a as i32).min(b as i32) as u32
+            })?;
+        }
+        Instruction::AMOMAXW(rdindex, rs1index, rs2index, _aqrl) => {
+            amo(register_file, bus, rdindex, rs1index, rs2index, |a, b| {
+                (a as i32).max(b as i32) as u32
+            })?;
+        }
+        Instruction::AMOMINUW(rdindex, rs1index, rs2index, _aqrl) => {
+            amo(register_file, bus, rdindex, rs1index, rs2index, u32::min)?;
+        }
+        Instruction::AMOMAXUW(rdindex, rs1index, rs2index, _aqrl) => {
+            amo(register_file, bus, rdindex, rs1index, rs2index, u32::max)?;
+        }
+        Instruction::FLW(rdindex, rs1index, iimmediate) => {
+            let rs1: RS1value = register_file.read(rs1index);
+            let sign_imm = sign_extend(iimmediate, 12) as i32;
+            let target = add_signed!(rs1, sign_imm);
+            let value = load(register_file, bus, target, Size::Word)?;
+            register_file.write_f32(rdindex, value);
+        }
+        Instruction::FSW(rs1index, rs2index, simmediate) => {
+            let rs1: RS1value = register_file.read(rs1index);
+            let sign_imm = sign_extend(simmediate, 12) as i32;
+            let target = add_signed!(rs1, sign_imm);
+            /* Stores the raw lower bits, no NaN-unboxing */
+            store(
+                register_file,
+                bus,
+                target,
+                Size::Word,
+                register_file.read_f64(rs2index) as u32,
+            )?;
+        }
+        Instruction::FMADDS(rdindex, rs1index, rs2index, rs3index, rm) => {
+            let rs1 = u64::from(register_file.read_f32(rs1index));
+            let rs2 = u64::from(register_file.read_f32(rs2index));
+            let rs3 = u64::from(register_file.read_f32(rs3index));
+            let value = float_op(register_file, rm, |env| {
+                env.fused_mul_add(SINGLE, rs1, rs2, rs3)
+            })?;
+            register_file.write_f32(rdindex, value as u32);
+        }
+        Instruction::FMSUBS(rdindex, rs1index, rs2index, rs3index, rm) => {
+            let rs1 = u64::from(register_file.read_f32(rs1index));
+            let rs2 = u64::from(register_file.read_f32(rs2index));
+            let rs3 = u64::from(register_file.read_f32(rs3index));
+            let value = float_op(register_file, rm, |env| {
+                env.fused_mul_add(SINGLE, rs1, rs2, rs3 ^ SIGN_SINGLE)
+            })?;
+            register_file.write_f32(rdindex, value as u32);
+        }
+        Instruction::FNMSUBS(rdindex, rs1index, rs2index, rs3index, rm) => {
+            let rs1 = u64::from(register_file.read_f32(rs1index));
+            let rs2 = u64::from(register_file.read_f32(rs2index));
+            let rs3 = u64::from(register_file.read_f32(rs3index));
+            let value = float_op(register_file, rm, |env| {
+                env.fused_mul_add(SINGLE, rs1 ^ SIGN_SINGLE, rs2, rs3)
+            })?;
+            register_file.write_f32(rdindex, value as u32);
+        }
+        Instruction::FNMADDS(rdindex, rs1index, rs2index, rs3index, rm) => {
+            let rs1 = u64::from(register_file.read_f32(rs1index));
+            let rs2 = u64::from(register_file.read_f32(rs2index));
+            let rs3 = u64::from(register_file.read_f32(rs3index));
+            let value = float_op(register_file, rm, |env| {
+                env.fused_mul_add(SINGLE, rs1 ^ SIGN_SINGLE, rs2, rs3 ^ SIGN_SINGLE)
+            })?;
+            register_file.write_f32(rdindex, value as u32);
+        }
+        Instruction::FADDS(rdindex, rs1index, rs2index, rm) => {
+            let rs1 = u64::from(register_file.read_f32(rs1index));
+            let rs2 = u64::from(register_file.read_f32(rs2index));
+            let value = float_op(register_file, rm, |env| env.add(SINGLE, rs1, rs2))?;
+            register_file.write_f32(rdindex, value as u32);
+        }
+        Instruction::FSUBS(rdindex, rs1index, rs2index, rm) => {
+            let rs1 = u64::from(register_file.read_f32(rs1index));
+            let rs2 = u64::from(register_file.read_f32(rs2index));
+            let value = float_op(register_file, rm, |env| env.sub(SINGLE, rs1, rs2))?;
+            register_file.write_f32(rdindex, value as u32);
+        }
+        Instruction::FMULS(rdindex, rs1index, rs2index, rm) => {
+            let rs1 = u64::from(register_file.read_f32(rs1index));
+            let rs2 = u64::from(register_file.read_f32(rs2index));
+            let value = float_op(register_file, rm, |env| env.mul(SINGLE, rs1, rs2))?;
+            register_file.write_f32(rdindex, value as u32);
+        }
+        Instruction::FDIVS(rdindex, rs1index, rs2index, rm) => {
+            let rs1 = u64::from(register_file.read_f32(rs1index));
+            let rs2 = u64::from(register_file.read_f32(rs2index));
+            let value = float_op(register_file, rm, |env| env.div(SINGLE, rs1, rs2))?;
+            register_file.write_f32(rdindex, value as u32);
+        }
+        Instruction::FSQRTS(rdindex, rs1index, rm) => {
+            let rs1 = u64::from(register_file.read_f32(rs1index));
+            let value = float_op(register_file, rm, |env| env.sqrt(SINGLE, rs1))?;
+            register_file.write_f32(rdindex, value as u32);
+        }
+        Instruction::FSGNJS(rdindex, rs1index, rs2index) => {
+            let rs1 = register_file.read_f32(rs1index);
+            let rs2 = register_file.read_f32(rs2index);
+            register_file.write_f32(rdindex, (rs1 & !(1 << 31)) | (rs2 & (1 << 31)));
+        }
+        Instruction::FSGNJNS(rdindex, rs1index, rs2index) => {
+            let rs1 = register_file.read_f32(rs1index);
+            let rs2 = register_file.read_f32(rs2index);
+            register_file.write_f32(rdindex, (rs1 & !(1 << 31)) | (!rs2 & (1 << 31)));
+        }
+        Instruction::FSGNJXS(rdindex, rs1index, rs2index) => {
+            let rs1 = register_file.read_f32(rs1index);
+            let rs2 = register_file.read_f32(rs2index);
+            register_file.write_f32(rdindex, rs1 ^ (rs2 & (1 << 31)));
+        }
+        Instruction::FMINS(rdindex, rs1index, rs2index) => {
+            let rs1 = u64::from(register_file.read_f32(rs1index));
+            let rs2 = u64::from(register_file.read_f32(rs2index));
+            let value = float_op(register_file, RM_NONE, |env| env.min(SINGLE, rs1, rs2))?;
+            register_file.write_f32(rdindex, value as u32);
+        }
+        Instruction::FMAXS(rdindex, rs1index, rs2index) => {
+            let rs1 = u64::from(register_file.read_f32(rs1index));
+            let rs2 = u64::from(register_file.read_f32(rs2index));
+            let value = float_op(register_file, RM_NONE, |env| env.max(SINGLE, rs1, rs2))?;
+            register_file.write_f32(rdindex, value as u32);
+        }
+        Instruction::FCVTWS(rdindex, rs1index, rm) => {
+            let rs1 = u64::from(register_file.read_f32(rs1index));
+            let value = float_op(register_file, rm, |env| env.float_to_int(SINGLE, rs1, true))?;
+            register_file.write(rdindex, value);
+        }
+        Instruction::FCVTWUS(rdindex, rs1index, rm) => {
+            let rs1 = u64::from(register_file.read_f32(rs1index));
+            let value = float_op(register_file, rm, |env| {
+                env.float_to_int(SINGLE, rs1, false)
+            })?;
+            register_file.write(rdindex, value);
+        }
+        Instruction::FMVXW(rdindex, rs1index) => {
+            /* Moves the raw lower bits, no NaN-unboxing */
+            register_file.write(rdindex, register_file.read_f64(rs1index) as u32);
+        }
+        Instruction::FEQS(rdindex, rs1index, rs2index) => {
+            let rs1 = u64::from(register_file.read_f32(rs1index));
+            let rs2 = u64::from(register_file.read_f32(rs2index));
+            let value = float_op(register_file, RM_NONE, |env| env.eq(SINGLE, rs1, rs2))?;
+            register_file.write(rdindex, u32::from(value));
+        }
+        Instruction::FLTS(rdindex, rs1index, rs2index) => {
+            let rs1 = u64::from(register_file.read_f32(rs1index));
+            let rs2 = u64::from(register_file.read_f32(rs2index));
+            let value = float_op(register_file, RM_NONE, |env| env.lt(SINGLE, rs1, rs2))?;
+            register_file.write(rdindex, u32::from(value));
+        }
+        Instruction::FLES(rdindex, rs1index, rs2index) => {
+            let rs1 = u64::from(register_file.read_f32(rs1index));
+            let rs2 = u64::from(register_file.read_f32(rs2index));
+            let value = float_op(register_file, RM_NONE, |env| env.le(SINGLE, rs1, rs2))?;
+            register_file.write(rdindex, u32::from(value));
+        }
+        Instruction::FCLASSS(rdindex, rs1index) => {
+            let rs1 = u64::from(register_file.read_f32(rs1index));
+            register_file.write(rdindex, classify(SINGLE, rs1));
+        }
+        Instruction::FCVTSW(rdindex, rs1index, rm) => {
+            let rs1: RS1value = register_file.read(rs1index);
+            let value = float_op(register_file, rm, |env| env.int_to_float(SINGLE, rs1, true))?;
+            register_file.write_f32(rdindex, value as u32);
+        }
+        Instruction::FCVTSWU(rdindex, rs1index, rm) => {
+            let rs1: RS1value = register_file.read(rs1index);
+            let value = float_op(register_file, rm, |env| {
+                env.int_to_float(SINGLE, rs1, false)
+            })?;
+            register_file.write_f32(rdindex, value as u32);
+        }
+        Instruction::FMVWX(rdindex, rs1index) => {
+            register_file.write_f32(rdindex, register_file.read(rs1index));
+        }
+        Instruction::FLD(rdindex, rs1index, iimmediate) => {
+            let rs1: RS1value = register_file.read(rs1index);
+            let sign_imm = sign_extend(iimmediate, 12) as i32;
+            let target = add_signed!(rs1, sign_imm);
+            let lower = load(register_file, bus, target, Size::Word)?;
+            let upper = load(register_file, bus, target.wrapping_add(4), Size::Word)?;
+            register_file.write_f64(rdindex, (u64::from(upper) << 32) | u64::from(lower));
+        }
+        Instruction::FSD(rs1index, rs2index, simmediate) => {
+            let rs1: RS1value = register_file.read(rs1index);
+            let sign_imm = sign_extend(simmediate, 12) as i32;
+            let target = add_signed!(rs1, sign_imm);
+            let value = register_file.read_f64(rs2index);
+            let upper_addr = target.wrapping_add(4);
+            let lower = store_target(register_file, bus, target, Size::Word)?;
+            let upper = store_target(register_file, bus, upper_addr, Size::Word)?;
+            store_to(bus, target, lower, Size::Word, value as u32)?;
+            store_to(bus, upper_addr, upper, Size::Word, (value >> 32) as u32)?;
+        }
+        Instruction::FMADDD(rdindex, rs1index, rs2index, rs3index, rm) => {
+            let rs1 = register_file.read_f64(rs1index);
+            let rs2 = register_file.read_f64(rs2index);
+            let rs3 = register_file.read_f64(rs3index);
+            let value = float_op(register_file, rm, |env| {
+                env.fused_mul_add(DOUBLE, rs1, rs2, rs3)
+            })?;
+            register_file.write_f64(rdindex, value);
+        }
+        Instruction::FMSUBD(rdindex, rs1index, rs2index, rs3index, rm) => {
+            let rs1 = register_file.read_f64(rs1index);
+            let rs2 = register_file.read_f64(rs2index);
+            let rs3 = register_file.read_f64(rs3index);
+            let value = float_op(register_file, rm, |env| {
+                env.fused_mul_add(DOUBLE, rs1, rs2, rs3 ^ SIGN_DOUBLE)
+            })?;
+            register_file.write_f64(rdindex, value);
+        }
+        Instruction::FNMSUBD(rdindex, rs1index, rs2index, rs3index, rm) => {
+            let rs1 = register_file.read_f64(rs1index);
+            let rs2 = register_file.read_f64(rs2index);
+            let rs3 = register_file.read_f64(rs3index);
+            let value = float_op(register_file, rm, |env| {
+                env.fused_mul_add(DOUBLE, rs1 ^ SIGN_DOUBLE, rs2, rs3)
+            })?;
+            register_file.write_f64(rdindex, value);
+        }
+        Instruction::FNMADDD(rdindex, rs1index, rs2index, rs3index, rm) => {
+            let rs1 = register_file.read_f64(rs1index);
+            let rs2 = register_file.read_f64(rs2index);
+            let rs3 = register_file.read_f64(rs3index);
+            let value = float_op(register_file, rm, |env| {
+                env.fused_mul_add(DOUBLE, rs1 ^ SIGN_DOUBLE, rs2, rs3 ^ SIGN_DOUBLE)
+            })?;
+            register_file.write_f64(rdindex, value);
+        }
+        Instruction::FADDD(rdindex, rs1index, rs2index, rm) => {
+            let rs1 = register_file.read_f64(rs1index);
+            let rs2 = register_file.read_f64(rs2index);
+            let value = float_op(register_file, rm, |env| env.add(DOUBLE, rs1, rs2))?;
+            register_file.write_f64(rdindex, value);
+        }
+        Instruction::FSUBD(rdindex, rs1index, rs2index, rm) => {
+            let rs1 = register_file.read_f64(rs1index);
+            let rs2 = register_file.read_f64(rs2index);
+            let value = float_op(register_file, rm, |env| env.sub(DOUBLE, rs1, rs2))?;
+            register_file.write_f64(rdindex, value);
+        }
+        Instruction::FMULD(rdindex, rs1index, rs2index, rm) => {
+            let rs1 = register_file.read_f64(rs1index);
+            let rs2 = register_file.read_f64(rs2index);
+            let value = float_op(register_file, rm, |env| env.mul(DOUBLE, rs1, rs2))?;
+            register_file.write_f64(rdindex, value);
+        }
+        Instruction::FDIVD(rdindex, rs1index, rs2index, rm) => {
+            let rs1 = register_file.read_f64(rs1index);
+            let rs2 = register_file.read_f64(rs2index);
+            let value = float_op(register_file, rm, |env| env.div(DOUBLE, rs1, rs2))?;
+            register_file.write_f64(rdindex, value);
+        }
+        Instruction::FSQRTD(rdindex, rs1index, rm) => {
+            let rs1 = register_file.read_f64(rs1index);
+            let value = float_op(register_file, rm, |env| env.sqrt(DOUBLE, rs1))?;
+            register_file.write_f64(rdindex, value);
+        }
+        Instruction::FSGNJD(rdindex, rs1index, rs2index) => {
+            let rs1 = register_file.read_f64(rs1index);
+            let rs2 = register_file.read_f64(rs2index);
+            register_file.write_f64(rdindex, (rs1 & !SIGN_DOUBLE) | (rs2 & SIGN_DOUBLE));
+        }
+        Instruction::FSGNJND(rdindex, rs1index, rs2index) => {
+            let rs1 = register_file.read_f64(rs1index);
+            let rs2 = register_file.read_f64(rs2index);
+            register_file.write_f64(rdindex, (rs1 & !SIGN_DOUBLE) | (!rs2 & SIGN_DOUBLE));
+        }
+        Instruction::FSGNJXD(rdindex, rs1index, rs2index) => {
+            let rs1 = register_file.read_f64(rs1index);
+            let rs2 = register_file.read_f64(rs2index);
+            register_file.write_f64(rdindex, rs1 ^ (rs2 & SIGN_DOUBLE));
+        }
+        Instruction::FMIND(rdindex, rs1index, rs2index) => {
+            let rs1 = register_file.read_f64(rs1index);
+            let rs2 = register_file.read_f64(rs2index);
+            let value = float_op(register_file, RM_NONE, |env| env.min(DOUBLE, rs1, rs2))?;
+            register_file.write_f64(rdindex, value);
+        }
+        Instruction::FMAXD(rdindex, rs1index, rs2index) => {
+            let rs1 = register_file.read_f64(rs1index);
+            let rs2 = register_file.read_f64(rs2index);
+            let value = float_op(register_file, RM_NONE, |env| env.max(DOUBLE, rs1, rs2))?;
+            register_file.write_f64(rdindex, value);
+        }
+        Instruction::FCVTSD(rdindex, rs1index, rm) => {
+            let rs1 = register_file.read_f64(rs1index);
+            let value = float_op(register_file, rm, |env| env.convert(DOUBLE, SINGLE, rs1))?;
+            register_file.write_f32(rdindex, value as u32);
+        }
+        Instruction::FCVTDS(rdindex, rs1index, rm) => {
+            /* Widening is always exact, rm is still checked for validity */
+            let rs1 = u64::from(register_file.read_f32(rs1index));
+            let value = float_op(register_file, rm, |env| env.convert(SINGLE, DOUBLE, rs1))?;
+            register_file.write_f64(rdindex, value);
+        }
+        Instruction::FEQD(rdindex, rs1index, rs2index) => {
+            let rs1 = register_file.read_f64(rs1index);
+            let rs2 = register_file.read_f64(rs2index);
+            let value = float_op(register_file, RM_NONE, |env| env.eq(DOUBLE, rs1, rs2))?;
+            register_file.write(rdindex, u32::from(value));
+        }
+        Instruction::FLTD(rdindex, rs1index, rs2index) => {
+            let rs1 = register_file.read_f64(rs1index);
+            let rs2 = register_file.read_f64(rs2index);
+            let value = float_op(register_file, RM_NONE, |env| env.lt(DOUBLE, rs1, rs2))?;
+            register_file.write(rdindex, u32::from(value));
+        }
+        Instruction::FLED(rdindex, rs1index, rs2index) => {
+            let rs1 = register_file.read_f64(rs1index);
+            let rs2 = register_file.read_f64(rs2index);
+            let value = float_op(register_file, RM_NONE, |env| env.le(DOUBLE, rs1, rs2))?;
+            register_file.write(rdindex, u32::from(value));
+        }
+        Instruction::FCLASSD(rdindex, rs1index) => {
+            let rs1 = register_file.read_f64(rs1index);
+            register_file.write(rdindex, classify(DOUBLE, rs1));
+        }
+        Instruction::FCVTWD(rdindex, rs1index, rm) => {
+            let rs1 = register_file.read_f64(rs1index);
+            let value = float_op(register_file, rm, |env| env.float_to_int(DOUBLE, rs1, true))?;
+            register_file.write(rdindex, value);
+        }
+        Instruction::FCVTWUD(rdindex, rs1index, rm) => {
+            let rs1 = register_file.read_f64(rs1index);
+            let value = float_op(register_file, rm, |env| {
+                env.float_to_int(DOUBLE, rs1, false)
+            })?;
+            register_file.write(rdindex, value);
+        }
+        Instruction::FCVTDW(rdindex, rs1index, rm) => {
+            let rs1: RS1value = register_file.read(rs1index);
+            let value = float_op(register_file, rm, |env| env.int_to_float(DOUBLE, rs1, true))?;
+            register_file.write_f64(rdindex, value);
+        }
+        Instruction::FCVTDWU(rdindex, rs1index, rm) => {
+            let rs1: RS1value = register_file.read(rs1index);
+            let value = float_op(register_file, rm, |env| {
+                env.int_to_float(DOUBLE, rs1, false)
+            })?;
+            register_file.write_f64(rdindex, value);
+        }
+    }
+    register_file.pc = register_file.pc.wrapping_add(instruction_len);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+    use crate::assembler::assemble;
+    use crate::system::{Image, FS_INITIAL, RAM_BASE};
+
+    const RAM_SIZE: usize = 0x6000;
+
+    /// Runs `source` from the start of RAM until it executes an `ebreak`
+    fn run(source: &str) -> RegisterFile {
+        let mut ram = assemble(source, RAM_BASE as u32).unwrap().code;
+        ram.resize(RAM_SIZE, 0);
+        let mut bus = Image::flat(ram).into_bus(Box::new(io::sink())).unwrap();
+        let mut register_file = RegisterFile::default();
+        register_file.pc = RAM_BASE as u32;
+        /* Like firmware would, give the lower privilege levels access to all memory */
+        register_file.csr.write(0x3B0, u32::MAX).unwrap();
+        register_file.csr.write(0x3A0, 0x1F).unwrap();
+        for _ in 0..10_000 {
+            if at_ebreak(&register_file, &bus) {
+                return register_file;
+            }
+            step(&mut register_file, &mut bus);
+        }
+        panic!("No ebreak at 0x{:08X}", register_file.pc);
+    }
+
+    /// Value of register `a0` after running `source`
+    fn a0(source: &str) -> u32 {
+        run(source).read(10)
+    }
+
+    /// Runs `source` in supervisor mode with Sv32 translation.
+    ///
+    /// The page table maps the first megapage of RAM to itself, virtual 0x40000000 to
+    /// `RAM_BASE + 0x3000` with the given PTE and 0x40001000 to `RAM_BASE + 0x5000`.
+    /// The tables are at `RAM_BASE + 0x1000` and `+ 0x2000`, traps stop in machine mode.
+    fn paged(pte: u32, source: &str) -> RegisterFile {
+        run(&format!(
+            "li t0, 0x80001800
+             li t1, 0x200000cf
+             sw t1, 0(t0)
+             li t0, 0x80001400
+             li t1, 0x20000801
+             sw t1, 0(t0)
+             li t0, 0x80002000
+             li t1, {pte:#x}
+             sw t1, 0(t0)
+             li t1, 0x200014c7
+             sw t1, 4(t0)
+             la t0, machine
+             csrw mtvec, t0
+             li t0, 0x80080001
+             csrw satp, t0
+             li t0, 0x800
+             csrs mstatus, t0
+             la t0, smode
+             csrw mepc, t0
+             mret
+             smode:
+             {source}
+             ebreak
+             machine:
+             ebreak"
+        ))
+    }
+
+    /// Runs `source` with a trap handler that stops at its `ebreak`
+    fn trap(source: &str) -> RegisterFile {
+        run(&format!(
+            "la t6, handler
+             csrw mtvec, t6
+             {source}
+             ebreak
+             handler:
+             ebreak"
+        ))
+    }
+
+    #[test]
+    fn upper_immediates() {
+        let register_file = run("lui a0, 0xfffff
+                                 auipc a1, 0x1
+                                 ebreak");
+        assert_eq!(register_file.read(10), 0xFFFF_F000);
+        assert_eq!(register_file.read(11), RAM_BASE as u32 + 0x1004);
+    }
+
+    #[test]
+    fn writes_to_x0_are_ignored() {
+        let register_file = run("addi zero, zero, 5
+                                 lui zero, 0x12345
+                                 jal zero, next
+                                 next:
+                                 la t0, next
+                                 lw zero, 0(t0)
+                                 ebreak");
+        assert_eq!(register_file.read(0), 0);
+    }
+
+    #[test]
+    fn jumps_link_the_next_instruction() {
+        let register_file = run("jal ra, target
+                                 li a0, 1
+                                 target:
+                                 la t0, function + 1
+                                 jalr t1, 0(t0)
+                                 li a1, 1
+                                 function:
+                                 ebreak");
+        assert_eq!(register_file.read(1), RAM_BASE as u32 + 4);
+        assert_eq!(register_file.read(10), 0);
+        assert_eq!(register_file.read(11), 0);
+        assert_eq!(register_file.read(6), register_file.pc - 4);
+    }
+
+    #[test]
+    fn jal_reaches_the_whole_megabyte() {
+        let mut bus = Image::flat(vec![0; RAM_SIZE])
+            .into_bus(Box::new(io::sink()))
+            .unwrap();
+        let mut register_file = RegisterFile::default();
+        register_file.pc = RAM_BASE as u32;
+        /* Bit 19 of the 21-bit offset is set, it is still a forward jump */
+        let far = Instruction::JAL(1, 0x8_0000);
+        exec(&mut register_file, &mut bus, &far, 4, Extensions::ALL).unwrap();
+        assert_eq!(register_file.pc, RAM_BASE as u32 + 0x8_0000);
+        assert_eq!(register_file.read(1), RAM_BASE as u32 + 4);
+        let back = Instruction::JAL(1, 0x10_0000);
+        exec(&mut register_file, &mut bus, &back, 4, Extensions::ALL).unwrap();
+        assert_eq!(register_file.pc, RAM_BASE as u32 - 0x8_0000);
+    }
+
+    #[test]
+    fn pc_wraps_around_at_the_end_of_the_address_space() {
+        let mut bus = Image::flat(vec![0; RAM_SIZE])
+            .into_bus(Box::new(io::sink()))
+            .unwrap();
+        let mut register_file = RegisterFile::default();
+        register_file.pc = 0xFFFF_FFFC;
+        let addi = Instruction::ADDI(10, 0, 1);
+        exec(&mut register_file, &mut bus, &addi, 4, Extensions::ALL).unwrap();
+        assert_eq!(register_file.pc, 0);
+        register_file.pc = 0xFFFF_FFFE;
+        let jal = Instruction::JAL(1, 0x10);
+        exec(&mut register_file, &mut bus, &jal, 2, Extensions::ALL).unwrap();
+        assert_eq!(register_file.read(1), 0);
+        register_file.pc = 0xFFFF_FFFC;
+        let jalr = Instruction::JALR(1, 0, 0x10);
+        exec(&mut register_file, &mut bus, &jalr, 4, Extensions::ALL).unwrap();
+        assert_eq!(register_file.read(1), 0);
+    }
+
+    #[test]
+    fn branches_compare_signed_and_unsigned() {
+        /* Every taken branch sets its bit in a0 */
+        let taken = a0("li t0, -1
+                        li t1, 1
+                        beq t0, t0, skip1
+                        ori a0, a0, 1
+                        skip1:
+                        bne t0, t1, skip2
+                        ori a0, a0, 2
+                        skip2:
+                        blt t0, t1, skip3
+                        ori a0, a0, 4
+                        skip3:
+                        bge t1, t0, skip4
+                        ori a0, a0, 8
+                        skip4:
+                        bltu t1, t0, skip5
+                        ori a0, a0, 16
+                        skip5:
+                        bgeu t0, t1, skip6
+                        ori a0, a0, 32
+                        skip6:
+                        beq t0, t1, skip7
+                        bne t0, t0, skip7
+                        blt t1, t0, skip7
+                        bge t0, t1, skip7
+                        bltu t0, t1, skip7
+                        bgeu t1, t0, skip7
+                        ebreak
+                        skip7:
+                        li a0, -1
+                        ebreak");
+        assert_eq!(taken, 0);
+    }
+
+    #[test]
+    fn loads_extend_and_stores_truncate() {
+        let register_file = run("la t0, data
+                                 li t1, 0x12345678
+                                 sw t1, 4(t0)
+                                 sh t1, 8(t0)
+                                 sb t1, 12(t0)
+                                 lb a0, 0(t0)
+                                 lbu a1, 0(t0)
+                                 lh a2, 0(t0)
+                                 lhu a3, 0(t0)
+                                 lw a4, 4(t0)
+                                 lw a5, 8(t0)
+                                 lw a6, 12(t0)
+                                 ebreak
+                                 .align 2
+                                 data:
+                                 .word 0xFFFF8080, 0, -1, -1");
+        assert_eq!(register_file.read(10), 0xFFFF_FF80);
+        assert_eq!(register_file.read(11), 0x80);
+        assert_eq!(register_file.read(12), 0xFFFF_8080);
+        assert_eq!(register_file.read(13), 0x8080);
+        assert_eq!(register_file.read(14), 0x1234_5678);
+        assert_eq!(register_file.read(15), 0xFFFF_5678);
+        assert_eq!(register_file.read(16), 0xFFFF_FF78);
+    }
+
+    #[test]
+    fn misaligned_accesses_are_supported() {
+        let register_file = run("la t0, data
+                                 li t1, -1
+                                 sh t1, 7(t0)
+                                 lw a0, 1(t0)
+                                 lhu a1, 6(t0)
+                                 ebreak
+                                 .align 2
+                                 data:
+                                 .word 0x44332211, 0x88776655");
+        assert_eq!(register_file.read(10), 0x5544_3322);
+        assert_eq!(register_file.read(11), 0xFF77);
+    }
+
+    #[test]
+    fn unmapped_accesses_trap() {
+        let register_file = trap(
+            "li t0, 0x10
+                                  lw a0, 0(t0)",
+        );
+        assert_eq!(register_file.csr.mcause, 5);
+        assert_eq!(register_file.csr.mtval, 0x10);
+
+        let register_file = trap(
+            "li t0, 0x10
+                                  sh a0, 0(t0)",
+        );
+        assert_eq!(register_file.csr.mcause, 7);
+        assert_eq!(register_file.csr.mtval, 0x10);
+    }
+
+    #[test]
+    fn immediate_arithmetic() {
+        let register_file = run("li t0, -8
+                                 addi a0, t0, -2048
+                                 slti a1, t0, 0
+                                 sltiu a2, t0, -1
+                                 sltiu a3, zero, 1
+                                 xori a4, t0, -1
+                                 ori a5, t0, 0x7f
+                                 andi a6, t0, 0x7f
+                                 ebreak");
+        assert_eq!(register_file.read(10), (-2056_i32) as u32);
+        assert_eq!(register_file.read(11), 1);
+        /* The immediate is sign extended, then compared unsigned */
+        assert_eq!(register_file.read(12), 1);
+        assert_eq!(register_file.read(13), 1);
+        assert_eq!(register_file.read(14), 7);
+        assert_eq!(register_file.read(15), 0xFFFF_FFFF);
+        assert_eq!(register_file.read(16), 0x78);
+    }
+
+    #[test]
+    fn shifts() {
+        let register_file = run("li t0, 0x80000010
+                                 li t1, 33
+                                 slli a0, t0, 1
+                                 srli a1, t0, 4
+                                 srai a2, t0, 4
+                                 srai a3, t0, 0
+                                 srai a4, t0, 31
+                                 sll a5, t0, t1
+                                 srl a6, t0, t1
+                                 sra a7, t0, t1
+                                 ebreak");
+        assert_eq!(register_file.read(10), 0x20);
+        assert_eq!(register_file.read(11), 0x0800_0001);
+        assert_eq!(register_file.read(12), 0xF800_0001);
+        assert_eq!(register_file.read(13), 0x8000_0010);
+        assert_eq!(register_file.read(14), 0xFFFF_FFFF);
+        /* Only the lower five bits of rs2 are the shift amount */
+        assert_eq!(register_file.read(15), 0x20);
+        assert_eq!(register_file.read(16), 0x4000_0008);
+        assert_eq!(register_file.read(17), 0xC000_0008);
+    }
+
+    #[test]
+    fn register_arithmetic() {
+        let register_file = run("li t0, -3
+                                 li t1, 5
+                                 add a0, t0, t1
+                                 sub a1, t1, t0
+                                 slt a2, t0, t1
+                                 sltu a3, t0, t1
+                                 xor a4, t0, t1
+                                 or a5, t0, t1
+                                 and a6, t0, t1
+                                 sub a7, zero, t1
+                                 ebreak");
+        assert_eq!(register_file.read(10), 2);
+        assert_eq!(register_file.read(11), 8);
+        assert_eq!(register_file.read(12), 1);
+        assert_eq!(register_file.read(13), 0);
+        assert_eq!(register_file.read(14), 0xFFFF_FFF8);
+        assert_eq!(register_file.read(15), 0xFFFF_FFFD);
+        assert_eq!(register_file.read(16), 5);
+        assert_eq!(register_file.read(17), (-5_i32) as u32);
+    }
+
+    #[test]
+    fn multiplication() {
+        let register_file = run("li t0, 0x80000000
+                                 li t1, -1
+                                 li t2, 7
+                                 mul a0, t1, t2
+                                 mulh a1, t0, t0
+                                 mulh a2, t1, t1
+                                 mulhsu a3, t1, t1
+                                 mulhsu a4, t2, t1
+                                 mulhu a5, t1, t1
+                                 mulh a6, t0, t2
+                                 ebreak");
+        assert_eq!(register_file.read(10), (-7_i32) as u32);
+        assert_eq!(register_file.read(11), 0x4000_0000);
+        assert_eq!(register_file.read(12), 0);
+        assert_eq!(register_file.read(13), 0xFFFF_FFFF);
+        assert_eq!(register_file.read(14), 6);
+        assert_eq!(register_file.read(15), 0xFFFF_FFFE);
+        assert_eq!(register_file.read(16), 0xFFFF_FFFC);
+    }
+
+    #[test]
+    fn division() {
+        let register_file = run("li t0, -7
+                                 li t1, 2
+                                 div a0, t0, t1
+                                 rem a1, t0, t1
+                                 divu a2, t0, t1
+                                 remu a3, t0, t1
+                                 ebreak");
+        /* Signed division rounds towards zero */
+        assert_eq!(register_file.read(10), (-3_i32) as u32);
+        assert_eq!(register_file.read(11), (-1_i32) as u32);
+        assert_eq!(register_file.read(12), 0x7FFF_FFFC);
+        assert_eq!(register_file.read(13), 1);
+    }
+
+    #[test]
+    fn division_by_zero_and_overflow() {
+        let register_file = run("li t0, 0x80000000
+                                 li t1, -1
+                                 div a0, t0, zero
+                                 rem a1, t0, zero
+                                 divu a2, t0, zero
+                                 remu a3, t0, zero
+                                 div a4, t0, t1
+                                 rem a5, t0, t1
+                                 ebreak");
+        assert_eq!(register_file.read(10), 0xFFFF_FFFF);
+        assert_eq!(register_file.read(11), 0x8000_0000);
+        assert_eq!(register_file.read(12), 0xFFFF_FFFF);
+        assert_eq!(register_file.read(13), 0x8000_0000);
+        assert_eq!(register_file.read(14), 0x8000_0000);
+        assert_eq!(register_file.read(15), 0);
+    }
+
+    #[test]
+    fn load_reserved_and_store_conditional() {
+        let register_file = run("la t0, data
+                                 li t1, 5
+                                 sc.w a0, t1, (t0)
+                                 lr.w a1, (t0)
+                                 sc.w a2, t1, (t0)
+                                 sc.w a3, t1, (t0)
+                                 lw a4, 0(t0)
+                                 ebreak
+                                 .align 2
+                                 data:
+                                 .word 3");
+        /* Without a reservation the store fails and memory is unchanged */
+        assert_eq!(register_file.read(10), 1);
+        assert_eq!(register_file.read(11), 3);
+        assert_eq!(register_file.read(12), 0);
+        /* A successful store conditional consumes the reservation */
+        assert_eq!(register_file.read(13), 1);
+        assert_eq!(register_file.read(14), 5);
+    }
+
+    #[test]
+    fn atomic_memory_operations() {
+        /* Each AMO returns the old value and leaves the result in memory */
+        let cases = [
+            ("amoswap.w", 0xF0, 0x0F, 0x0F),
+            ("amoadd.w", 0xF0, 0x0F, 0xFF),
+            ("amoxor.w", 0xFF, 0x0F, 0xF0),
+            ("amoand.w", 0xFF, 0x0F, 0x0F),
+            ("amoor.w", 0xF0, 0x0F, 0xFF),
+            ("amomin.w", -1, 1, -1),
+            ("amomax.w", -1, 1, 1),
+            ("amominu.w", -1, 1, 1),
+            ("amomaxu.w", -1, 1, -1),
+        ];
+        for (amo, old, operand, result) in cases {
+            let register_file = run(&format!(
+                "la t0, data
+                 li t1, {old}
+                 sw t1, 0(t0)
+                 li t1, {operand}
+                 {amo}.aqrl a0, t1, (t0)
+                 lw a1, 0(t0)
+                 ebreak
+                 .align 2
+                 data:
+                 .word 0"
+            ));
+            assert_eq!(register_file.read(10), old as u32, "{amo}");
+            assert_eq!(register_file.read(11), result as u32, "{amo}");
+        }
+    }
+
+    #[test]
+    fn misaligned_atomics_trap() {
+        let register_file = trap(
+            "la t0, handler + 2
+                                  amoadd.w a0, a0, (t0)",
+        );
+        assert_eq!(register_file.csr.mcause, 6);
+        assert_eq!(register_file.csr.mtval, register_file.pc + 2);
+        let register_file = trap(
+            "la t0, handler + 2
+                                  lr.w a0, (t0)",
+        );
+        assert_eq!(register_file.csr.mcause, 4);
+    }
+
+    #[test]
+    fn single_precision_arithmetic() {
+        let register_file = run("li t0, 0x3fc00000
+                                 li t1, 0x40100000
+                                 fmv.w.x fa0, t0
+                                 fmv.w.x fa1, t1
+                                 fadd.s fa2, fa0, fa1
+                                 fsub.s fa3, fa0, fa1
+                                 fmul.s fa4, fa0, fa1
+                                 fdiv.s fa5, fa0, fa1
+                                 fsqrt.s fa6, fa1
+                                 fmadd.s fs0, fa0, fa1, fa0
+                                 fmsub.s fs1, fa0, fa1, fa0
+                                 fnmsub.s fs2, fa0, fa1, fa0
+                                 fnmadd.s fs3, fa0, fa1, fa0
+                                 fmv.x.w a0, fa2
+                                 fmv.x.w a1, fa3
+                                 fmv.x.w a2, fa4
+                                 fmv.x.w a3, fa5
+                                 fmv.x.w a4, fa6
+                                 fmv.x.w a5, fs0
+                                 fmv.x.w a6, fs1
+                                 fmv.x.w a7, fs2
+                                 fmv.x.w s2, fs3
+                                 csrr s3, fflags
+                                 ebreak");
+        /* 1.5 and 2.25 */
+        assert_eq!(register_file.read(10), 0x4070_0000);
+        assert_eq!(register_file.read(11), 0xBF40_0000);
+        assert_eq!(register_file.read(12), 0x4058_0000);
+        assert_eq!(register_file.read(13), 0x3F2A_AAAB);
+        assert_eq!(register_file.read(14), 0x3FC0_0000);
+        assert_eq!(register_file.read(15), 0x409C_0000);
+        assert_eq!(register_file.read(16), 0x3FF0_0000);
+        assert_eq!(register_file.read(17), 0xBFF0_0000);
+        assert_eq!(register_file.read(18), 0xC09C_0000);
+        /* Only the division was inexact */
+        assert_eq!(register_file.read(19), 0b0_0001);
+    }
+
+    #[test]
+    fn single_precision_compare_and_sign() {
+        let register_file = run("li t0, 0x3f800000
+                                 li t1, 0xbf800000
+                                 li t2, 0x7fc00000
+                                 fmv.w.x fa0, t0
+                                 fmv.w.x fa1, t1
+                                 fmv.w.x fa2, t2
+                                 fmin.s fa3, fa0, fa1
+                                 fmax.s fa4, fa2, fa0
+                                 fsgnj.s fa5, fa0, fa1
+                                 fsgnjn.s fa6, fa1, fa1
+                                 fsgnjx.s fa7, fa1, fa1
+                                 fmv.x.w a0, fa3
+                                 fmv.x.w a1, fa4
+                                 fmv.x.w a2, fa5
+                                 fmv.x.w a3, fa6
+                                 fmv.x.w a4, fa7
+                                 feq.s a5, fa0, fa0
+                                 flt.s a6, fa0, fa1
+                                 fle.s a7, fa1, fa0
+                                 feq.s s2, fa2, fa2
+                                 csrr s3, fflags
+                                 flt.s s4, fa2, fa0
+                                 csrr s5, fflags
+                                 fclass.s s6, fa1
+                                 fclass.s s7, fa2
+                                 ebreak");
+        assert_eq!(register_file.read(10), 0xBF80_0000);
+        /* A quiet NaN operand is ignored by min and max */
+        assert_eq!(register_file.read(11), 0x3F80_0000);
+        assert_eq!(register_file.read(12), 0xBF80_0000);
+        assert_eq!(register_file.read(13), 0x3F80_0000);
+        assert_eq!(register_file.read(14), 0x3F80_0000);
+        assert_eq!(register_file.read(15), 1);
+        assert_eq!(register_file.read(16), 0);
+        assert_eq!(register_file.read(17), 1);
+        assert_eq!(register_file.read(18), 0);
+        /* Equality is quiet, ordering a NaN is invalid */
+        assert_eq!(register_file.read(19), 0);
+        assert_eq!(register_file.read(20), 0);
+        assert_eq!(register_file.read(21), 0b1_0000);
+        assert_eq!(register_file.read(22), 1 << 1);
+        assert_eq!(register_file.read(23), 1 << 9);
+    }
+
+    #[test]
+    fn single_precision_conversions() {
+        let register_file = run("li t0, 0xbfc00000
+                                 li t1, 0x4f32d05e
+                                 fmv.w.x fa0, t0
+                                 fmv.w.x fa1, t1
+                                 fcvt.w.s a0, fa0, rtz
+                                 fcvt.w.s a1, fa0
+                                 fcvt.wu.s a2, fa0, rtz
+                                 fcvt.w.s a3, fa1
+                                 fcvt.wu.s a4, fa1
+                                 li t2, -7
+                                 fcvt.s.w fa2, t2
+                                 fcvt.s.wu fa3, t2
+                                 fmv.x.w a5, fa2
+                                 fmv.x.w a6, fa3
+                                 ebreak");
+        /* -1.5 and 3e9 */
+        assert_eq!(register_file.read(10), (-1_i32) as u32);
+        assert_eq!(register_file.read(11), (-2_i32) as u32);
+        assert_eq!(register_file.read(12), 0);
+        assert_eq!(register_file.read(13), 0x7FFF_FFFF);
+        assert_eq!(register_file.read(14), 3_000_000_000);
+        assert_eq!(register_file.read(15), 0xC0E0_0000);
+        assert_eq!(register_file.read(16), 0x4F80_0000);
+    }
+
+    #[test]
+    fn rounding_mode_comes_from_frm() {
+        let register_file = trap(
+            "li t0, 0xbfc00000
+                                  fmv.w.x fa0, t0
+                                  csrwi frm, 2
+                                  fcvt.w.s a0, fa0
+                                  csrwi frm, 3
+                                  fcvt.w.s a1, fa0
+                                  csrwi frm, 5
+                                  fcvt.w.s a2, fa0",
+        );
+        assert_eq!(register_file.read(10), (-2_i32) as u32);
+        assert_eq!(register_file.read(11), (-1_i32) as u32);
+        /* A reserved rounding mode makes dynamic rounding illegal */
+        assert_eq!(register_file.csr.mcause, 2);
+    }
+
+    #[test]
+    fn single_precision_memory_and_nan_boxing() {
+        let register_file = run("la t0, data
+                                 flw fa0, 0(t0)
+                                 fsw fa0, 4(t0)
+                                 lw a0, 4(t0)
+                                 fld fa1, 8(t0)
+                                 fmv.x.w a1, fa1
+                                 fadd.s fa2, fa1, fa0
+                                 fmv.x.w a2, fa2
+                                 ebreak
+                                 .align 3
+                                 data:
+                                 .word 0x40490fdb, 0, 0x3f800000, 0");
+        assert_eq!(register_file.read(10), 0x4049_0FDB);
+        assert_eq!(register_file.read_f64(10), 0xFFFF_FFFF_4049_0FDB);
+        /* Moves copy the raw bits, arithmetic reads a double as the canonical NaN */
+        assert_eq!(register_file.read(11), 0x3F80_0000);
+        assert_eq!(register_file.read(12), 0x7FC0_0000);
+    }
+
+    #[test]
+    fn double_precision() {
+        let register_file = run("la t0, data
+                                 fld fa0, 0(t0)
+                                 fld fa1, 8(t0)
+                                 fadd.d fa2, fa0, fa1
+                                 fsub.d fa3, fa0, fa1
+                                 fmul.d fa4, fa0, fa1
+                                 fdiv.d fa5, fa0, fa1
+                                 fsqrt.d fa6, fa1
+                                 fmadd.d fa7, fa0, fa1, fa1
+                                 fmsub.d fs0, fa0, fa1, fa1
+                                 fnmsub.d fs1, fa0, fa1, fa1
+                                 fnmadd.d fs2, fa0, fa1, fa1
+                                 fsd fa2, 16(t0)
+                                 lw a0, 16(t0)
+                                 lw a1, 20(t0)
+                                 ebreak
+                                 .align 3
+                                 data:
+                                 .word 0, 0x3ff80000, 0, 0x40100000, 0, 0");
+        /* 1.5 and 4.0 */
+        assert_eq!(register_file.read_f64(12), 0x4016_0000_0000_0000);
+        assert_eq!(register_file.read_f64(13), 0xC004_0000_0000_0000);
+        assert_eq!(register_file.read_f64(14), 0x4018_0000_0000_0000);
+        assert_eq!(register_file.read_f64(15), 0x3FD8_0000_0000_0000);
+        assert_eq!(register_file.read_f64(16), 0x4000_0000_0000_0000);
+        assert_eq!(register_file.read_f64(17), 0x4024_0000_0000_0000);
+        assert_eq!(register_file.read_f64(8), 0x4000_0000_0000_0000);
+        assert_eq!(register_file.read_f64(9), 0xC000_0000_0000_0000);
+        assert_eq!(register_file.read_f64(18), 0xC024_0000_0000_0000);
+        assert_eq!(register_file.read(10), 0);
+        assert_eq!(register_file.read(11), 0x4016_0000);
+    }
+
+    #[test]
+    fn double_precision_compare_and_convert() {
+        let register_file = run("la t0, data
+                                 fld fa0, 0(t0)
+                                 fld fa1, 8(t0)
+                                 fmin.d fa2, fa0, fa1
+                                 fmax.d fa3, fa0, fa1
+                                 fsgnj.d fa4, fa1, fa0
+                                 fsgnjn.d fa5, fa1, fa1
+                                 fsgnjx.d fa6, fa0, fa0
+                                 feq.d a0, fa0, fa1
+                                 flt.d a1, fa0, fa1
+                                 fle.d a2, fa1, fa1
+                                 fclass.d a3, fa0
+                                 fcvt.w.d a4, fa0
+                                 fcvt.wu.d a5, fa0
+                                 li t1, -7
+                                 fcvt.d.w fs0, t1
+                                 fcvt.d.wu fs1, t1
+                                 fcvt.s.d fs2, fa1
+                                 fcvt.d.s fs3, fs2
+                                 csrr a6, fflags
+                                 ebreak
+                                 .align 3
+                                 data:
+                                 .word 0, 0xc0040000, 0x9999999a, 0x3fb99999");
+        /* -2.5 and 0.1 */
+        assert_eq!(register_file.read_f64(12), 0xC004_0000_0000_0000);
+        assert_eq!(register_file.read_f64(13), 0x3FB9_9999_9999_999A);
+        assert_eq!(register_file.read_f64(14), 0xBFB9_9999_9999_999A);
+        assert_eq!(register_file.read_f64(15), 0xBFB9_9999_9999_999A);
+        assert_eq!(register_file.read_f64(16), 0x4004_0000_0000_0000);
+        assert_eq!(register_file.read(10), 0);
+        assert_eq!(register_file.read(11), 1);
+        assert_eq!(register_file.read(12), 1);
+        assert_eq!(register_file.read(13), 1 << 1);
+        assert_eq!(register_file.read(14), (-2_i32) as u32);
+        assert_eq!(register_file.read(15), 0);
+        assert_eq!(register_file.read_f64(8), 0xC01C_0000_0000_0000);
+        assert_eq!(register_file.read_f64(9), 0x41EF_FFFF_FF20_0000);
+        assert_eq!(register_file.read_f64(18), 0xFFFF_FFFF_3DCC_CCCD);
+        assert_eq!(register_file.read_f64(19), 0x3FB9_9999_A000_0000);
+        /* Converting negative to unsigned is invalid, rounding is inexact */
+        assert_eq!(register_file.read(16), 0b1_0001);
+    }
+
+    #[test]
+    fn fsd_writes_nothing_when_its_upper_word_faults() {
+        let register_file = run("la t6, handler
+                                 csrw mtvec, t6
+                                 li t0, 0x80005ffc
+                                 li t1, 0x12345678
+                                 fmv.w.x ft0, t1
+                                 fsd ft0, 0(t0)
+                                 ebreak
+                                 handler:
+                                 lw a0, 0(t0)
+                                 ebreak");
+        assert_eq!(register_file.csr.mcause, 7);
+        assert_eq!(register_file.csr.mtval, RAM_BASE as u32 + 0x6000);
+        assert_eq!(register_file.read(10), 0);
+    }
+
+    #[test]
+    fn csr_read_modify_write() {
+        let register_file = run("li t0, 0xf0
+                                 csrw mscratch, t0
+                                 li t1, 0x3c
+                                 csrrw a0, mscratch, t1
+                                 csrrs a1, mscratch, t0
+                                 csrrc a2, mscratch, t1
+                                 csrrwi a3, mscratch, 0x1f
+                                 csrrsi a4, mscratch, 0x10
+                                 csrrci a5, mscratch, 0x0f
+                                 csrr a6, mscratch
+                                 csrr a7, mvendorid
+                                 ebreak");
+        assert_eq!(register_file.read(10), 0xF0);
+        assert_eq!(register_file.read(11), 0x3C);
+        assert_eq!(register_file.read(12), 0xFC);
+        assert_eq!(register_file.read(13), 0xC0);
+        assert_eq!(register_file.read(14), 0x1F);
+        assert_eq!(register_file.read(15), 0x1F);
+        assert_eq!(register_file.read(16), 0x10);
+        assert_eq!(register_file.read(17), 0);
+    }
+
+    #[test]
+    fn trap_value_csrs_are_writable() {
+        let register_file = run("li t0, 0x1234
+                                 csrw mtval, t0
+                                 csrw mtinst, t0
+                                 csrw mtval2, t0
+                                 csrw mstatush, t0
+                                 csrr a0, mtval
+                                 csrr a1, mtinst
+                                 csrr a2, mtval2
+                                 csrr a3, mstatush
+                                 ebreak");
+        assert_eq!(register_file.read(10), 0x1234);
+        assert_eq!(register_file.read(11), 0x1234);
+        assert_eq!(register_file.read(12), 0x1234);
+        assert_eq!(register_file.read(13), 0);
+    }
+
+    #[test]
+    fn floating_point_state_is_tracked_in_fs() {
+        /* FS starts Initial and becomes Dirty, with SD set, once an F instruction writes */
+        let register_file = run("csrr a0, mstatus
+                                 fmv.w.x ft0, zero
+                                 csrr a1, mstatus
+                                 csrr a2, sstatus
+                                 ebreak");
+        assert_eq!(register_file.read(10) >> 13 & 0b11, 0b01);
+        assert_eq!(register_file.read(11) >> 13 & 0b11, 0b11);
+        assert_eq!(register_file.read(11) >> 31, 1);
+        assert_eq!(register_file.read(12) & 0x8000_6000, 0x8000_6000);
+
+        /* Storing doesn't change the state, writing fcsr does */
+        let register_file = run("li t0, 0x2000
+                                 csrw mstatus, t0
+                                 li t1, 0x80004000
+                                 fsw ft0, 0(t1)
+                                 csrr a0, mstatus
+                                 csrwi fflags, 1
+                                 csrr a1, mstatus
+                                 ebreak");
+        assert_eq!(register_file.read(10), 0x2000);
+        assert_eq!(register_file.read(11), 0x8000_6000);
+    }
+
+    #[test]
+    fn floating_point_is_illegal_with_fs_off() {
+        let register_file = trap(
+            "li t0, 0x6000
+                                  csrc mstatus, t0
+                                  fadd.s ft0, ft0, ft0",
+        );
+        assert_eq!(register_file.csr.mcause, 2);
+        let register_file = trap(
+            "li t0, 0x6000
+                                  csrc mstatus, t0
+                                  csrr a0, fcsr",
+        );
+        assert_eq!(register_file.csr.mcause, 2);
+    }
+
+    #[test]
+    fn writing_a_read_only_csr_traps() {
+        let register_file = trap("csrw mvendorid, a0");
+        assert_eq!(register_file.csr.mcause, 2);
+        let register_file = trap("csrr a0, 0x7ff");
+        assert_eq!(register_file.csr.mcause, 2);
+        /* Set and clear with x0 don't write, so reading is fine */
+        let register_file = trap(
+            "csrrs a0, mvendorid, zero
+                                  csrrci a0, mhartid, 0",
+        );
+        assert_eq!(register_file.csr.mcause, 0);
+    }
+
+    #[test]
+    fn environment_call_and_return() {
+        let register_file = trap("ecall");
+        assert_eq!(register_file.csr.mcause, 11);
+        assert_eq!(register_file.csr.mepc, RAM_BASE as u32 + 12);
+
+        let register_file = run("la t0, resume
+                                 csrw mepc, t0
+                                 li t1, 0x1880
+                                 csrs mstatus, t1
+                                 mret
+                                 li a0, 1
+                                 resume:
+                                 csrr a1, mstatus
+                                 ebreak");
+        assert_eq!(register_file.read(10), 0);
+        /* MIE is restored from MPIE, which is set again, and MPP drops to user mode */
+        assert_eq!(register_file.read(11) & (MSTATUS_MIE | MSTATUS_MPIE), 0x88);
+        assert_eq!(register_file.read(11) & MSTATUS_MPP, 0);
+        assert_eq!(register_file.privilege, Privilege::Machine);
+    }
+
+    #[test]
+    fn ebreak_takes_the_breakpoint_trap() {
+        let mut ram = assemble("ebreak", RAM_BASE as u32).unwrap().code;
+        ram.resize(RAM_SIZE, 0);
+        let mut bus = Image::flat(ram).into_bus(Box::new(io::sink())).unwrap();
+        let mut register_file = RegisterFile::default();
+        register_file.pc = RAM_BASE as u32;
+        register_file.csr.mtvec = RAM_BASE as u32 + 0x100;
+        assert!(at_ebreak(&register_file, &bus));
+
+        assert_eq!(
+            step(&mut register_file, &mut bus),
+            Err(Exception::Breakpoint(RAM_BASE as u32))
+        );
+        assert_eq!(register_file.csr.mcause, 3);
+        assert_eq!(register_file.csr.mepc, RAM_BASE as u32);
+        assert_eq!(register_file.csr.mtval, RAM_BASE as u32);
+        assert_eq!(register_file.pc, RAM_BASE as u32 + 0x100);
+        assert!(!at_ebreak(&register_file, &bus));
+    }
+
+    #[test]
+    fn delegated_exceptions_trap_into_supervisor_mode() {
+        let register_file = run("la t0, machine
+                                 csrw mtvec, t0
+                                 la t0, supervisor
+                                 csrw stvec, t0
+                                 li t0, 0x4
+                                 csrw medeleg, t0
+                                 li t0, 0x800
+                                 csrs mstatus, t0
+                                 la t0, smode
+                                 csrw mepc, t0
+                                 mret
+                                 smode:
+                                 csrr a0, mstatus
+                                 supervisor:
+                                 csrr a1, scause
+                                 csrr a2, sepc
+                                 csrr a3, sstatus
+                                 ecall
+                                 machine:
+                                 csrr a4, mcause
+                                 csrr a5, mstatus
+                                 ebreak");
+        /* Machine CSRs are illegal in supervisor mode, delegated to its own handler */
+        assert_eq!(register_file.read(10), 0);
+        assert_eq!(register_file.read(11), 2);
+        assert_eq!(register_file.read(12), RAM_BASE as u32 + 60);
+        assert_eq!(register_file.read(13) & MSTATUS_SPP, MSTATUS_SPP);
+        /* The environment call isn't delegated and goes to machine mode */
+        assert_eq!(register_file.read(14), 9);
+        assert_eq!(register_file.read(15) & MSTATUS_MPP, 0x800);
+        assert_eq!(register_file.privilege, Privilege::Machine);
+    }
+
+    #[test]
+    fn returns_are_privileged() {
+        /* MPP is user mode after reset */
+        let register_file = trap(
+            "la t0, user
+                                  csrw mepc, t0
+                                  mret
+                                  user:
+                                  csrr a0, sstatus",
+        );
+        assert_eq!(register_file.csr.mcause, 2);
+        assert_eq!(register_file.csr.mstatus & MSTATUS_MPP, 0);
+
+        let register_file = trap(
+            "li t0, 0x800
+                                  csrs mstatus, t0
+                                  la t0, smode
+                                  csrw mepc, t0
+                                  mret
+                                  smode:
+                                  mret",
+        );
+        assert_eq!(register_file.csr.mcause, 2);
+        assert_eq!(register_file.csr.mepc, RAM_BASE as u32 + 40);
+
+        /* With TSR machine mode gets to emulate SRET */
+        let register_file = trap(
+            "li t0, 0x400800
+                                  csrs mstatus, t0
+                                  la t0, smode
+                                  csrw mepc, t0
+                                  mret
+                                  smode:
+                                  csrr a0, satp
+                                  sret",
+        );
+        assert_eq!(register_file.csr.mcause, 2);
+        assert_eq!(register_file.csr.mepc, RAM_BASE as u32 + 44);
+    }
+
+    #[test]
+    fn supervisor_return_restores_the_previous_mode() {
+        let register_file = run("la t0, supervisor
+                                 csrw stvec, t0
+                                 li t0, 0x100
+                                 csrw medeleg, t0
+                                 la t0, user
+                                 csrw mepc, t0
+                                 mret
+                                 user:
+                                 li a0, 1
+                                 ecall
+                                 supervisor:
+                                 csrr a1, scause
+                                 csrr a2, sstatus
+                                 ebreak");
+        assert_eq!(register_file.read(10), 1);
+        assert_eq!(register_file.read(11), 8);
+        /* The trap came from user mode */
+        assert_eq!(register_file.read(12) & MSTATUS_SPP, 0);
+        assert_eq!(register_file.privilege, Privilege::Supervisor);
+
+        let register_file = run("li t0, 0x120
+                                 csrs sstatus, t0
+                                 la t0, resume
+                                 csrw sepc, t0
+                                 sret
+                                 resume:
+                                 csrr a0, sstatus
+                                 ebreak");
+        assert_eq!(
+            register_file.read(10),
+            MSTATUS_SIE | MSTATUS_SPIE | FS_INITIAL
+        );
+        assert_eq!(register_file.privilege, Privilege::Supervisor);
+    }
+
+    #[test]
+    fn delegated_interrupts_only_interrupt_supervisor_mode() {
+        let register_file = run("la t0, handler
+                                 csrw stvec, t0
+                                 li t0, 0x20
+                                 csrw mideleg, t0
+                                 csrw mie, t0
+                                 csrs mip, t0
+                                 csrsi mstatus, 0x8
+                                 li t0, 0x800
+                                 csrs mstatus, t0
+                                 la t0, smode
+                                 csrw mepc, t0
+                                 mret
+                                 smode:
+                                 csrr a0, sip
+                                 csrsi sstatus, 0x2
+                                 li a1, 1
+                                 handler:
+                                 csrr a2, scause
+                                 ebreak");
+        assert_eq!(register_file.read(10), 0x20);
+        assert_eq!(register_file.read(11), 0);
+        assert_eq!(register_file.read(12), 0x8000_0005);
+        assert_eq!(register_file.privilege, Privilege::Supervisor);
+    }
+
+    #[test]
+    fn user_mode_can_only_read_enabled_counters() {
+        let user = "la t0, user
+                    csrw mepc, t0
+                    mret
+                    user:
+                    rdcycle a0
+                    rdinstret a1";
+        let register_file = trap(user);
+        assert_eq!(register_file.csr.mcause, 2);
+        assert_eq!(register_file.privilege, Privilege::Machine);
+
+        /* Supervisor mode has to pass the enable on to user mode */
+        let register_file = trap(&format!(
+            "li t0, 0x5
+                                           csrw mcounteren, t0
+                                           {user}"
+        ));
+        assert_eq!(register_file.csr.mcause, 2);
+
+        let register_file = trap(&format!(
+            "li t0, 0x5
+                                           csrw mcounteren, t0
+                                           csrw scounteren, t0
+                                           {user}"
+        ));
+        assert_eq!(register_file.csr.mcause, 0);
+        assert_eq!(register_file.privilege, Privilege::User);
+    }
+
+    #[test]
+    fn wait_for_interrupt_is_privileged() {
+        let register_file = trap(
+            "la t0, user
+                                  csrw mepc, t0
+                                  mret
+                                  user:
+                                  wfi",
+        );
+        assert_eq!(register_file.csr.mcause, 2);
+
+        let supervisor = "li t0, 0x800
+                          csrs mstatus, t0
+                          la t0, smode
+                          csrw mepc, t0
+                          mret
+                          smode:
+                          wfi
+                          ecall";
+        let register_file = trap(supervisor);
+        assert_eq!(register_file.csr.mcause, 9);
+        let register_file = trap(&format!(
+            "li t0, 0x200000
+                                           csrs mstatus, t0
+                                           {supervisor}"
+        ));
+        assert_eq!(register_file.csr.mcause, 2);
+    }
+
+    #[test]
+    fn modified_privilege_of_data_accesses() {
+        let register_file = run("li t0, 0x20800
+                                 csrs mstatus, t0
+                                 ebreak");
+        assert_eq!(register_file.privilege, Privilege::Machine);
+        assert_eq!(register_file.data_privilege(), Privilege::Supervisor);
+
+        /* Returning to a lower privilege level clears MPRV */
+        let register_file = run("li t0, 0x20000
+                                 csrs mstatus, t0
+                                 la t0, user
+                                 csrw mepc, t0
+                                 mret
+                                 user:
+                                 ebreak");
+        assert_eq!(register_file.privilege, Privilege::User);
+        assert_eq!(register_file.csr.mstatus & MSTATUS_MPRV, 0);
+        assert_eq!(register_file.data_privilege(), Privilege::User);
+    }
+
+    #[test]
+    fn sv32_translates_and_sets_accessed_and_dirty() {
+        let register_file = paged(
+            0x2000_0C07,
+            "li t0, 0x40000000
+             li t1, 0x1234
+             li t2, 0x80002000
+             lw a0, 0(t0)
+             lw a1, 0(t2)
+             sw t1, 0(t0)
+             lw a2, 0(t2)
+             li t2, 0x80003000
+             lw a3, 0(t2)",
+        );
+        assert_eq!(register_file.privilege, Privilege::Supervisor);
+        assert_eq!(register_file.read(10), 0);
+        /* Loads set A, the first store also D */
+        assert_eq!(register_file.read(11), 0x2000_0C47);
+        assert_eq!(register_file.read(12), 0x2000_0CC7);
+        assert_eq!(register_file.read(13), 0x1234);
+    }
+
+    #[test]
+    fn misaligned_accesses_span_pages() {
+        let register_file = paged(
+            0x2000_0CC7,
+            "li t0, 0x40000ffe
+             li t1, 0x11223344
+             sw t1, 0(t0)
+             lw a0, 0(t0)
+             li t2, 0x80003ffe
+             lhu a1, 0(t2)
+             li t2, 0x80005000
+             lhu a2, 0(t2)",
+        );
+        assert_eq!(register_file.read(10), 0x1122_3344);
+        assert_eq!(register_file.read(11), 0x3344);
+        assert_eq!(register_file.read(12), 0x1122);
+    }
+
+    #[test]
+    fn page_faults_report_the_virtual_address() {
+        let load = "li t0, 0x40000000
+                    lw a0, 0(t0)";
+        /* Invalid */
+        let register_file = paged(0, load);
+        assert_eq!(register_file.csr.mcause, 13);
+        assert_eq!(register_file.csr.mtval, 0x4000_0000);
+        /* User page, only accessible to supervisor mode with SUM */
+        let register_file = paged(0x2000_0CD7, load);
+        assert_eq!(register_file.csr.mcause, 13);
+        let register_file = paged(
+            0x2000_0CD7,
+            &format!("li t0, 0x40000\ncsrs sstatus, t0\n{load}"),
+        );
+        assert_eq!(register_file.privilege, Privilege::Supervisor);
+        /* Read-only */
+        let register_file = paged(
+            0x2000_0C43,
+            "li t0, 0x40000ffc
+             lw a0, 0(t0)
+             sw a0, 0(t0)",
+        );
+        assert_eq!(register_file.csr.mcause, 15);
+        assert_eq!(register_file.csr.mtval, 0x4000_0FFC);
+        /* Not executable */
+        let register_file = paged(
+            0x2000_0CC7,
+            "li t0, 0x40000000
+             jr t0",
+        );
+        assert_eq!(register_file.csr.mcause, 12);
+        assert_eq!(register_file.csr.mtval, 0x4000_0000);
+        /* Megapage that isn't aligned */
+        let register_file = paged(
+            0x2000_0C01,
+            "li t0, 0x80001400
+             li t1, 0x20000c0f
+             sw t1, 0(t0)
+             sfence.vma
+             li t0, 0x40000000
+             lw a0, 0(t0)",
+        );
+        assert_eq!(register_file.csr.mcause, 13);
+    }
+
+    #[test]
+    fn stale_translations_until_sfence_vma() {
+        /* The page doesn't share its TLB slot with the code */
+        let register_file = paged(
+            0,
+            "li t0, 0x40001000
+             li t1, 1
+             sw t1, 0(t0)
+             li t2, 0x80002000
+             li t3, 0x20000cc7
+             sw t3, 4(t2)
+             li t1, 2
+             sw t1, 0(t0)
+             sfence.vma t0
+             li t1, 3
+             sw t1, 0(t0)
+             li t2, 0x80005000
+             lw a0, 0(t2)
+             li t2, 0x80003000
+             lw a1, 0(t2)",
+        );
+        assert_eq!(register_file.read(10), 2);
+        assert_eq!(register_file.read(11), 3);
+    }
+
+    #[test]
+    fn sfence_vma_of_an_address_flushes_its_whole_megapage() {
+        /* Map virtual 0x40400000 as megapage onto RAM, cache two of its pages, unmap it
+         * and flush only the first of them */
+        let register_file = paged(
+            0,
+            "li t0, 0x80001404
+             li t1, 0x200000c7
+             sw t1, 0(t0)
+             li t2, 0x40403000
+             lw zero, 0(t2)
+             li t3, 0x40405000
+             lw zero, 0(t3)
+             sw zero, 0(t0)
+             sfence.vma t2
+             lw zero, 0(t3)",
+        );
+        assert_eq!(register_file.csr.mcause, 13);
+        assert_eq!(register_file.csr.mtval, 0x4040_5000);
+    }
+
+    #[test]
+    fn pmp_denies_lower_levels_without_a_matching_entry() {
+        let register_file = trap(
+            "csrw pmpcfg0, zero
+                                  la t0, user
+                                  csrw mepc, t0
+                                  mret
+                                  user:
+                                  ebreak",
+        );
+        assert_eq!(register_file.csr.mcause, 1);
+        assert_eq!(register_file.csr.mtval, RAM_BASE as u32 + 32);
+    }
+
+    #[test]
+    fn pmp_top_of_range_regions() {
+        /* Code up to RAM_BASE + 0x4000 is executable, the page after it read-only */
+        let register_file = trap(
+            "li t0, 0x20001000
+                                  csrw pmpaddr0, t0
+                                  li t0, 0x20001400
+                                  csrw pmpaddr1, t0
+                                  li t0, 0x090d
+                                  csrw pmpcfg0, t0
+                                  la t0, user
+                                  csrw mepc, t0
+                                  mret
+                                  user:
+                                  li t0, 0x80004000
+                                  lw a0, 0(t0)
+                                  li a0, 1
+                                  sw a0, 0(t0)",
+        );
+        assert_eq!(register_file.read(10), 1);
+        assert_eq!(register_file.csr.mcause, 7);
+        assert_eq!(register_file.csr.mtval, 0x8000_4000);
+        assert_eq!(register_file.csr.mstatus & MSTATUS_MPP, 0);
+    }
+
+    #[test]
+    fn pmp_naturally_aligned_regions_match_whole_accesses() {
+        /* 16 read-only bytes at RAM_BASE + 0x4000, everything else is accessible */
+        let register_file = trap(
+            "li t0, 0x20001001
+                                  csrw pmpaddr0, t0
+                                  li t0, -1
+                                  csrw pmpaddr1, t0
+                                  li t0, 0x1f19
+                                  csrw pmpcfg0, t0
+                                  la t0, user
+                                  csrw mepc, t0
+                                  mret
+                                  user:
+                                  li t0, 0x80004000
+                                  sw zero, 16(t0)
+                                  lw a0, 12(t0)
+                                  li a0, 1
+                                  lw a1, 14(t0)",
+        );
+        assert_eq!(register_file.read(10), 1);
+        /* Only half of the load is in the region, which fails */
+        assert_eq!(register_file.csr.mcause, 5);
+        assert_eq!(register_file.csr.mtval, 0x8000_400E);
+    }
+
+    #[test]
+    fn locked_pmp_entries_apply_to_machine_mode() {
+        let register_file = trap(
+            "li t0, 0x20001000
+                                  csrw pmpaddr0, t0
+                                  li t0, 0x91
+                                  csrw pmpcfg0, t0
+                                  csrw pmpcfg0, zero
+                                  csrw pmpaddr0, zero
+                                  csrr a0, pmpcfg0
+                                  csrr a1, pmpaddr0
+                                  li t0, 0x80004000
+                                  sw zero, 4(t0)
+                                  lw a2, 0(t0)
+                                  li a2, 1
+                                  sw a2, 0(t0)",
+        );
+        assert_eq!(register_file.read(10), 0x91);
+        assert_eq!(register_file.read(11), 0x2000_1000);
+        assert_eq!(register_file.read(12), 1);
+        assert_eq!(register_file.csr.mcause, 7);
+        assert_eq!(register_file.privilege, Privilege::Machine);
+    }
+
+    #[test]
+    fn illegal_instruction_reports_its_bits() {
+        let register_file = trap(".word 0x40001033");
+        assert_eq!(register_file.csr.mcause, 2);
+        assert_eq!(register_file.csr.mtval, 0x4000_1033);
+        assert_eq!(register_file.csr.mepc, RAM_BASE as u32 + 12);
+    }
+
+    #[test]
+    fn instructions_of_disabled_extensions_are_illegal() {
+        let mut bus = Image::flat(vec![0; RAM_SIZE])
+            .into_bus(Box::new(io::sink()))
+            .unwrap();
+        let mut register_file = RegisterFile::default();
+        register_file.write(5, 6);
+        let mul = Instruction::MUL(10, 5, 5);
+        let without_m = Extensions {
+            m: false,
+            ..Extensions::ALL
+        };
+        assert_eq!(
+            exec(&mut register_file, &mut bus, &mul, 4, without_m),
+            Err(Exception::IllegalInstruction(0))
+        );
+        assert_eq!(register_file.read(10), 0);
+        exec(&mut register_file, &mut bus, &mul, 4, Extensions::ALL).unwrap();
+        assert_eq!(register_file.read(10), 36);
+    }
+
+    #[test]
+    fn fence_and_wfi_continue() {
+        let register_file = run("fence
+                                 wfi
+                                 li a0, 1
+                                 ebreak");
+        assert_eq!(register_file.read(10), 1);
+    }
+
+    #[test]
+    fn compressed_instructions_advance_by_two() {
+        let register_file = run("la a5, target
+                                 .half 0x4505
+                                 .half 0x9782
+                                 target:
+                                 ebreak");
+        /* c.li a0, 1 then c.jalr a5 */
+        assert_eq!(register_file.read(10), 1);
+        assert_eq!(register_file.read(1), RAM_BASE as u32 + 12);
     }
-    register_file.pc += 4;
-    true
 }
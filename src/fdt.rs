@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+
+/* Structure block tokens */
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_END: u32 = 9;
+
+const FDT_MAGIC: u32 = 0xD00D_FEED;
+const HEADER_LEN: usize = 40;
+/* Version 17, readable by software that understands version 16 */
+const VERSION: u32 = 17;
+const LAST_COMPATIBLE_VERSION: u32 = 16;
+
+/// Writes a flattened device tree blob, as described by the Devicetree Specification.
+///
+/// Nodes are opened and closed in the order they appear in the tree, properties belong
+/// to the innermost open node. All values are big-endian.
+#[derive(Default)]
+pub struct Fdt {
+    structure: Vec<u8>,
+    strings: Vec<u8>,
+    /* Offsets of property names already in the strings block */
+    names: HashMap<String, u32>,
+    depth: usize,
+}
+
+impl Fdt {
+    fn token(&mut self, token: u32) {
+        self.structure.extend_from_slice(&token.to_be_bytes());
+    }
+
+    /// Pads the structure block to the next 4 byte boundary
+    fn align(&mut self) {
+        self.structure
+            .resize(self.structure.len().next_multiple_of(4), 0);
+    }
+
+    /// Opens a node, the root node has the empty name
+    pub fn begin_node(&mut self, name: &str) {
+        self.token(FDT_BEGIN_NODE);
+        self.structure.extend_from_slice(name.as_bytes());
+        self.structure.push(0);
+        self.align();
+        self.depth += 1;
+    }
+
+    pub fn end_node(&mut self) {
+        self.token(FDT_END_NODE);
+        self.depth -= 1;
+    }
+
+    pub fn property(&mut self, name: &str, value: &[u8]) {
+        let strings = &mut self.strings;
+        let name_offset = *self.names.entry(name.to_string()).or_insert_with(|| {
+            let offset = strings.len() as u32;
+            strings.extend_from_slice(name.as_bytes());
+            strings.push(0);
+            offset
+        });
+        self.token(FDT_PROP);
+        self.token(value.len() as u32);
+        self.token(name_offset);
+        self.structure.extend_from_slice(value);
+        self.align();
+    }
+
+    /// A property without value, its presence is the information
+    pub fn property_empty(&mut self, name: &str) {
+        self.property(name, &[]);
+    }
+
+    pub fn property_u32(&mut self, name: &str, value: u32) {
+        self.property_cells(name, &[value]);
+    }
+
+    pub fn property_cells(&mut self, name: &str, cells: &[u32]) {
+        let value: Vec<u8> = cells.iter().flat_map(|cell| cell.to_be_bytes()).collect();
+        self.property(name, &value);
+    }
+
+    pub fn property_string(&mut self, name: &str, value: &str) {
+        self.property_strings(name, &[value]);
+    }
+
+    /// A string list, each string is NUL terminated
+    pub fn property_strings(&mut self, name: &str, values: &[&str]) {
+        let mut value = Vec::new();
+        for string in values {
+            value.extend_from_slice(string.as_bytes());
+            value.push(0);
+        }
+        self.property(name, &value);
+    }
+
+    /// The blob, with an empty memory reservation block. All nodes must be closed.
+    pub fn finish(mut self) -> Vec<u8> {
+        assert_eq!(self.depth, 0, "Device tree has unclosed nodes");
+        self.token(FDT_END);
+
+        /* The memory reservation block is a single terminating entry of 16 zero bytes */
+        let reservations = HEADER_LEN;
+        let structure = reservations + 16;
+        let strings = structure + self.structure.len();
+        let total = strings + self.strings.len();
+
+        let header = [
+            FDT_MAGIC,
+            total as u32,
+            structure as u32,
+            strings as u32,
+            reservations as u32,
+            VERSION,
+            LAST_COMPATIBLE_VERSION,
+            /* boot_cpuid_phys */
+            0,
+            self.strings.len() as u32,
+            self.structure.len() as u32,
+        ];
+        let mut blob: Vec<u8> = header.iter().flat_map(|word| word.to_be_bytes()).collect();
+        blob.resize(structure, 0);
+        blob.extend_from_slice(&self.structure);
+        blob.extend_from_slice(&self.strings);
+        blob
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// A node of a blob read back by `parse`
+    pub struct Node {
+        pub name: String,
+        pub properties: Vec<(String, Vec<u8>)>,
+        pub children: Vec<Node>,
+    }
+
+    impl Node {
+        pub fn child(&self, name: &str) -> &Node {
+            self.children
+                .iter()
+                .find(|child| child.name == name)
+                .unwrap_or_else(|| panic!("No node {name} in {}", self.name))
+        }
+
+        pub fn get(&self, name: &str) -> Option<&[u8]> {
+            self.properties
+                .iter()
+                .find(|(property, _)| property == name)
+                .map(|(_, value)| value.as_slice())
+        }
+
+        pub fn cells(&self, name: &str) -> Vec<u32> {
+            let value = self.get(name).unwrap();
+            value
+                .chunks(4)
+                .map(|cell| u32::from_be_bytes(cell.try_into().unwrap()))
+                .collect()
+        }
+
+        pub fn string(&self, name: &str) -> &str {
+            let value = self.get(name).unwrap();
+            std::str::from_utf8(value.strip_suffix(&[0]).unwrap()).unwrap()
+        }
+    }
+
+    fn word(blob: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(blob[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn c_string(bytes: &[u8]) -> String {
+        let end = bytes.iter().position(|&byte| byte == 0).unwrap();
+        String::from_utf8(bytes[..end].to_vec()).unwrap()
+    }
+
+    /// Reads back a blob, checking its header and the nesting of the structure block
+    pub fn parse(blob: &[u8]) -> Node {
+        assert_eq!(word(blob, 0), FDT_MAGIC);
+        assert_eq!(word(blob, 4) as usize, blob.len());
+        let structure = word(blob, 8) as usize;
+        let strings = word(blob, 12) as usize;
+        assert_eq!(word(blob, 20), VERSION);
+        assert_eq!(word(blob, 24), LAST_COMPATIBLE_VERSION);
+        assert_eq!(structure + word(blob, 36) as usize, strings);
+        assert_eq!(strings + word(blob, 32) as usize, blob.len());
+
+        let mut stack: Vec<Node> = Vec::new();
+        let mut root = None;
+        let mut offset = structure;
+        loop {
+            let token = word(blob, offset);
+            offset += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = c_string(&blob[offset..]);
+                    offset = (offset + name.len() + 1).next_multiple_of(4);
+                    stack.push(Node {
+                        name,
+                        properties: Vec::new(),
+                        children: Vec::new(),
+                    });
+                }
+                FDT_END_NODE => {
+                    let node = stack.pop().unwrap();
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(node),
+                        None => root = Some(node),
+                    }
+                }
+                FDT_PROP => {
+                    let len = word(blob, offset) as usize;
+                    let name = c_string(&blob[strings + word(blob, offset + 4) as usize..]);
+                    let value = blob[offset + 8..offset + 8 + len].to_vec();
+                    offset = (offset + 8 + len).next_multiple_of(4);
+                    stack.last_mut().unwrap().properties.push((name, value));
+                }
+                FDT_END => break,
+                _ => panic!("Unknown token {token} at 0x{offset:X}"),
+            }
+        }
+        assert!(stack.is_empty());
+        root.unwrap()
+    }
+
+    #[test]
+    fn header_and_blocks() {
+        let mut fdt = Fdt::default();
+        fdt.begin_node("");
+        fdt.end_node();
+        let blob = fdt.finish();
+        /* Header, the empty reservation block and BEGIN_NODE with its name, END_NODE, END */
+        assert_eq!(blob.len(), HEADER_LEN + 16 + 8 + 4 + 4);
+        assert_eq!(word(&blob, 8) as usize, HEADER_LEN + 16);
+        assert_eq!(word(&blob, 16) as usize, HEADER_LEN);
+        assert_eq!(blob[HEADER_LEN..HEADER_LEN + 16], [0; 16]);
+        assert_eq!(word(&blob, 28), 0);
+        let root = parse(&blob);
+        assert_eq!(root.name, "");
+        assert!(root.properties.is_empty() && root.children.is_empty());
+    }
+
+    #[test]
+    fn nodes_and_properties() {
+        let mut fdt = Fdt::default();
+        fdt.begin_node("");
+        fdt.property_u32("#address-cells", 1);
+        fdt.begin_node("memory@80000000");
+        fdt.property_cells("reg", &[0x8000_0000, 0x400_0000]);
+        fdt.property_string("device_type", "memory");
+        fdt.end_node();
+        fdt.begin_node("cpus");
+        fdt.property_u32("#address-cells", 1);
+        fdt.property_empty("ranges");
+        fdt.property_strings("compatible", &["a,b", "c"]);
+        fdt.property("bytes", &[1, 2, 3]);
+        fdt.end_node();
+        fdt.end_node();
+        let blob = fdt.finish();
+
+        let root = parse(&blob);
+        assert_eq!(root.cells("#address-cells"), [1]);
+        let memory = root.child("memory@80000000");
+        assert_eq!(memory.cells("reg"), [0x8000_0000, 0x400_0000]);
+        assert_eq!(memory.string("device_type"), "memory");
+        let cpus = root.child("cpus");
+        assert_eq!(cpus.get("ranges"), Some(&[][..]));
+        assert_eq!(cpus.get("compatible"), Some(&b"a,b\0c\0"[..]));
+        assert_eq!(cpus.get("bytes"), Some(&[1, 2, 3][..]));
+
+        /* Names used twice are stored once */
+        let strings = &blob[word(&blob, 12) as usize..];
+        assert_eq!(
+            strings,
+            b"#address-cells\0reg\0device_type\0ranges\0compatible\0bytes\0"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Device tree has unclosed nodes")]
+    fn unclosed_nodes_are_a_bug() {
+        let mut fdt = Fdt::default();
+        fdt.begin_node("");
+        fdt.finish();
+    }
+}
@@ -0,0 +1,562 @@
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+use crate::bus::{Bus, Size};
+use crate::debugger::{Debugger, Stop};
+use crate::mmu::{peek_translate, PAGE_SIZE};
+use crate::system::{csr_name, RegisterFile, FP_REGISTER_NAMES, REGISTER_NAMES};
+use crate::watch::{WatchKind, Watchpoint};
+
+/* Register numbers as GDB knows them, CSRs follow the FP registers in the order of their numbers */
+const PC_REGNUM: usize = 32;
+const FIRST_FP_REGNUM: usize = 33;
+const FIRST_CSR_REGNUM: usize = 65;
+const CSR_COUNT: usize = 4096;
+
+/// Time the guest runs between two checks for an interrupt from GDB
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// The byte GDB sends to interrupt a running target, Ctrl-C
+const INTERRUPT: u8 = 0x03;
+
+/* Signals of stop replies */
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+/// What GDB sent
+enum Request {
+    Packet(String),
+    /// The connection is gone
+    Closed,
+}
+
+/// A GDB remote serial protocol session on a single connection.
+struct Session {
+    input: Receiver<u8>,
+    output: Box<dyn Write>,
+    /* Packets are acknowledged until GDB switches to no-ack mode */
+    ack: bool,
+    /* Watchpoints set by GDB, with the virtual address it knows them by */
+    watchpoints: Vec<(usize, Watchpoint)>,
+}
+
+/// Waits for GDB on `target` and lets it control the guest until it detaches or kills it.
+///
+/// `target` is a TCP port on localhost, a `host:port` pair or the path of a unix socket.
+pub fn serve(
+    target: &str,
+    rf: &mut RegisterFile,
+    bus: &mut Bus,
+    debugger: &mut Debugger,
+) -> anyhow::Result<()> {
+    let (input, output) = listen(target)?;
+    let mut session = Session {
+        input,
+        output,
+        ack: true,
+        watchpoints: Vec::new(),
+    };
+    session.run(rf, bus, debugger)
+}
+
+fn listen(target: &str) -> anyhow::Result<(Receiver<u8>, Box<dyn Write>)> {
+    let address = match target.parse::<u16>() {
+        Ok(port) => Some(format!("127.0.0.1:{port}")),
+        Err(_) if target.contains(':') => Some(target.to_string()),
+        Err(_) => None,
+    };
+    eprintln!("Waiting for GDB on {target}");
+    match address {
+        Some(address) => {
+            let (stream, _) = TcpListener::bind(&address)?.accept()?;
+            stream.set_nodelay(true)?;
+            Ok((spawn_reader(stream.try_clone()?), Box::new(stream)))
+        }
+        None => listen_unix(target),
+    }
+}
+
+#[cfg(unix)]
+fn listen_unix(path: &str) -> anyhow::Result<(Receiver<u8>, Box<dyn Write>)> {
+    use std::fs;
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixListener;
+
+    /* A socket left behind by an earlier run would make binding fail */
+    if fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+    let (stream, _) = UnixListener::bind(path)?.accept()?;
+    Ok((spawn_reader(stream.try_clone()?), Box::new(stream)))
+}
+
+#[cfg(not(unix))]
+fn listen_unix(path: &str) -> anyhow::Result<(Receiver<u8>, Box<dyn Write>)> {
+    anyhow::bail!("Unix sockets are not supported on this platform: {path}")
+}
+
+/// Reads the connection on a background thread, so Ctrl-C can be seen while the guest runs
+fn spawn_reader(mut stream: impl Read + Send + 'static) -> Receiver<u8> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buffer = [0; 4096];
+        while let Ok(length @ 1..) = stream.read(&mut buffer) {
+            if buffer[..length]
+                .iter()
+                .any(|&byte| sender.send(byte).is_err())
+            {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+/// Hex digits of the `bytes` low bytes of `value` in target (little-endian) order
+fn hex_le(value: u64, bytes: usize) -> String {
+    value.to_le_bytes()[..bytes]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn parse_hex_le(hex: &str) -> Option<u64> {
+    if !hex.len().is_multiple_of(2) || hex.len() > 16 {
+        return None;
+    }
+    (0..hex.len()).step_by(2).rev().try_fold(0, |value, index| {
+        Some((value << 8) | u64::from_str_radix(hex.get(index..index + 2)?, 16).ok()?)
+    })
+}
+
+fn parse_hex(hex: &str) -> Option<usize> {
+    usize::from_str_radix(hex, 16).ok()
+}
+
+/// Target description, which tells GDB about the FP registers and CSRs
+fn target_xml(rf: &RegisterFile) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n<target>\n\
+         <architecture>riscv:rv32</architecture>\n<feature name=\"org.gnu.gdb.riscv.cpu\">\n",
+    );
+    for (regnum, name) in REGISTER_NAMES.iter().enumerate() {
+        let kind = match *name {
+            "sp" => "data_ptr",
+            "ra" => "code_ptr",
+            _ => "int",
+        };
+        xml +=
+            &format!("<reg name=\"{name}\" bitsize=\"32\" type=\"{kind}\" regnum=\"{regnum}\"/>\n");
+    }
+    xml += &format!(
+        "<reg name=\"pc\" bitsize=\"32\" type=\"code_ptr\" regnum=\"{PC_REGNUM}\"/>\n</feature>\n"
+    );
+
+    xml += "<feature name=\"org.gnu.gdb.riscv.fpu\">\n<union id=\"riscv_double\">\
+            <field name=\"float\" type=\"ieee_single\"/>\
+            <field name=\"double\" type=\"ieee_double\"/></union>\n";
+    for (index, name) in FP_REGISTER_NAMES.iter().enumerate() {
+        let regnum = FIRST_FP_REGNUM + index;
+        xml += &format!(
+            "<reg name=\"{name}\" bitsize=\"64\" type=\"riscv_double\" regnum=\"{regnum}\"/>\n"
+        );
+    }
+    /* The FP CSRs belong to the FP feature, all other CSRs to the CSR one */
+    let csrs = |fp: bool| {
+        (0..CSR_COUNT as u32)
+            .filter(move |&index| (1..=3).contains(&index) == fp && rf.csr.read(index).is_ok())
+            .filter_map(|index| Some((FIRST_CSR_REGNUM + index as usize, csr_name(index)?)))
+    };
+    for (regnum, name) in csrs(true) {
+        xml += &format!("<reg name=\"{name}\" bitsize=\"32\" type=\"int\" regnum=\"{regnum}\"/>\n");
+    }
+    xml += "</feature>\n<feature name=\"org.gnu.gdb.riscv.csr\">\n";
+    for (regnum, name) in csrs(false) {
+        xml += &format!(
+            "<reg name=\"{name}\" bitsize=\"32\" type=\"int\" regnum=\"{regnum}\" group=\"csr\"/>\n"
+        );
+    }
+    xml + "</feature>\n</target>\n"
+}
+
+/// Hex digits of register `regnum`, `None` if there is no such register
+fn read_register(rf: &RegisterFile, regnum: usize) -> Option<String> {
+    match regnum {
+        0..PC_REGNUM => Some(hex_le(u64::from(rf.read(regnum)), 4)),
+        PC_REGNUM => Some(hex_le(u64::from(rf.pc), 4)),
+        FIRST_FP_REGNUM..FIRST_CSR_REGNUM => Some(hex_le(rf.read_f64(regnum - FIRST_FP_REGNUM), 8)),
+        _ => {
+            let index = u32::try_from(regnum - FIRST_CSR_REGNUM).ok()?;
+            let value = rf.csr.read(index).ok()?;
+            Some(hex_le(u64::from(value), 4))
+        }
+    }
+}
+
+fn write_register(rf: &mut RegisterFile, regnum: usize, value: u64) -> Option<()> {
+    match regnum {
+        0..PC_REGNUM => rf.write(regnum, value as u32),
+        PC_REGNUM => rf.pc = value as u32,
+        FIRST_FP_REGNUM..FIRST_CSR_REGNUM => rf.write_f64(regnum - FIRST_FP_REGNUM, value),
+        _ => {
+            let index = u32::try_from(regnum - FIRST_CSR_REGNUM).ok()?;
+            rf.csr.write(index, value as u32).ok()?;
+        }
+    }
+    Some(())
+}
+
+/// Stop reply for why the guest stopped, `None` for a step that completed normally
+fn stop_reply(stop: Option<Stop>, watchpoints: &[(usize, Watchpoint)]) -> String {
+    match stop {
+        None => format!("T{SIGTRAP:02x}"),
+        Some(Stop::Breakpoint(_)) => format!("T{SIGTRAP:02x}swbreak:;"),
+        Some(Stop::Watchpoint(hit)) => {
+            let kind = match hit.watchpoint.kind {
+                WatchKind::Read => "rwatch",
+                WatchKind::Access => "awatch",
+                WatchKind::Write | WatchKind::Change => "watch",
+            };
+            let addr = watchpoints
+                .iter()
+                .find(|(_, watchpoint)| *watchpoint == hit.watchpoint)
+                .map_or(hit.watchpoint.addr, |&(addr, _)| addr);
+            format!("T{SIGTRAP:02x}{kind}:{addr:x};")
+        }
+        Some(Stop::Ebreak(_) | Stop::Trap(_)) => format!("T{SIGTRAP:02x}"),
+    }
+}
+
+impl Session {
+    fn run(
+        &mut self,
+        rf: &mut RegisterFile,
+        bus: &mut Bus,
+        debugger: &mut Debugger,
+    ) -> anyhow::Result<()> {
+        loop {
+            let packet = match self.receive()? {
+                Request::Packet(packet) => packet,
+                Request::Closed => return Ok(()),
+            };
+            let reply = match packet.split_at(packet.len().min(1)) {
+                ("?", _) => stop_reply(None, &self.watchpoints),
+                ("g", _) => (0..=PC_REGNUM)
+                    .filter_map(|regnum| read_register(rf, regnum))
+                    .collect(),
+                ("G", registers) => self.write_registers(rf, registers),
+                ("p", regnum) => parse_hex(regnum)
+                    .and_then(|regnum| read_register(rf, regnum))
+                    .unwrap_or_else(|| "E01".to_string()),
+                ("P", assignment) => assignment
+                    .split_once('=')
+                    .and_then(|(regnum, value)| {
+                        write_register(rf, parse_hex(regnum)?, parse_hex_le(value)?)
+                    })
+                    .map_or_else(|| "E01".to_string(), |()| "OK".to_string()),
+                ("m", range) => Self::read_memory(rf, bus, range),
+                ("M", write) => Self::write_memory(rf, bus, write),
+                ("Z" | "z", point) => self.breakpoint(rf, bus, debugger, &packet[..1], point),
+                ("s", addr) => {
+                    if let Some(addr) = parse_hex(addr) {
+                        rf.pc = addr as u32;
+                    }
+                    stop_reply(debugger.step(rf, bus), &self.watchpoints)
+                }
+                ("c", addr) => {
+                    if let Some(addr) = parse_hex(addr) {
+                        rf.pc = addr as u32;
+                    }
+                    match self.resume(rf, bus, debugger) {
+                        Some(stop) => stop_reply(Some(stop), &self.watchpoints),
+                        None => format!("T{SIGINT:02x}"),
+                    }
+                }
+                ("D", _) => {
+                    self.send("OK")?;
+                    return Ok(());
+                }
+                ("k", _) => return Ok(()),
+                ("H", _) => "OK".to_string(),
+                ("T", _) => "OK".to_string(),
+                ("q" | "Q" | "v", _) => match self.query(rf, &packet) {
+                    Some(reply) => reply,
+                    None => return Ok(()),
+                },
+                _ => String::new(),
+            };
+            self.send(&reply)?;
+        }
+    }
+
+    /// Answers general queries and `v` packets, `None` if GDB asked to end the session
+    fn query(&mut self, rf: &RegisterFile, packet: &str) -> Option<String> {
+        let reply = match packet {
+            _ if packet.starts_with("qSupported") => {
+                "PacketSize=4000;qXfer:features:read+;swbreak+;hwbreak+;QStartNoAckMode+"
+                    .to_string()
+            }
+            "QStartNoAckMode" => {
+                /* GDB still acknowledges the OK, only later packets go unacknowledged */
+                self.ack = false;
+                "OK".to_string()
+            }
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            "qSymbol::" => "OK".to_string(),
+            "vKill;1" | "vKill" => return None,
+            _ => match packet.strip_prefix("qXfer:features:read:target.xml:") {
+                Some(range) => {
+                    let xml = target_xml(rf);
+                    range
+                        .split_once(',')
+                        .and_then(|(offset, length)| Some((parse_hex(offset)?, parse_hex(length)?)))
+                        .map_or_else(
+                            || "E01".to_string(),
+                            |(offset, length)| {
+                                let start = offset.min(xml.len());
+                                let end = offset.saturating_add(length).min(xml.len());
+                                let more = if end < xml.len() { 'm' } else { 'l' };
+                                format!("{more}{}", &xml[start..end])
+                            },
+                        )
+                }
+                None => String::new(),
+            },
+        };
+        Some(reply)
+    }
+
+    fn write_registers(&self, rf: &mut RegisterFile, registers: &str) -> String {
+        for (regnum, chunk) in registers
+            .as_bytes()
+            .chunks(8)
+            .take(PC_REGNUM + 1)
+            .enumerate()
+        {
+            let value = std::str::from_utf8(chunk).ok().and_then(parse_hex_le);
+            match value {
+                Some(value) => {
+                    write_register(rf, regnum, value);
+                }
+                None => return "E01".to_string(),
+            }
+        }
+        "OK".to_string()
+    }
+
+    /// Reads memory without side effects on devices, as far as it can be read.
+    ///
+    /// Addresses are virtual ones of the current privilege level, like the pc.
+    fn read_memory(rf: &RegisterFile, bus: &Bus, range: &str) -> String {
+        let Some((addr, length)) = range
+            .split_once(',')
+            .and_then(|(addr, length)| Some((parse_hex(addr)?, parse_hex(length)?)))
+        else {
+            return "E01".to_string();
+        };
+        let bytes: String = (addr..addr.saturating_add(length))
+            .map_while(|addr| bus.peek(peek_translate(rf, bus, addr as u32)?, Size::Byte))
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        if bytes.is_empty() && length > 0 {
+            "E14".to_string()
+        } else {
+            bytes
+        }
+    }
+
+    fn write_memory(rf: &RegisterFile, bus: &mut Bus, write: &str) -> String {
+        let Some((addr, data)) = write.split_once(',').and_then(|(addr, rest)| {
+            let (_, data) = rest.split_once(':')?;
+            Some((parse_hex(addr)?, data))
+        }) else {
+            return "E01".to_string();
+        };
+        let mut result = "OK".to_string();
+        for (offset, chunk) in data.as_bytes().chunks(2).enumerate() {
+            let byte = std::str::from_utf8(chunk)
+                .ok()
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok());
+            let paddr = peek_translate(rf, bus, (addr + offset) as u32);
+            match (byte, paddr) {
+                (Some(byte), Some(paddr)) if bus.write(paddr, Size::Byte, byte).is_ok() => {}
+                _ => {
+                    result = "E14".to_string();
+                    break;
+                }
+            }
+        }
+        /* Writes of the debugger are not the guest's business */
+        bus.take_watch_hit();
+        result
+    }
+
+    /// Inserts (`Z`) or removes (`z`) a breakpoint or watchpoint.
+    ///
+    /// Watchpoints are checked by the bus on physical addresses, so a watched range is
+    /// translated when it is inserted and stays on the physical memory it was mapped to.
+    /// Ranges that aren't mapped or not physically contiguous are refused.
+    fn breakpoint(
+        &mut self,
+        rf: &RegisterFile,
+        bus: &mut Bus,
+        debugger: &mut Debugger,
+        insert: &str,
+        point: &str,
+    ) -> String {
+        let mut fields = point.split(',');
+        let (Some(kind), Some(addr), Some(length)) = (
+            fields.next(),
+            fields.next().and_then(parse_hex),
+            fields
+                .next()
+                .and_then(|length| parse_hex(length.split(';').next()?)),
+        ) else {
+            return "E01".to_string();
+        };
+        let insert = insert == "Z";
+        let kind = match kind {
+            /* Software and hardware breakpoints are both kept by the debugger, memory isn't patched */
+            "0" | "1" => {
+                if insert {
+                    debugger.set_breakpoint(addr as u32);
+                } else {
+                    debugger.clear_breakpoint(addr as u32);
+                }
+                return "OK".to_string();
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return String::new(),
+        };
+        let len = length.max(1);
+        if !insert {
+            let Some(index) = self.watchpoints.iter().position(|(watched, watchpoint)| {
+                *watched == addr && watchpoint.kind == kind && watchpoint.len == len
+            }) else {
+                return "E01".to_string();
+            };
+            let (_, watchpoint) = self.watchpoints.remove(index);
+            if let Some(index) = bus.watchpoints().iter().position(|w| *w == watchpoint) {
+                bus.unwatch(index);
+            }
+            return "OK".to_string();
+        }
+        let Some(last) = addr
+            .checked_add(len - 1)
+            .filter(|&last| last <= u32::MAX as usize)
+        else {
+            return "E01".to_string();
+        };
+        /* Every page of the range has to continue where the one before it ends */
+        let Some(paddr) = peek_translate(rf, bus, addr as u32) else {
+            return "E14".to_string();
+        };
+        let contiguous = (addr..=last)
+            .step_by(PAGE_SIZE as usize)
+            .chain([last])
+            .all(|vaddr| peek_translate(rf, bus, vaddr as u32) == Some(paddr + (vaddr - addr)));
+        if !contiguous {
+            return "E14".to_string();
+        }
+        let watchpoint = Watchpoint {
+            kind,
+            addr: paddr,
+            len,
+            condition: None,
+        };
+        bus.watch(watchpoint);
+        self.watchpoints.push((addr, watchpoint));
+        "OK".to_string()
+    }
+
+    /// Runs the guest until it stops, `None` if GDB interrupted it first
+    fn resume(
+        &mut self,
+        rf: &mut RegisterFile,
+        bus: &mut Bus,
+        debugger: &mut Debugger,
+    ) -> Option<Stop> {
+        /* The first step gets past a breakpoint the guest is stopped at */
+        let mut stop = debugger.step(rf, bus);
+        loop {
+            match stop {
+                /* The guest handles its own traps, GDB is only told about them when stepping */
+                None | Some(Stop::Trap(_)) => {}
+                Some(stop) => return Some(stop),
+            }
+            if self.interrupted() {
+                return None;
+            }
+            stop = debugger.run(rf, bus, POLL_INTERVAL);
+        }
+    }
+
+    /// Whether GDB sent Ctrl-C, nothing else arrives while the guest runs
+    fn interrupted(&self) -> bool {
+        loop {
+            match self.input.try_recv() {
+                Ok(INTERRUPT) | Err(TryRecvError::Disconnected) => return true,
+                Ok(_) => {}
+                Err(TryRecvError::Empty) => return false,
+            }
+        }
+    }
+
+    fn next_byte(&self) -> Option<u8> {
+        self.input.recv().ok()
+    }
+
+    fn receive(&mut self) -> anyhow::Result<Request> {
+        loop {
+            /* Acknowledgements and interrupts of a target that already stopped are dropped */
+            loop {
+                match self.next_byte() {
+                    Some(b'$') => break,
+                    Some(_) => {}
+                    None => return Ok(Request::Closed),
+                }
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.next_byte() {
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                    None => return Ok(Request::Closed),
+                }
+            }
+            let (Some(high), Some(low)) = (self.next_byte(), self.next_byte()) else {
+                return Ok(Request::Closed);
+            };
+            let expected = std::str::from_utf8(&[high, low])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            if self.ack {
+                if expected != Some(checksum(&data)) {
+                    self.output.write_all(b"-")?;
+                    continue;
+                }
+                self.output.write_all(b"+")?;
+            }
+            return Ok(Request::Packet(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+
+    fn send(&mut self, data: &str) -> anyhow::Result<()> {
+        let packet = format!("${data}#{:02x}", checksum(data.as_bytes()));
+        self.output.write_all(packet.as_bytes())?;
+        self.output.flush()?;
+        Ok(())
+    }
+}
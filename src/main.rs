@@ -4,9 +4,11 @@
 
 use std::env;
 use std::fs;
-use std::io;
+use std::io::{self, Write};
+use std::sync::mpsc;
+use std::time::Duration;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 use tui::{
     backend::{Backend, CrosstermBackend},
@@ -20,44 +22,299 @@ use crossterm::{
 };
 
 mod ui;
-use ui::ViewState;
+use ui::{key_bytes, leaves_input, ViewState};
+
+mod assembler;
+use assembler::assemble;
+
+mod boot;
+use boot::{Kernel, Machine};
+
+mod bus;
+
+mod clint;
+use clint::{Clint, TimerSource, CLINT_LEN};
+
+mod console;
+use console::Console;
+
+mod debugger;
+use debugger::{Debugger, Stop};
 
 mod decoder;
-use decoder::{decode, Instruction};
+
+mod disasm;
+
+mod encoder;
+
+mod fdt;
+
+mod memory;
+
+mod mmu;
+
+mod plic;
+use plic::{Plic, PLIC_LEN};
+
+mod pmp;
+
+mod gdb;
 
 mod executer;
-use executer::exec;
+
+mod softfloat;
+
+mod symbols;
+use symbols::Symbols;
 
 mod system;
-use system::{Memory, RegisterFile};
+use system::{Image, Privilege, RegisterFile, RAM_BASE};
+
+mod trap;
+use trap::Exception;
+
+mod uart;
+use uart::{stdin_input, Uart, UART_LEN};
+
+mod watch;
+use watch::{parse_watchpoint, Watchpoint};
+
+/// How often the TUI is redrawn while the guest runs
+const REFRESH_INTERVAL: Duration = Duration::from_millis(50);
 
 #[derive(Parser, Debug)]
+#[command(subcommand_negates_reqs = true)]
 struct Args {
-    /// Name of the person to greet
-    #[arg(short, long)]
-    file: String,
+    /// ELF executable to run, `.s` files are assembled, anything else is loaded as flat
+    /// binary into RAM
+    #[arg(short, long, required_unless_present = "kernel")]
+    file: Option<String>,
+
+    /// Kernel `Image` to boot with the built-in SBI firmware, instead of a program. It is
+    /// started like RISC-V Linux, with the hart ID in a0 and a device tree in a1.
+    #[arg(long, conflicts_with = "file")]
+    kernel: Option<String>,
+
+    /// Initial ramdisk for the kernel
+    #[arg(long, requires = "kernel", conflicts_with = "file")]
+    initrd: Option<String>,
+
+    /// Command line of the kernel
+    #[arg(
+        long,
+        requires = "kernel",
+        conflicts_with = "file",
+        default_value = "console=ttyS0"
+    )]
+    bootargs: String,
+
+    /// RAM of the machine the kernel boots on, in MiB
+    #[arg(long, requires = "kernel", conflicts_with = "file", value_parser = clap::value_parser!(u16).range(8..=2048), default_value_t = 64)]
+    memory: u16,
 
-    /// Number of times to greet
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Run without the TUI until the program hits an EBREAK
     #[arg(long, default_value_t = false)]
     headless: bool,
+
+    /// Let GDB control the guest, listening on a TCP port, host:port or unix socket path
+    #[arg(long, value_name = "PORT|SOCKET")]
+    gdb: Option<String>,
+
+    /// Address the CLINT is mapped at
+    #[arg(long, value_parser = parse_address, default_value = "0x02000000")]
+    clint_base: usize,
+
+    /// What advances mtime of the CLINT, retired instructions by default. A kernel is told
+    /// the frequency of mtime, it always runs from the wall clock.
+    #[arg(long, value_enum)]
+    timer: Option<TimerSource>,
+
+    /// Address the PLIC is mapped at
+    #[arg(long, value_parser = parse_address, default_value = "0x0C000000")]
+    plic_base: usize,
+
+    /// Number of interrupt sources of the PLIC
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..1024), default_value_t = 31)]
+    plic_sources: u16,
+
+    /// Number of contexts of the PLIC, context 0 interrupts machine mode, context 1 supervisor
+    /// mode
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..15872), default_value_t = 2)]
+    plic_contexts: u16,
+
+    /// Address the UART is mapped at
+    #[arg(long, value_parser = parse_address, default_value = "0x10000000")]
+    uart_base: usize,
+
+    /// PLIC source the UART interrupt is wired to
+    #[arg(long, default_value_t = 10)]
+    uart_irq: usize,
+
+    /// Print the memory map before running
+    #[arg(long, default_value_t = false)]
+    memory_map: bool,
+
+    /// Stop when memory is accessed, as "KIND ADDR[+LEN] [OP VALUE]" with KIND one of
+    /// read, write, change or access, e.g. "change 0x80001000+4 == 0x2A"
+    #[arg(long, value_parser = parse_watchpoint)]
+    watch: Vec<Watchpoint>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the instructions of a program instead of running it
+    Disasm {
+        /// ELF executable, anything else is disassembled as flat binary loaded into RAM
+        file: String,
+    },
+    /// Assemble a source file into a flat binary that runs from the start of RAM
+    Asm {
+        /// Assembly source
+        file: String,
+
+        /// Where to write the binary
+        #[arg(short, long)]
+        output: String,
+    },
+}
+
+fn parse_address(arg: &str) -> Result<usize, std::num::ParseIntError> {
+    match arg.strip_prefix("0x").or_else(|| arg.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => arg.parse(),
+    }
 }
 
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
+    match &args.command {
+        Some(Command::Disasm { file }) => {
+            let data = fs::read(file)?;
+            /* A reader like `head` that stops early closes the pipe, that is no error */
+            return match disasm::print_listing(&data, &mut io::stdout().lock()) {
+                Err(error)
+                    if error
+                        .downcast_ref::<io::Error>()
+                        .is_some_and(|error| error.kind() == io::ErrorKind::BrokenPipe) =>
+                {
+                    Ok(())
+                }
+                result => result,
+            };
+        }
+        Some(Command::Asm { file, output }) => {
+            let program = assemble(&fs::read_to_string(file)?, RAM_BASE as u32)?;
+            fs::write(output, program.code)?;
+            return Ok(());
+        }
+        None => {}
+    }
+
+    let timer = match (&args.kernel, args.timer) {
+        (Some(_), Some(TimerSource::Instret)) => {
+            anyhow::bail!("A kernel needs mtime at a known frequency, use --timer wall-clock")
+        }
+        (Some(_), _) => TimerSource::WallClock,
+        (None, timer) => timer.unwrap_or(TimerSource::Instret),
+    };
 
     let mut register_file: RegisterFile = RegisterFile::default();
-    let mut memory: Memory = Memory::default_ram(fs::read(args.file).unwrap());
-    register_file.pc = u32::try_from(memory.ram_base).unwrap();
+    let (image, symbols) = if let Some(path) = &args.kernel {
+        let machine = Machine {
+            ram_size: usize::from(args.memory) << 20,
+            clint_base: args.clint_base,
+            plic_base: args.plic_base,
+            plic_sources: usize::from(args.plic_sources),
+            plic_contexts: usize::from(args.plic_contexts),
+            uart_base: args.uart_base,
+            uart_irq: args.uart_irq,
+        };
+        let initrd = args.initrd.as_ref().map(fs::read).transpose()?;
+        let kernel = Kernel {
+            image: &fs::read(path)?,
+            initrd: initrd.as_deref(),
+            bootargs: &args.bootargs,
+        };
+        boot::linux(&machine, &kernel)?
+    } else {
+        let path = args.file.as_ref().unwrap();
+        let file = fs::read(path)?;
+        if file.starts_with(b"\x7fELF") {
+            (Image::from_elf(&file)?, Symbols::from_elf(&file)?)
+        } else if path.ends_with(".s") || path.ends_with(".S") {
+            let program = assemble(&String::from_utf8(file)?, RAM_BASE as u32)?;
+            (Image::flat(program.code), program.symbols)
+        } else {
+            (Image::flat(file), Symbols::default())
+        }
+    };
+    register_file.pc = image.entry;
 
-    if args.headless {
-        loop {
-            let inst = decode(memory.read_word(register_file.pc as usize)).unwrap();
+    /* The TUI owns the terminal, guest output goes to its I/O pane and its keys to the guest */
+    let headless = args.headless || args.gdb.is_some();
+    let console = Console::new();
+    let output = || -> Box<dyn Write> {
+        if headless {
+            Box::new(io::stdout())
+        } else {
+            Box::new(console.clone())
+        }
+    };
+    let (keys, input) = if headless {
+        (None, stdin_input())
+    } else {
+        let (keys, input) = mpsc::channel();
+        (Some(keys), input)
+    };
+
+    let mut bus = image.into_bus(output())?;
+    bus.attach("clint", args.clint_base, CLINT_LEN, Clint::new(timer))?;
+    let plic = Plic::new(
+        usize::from(args.plic_sources),
+        usize::from(args.plic_contexts),
+    );
+    bus.attach("plic", args.plic_base, PLIC_LEN, plic)?;
+    let uart = Uart::new(input, output());
+    bus.attach("uart", args.uart_base, UART_LEN, uart)?;
+    bus.route_irq("uart", args.uart_irq)?;
+    for watchpoint in args.watch {
+        bus.watch(watchpoint);
+    }
+    if args.memory_map {
+        print!("{bus}");
+    }
+
+    /* A kernel takes its own breakpoint traps, only those of the firmware stop the machine */
+    let mut debugger = Debugger::default();
+    debugger.ebreak = Some(if args.kernel.is_some() {
+        Privilege::Machine
+    } else {
+        Privilege::User
+    });
 
-            if !exec(&mut register_file, &mut memory, &inst, true, true) {
-                break;
+    if let Some(target) = &args.gdb {
+        gdb::serve(target, &mut register_file, &mut bus, &mut debugger)?;
+    } else if args.headless {
+        loop {
+            match debugger.step(&mut register_file, &mut bus) {
+                Some(Stop::Ebreak(_)) => break,
+                Some(Stop::Trap(Exception::InstructionAccessFault(addr)))
+                    if addr == register_file.csr.mtvec & !0b11
+                        || addr == register_file.csr.stvec & !0b11 =>
+                {
+                    anyhow::bail!("Trap handler at 0x{addr:08X} is outside the memory map");
+                }
+                Some(stop @ Stop::Watchpoint(_)) => anyhow::bail!("{stop}"),
+                _ => {}
             }
         }
-        anyhow::ensure!(register_file.read(17) == 93, "Test failed");
+        /* Programs tell whether a test passed in a7, a kernel just shuts the machine down */
+        if args.kernel.is_none() {
+            anyhow::ensure!(register_file.read(17) == 93, "Test failed");
+        }
     } else {
         enable_raw_mode()?;
         let stdout = io::stdout();
@@ -65,24 +322,81 @@ fn main() -> anyhow::Result<()> {
         let mut terminal = Terminal::new(backend)?;
         terminal.clear();
 
-        let mut ui = ViewState::new();
+        let mut ui = ViewState::new(console, symbols);
+        let keys = keys.unwrap();
+        let mut running = false;
 
         loop {
-            terminal.draw(|f| ui.ui(f, &register_file, &memory))?;
+            terminal.draw(|f| ui.ui(f, &register_file, &bus, &debugger))?;
+
+            /* While running the screen is refreshed between slices of execution */
+            if running {
+                if let Some(stop) = debugger.run(&mut register_file, &mut bus, REFRESH_INTERVAL) {
+                    running = false;
+                    ui.status = stop.to_string();
+                }
+                if !event::poll(Duration::ZERO)? {
+                    continue;
+                }
+            }
 
-            if let Event::Key(key) = event::read()? {
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if ui.input_mode {
+                if leaves_input(key) {
+                    ui.input_mode = false;
+                } else {
+                    for byte in key_bytes(key) {
+                        keys.send(byte)?;
+                    }
+                }
+                continue;
+            }
+            if let Some(command) = &mut ui.command {
                 match key.code {
-                    KeyCode::Char('q') => {
-                        break;
+                    KeyCode::Enter => {
+                        let line = ui.command.take().unwrap();
+                        ui.status = debugger.command(&line, &mut bus, &ui.symbols);
                     }
-                    KeyCode::Char('s') => {
-                        let inst = decode(memory.read_word(register_file.pc as usize)).unwrap();
-                        if !exec(&mut register_file, &mut memory, &inst, true, false) {
-                            break;
-                        }
+                    KeyCode::Esc => ui.command = None,
+                    KeyCode::Backspace => {
+                        command.pop();
                     }
-                    _ => todo!(),
+                    KeyCode::Char(c) => command.push(c),
+                    _ => {}
                 }
+                continue;
+            }
+            if running {
+                running = false;
+                ui.status = "Stopped".to_string();
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') => {
+                    break;
+                }
+                KeyCode::Char('s') => {
+                    ui.status = match debugger.step(&mut register_file, &mut bus) {
+                        Some(stop) => stop.to_string(),
+                        None => String::new(),
+                    };
+                }
+                /* The first step ignores breakpoints to get past the one it is stopped at */
+                KeyCode::Char('c') => match debugger.step(&mut register_file, &mut bus) {
+                    Some(stop) => ui.status = stop.to_string(),
+                    None => {
+                        running = true;
+                        ui.status = "Running, press any key to stop".to_string();
+                    }
+                },
+                KeyCode::Char('b') => ui.command = Some("break ".to_string()),
+                KeyCode::Char(':') => ui.command = Some(String::new()),
+                KeyCode::Char('i') => ui.input_mode = true,
+                KeyCode::PageUp => ui.scroll_up(10),
+                KeyCode::PageDown => ui.scroll_down(10),
+                _ => {}
             }
         }
 
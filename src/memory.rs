@@ -0,0 +1,78 @@
+use std::io::Write;
+
+use crate::bus::{AccessFault, Device, Size};
+
+/// RAM or ROM, backed by a little-endian byte array.
+pub struct Memory {
+    data: Vec<u8>,
+    writable: bool,
+}
+
+impl Memory {
+    pub fn ram(data: Vec<u8>) -> Self {
+        Self {
+            data,
+            writable: true,
+        }
+    }
+
+    pub fn rom(data: Vec<u8>) -> Self {
+        Self {
+            data,
+            writable: false,
+        }
+    }
+
+    fn bytes(&self, offset: usize, size: Size) -> Option<&[u8]> {
+        self.data.get(offset..offset.checked_add(size.bytes())?)
+    }
+}
+
+impl Device for Memory {
+    fn read(&mut self, offset: usize, size: Size) -> Result<u32, AccessFault> {
+        self.peek(offset, size).ok_or(AccessFault)
+    }
+
+    fn write(&mut self, offset: usize, size: Size, value: u32) -> Result<(), AccessFault> {
+        if !self.writable {
+            return Err(AccessFault);
+        }
+        let end = offset.checked_add(size.bytes()).ok_or(AccessFault)?;
+        let bytes = self.data.get_mut(offset..end).ok_or(AccessFault)?;
+        bytes.copy_from_slice(&value.to_le_bytes()[..size.bytes()]);
+        Ok(())
+    }
+
+    fn peek(&self, offset: usize, size: Size) -> Option<u32> {
+        let bytes = self.bytes(offset, size)?;
+        Some(
+            bytes
+                .iter()
+                .rev()
+                .fold(0, |value, &byte| (value << 8) | u32::from(byte)),
+        )
+    }
+}
+
+/// Write-only port that prints every byte written to it.
+pub struct PrintPort {
+    output: Box<dyn Write>,
+}
+
+impl PrintPort {
+    pub fn new(output: Box<dyn Write>) -> Self {
+        Self { output }
+    }
+}
+
+impl Device for PrintPort {
+    fn read(&mut self, _offset: usize, _size: Size) -> Result<u32, AccessFault> {
+        Err(AccessFault)
+    }
+
+    fn write(&mut self, _offset: usize, _size: Size, value: u32) -> Result<(), AccessFault> {
+        let _ = self.output.write_all(&[value as u8]);
+        let _ = self.output.flush();
+        Ok(())
+    }
+}
@@ -0,0 +1,290 @@
+use crate::bus::{Bus, Size};
+use crate::pmp::Pmp;
+use crate::system::{Privilege, RegisterFile, MSTATUS_MXR, MSTATUS_SUM};
+use crate::trap::Exception;
+
+/* Fields of a page table entry */
+const PTE_V: u32 = 1 << 0;
+const PTE_R: u32 = 1 << 1;
+const PTE_W: u32 = 1 << 2;
+const PTE_X: u32 = 1 << 3;
+const PTE_U: u32 = 1 << 4;
+const PTE_A: u32 = 1 << 6;
+const PTE_D: u32 = 1 << 7;
+
+/* Fields of satp */
+const SATP_MODE_SV32: u32 = 1 << 31;
+const SATP_PPN: u32 = (1 << 22) - 1;
+
+const PAGE_SHIFT: u32 = 12;
+pub const PAGE_SIZE: u32 = 1 << PAGE_SHIFT;
+
+/// Number of translations the TLB holds, a power of two
+const TLB_ENTRIES: usize = 64;
+
+/// Kind of a memory access, they need different permissions and raise different faults
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessType {
+    Fetch,
+    Load,
+    /* AMOs translate as stores, they need both read and write permission */
+    Store,
+}
+
+impl AccessType {
+    fn page_fault(self, addr: u32) -> Exception {
+        match self {
+            Self::Fetch => Exception::InstructionPageFault(addr),
+            Self::Load => Exception::LoadPageFault(addr),
+            Self::Store => Exception::StorePageFault(addr),
+        }
+    }
+
+    fn access_fault(self, addr: u32) -> Exception {
+        match self {
+            Self::Fetch => Exception::InstructionAccessFault(addr),
+            Self::Load => Exception::LoadAccessFault(addr),
+            Self::Store => Exception::StoreAccessFault(addr),
+        }
+    }
+}
+
+/// A cached leaf of the page table, superpages are cached per 4 KiB page
+#[derive(Debug, Clone, Copy)]
+struct TlbEntry {
+    /* The satp the translation was made with, ASIDs are not implemented */
+    satp: u32,
+    vpn: u32,
+    ppn: u32,
+    /* Whether the leaf is a megapage, all of its 4 KiB slices go with an SFENCE.VMA */
+    megapage: bool,
+    /* Permission, A and D bits of the leaf PTE */
+    flags: u32,
+}
+
+/// Software TLB of recent Sv32 translations, direct-mapped by virtual page number.
+///
+/// Permissions are checked on every access, changes of the privilege level, SUM or MXR
+/// don't need a flush. Changes to the page tables only become visible after
+/// `SFENCE.VMA`, like on hardware.
+pub struct Tlb {
+    entries: [Option<TlbEntry>; TLB_ENTRIES],
+}
+
+impl Default for Tlb {
+    fn default() -> Self {
+        Self {
+            entries: [None; TLB_ENTRIES],
+        }
+    }
+}
+
+impl Tlb {
+    fn slot(vpn: u32) -> usize {
+        vpn as usize & (TLB_ENTRIES - 1)
+    }
+
+    fn lookup(&self, satp: u32, vpn: u32) -> Option<TlbEntry> {
+        self.entries[Self::slot(vpn)].filter(|entry| entry.satp == satp && entry.vpn == vpn)
+    }
+
+    fn insert(&mut self, entry: TlbEntry) {
+        self.entries[Self::slot(entry.vpn)] = Some(entry);
+    }
+
+    /// Drops the translations that use the leaf PTE of `addr`, or all of them for `None`.
+    ///
+    /// For a megapage these are the translations of every 4 KiB page within it.
+    pub fn flush(&mut self, addr: Option<u32>) {
+        match addr {
+            Some(addr) => {
+                let vpn = addr >> PAGE_SHIFT;
+                for slot in &mut self.entries {
+                    let matches = slot.is_some_and(|entry| {
+                        entry.vpn == vpn || (entry.megapage && entry.vpn >> 10 == vpn >> 10)
+                    });
+                    if matches {
+                        *slot = None;
+                    }
+                }
+            }
+            None => self.entries = [None; TLB_ENTRIES],
+        }
+    }
+}
+
+/// Whether a leaf PTE with `flags` grants the access
+fn permitted(flags: u32, access: AccessType, privilege: Privilege, mstatus: u32) -> bool {
+    let user_page = flags & PTE_U != 0;
+    let privilege_ok = match privilege {
+        Privilege::User => user_page,
+        /* Supervisor mode can access user pages with SUM, but never execute them */
+        Privilege::Supervisor => {
+            !user_page || (access != AccessType::Fetch && mstatus & MSTATUS_SUM != 0)
+        }
+        Privilege::Machine => true,
+    };
+    let kind_ok = match access {
+        AccessType::Fetch => flags & PTE_X != 0,
+        /* MXR makes executable pages readable */
+        AccessType::Load => {
+            flags & PTE_R != 0 || (flags & PTE_X != 0 && mstatus & MSTATUS_MXR != 0)
+        }
+        AccessType::Store => flags & PTE_W != 0,
+    };
+    privilege_ok && kind_ok
+}
+
+/// Walks the two level page table for `addr`, reading it without side effects.
+///
+/// The table is accessed like supervisor mode would, the PMP has to permit that.
+/// Besides the translation this returns the address and new value of the leaf PTE if its
+/// A bit, or D bit for stores, still has to be set in memory.
+fn walk(
+    bus: &Bus,
+    pmp: &Pmp,
+    satp: u32,
+    addr: u32,
+    access: AccessType,
+) -> Result<(TlbEntry, Option<(usize, u32)>), Exception> {
+    let page_fault = access.page_fault(addr);
+    let access_fault = access.access_fault(addr);
+    let vpn = [(addr >> PAGE_SHIFT) & 0x3FF, addr >> 22];
+
+    let mut table = (satp & SATP_PPN) as usize * PAGE_SIZE as usize;
+    for level in (0..2).rev() {
+        let pte_addr = table + 4 * vpn[level] as usize;
+        if !pmp.check(pte_addr, 4, AccessType::Load, Privilege::Supervisor) {
+            return Err(access_fault);
+        }
+        let pte = bus.peek(pte_addr, Size::Word).ok_or(access_fault)?;
+        let ppn = pte >> 10;
+        if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) {
+            return Err(page_fault);
+        }
+        if pte & (PTE_R | PTE_X) == 0 {
+            /* A pointer to the next level of the table */
+            table = ppn as usize * PAGE_SIZE as usize;
+            continue;
+        }
+
+        /* A superpage must be aligned to its size */
+        if level == 1 && ppn & 0x3FF != 0 {
+            return Err(page_fault);
+        }
+        let mut flags = (pte & 0xFF) | PTE_A;
+        if access == AccessType::Store {
+            flags |= PTE_D;
+        }
+        let update = (flags != pte & 0xFF).then_some((pte_addr, pte | flags));
+        let entry = TlbEntry {
+            satp,
+            vpn: addr >> PAGE_SHIFT,
+            ppn: if level == 1 { ppn | vpn[0] } else { ppn },
+            megapage: level == 1,
+            flags,
+        };
+        return Ok((entry, update));
+    }
+    /* Ran out of levels without finding a leaf */
+    Err(page_fault)
+}
+
+/// Privilege the access is made with, `None` if it isn't translated
+fn translated_privilege(register_file: &RegisterFile, access: AccessType) -> Option<Privilege> {
+    let privilege = match access {
+        AccessType::Fetch => register_file.privilege,
+        AccessType::Load | AccessType::Store => register_file.data_privilege(),
+    };
+    let enabled = register_file.csr.satp & SATP_MODE_SV32 != 0;
+    (enabled && privilege != Privilege::Machine).then_some(privilege)
+}
+
+/// Translates the virtual address `addr` of an access into a physical address.
+///
+/// Machine mode and `satp` in Bare mode use physical addresses directly. Otherwise the
+/// translation comes from the TLB or a walk of the Sv32 page table, which is cached.
+/// Missing permissions raise a page fault, a page table outside of memory an access fault.
+pub fn translate(
+    register_file: &mut RegisterFile,
+    bus: &mut Bus,
+    addr: u32,
+    access: AccessType,
+) -> Result<usize, Exception> {
+    let Some(privilege) = translated_privilege(register_file, access) else {
+        return Ok(addr as usize);
+    };
+    let satp = register_file.csr.satp;
+    let mstatus = register_file.csr.mstatus;
+
+    let vpn = addr >> PAGE_SHIFT;
+    let cached = register_file
+        .tlb
+        .lookup(satp, vpn)
+        /* The first store to a page has to go to the table to set its D bit */
+        .filter(|entry| access != AccessType::Store || entry.flags & PTE_D != 0);
+    let entry = match cached {
+        Some(entry) => entry,
+        None => {
+            let (entry, update) = walk(bus, &register_file.csr.pmp, satp, addr, access)?;
+            /* A and D are only set for accesses that are permitted */
+            if !permitted(entry.flags, access, privilege, mstatus) {
+                return Err(access.page_fault(addr));
+            }
+            if let Some((pte_addr, pte)) = update {
+                let pmp = &register_file.csr.pmp;
+                if !pmp.check(pte_addr, 4, AccessType::Store, Privilege::Supervisor) {
+                    return Err(access.access_fault(addr));
+                }
+                bus.write(pte_addr, Size::Word, pte)
+                    .map_err(|_| access.access_fault(addr))?;
+            }
+            register_file.tlb.insert(entry);
+            entry
+        }
+    };
+
+    if !permitted(entry.flags, access, privilege, mstatus) {
+        return Err(access.page_fault(addr));
+    }
+    Ok(((entry.ppn as usize) << PAGE_SHIFT) | (addr & (PAGE_SIZE - 1)) as usize)
+}
+
+/// Translates `addr` as a load or fetch would, for the debugger.
+///
+/// Nothing is changed, neither the TLB nor A and D bits, and permissions are not checked.
+/// `None` if the address is not mapped.
+pub fn peek_translate(register_file: &RegisterFile, bus: &Bus, addr: u32) -> Option<usize> {
+    if translated_privilege(register_file, AccessType::Fetch).is_none() {
+        return Some(addr as usize);
+    }
+    let csr = &register_file.csr;
+    let (entry, _) = walk(bus, &csr.pmp, csr.satp, addr, AccessType::Load).ok()?;
+    Some(((entry.ppn as usize) << PAGE_SHIFT) | (addr & (PAGE_SIZE - 1)) as usize)
+}
+
+/// Checks an access of `size` bytes at the physical address `paddr` against the PMP.
+///
+/// A denied access faults with its virtual address `addr`.
+pub fn check_pmp(
+    register_file: &RegisterFile,
+    addr: u32,
+    paddr: usize,
+    size: usize,
+    access: AccessType,
+) -> Result<(), Exception> {
+    let privilege = match access {
+        AccessType::Fetch => register_file.privilege,
+        AccessType::Load | AccessType::Store => register_file.data_privilege(),
+    };
+    if register_file.csr.pmp.check(paddr, size, access, privilege) {
+        Ok(())
+    } else {
+        Err(access.access_fault(addr))
+    }
+}
+
+/// Whether an access of `size` at `addr` spans two pages
+pub fn crosses_page(addr: u32, size: Size) -> bool {
+    (addr & (PAGE_SIZE - 1)) as usize + size.bytes() > PAGE_SIZE as usize
+}
@@ -0,0 +1,296 @@
+use crate::bus::{AccessFault, Device, Size};
+use crate::trap::Interrupt;
+
+/* Register blocks within the PLIC, as laid out by the RISC-V PLIC specification */
+const PRIORITY: usize = 0x00_0000;
+const PENDING: usize = 0x00_1000;
+const ENABLE: usize = 0x00_2000;
+const ENABLE_STRIDE: usize = 0x80;
+const CONTEXT: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+
+/// Size of the address range taken by the PLIC
+pub const PLIC_LEN: usize = 0x400_0000;
+
+/// Priorities and thresholds are WARL, only these bits are implemented
+const PRIORITY_MASK: u32 = 0b111;
+
+/// Platform-level interrupt controller, routing device interrupts to the hart.
+///
+/// Sources are numbered from 1, source 0 means "no interrupt". Context 0 is machine mode
+/// of the hart and raises MEIP, context 1 is supervisor mode and raises SEIP. Further
+/// contexts are not wired to the hart.
+pub struct Plic {
+    sources: usize,
+    priority: Vec<u32>,
+    /* One bit per source, packed into words like the pending and enable registers */
+    pending: Vec<u32>,
+    claimed: Vec<u32>,
+    level: Vec<u32>,
+    enable: Vec<Vec<u32>>,
+    threshold: Vec<u32>,
+}
+
+fn bit(bits: &[u32], index: usize) -> bool {
+    bits[index / 32] & (1 << (index % 32)) != 0
+}
+
+fn set_bit(bits: &mut [u32], index: usize, value: bool) {
+    if value {
+        bits[index / 32] |= 1 << (index % 32);
+    } else {
+        bits[index / 32] &= !(1 << (index % 32));
+    }
+}
+
+impl Plic {
+    /// Creates a PLIC with `sources` interrupt sources (1 to 1023) and `contexts` contexts
+    pub fn new(sources: usize, contexts: usize) -> Self {
+        let words = (sources + 1).div_ceil(32);
+        Self {
+            sources,
+            priority: vec![0; sources + 1],
+            pending: vec![0; words],
+            claimed: vec![0; words],
+            level: vec![0; words],
+            enable: vec![vec![0; words]; contexts],
+            threshold: vec![0; contexts],
+        }
+    }
+
+    /// Sets the level of the interrupt line of `source`, devices call this when their state changes
+    pub fn set_level(&mut self, source: usize, level: bool) {
+        if source == 0 || source > self.sources {
+            return;
+        }
+        set_bit(&mut self.level, source, level);
+        /* The gateway forwards a new request only once the previous one is completed */
+        if level && !bit(&self.claimed, source) {
+            set_bit(&mut self.pending, source, true);
+        }
+    }
+
+    /// Highest priority source that is pending and enabled above the threshold of `context`
+    fn best_source(&self, context: usize) -> Option<usize> {
+        (1..=self.sources)
+            .filter(|&source| bit(&self.pending, source) && bit(&self.enable[context], source))
+            .filter(|&source| self.priority[source] > self.threshold[context])
+            /* Ties go to the lowest source number */
+            .max_by_key(|&source| (self.priority[source], usize::MAX - source))
+    }
+
+    fn claim(&mut self, context: usize) -> u32 {
+        match self.best_source(context) {
+            Some(source) => {
+                set_bit(&mut self.pending, source, false);
+                set_bit(&mut self.claimed, source, true);
+                source as u32
+            }
+            None => 0,
+        }
+    }
+
+    fn complete(&mut self, context: usize, source: usize) {
+        /* Completions for sources not enabled for the context are ignored */
+        if source == 0 || source > self.sources || !bit(&self.enable[context], source) {
+            return;
+        }
+        set_bit(&mut self.claimed, source, false);
+        if bit(&self.level, source) {
+            set_bit(&mut self.pending, source, true);
+        }
+    }
+
+    /// Interrupts currently raised by the PLIC, as `mip` bits
+    fn pending(&self) -> u32 {
+        [Interrupt::MachineExternal, Interrupt::SupervisorExternal]
+            .into_iter()
+            .enumerate()
+            .filter(|&(context, _)| {
+                context < self.threshold.len() && self.best_source(context).is_some()
+            })
+            .fold(0, |pending, (_, interrupt)| pending | interrupt.mask())
+    }
+
+    /// Splits an offset into a register block into the context and offset within it
+    fn context(&self, offset: usize, base: usize, stride: usize) -> Option<(usize, usize)> {
+        let context = (offset - base) / stride;
+        (context < self.threshold.len()).then_some((context, (offset - base) % stride))
+    }
+
+    /// Bits of the sources that exist in word `word` of the pending and enable registers
+    fn source_mask(&self, word: usize) -> u32 {
+        let first = word * 32;
+        (first..first + 32)
+            .filter(|source| (1..=self.sources).contains(source))
+            .fold(0, |mask, source| mask | (1 << (source - first)))
+    }
+
+    /// Reads a register, claiming an interrupt is a side effect of reading its register
+    fn read_word(&mut self, offset: usize) -> Option<u32> {
+        if !offset.is_multiple_of(4) || offset >= PLIC_LEN {
+            return None;
+        }
+        let value = match offset {
+            PRIORITY..PENDING => self.priority.get((offset - PRIORITY) / 4).copied(),
+            PENDING..ENABLE => self.pending.get((offset - PENDING) / 4).copied(),
+            ENABLE..CONTEXT => self
+                .context(offset, ENABLE, ENABLE_STRIDE)
+                .and_then(|(context, offset)| self.enable[context].get(offset / 4).copied()),
+            _ => match self.context(offset, CONTEXT, CONTEXT_STRIDE) {
+                Some((context, 0)) => Some(self.threshold[context]),
+                Some((context, 4)) => Some(self.claim(context)),
+                _ => None,
+            },
+        };
+        /* Registers of sources and contexts that don't exist read as zero */
+        Some(value.unwrap_or(0))
+    }
+
+    fn write_word(&mut self, offset: usize, value: u32) -> Option<()> {
+        if !offset.is_multiple_of(4) || offset >= PLIC_LEN {
+            return None;
+        }
+        match offset {
+            PRIORITY..PENDING => {
+                let source = (offset - PRIORITY) / 4;
+                if (1..=self.sources).contains(&source) {
+                    self.priority[source] = value & PRIORITY_MASK;
+                }
+            }
+            /* Pending bits are read-only */
+            PENDING..ENABLE => {}
+            ENABLE..CONTEXT => {
+                if let Some((context, offset)) = self.context(offset, ENABLE, ENABLE_STRIDE) {
+                    let word = offset / 4;
+                    let mask = self.source_mask(word);
+                    if let Some(enable) = self.enable[context].get_mut(word) {
+                        *enable = value & mask;
+                    }
+                }
+            }
+            _ => match self.context(offset, CONTEXT, CONTEXT_STRIDE) {
+                Some((context, 0)) => self.threshold[context] = value & PRIORITY_MASK,
+                Some((context, 4)) => self.complete(context, value as usize),
+                _ => {}
+            },
+        }
+        Some(())
+    }
+}
+
+/* All registers are 32 bit wide and only support word accesses */
+impl Device for Plic {
+    fn read(&mut self, offset: usize, size: Size) -> Result<u32, AccessFault> {
+        if size != Size::Word {
+            return Err(AccessFault);
+        }
+        self.read_word(offset).ok_or(AccessFault)
+    }
+
+    fn write(&mut self, offset: usize, size: Size, value: u32) -> Result<(), AccessFault> {
+        if size != Size::Word {
+            return Err(AccessFault);
+        }
+        self.write_word(offset, value).ok_or(AccessFault)
+    }
+
+    fn interrupts(&self) -> u32 {
+        self.pending()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLAIM: usize = CONTEXT + 4;
+
+    /// A PLIC with sources 1 to 3 at the given priorities, all enabled for context 0
+    fn plic(priorities: [u32; 3]) -> Plic {
+        let mut plic = Plic::new(3, 2);
+        for (index, priority) in priorities.into_iter().enumerate() {
+            plic.write(PRIORITY + 4 * (index + 1), Size::Word, priority)
+                .unwrap();
+        }
+        plic.write(ENABLE, Size::Word, 0b1110).unwrap();
+        plic
+    }
+
+    #[test]
+    fn claim_returns_the_highest_priority_source() {
+        let mut plic = plic([1, 3, 3]);
+        plic.set_level(1, true);
+        plic.set_level(2, true);
+        plic.set_level(3, true);
+        assert_eq!(plic.read(PENDING, Size::Word), Ok(0b1110));
+        assert_eq!(plic.interrupts(), Interrupt::MachineExternal.mask());
+
+        /* Ties go to the lower source number */
+        assert_eq!(plic.read(CLAIM, Size::Word), Ok(2));
+        assert_eq!(plic.read(CLAIM, Size::Word), Ok(3));
+        assert_eq!(plic.read(CLAIM, Size::Word), Ok(1));
+        assert_eq!(plic.read(CLAIM, Size::Word), Ok(0));
+        assert_eq!(plic.read(PENDING, Size::Word), Ok(0));
+        assert_eq!(plic.interrupts(), 0);
+    }
+
+    #[test]
+    fn claimed_source_is_pending_again_only_after_completion() {
+        let mut plic = plic([1, 1, 1]);
+        plic.set_level(1, true);
+        assert_eq!(plic.read(CLAIM, Size::Word), Ok(1));
+
+        /* The line is still high, but the gateway holds it back until completion */
+        plic.set_level(1, true);
+        assert_eq!(plic.interrupts(), 0);
+        plic.write(CLAIM, Size::Word, 1).unwrap();
+        assert_eq!(plic.interrupts(), Interrupt::MachineExternal.mask());
+        assert_eq!(plic.read(CLAIM, Size::Word), Ok(1));
+
+        /* Once the line is low, completion leaves nothing pending */
+        plic.set_level(1, false);
+        plic.write(CLAIM, Size::Word, 1).unwrap();
+        assert_eq!(plic.interrupts(), 0);
+    }
+
+    #[test]
+    fn threshold_masks_lower_priorities() {
+        let mut plic = plic([2, 5, 0]);
+        plic.write(CONTEXT, Size::Word, 2).unwrap();
+        plic.set_level(1, true);
+        plic.set_level(3, true);
+        assert_eq!(plic.interrupts(), 0);
+        assert_eq!(plic.read(CLAIM, Size::Word), Ok(0));
+
+        plic.set_level(2, true);
+        assert_eq!(plic.interrupts(), Interrupt::MachineExternal.mask());
+        assert_eq!(plic.read(CLAIM, Size::Word), Ok(2));
+
+        /* Priorities and thresholds only keep their implemented bits */
+        plic.write(CONTEXT, Size::Word, 0xFF).unwrap();
+        assert_eq!(plic.read(CONTEXT, Size::Word), Ok(7));
+    }
+
+    #[test]
+    fn supervisor_context_raises_seip() {
+        let mut plic = plic([1, 1, 1]);
+        plic.write(ENABLE, Size::Word, 0).unwrap();
+        plic.write(ENABLE + ENABLE_STRIDE, Size::Word, 0b10)
+            .unwrap();
+        plic.set_level(1, true);
+        assert_eq!(plic.interrupts(), Interrupt::SupervisorExternal.mask());
+        assert_eq!(plic.read(CLAIM, Size::Word), Ok(0));
+        assert_eq!(plic.read(CLAIM + CONTEXT_STRIDE, Size::Word), Ok(1));
+    }
+
+    #[test]
+    fn registers_only_take_word_accesses() {
+        let mut plic = plic([1, 1, 1]);
+        assert_eq!(plic.read(PRIORITY + 4, Size::Byte), Err(AccessFault));
+        assert_eq!(plic.write(PRIORITY + 2, Size::Word, 1), Err(AccessFault));
+        /* Sources that don't exist read as zero and ignore writes */
+        plic.write(PRIORITY + 4 * 4, Size::Word, 1).unwrap();
+        assert_eq!(plic.read(PRIORITY + 4 * 4, Size::Word), Ok(0));
+    }
+}
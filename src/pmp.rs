@@ -0,0 +1,131 @@
+use crate::mmu::AccessType;
+use crate::system::Privilege;
+
+/* Fields of an entry in pmpcfg */
+const PMP_R: u8 = 1 << 0;
+const PMP_W: u8 = 1 << 1;
+const PMP_X: u8 = 1 << 2;
+const PMP_A: u8 = 0b11 << 3;
+const PMP_L: u8 = 1 << 7;
+
+/* Address matching modes of the A field */
+const A_OFF: u8 = 0;
+const A_TOR: u8 = 1;
+const A_NA4: u8 = 2;
+
+/// Number of implemented PMP entries, the CSRs of further entries read as zero
+pub const PMP_ENTRIES: usize = 16;
+
+/// Physical Memory Protection, the entries of `pmpcfg0..3` and `pmpaddr0..15`.
+///
+/// The lowest numbered entry that matches an access decides whether it is permitted.
+/// Machine mode is only restricted by locked entries, supervisor and user mode accesses
+/// that no entry matches fail.
+#[derive(Default)]
+pub struct Pmp {
+    cfg: [u8; PMP_ENTRIES],
+    /* Bits 33:2 of the physical address */
+    addr: [u32; PMP_ENTRIES],
+}
+
+impl Pmp {
+    fn mode(&self, entry: usize) -> u8 {
+        (self.cfg[entry] & PMP_A) >> 3
+    }
+
+    fn locked(&self, entry: usize) -> bool {
+        self.cfg[entry] & PMP_L != 0
+    }
+
+    /// Value of `pmpcfg<index>`, which packs four entries
+    pub fn read_cfg(&self, index: usize) -> u32 {
+        (0..4).fold(0, |value, byte| {
+            value | (u32::from(self.cfg[4 * index + byte]) << (8 * byte))
+        })
+    }
+
+    pub fn write_cfg(&mut self, index: usize, value: u32) {
+        for byte in 0..4 {
+            let entry = 4 * index + byte;
+            if self.locked(entry) {
+                continue;
+            }
+            /* WARL / bits 6:5 are reserved, as is write permission without read permission */
+            let mut cfg = (value >> (8 * byte)) as u8 & (PMP_R | PMP_W | PMP_X | PMP_A | PMP_L);
+            if cfg & PMP_R == 0 {
+                cfg &= !PMP_W;
+            }
+            self.cfg[entry] = cfg;
+        }
+    }
+
+    pub fn read_addr(&self, entry: usize) -> u32 {
+        self.addr[entry]
+    }
+
+    pub fn write_addr(&mut self, entry: usize, value: u32) {
+        /* A locked entry also locks the address below it when that is the bottom of its range */
+        let locked_by_next =
+            entry + 1 < PMP_ENTRIES && self.locked(entry + 1) && self.mode(entry + 1) == A_TOR;
+        if !self.locked(entry) && !locked_by_next {
+            self.addr[entry] = value;
+        }
+    }
+
+    /// Range of physical addresses an entry matches, `None` if it is off
+    fn range(&self, entry: usize) -> Option<(u64, u64)> {
+        let addr = u64::from(self.addr[entry]) << 2;
+        match self.mode(entry) {
+            A_OFF => None,
+            A_TOR => {
+                let bottom = match entry {
+                    0 => 0,
+                    _ => u64::from(self.addr[entry - 1]) << 2,
+                };
+                /* A range with its top below the bottom matches nothing */
+                (bottom < addr).then_some((bottom, addr))
+            }
+            A_NA4 => Some((addr, addr + 4)),
+            _ => {
+                /* NAPOT, the trailing ones encode the size, 2^(ones + 3) bytes */
+                let ones = self.addr[entry].trailing_ones();
+                let size = 1u64 << (ones + 3);
+                let base = addr & !(size - 1);
+                Some((base, base + size))
+            }
+        }
+    }
+
+    /// Whether an access of `size` bytes at the physical address `addr` is permitted
+    pub fn check(
+        &self,
+        addr: usize,
+        size: usize,
+        access: AccessType,
+        privilege: Privilege,
+    ) -> bool {
+        let (start, end) = (addr as u64, (addr + size) as u64);
+        for entry in 0..PMP_ENTRIES {
+            let Some((bottom, top)) = self.range(entry) else {
+                continue;
+            };
+            if end <= bottom || top <= start {
+                continue;
+            }
+            /* An access that only partially matches fails */
+            if start < bottom || top < end {
+                return false;
+            }
+            if privilege == Privilege::Machine && !self.locked(entry) {
+                return true;
+            }
+            let permission = match access {
+                AccessType::Fetch => PMP_X,
+                AccessType::Load => PMP_R,
+                AccessType::Store => PMP_W,
+            };
+            return self.cfg[entry] & permission != 0;
+        }
+        privilege == Privilege::Machine
+    }
+}
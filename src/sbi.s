@@ -0,0 +1,360 @@
+# Built-in SBI firmware, runs in machine mode from the start of RAM.
+#
+# It hands the hart to the kernel in supervisor mode, with a0 = hart ID and a1 = address
+# of the device tree, and then serves the ECALLs of the kernel: the legacy console, timer
+# and shutdown calls, and the Base, TIME, IPI, RFENCE, HSM, SRST and DBCN extensions of
+# SBI v2.0. The boot code defines KERNEL_ENTRY, DTB_ADDR, FIRMWARE_PMPADDR, CLINT_BASE and
+# UART_BASE in front of this file.
+
+.equ MTIMECMP, CLINT_BASE + 0x4000
+.equ UART_LSR, 5
+.equ LSR_DR, 0x01
+.equ LSR_THRE, 0x20
+
+.equ MIP_SSIP, 0x02
+.equ MIP_STIP, 0x20
+.equ MIE_MTIE, 0x80
+.equ MSTATUS_MPP_S, 0x800
+
+.equ SBI_SPEC_VERSION, 0x02000000
+# Not a registered implementation ID
+.equ SBI_IMPL_ID, 0x7276
+.equ SBI_IMPL_VERSION, 1
+
+.equ SBI_ERR_NOT_SUPPORTED, -2
+.equ SBI_ERR_INVALID_PARAM, -3
+
+.equ EXT_BASE, 0x10
+.equ EXT_TIME, 0x54494D45
+.equ EXT_IPI, 0x735049
+.equ EXT_RFENCE, 0x52464E43
+.equ EXT_HSM, 0x48534D
+.equ EXT_SRST, 0x53525354
+.equ EXT_DBCN, 0x4442434E
+
+_start:
+    la t0, trap
+    csrw mtvec, t0
+    la t0, stack_top
+    csrw mscratch, t0
+
+    # The firmware is off limits to the kernel, the rest of the address space isn't
+    li t0, FIRMWARE_PMPADDR
+    csrw pmpaddr0, t0
+    li t0, -1
+    csrw pmpaddr1, t0
+    li t0, 0x1F18
+    csrw pmpcfg0, t0
+
+    # The kernel handles its exceptions, except for its own ECALLs, and the supervisor
+    # interrupts. The machine timer is taken here and passed on as supervisor timer.
+    li t0, 0xB1FF
+    csrw medeleg, t0
+    li t0, 0x222
+    csrw mideleg, t0
+    li t0, MIE_MTIE
+    csrw mie, t0
+    li t0, 0x7
+    csrw mcounteren, t0
+
+    li t0, MSTATUS_MPP_S
+    csrs mstatus, t0
+    li t0, KERNEL_ENTRY
+    csrw mepc, t0
+    csrr a0, mhartid
+    li a1, DTB_ADDR
+    mret
+
+trap:
+    csrrw sp, mscratch, sp
+    addi sp, sp, -32
+    sw t0, 0(sp)
+    sw t1, 4(sp)
+    sw t2, 8(sp)
+    sw t3, 12(sp)
+    sw ra, 16(sp)
+
+    csrr t0, mcause
+    bltz t0, interrupt
+    li t1, 9
+    bne t0, t1, unexpected
+
+    # An ECALL from supervisor mode, a7 selects the extension and a6 the function
+    csrr t0, mepc
+    addi t0, t0, 4
+    csrw mepc, t0
+    beqz a7, legacy_set_timer
+    li t0, 0x01
+    beq a7, t0, legacy_putchar
+    li t0, 0x02
+    beq a7, t0, legacy_getchar
+    li t0, 0x08
+    beq a7, t0, shutdown
+    li t0, EXT_BASE
+    beq a7, t0, base
+    li t0, EXT_TIME
+    beq a7, t0, time
+    li t0, EXT_IPI
+    beq a7, t0, ipi
+    li t0, EXT_RFENCE
+    beq a7, t0, rfence
+    li t0, EXT_HSM
+    beq a7, t0, hsm
+    li t0, EXT_SRST
+    beq a7, t0, srst
+    li t0, EXT_DBCN
+    beq a7, t0, dbcn
+
+not_supported:
+    li a0, SBI_ERR_NOT_SUPPORTED
+    j return
+
+invalid_param:
+    li a0, SBI_ERR_INVALID_PARAM
+    j return
+
+success:
+    li a0, 0
+
+return:
+    lw t0, 0(sp)
+    lw t1, 4(sp)
+    lw t2, 8(sp)
+    lw t3, 12(sp)
+    lw ra, 16(sp)
+    addi sp, sp, 32
+    csrrw sp, mscratch, sp
+    mret
+
+interrupt:
+    # The timer interrupt stays masked until the kernel sets the next one
+    li t0, MIE_MTIE
+    csrc mie, t0
+    li t0, MIP_STIP
+    csrs mip, t0
+    j return
+
+unexpected:
+    # Everything else is delegated, this is a bug in the firmware
+    li a7, 1
+    ebreak
+    j unexpected
+
+# Legacy extensions, they only return a value in a0
+
+legacy_set_timer:
+    jal set_timer
+    j success
+
+legacy_putchar:
+    jal putchar
+    j success
+
+legacy_getchar:
+    jal getchar
+    j return
+
+# Base extension
+
+base:
+    beqz a6, base_spec_version
+    li t0, 1
+    beq a6, t0, base_impl_id
+    li t0, 2
+    beq a6, t0, base_impl_version
+    li t0, 3
+    beq a6, t0, base_probe
+    li t0, 4
+    beq a6, t0, base_mvendorid
+    li t0, 5
+    beq a6, t0, base_marchid
+    li t0, 6
+    beq a6, t0, base_mimpid
+    j not_supported
+
+base_spec_version:
+    li a1, SBI_SPEC_VERSION
+    j success
+
+base_impl_id:
+    li a1, SBI_IMPL_ID
+    j success
+
+base_impl_version:
+    li a1, SBI_IMPL_VERSION
+    j success
+
+base_probe:
+    la t0, extensions
+    la t1, extensions_end
+    li a1, 1
+probe_next:
+    beq t0, t1, probe_missing
+    lw t2, 0(t0)
+    beq t2, a0, success
+    addi t0, t0, 4
+    j probe_next
+probe_missing:
+    li a1, 0
+    j success
+
+base_mvendorid:
+    csrr a1, mvendorid
+    j success
+
+base_marchid:
+    csrr a1, marchid
+    j success
+
+base_mimpid:
+    csrr a1, mimpid
+    j success
+
+# TIME extension
+
+time:
+    bnez a6, not_supported
+    jal set_timer
+    j success
+
+# IPI extension, the only hart can only interrupt itself
+
+ipi:
+    bnez a6, not_supported
+    li t0, -1
+    beq a1, t0, ipi_self
+    bnez a1, success
+    andi t0, a0, 1
+    beqz t0, success
+ipi_self:
+    csrsi mip, MIP_SSIP
+    j success
+
+# RFENCE extension, without instruction cache all fences are a TLB flush
+
+rfence:
+    li t0, 2
+    bgtu a6, t0, not_supported
+    sfence.vma
+    j success
+
+# HSM extension, the only hart is always started
+
+hsm:
+    li t0, 2
+    bne a6, t0, not_supported
+    bnez a0, invalid_param
+    li a1, 0
+    j success
+
+# SRST extension, every reset shuts the machine down
+
+srst:
+    bnez a6, not_supported
+    bnez a1, failure
+    j shutdown
+
+# DBCN extension
+
+dbcn:
+    beqz a6, dbcn_write
+    li t0, 1
+    beq a6, t0, dbcn_read
+    li t0, 2
+    beq a6, t0, dbcn_write_byte
+    j not_supported
+
+dbcn_write:
+    bnez a2, invalid_param
+    mv t2, a1
+    add t3, a1, a0
+    mv a1, a0
+    li t0, UART_BASE
+dbcn_write_next:
+    beq t2, t3, success
+    lbu t1, UART_LSR(t0)
+    andi t1, t1, LSR_THRE
+    beqz t1, dbcn_write_next
+    lbu t1, 0(t2)
+    sb t1, 0(t0)
+    addi t2, t2, 1
+    j dbcn_write_next
+
+dbcn_read:
+    bnez a2, invalid_param
+    mv t2, a1
+    add t3, a1, a0
+    li t0, UART_BASE
+dbcn_read_next:
+    beq t2, t3, dbcn_read_done
+    lbu t1, UART_LSR(t0)
+    andi t1, t1, LSR_DR
+    beqz t1, dbcn_read_done
+    lbu t1, 0(t0)
+    sb t1, 0(t2)
+    addi t2, t2, 1
+    j dbcn_read_next
+dbcn_read_done:
+    sub a1, t2, a1
+    j success
+
+dbcn_write_byte:
+    jal putchar
+    li a1, 0
+    j success
+
+# Stops the machine, a7 tells the debugger whether it was a shutdown or a failure
+
+shutdown:
+    li a7, 93
+    ebreak
+    j shutdown
+
+failure:
+    li a7, 1
+    ebreak
+    j failure
+
+# Sets the timer to a0 (low) and a1 (high) and clears the pending timer interrupt
+set_timer:
+    li t0, MTIMECMP
+    li t1, -1
+    sw t1, 4(t0)
+    sw a0, 0(t0)
+    sw a1, 4(t0)
+    li t0, MIP_STIP
+    csrc mip, t0
+    li t0, MIE_MTIE
+    csrs mie, t0
+    ret
+
+# Writes the byte in a0 to the UART
+putchar:
+    li t0, UART_BASE
+putchar_wait:
+    lbu t1, UART_LSR(t0)
+    andi t1, t1, LSR_THRE
+    beqz t1, putchar_wait
+    sb a0, 0(t0)
+    ret
+
+# Reads a byte from the UART into a0, -1 if there is none
+getchar:
+    li t0, UART_BASE
+    li a0, -1
+    lbu t1, UART_LSR(t0)
+    andi t1, t1, LSR_DR
+    beqz t1, getchar_done
+    lbu a0, 0(t0)
+getchar_done:
+    ret
+
+.align 2
+extensions:
+    .word 0x00, 0x01, 0x02, 0x08
+    .word EXT_BASE, EXT_TIME, EXT_IPI, EXT_RFENCE, EXT_HSM, EXT_SRST, EXT_DBCN
+extensions_end:
+
+    .space 1024
+.align 4
+stack_top:
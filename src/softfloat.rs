@@ -0,0 +1,660 @@
+use std::cmp::Ordering;
+
+/* Accrued exception flags, laid out as in fflags */
+pub const INEXACT: u32 = 0b0_0001;
+pub const UNDERFLOW: u32 = 0b0_0010;
+pub const OVERFLOW: u32 = 0b0_0100;
+pub const DIVIDE_BY_ZERO: u32 = 0b0_1000;
+pub const INVALID: u32 = 0b1_0000;
+
+/// An IEEE-754 binary interchange format, values are passed around as raw bits.
+#[derive(Clone, Copy)]
+pub struct Format {
+    exponent_bits: u32,
+    fraction_bits: u32,
+}
+
+pub const SINGLE: Format = Format {
+    exponent_bits: 8,
+    fraction_bits: 23,
+};
+
+pub const DOUBLE: Format = Format {
+    exponent_bits: 11,
+    fraction_bits: 52,
+};
+
+impl Format {
+    fn precision(self) -> i32 {
+        self.fraction_bits as i32 + 1
+    }
+
+    fn bias(self) -> i32 {
+        (1 << (self.exponent_bits - 1)) - 1
+    }
+
+    fn exponent_max(self) -> u64 {
+        (1 << self.exponent_bits) - 1
+    }
+
+    fn fraction_mask(self) -> u64 {
+        (1 << self.fraction_bits) - 1
+    }
+
+    fn sign_bit(self) -> u64 {
+        1 << (self.exponent_bits + self.fraction_bits)
+    }
+
+    fn signed(self, sign: bool, bits: u64) -> u64 {
+        if sign {
+            self.sign_bit() | bits
+        } else {
+            bits
+        }
+    }
+
+    fn zero(self, sign: bool) -> u64 {
+        self.signed(sign, 0)
+    }
+
+    fn infinity(self, sign: bool) -> u64 {
+        self.signed(sign, self.exponent_max() << self.fraction_bits)
+    }
+
+    fn max_finite(self, sign: bool) -> u64 {
+        self.signed(sign, self.infinity(false) - 1)
+    }
+
+    /// The one NaN RISC-V produces for all operations that return a NaN
+    pub fn canonical_nan(self) -> u64 {
+        self.infinity(false) | (1 << (self.fraction_bits - 1))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RoundingMode {
+    NearestEven,
+    TowardZero,
+    Down,
+    Up,
+    NearestMaxMagnitude,
+}
+
+impl RoundingMode {
+    /// Decodes the `rm` field, reserved encodings yield `None`
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        match bits {
+            0b000 => Some(Self::NearestEven),
+            0b001 => Some(Self::TowardZero),
+            0b010 => Some(Self::Down),
+            0b011 => Some(Self::Up),
+            0b100 => Some(Self::NearestMaxMagnitude),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Zero,
+    Finite,
+    Infinity,
+    QuietNaN,
+    SignalingNaN,
+}
+
+/// A finite value is `sig * 2^exp`, with `sig` normalized to the precision of its format
+#[derive(Clone, Copy)]
+struct Unpacked {
+    sign: bool,
+    kind: Kind,
+    exp: i32,
+    sig: u128,
+}
+
+impl Unpacked {
+    fn is_nan(&self) -> bool {
+        matches!(self.kind, Kind::QuietNaN | Kind::SignalingNaN)
+    }
+
+    fn top(&self) -> i32 {
+        self.exp + bit_length(self.sig) - 1
+    }
+}
+
+fn unpack(format: Format, bits: u64) -> Unpacked {
+    let sign = bits & format.sign_bit() != 0;
+    let exponent = (bits >> format.fraction_bits) & format.exponent_max();
+    let fraction = bits & format.fraction_mask();
+    let mut value = Unpacked {
+        sign,
+        kind: Kind::Finite,
+        exp: 0,
+        sig: u128::from(fraction),
+    };
+
+    if exponent == format.exponent_max() {
+        value.kind = if fraction == 0 {
+            Kind::Infinity
+        } else if fraction >> (format.fraction_bits - 1) == 0 {
+            Kind::SignalingNaN
+        } else {
+            Kind::QuietNaN
+        };
+    } else if exponent == 0 {
+        if fraction == 0 {
+            value.kind = Kind::Zero;
+        } else {
+            /* Subnormal, normalize it so it looks like every other finite value */
+            let shift = format.precision() - bit_length(value.sig);
+            value.sig <<= shift;
+            value.exp = 1 - format.bias() - format.fraction_bits as i32 - shift;
+        }
+    } else {
+        value.sig |= 1 << format.fraction_bits;
+        value.exp = exponent as i32 - format.bias() - format.fraction_bits as i32;
+    }
+    value
+}
+
+fn bit_length(value: u128) -> i32 {
+    128 - value.leading_zeros() as i32
+}
+
+fn shift_right_sticky(value: u128, shift: u32) -> (u128, bool) {
+    if shift == 0 {
+        (value, false)
+    } else if shift >= 128 {
+        (0, value != 0)
+    } else {
+        (value >> shift, value & ((1 << shift) - 1) != 0)
+    }
+}
+
+/// Splits off the lowest `shift` bits of `sig`, returning the kept bits, how the
+/// dropped bits compare to one half of the kept lsb and whether they were all zero.
+fn split(sig: u128, shift: u32, sticky: bool) -> (u128, Ordering, bool) {
+    let (kept, cmp, exact) = if shift > 128 {
+        (0, Ordering::Less, sig == 0)
+    } else {
+        let kept = sig.checked_shr(shift).unwrap_or(0);
+        let rem = if shift == 128 {
+            sig
+        } else {
+            sig & ((1 << shift) - 1)
+        };
+        (kept, rem.cmp(&(1 << (shift - 1))), rem == 0)
+    };
+    if sticky && cmp == Ordering::Equal {
+        return (kept, Ordering::Greater, false);
+    }
+    (kept, cmp, exact && !sticky)
+}
+
+fn isqrt(value: u128) -> u128 {
+    if value == 0 {
+        return 0;
+    }
+    let mut remainder = value;
+    let mut root = 0;
+    let mut bit = 1 << ((bit_length(value) - 1) & !1);
+    while bit != 0 {
+        if remainder >= root + bit {
+            remainder -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    root
+}
+
+/// Rounding mode and accrued exception flags for a sequence of operations.
+pub struct FloatEnv {
+    pub rounding_mode: RoundingMode,
+    pub flags: u32,
+}
+
+impl FloatEnv {
+    pub fn new(rounding_mode: RoundingMode) -> Self {
+        Self {
+            rounding_mode,
+            flags: 0,
+        }
+    }
+
+    fn round_up(&self, sign: bool, kept: u128, cmp: Ordering, exact: bool) -> bool {
+        match self.rounding_mode {
+            RoundingMode::NearestEven => {
+                cmp == Ordering::Greater || (cmp == Ordering::Equal && kept & 1 == 1)
+            }
+            RoundingMode::TowardZero => false,
+            RoundingMode::Down => sign && !exact,
+            RoundingMode::Up => !sign && !exact,
+            RoundingMode::NearestMaxMagnitude => cmp != Ordering::Less,
+        }
+    }
+
+    fn overflow(&mut self, format: Format, sign: bool) -> u64 {
+        self.flags |= OVERFLOW | INEXACT;
+        let to_infinity = match self.rounding_mode {
+            RoundingMode::NearestEven | RoundingMode::NearestMaxMagnitude => true,
+            RoundingMode::TowardZero => false,
+            RoundingMode::Down => sign,
+            RoundingMode::Up => !sign,
+        };
+        if to_infinity {
+            format.infinity(sign)
+        } else {
+            format.max_finite(sign)
+        }
+    }
+
+    /// Rounds `sig * 2^exp` to `format`. `sticky` marks a value slightly larger in
+    /// magnitude than `sig * 2^exp`, but by less than one unit of `sig`.
+    fn round_pack(&mut self, format: Format, sign: bool, exp: i32, sig: u128, sticky: bool) -> u64 {
+        let precision = format.precision();
+        let emin = 1 - format.bias();
+        let (mut exp, mut sig) = (exp, sig);
+
+        /* Keep at least two bits below the precision, so the sticky bit can't round */
+        let length = bit_length(sig);
+        if length < precision + 2 {
+            sig <<= precision + 2 - length;
+            exp -= precision + 2 - length;
+        }
+        let top = exp + bit_length(sig) - 1;
+
+        let mut lsb = top.max(emin) - (precision - 1);
+        let (kept, cmp, exact) = split(sig, (lsb - exp) as u32, sticky);
+        let mut kept = kept + u128::from(self.round_up(sign, kept, cmp, exact));
+        if kept >> precision != 0 {
+            kept >>= 1;
+            lsb += 1;
+        }
+
+        if !exact {
+            self.flags |= INEXACT;
+            /* Tininess is detected after rounding, as if the exponent range was unbounded */
+            if top < emin {
+                let (unbounded, cmp, exact) =
+                    split(sig, (top - (precision - 1) - exp) as u32, sticky);
+                let carry = (unbounded + u128::from(self.round_up(sign, unbounded, cmp, exact)))
+                    >> precision
+                    != 0;
+                if !(carry && top + 1 == emin) {
+                    self.flags |= UNDERFLOW;
+                }
+            }
+        }
+
+        let normal = kept >> (precision - 1) != 0;
+        if normal && lsb + precision - 1 > format.bias() {
+            return self.overflow(format, sign);
+        }
+        let exponent = if normal {
+            (lsb + precision - 1 + format.bias()) as u64
+        } else {
+            0
+        };
+        format.signed(
+            sign,
+            (exponent << format.fraction_bits) | (kept as u64 & format.fraction_mask()),
+        )
+    }
+
+    /// Any NaN operand turns the result into the canonical NaN, signaling ones are invalid
+    fn propagate_nan(&mut self, format: Format, operands: &[Unpacked]) -> Option<u64> {
+        if operands.iter().any(|x| x.kind == Kind::SignalingNaN) {
+            self.flags |= INVALID;
+        }
+        if operands.iter().any(Unpacked::is_nan) {
+            return Some(format.canonical_nan());
+        }
+        None
+    }
+
+    fn invalid(&mut self, format: Format) -> u64 {
+        self.flags |= INVALID;
+        format.canonical_nan()
+    }
+
+    /// Exact sum of two non-zero finite values, rounded once
+    fn add_finite(&mut self, format: Format, a: Unpacked, b: Unpacked) -> u64 {
+        let (mut a, mut b) = if b.top() > a.top() { (b, a) } else { (a, b) };
+
+        /* Leave plenty of room below the larger operand, the smaller one is aligned to it */
+        let shift = 125 - (bit_length(a.sig) - 1);
+        a.sig <<= shift;
+        a.exp -= shift;
+        let (sig, sticky) = if b.exp >= a.exp {
+            (b.sig << (b.exp - a.exp), false)
+        } else {
+            shift_right_sticky(b.sig, (a.exp - b.exp) as u32)
+        };
+        b.sig = sig;
+
+        if a.sign == b.sign {
+            return self.round_pack(format, a.sign, a.exp, a.sig + b.sig, sticky);
+        }
+        match a.sig.cmp(&b.sig) {
+            Ordering::Equal if !sticky => format.zero(self.rounding_mode == RoundingMode::Down),
+            Ordering::Less => self.round_pack(format, b.sign, a.exp, b.sig - a.sig, false),
+            /* The sticky bits are subtracted as a whole unit and added back as sticky */
+            _ => self.round_pack(
+                format,
+                a.sign,
+                a.exp,
+                a.sig - b.sig - u128::from(sticky),
+                sticky,
+            ),
+        }
+    }
+
+    pub fn add(&mut self, format: Format, a: u64, b: u64) -> u64 {
+        let (x, y) = (unpack(format, a), unpack(format, b));
+        if let Some(nan) = self.propagate_nan(format, &[x, y]) {
+            return nan;
+        }
+        match (x.kind, y.kind) {
+            (Kind::Infinity, Kind::Infinity) if x.sign != y.sign => self.invalid(format),
+            (Kind::Infinity, _) => a,
+            (_, Kind::Infinity) => b,
+            (Kind::Zero, Kind::Zero) if x.sign != y.sign => {
+                format.zero(self.rounding_mode == RoundingMode::Down)
+            }
+            (Kind::Zero, _) => b,
+            (_, Kind::Zero) => a,
+            _ => self.add_finite(format, x, y),
+        }
+    }
+
+    pub fn sub(&mut self, format: Format, a: u64, b: u64) -> u64 {
+        self.add(format, a, b ^ format.sign_bit())
+    }
+
+    pub fn mul(&mut self, format: Format, a: u64, b: u64) -> u64 {
+        let (x, y) = (unpack(format, a), unpack(format, b));
+        if let Some(nan) = self.propagate_nan(format, &[x, y]) {
+            return nan;
+        }
+        let sign = x.sign != y.sign;
+        match (x.kind, y.kind) {
+            (Kind::Infinity, Kind::Zero) | (Kind::Zero, Kind::Infinity) => self.invalid(format),
+            (Kind::Infinity, _) | (_, Kind::Infinity) => format.infinity(sign),
+            (Kind::Zero, _) | (_, Kind::Zero) => format.zero(sign),
+            _ => self.round_pack(format, sign, x.exp + y.exp, x.sig * y.sig, false),
+        }
+    }
+
+    pub fn div(&mut self, format: Format, a: u64, b: u64) -> u64 {
+        let (x, y) = (unpack(format, a), unpack(format, b));
+        if let Some(nan) = self.propagate_nan(format, &[x, y]) {
+            return nan;
+        }
+        let sign = x.sign != y.sign;
+        match (x.kind, y.kind) {
+            (Kind::Infinity, Kind::Infinity) | (Kind::Zero, Kind::Zero) => self.invalid(format),
+            (Kind::Infinity, _) => format.infinity(sign),
+            (_, Kind::Infinity) | (Kind::Zero, _) => format.zero(sign),
+            (_, Kind::Zero) => {
+                self.flags |= DIVIDE_BY_ZERO;
+                format.infinity(sign)
+            }
+            _ => {
+                /* Enough quotient bits for rounding, the remainder becomes the sticky bit */
+                let shift = format.precision() + 3;
+                let dividend = x.sig << shift;
+                let quotient = dividend / y.sig;
+                let sticky = !dividend.is_multiple_of(y.sig);
+                self.round_pack(format, sign, x.exp - y.exp - shift, quotient, sticky)
+            }
+        }
+    }
+
+    pub fn sqrt(&mut self, format: Format, a: u64) -> u64 {
+        let x = unpack(format, a);
+        if let Some(nan) = self.propagate_nan(format, &[x]) {
+            return nan;
+        }
+        match x.kind {
+            Kind::Zero => a,
+            _ if x.sign => self.invalid(format),
+            Kind::Infinity => a,
+            _ => {
+                let (mut exp, mut sig) = (x.exp, x.sig);
+                if exp.rem_euclid(2) == 1 {
+                    sig <<= 1;
+                    exp -= 1;
+                }
+                /* Scale by an even power of two so the root has enough bits for rounding */
+                let shift = 2 * (format.precision() / 2 + 3);
+                sig <<= shift;
+                exp -= shift;
+                let root = isqrt(sig);
+                self.round_pack(format, false, exp / 2, root, root * root != sig)
+            }
+        }
+    }
+
+    /// Computes `a * b + c` with a single rounding
+    pub fn fused_mul_add(&mut self, format: Format, a: u64, b: u64, c: u64) -> u64 {
+        let (x, y, z) = (unpack(format, a), unpack(format, b), unpack(format, c));
+        let product_invalid = matches!(
+            (x.kind, y.kind),
+            (Kind::Infinity, Kind::Zero) | (Kind::Zero, Kind::Infinity)
+        );
+        /* RISC-V raises invalid for infinity times zero even if the addend is a quiet NaN */
+        if product_invalid {
+            self.flags |= INVALID;
+        }
+        if let Some(nan) = self.propagate_nan(format, &[x, y, z]) {
+            return nan;
+        }
+        if product_invalid {
+            return format.canonical_nan();
+        }
+
+        let sign = x.sign != y.sign;
+        if x.kind == Kind::Infinity || y.kind == Kind::Infinity {
+            if z.kind == Kind::Infinity && z.sign != sign {
+                return self.invalid(format);
+            }
+            return format.infinity(sign);
+        }
+        if z.kind == Kind::Infinity {
+            return c;
+        }
+        if x.kind == Kind::Zero || y.kind == Kind::Zero {
+            if z.kind != Kind::Zero {
+                return c;
+            }
+            if z.sign == sign {
+                return format.zero(sign);
+            }
+            return format.zero(self.rounding_mode == RoundingMode::Down);
+        }
+
+        let product = Unpacked {
+            sign,
+            kind: Kind::Finite,
+            exp: x.exp + y.exp,
+            sig: x.sig * y.sig,
+        };
+        if z.kind == Kind::Zero {
+            return self.round_pack(format, sign, product.exp, product.sig, false);
+        }
+        self.add_finite(format, product, z)
+    }
+
+    /// Quiet comparison, only signaling NaNs are invalid
+    pub fn eq(&mut self, format: Format, a: u64, b: u64) -> bool {
+        let (x, y) = (unpack(format, a), unpack(format, b));
+        if self.propagate_nan(format, &[x, y]).is_some() {
+            return false;
+        }
+        compare(format, a, b) == Ordering::Equal
+    }
+
+    /// Signaling comparison, any NaN is invalid
+    pub fn lt(&mut self, format: Format, a: u64, b: u64) -> bool {
+        let (x, y) = (unpack(format, a), unpack(format, b));
+        if x.is_nan() || y.is_nan() {
+            self.flags |= INVALID;
+            return false;
+        }
+        compare(format, a, b) == Ordering::Less
+    }
+
+    /// Signaling comparison, any NaN is invalid
+    pub fn le(&mut self, format: Format, a: u64, b: u64) -> bool {
+        let (x, y) = (unpack(format, a), unpack(format, b));
+        if x.is_nan() || y.is_nan() {
+            self.flags |= INVALID;
+            return false;
+        }
+        compare(format, a, b) != Ordering::Greater
+    }
+
+    /// IEEE 754-2019 minimumNumber, -0 is smaller than +0
+    pub fn min(&mut self, format: Format, a: u64, b: u64) -> u64 {
+        self.min_max(format, a, b, Ordering::Less)
+    }
+
+    /// IEEE 754-2019 maximumNumber, +0 is larger than -0
+    pub fn max(&mut self, format: Format, a: u64, b: u64) -> u64 {
+        self.min_max(format, a, b, Ordering::Greater)
+    }
+
+    fn min_max(&mut self, format: Format, a: u64, b: u64, pick: Ordering) -> u64 {
+        let (x, y) = (unpack(format, a), unpack(format, b));
+        if x.kind == Kind::SignalingNaN || y.kind == Kind::SignalingNaN {
+            self.flags |= INVALID;
+        }
+        match (x.is_nan(), y.is_nan()) {
+            (true, true) => format.canonical_nan(),
+            (true, false) => b,
+            (false, true) => a,
+            _ => {
+                let order = if x.kind == Kind::Zero && y.kind == Kind::Zero {
+                    /* Order the zeros by their sign */
+                    y.sign.cmp(&x.sign)
+                } else {
+                    compare(format, a, b)
+                };
+                if order == pick {
+                    a
+                } else {
+                    b
+                }
+            }
+        }
+    }
+
+    /// Converts to a 32-bit integer, out of range values and NaNs saturate and are invalid
+    pub fn float_to_int(&mut self, format: Format, a: u64, signed: bool) -> u32 {
+        let x = unpack(format, a);
+        let (min, max) = if signed {
+            (i32::MIN as u32, i32::MAX as u32)
+        } else {
+            (u32::MIN, u32::MAX)
+        };
+
+        let (magnitude, exact) = match x.kind {
+            Kind::QuietNaN | Kind::SignalingNaN => {
+                self.flags |= INVALID;
+                return max;
+            }
+            Kind::Infinity => (u128::MAX, true),
+            Kind::Zero => (0, true),
+            Kind::Finite if x.exp >= 0 => {
+                if x.exp > 64 {
+                    (u128::MAX, true)
+                } else {
+                    (x.sig << x.exp, true)
+                }
+            }
+            Kind::Finite => {
+                let (kept, cmp, exact) = split(x.sig, x.exp.unsigned_abs(), false);
+                (
+                    kept + u128::from(self.round_up(x.sign, kept, cmp, exact)),
+                    exact,
+                )
+            }
+        };
+
+        let limit = if x.sign {
+            u128::from(min.wrapping_neg())
+        } else {
+            u128::from(max)
+        };
+        if magnitude > limit {
+            self.flags |= INVALID;
+            return if x.sign { min } else { max };
+        }
+        if !exact {
+            self.flags |= INEXACT;
+        }
+        if x.sign {
+            (magnitude as u32).wrapping_neg()
+        } else {
+            magnitude as u32
+        }
+    }
+
+    /// Converts a 32-bit integer, `signed` selects how `value` is interpreted
+    pub fn int_to_float(&mut self, format: Format, value: u32, signed: bool) -> u64 {
+        let sign = signed && (value as i32).is_negative();
+        let magnitude = if sign { value.wrapping_neg() } else { value };
+        if magnitude == 0 {
+            return format.zero(false);
+        }
+        self.round_pack(format, sign, 0, u128::from(magnitude), false)
+    }
+
+    /// Converts between formats, rounding if `to` is the narrower one
+    pub fn convert(&mut self, from: Format, to: Format, a: u64) -> u64 {
+        let x = unpack(from, a);
+        if let Some(nan) = self.propagate_nan(to, &[x]) {
+            return nan;
+        }
+        match x.kind {
+            Kind::Zero => to.zero(x.sign),
+            Kind::Infinity => to.infinity(x.sign),
+            _ => self.round_pack(to, x.sign, x.exp, x.sig, false),
+        }
+    }
+}
+
+/// Orders two non-NaN values, both zeros compare equal
+fn compare(format: Format, a: u64, b: u64) -> Ordering {
+    let key = |bits: u64| {
+        let magnitude = i128::from(bits & !format.sign_bit());
+        if bits & format.sign_bit() == 0 {
+            magnitude
+        } else {
+            -magnitude
+        }
+    };
+    key(a).cmp(&key(b))
+}
+
+/// Classifies a value into the ten classes of `FCLASS`, returned as a one-hot mask
+pub fn classify(format: Format, a: u64) -> u32 {
+    let x = unpack(format, a);
+    let subnormal = (a >> format.fraction_bits) & format.exponent_max() == 0;
+    let class = match (x.kind, x.sign) {
+        (Kind::Infinity, true) => 0,
+        (Kind::Finite, true) if !subnormal => 1,
+        (Kind::Finite, true) => 2,
+        (Kind::Zero, true) => 3,
+        (Kind::Zero, false) => 4,
+        (Kind::Finite, false) if subnormal => 5,
+        (Kind::Finite, false) => 6,
+        (Kind::Infinity, false) => 7,
+        (Kind::SignalingNaN, _) => 8,
+        (Kind::QuietNaN, _) => 9,
+    };
+    1 << class
+}
@@ -0,0 +1,94 @@
+use std::fmt;
+
+use elf::{abi, endian::LittleEndian, ElfBytes};
+
+/// Addresses of the functions and objects named in an ELF symbol table
+#[derive(Default)]
+pub struct Symbols {
+    /* Sorted by address, symbols at the same address keep the order of the table */
+    symbols: Vec<(u32, String)>,
+}
+
+impl Symbols {
+    /// Reads the symbol table of an ELF file, a file without one has no symbols
+    pub fn from_elf(elf_data: &[u8]) -> anyhow::Result<Self> {
+        let file = ElfBytes::<LittleEndian>::minimal_parse(elf_data)?;
+        let Some((table, strings)) = file.symbol_table()? else {
+            return Ok(Self::default());
+        };
+        let mut symbols = Vec::new();
+        for symbol in table.iter() {
+            /* Sections, files and undefined symbols don't name an address of the program */
+            let defined = symbol.st_shndx != abi::SHN_UNDEF;
+            let kind = matches!(
+                symbol.st_symtype(),
+                abi::STT_NOTYPE | abi::STT_FUNC | abi::STT_OBJECT
+            );
+            if !defined || !kind || symbol.st_name == 0 {
+                continue;
+            }
+            let name = strings.get(symbol.st_name as usize)?;
+            /* Mapping symbols like `$x` and assembler local labels only clutter the output */
+            if name.starts_with('$') || name.starts_with(".L") {
+                continue;
+            }
+            symbols.push((u32::try_from(symbol.st_value)?, name.to_string()));
+        }
+        symbols.sort_by_key(|&(addr, _)| addr);
+        Ok(Self { symbols })
+    }
+
+    /// Name of the symbol starting at `addr`, if any
+    pub fn at(&self, addr: u32) -> Option<&str> {
+        let index = self.symbols.partition_point(|&(start, _)| start < addr);
+        match self.symbols.get(index) {
+            Some((start, name)) if *start == addr => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Address of the symbol called `name`
+    pub fn address(&self, name: &str) -> Option<u32> {
+        self.symbols
+            .iter()
+            .find(|(_, symbol)| symbol == name)
+            .map(|&(addr, _)| addr)
+    }
+
+    /// Nearest symbol at or below `addr` and the offset of `addr` from it
+    pub fn lookup(&self, addr: u32) -> Option<(&str, u32)> {
+        let index = self.symbols.partition_point(|&(start, _)| start <= addr);
+        let (start, _) = self.symbols[..index].last()?;
+        Some((self.at(*start)?, addr - start))
+    }
+
+    /// `<name+0x14>` style label for `addr`, `None` if no symbol precedes it
+    pub fn label(&self, addr: u32) -> Option<Label<'_>> {
+        self.lookup(addr)
+            .map(|(name, offset)| Label { name, offset })
+    }
+}
+
+/// Collects symbols from address and name pairs, like the labels of an assembled program
+impl FromIterator<(u32, String)> for Symbols {
+    fn from_iter<I: IntoIterator<Item = (u32, String)>>(iter: I) -> Self {
+        let mut symbols: Vec<(u32, String)> = iter.into_iter().collect();
+        symbols.sort();
+        Self { symbols }
+    }
+}
+
+/// A symbol and an offset into it, displayed as `<name+0x14>`
+pub struct Label<'a> {
+    pub name: &'a str,
+    pub offset: u32,
+}
+
+impl fmt::Display for Label<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.offset {
+            0 => write!(f, "<{}>", self.name),
+            offset => write!(f, "<{}+0x{offset:x}>", self.name),
+        }
+    }
+}
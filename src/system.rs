@@ -1,4 +1,89 @@
+use std::io::Write;
+
+use anyhow::{anyhow, ensure};
+use elf::{abi, endian::LittleEndian, file::Class, ElfBytes};
+
+use crate::bus::Bus;
 use crate::decoder::Rindex;
+use crate::memory::{Memory, PrintPort};
+use crate::mmu::Tlb;
+use crate::pmp::Pmp;
+use crate::trap::{Exception, Interrupt};
+
+/* Fields of mstatus, sstatus is a restricted view of it */
+pub const MSTATUS_SIE: u32 = 1 << 1;
+pub const MSTATUS_MIE: u32 = 1 << 3;
+pub const MSTATUS_SPIE: u32 = 1 << 5;
+pub const MSTATUS_MPIE: u32 = 1 << 7;
+pub const MSTATUS_SPP: u32 = 1 << 8;
+pub const MSTATUS_MPP: u32 = 0b11 << 11;
+pub const MSTATUS_FS: u32 = 0b11 << 13;
+pub const MSTATUS_MPRV: u32 = 1 << 17;
+pub const MSTATUS_SUM: u32 = 1 << 18;
+pub const MSTATUS_MXR: u32 = 1 << 19;
+pub const MSTATUS_TVM: u32 = 1 << 20;
+pub const MSTATUS_TW: u32 = 1 << 21;
+pub const MSTATUS_TSR: u32 = 1 << 22;
+pub const MSTATUS_SD: u32 = 1 << 31;
+const SSTATUS_MASK: u32 =
+    MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP | MSTATUS_FS | MSTATUS_SUM | MSTATUS_MXR | MSTATUS_SD;
+
+/* Values of mstatus.FS, the state of the floating-point unit */
+pub const FS_OFF: u32 = 0b00 << 13;
+pub const FS_INITIAL: u32 = 0b01 << 13;
+pub const FS_DIRTY: u32 = 0b11 << 13;
+
+/* Exceptions up to the supervisor environment call and the page faults can be delegated,
+ * machine ones can't */
+const DELEGABLE_EXCEPTIONS: u32 = 0b1011_0011_1111_1111;
+
+/// Privilege levels of the hart, the discriminant is their encoding in `mstatus.MPP`
+/// and in bits 9:8 of a CSR number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Privilege {
+    User = 0,
+    Supervisor = 1,
+    #[default]
+    Machine = 3,
+}
+
+impl Privilege {
+    /// Decodes a two bit privilege field, 2 is reserved
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        match bits {
+            0 => Some(Self::User),
+            1 => Some(Self::Supervisor),
+            3 => Some(Self::Machine),
+            _ => None,
+        }
+    }
+}
+
+/// Mask of the interrupts in `mip` and `mie` that satisfy `filter`
+fn interrupts(filter: impl Fn(&Interrupt) -> bool) -> u32 {
+    Interrupt::PRIORITY
+        .iter()
+        .filter(|interrupt| filter(interrupt))
+        .fold(0, |mask, interrupt| mask | interrupt.mask())
+}
+
+/// Events that can be selected in `mhpmevent3..31` to be counted by the matching `mhpmcounter`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Load = 1,
+    Store = 2,
+    Branch = 3,
+    BranchTaken = 4,
+    Jump = 5,
+    Trap = 6,
+}
+
+impl Event {
+    /// Bit of the event in the event set passed to `CSR::count`
+    pub fn mask(self) -> u32 {
+        1 << self as u32
+    }
+}
 
 #[derive(Default)]
 pub struct CSR {
@@ -25,17 +110,108 @@ pub struct CSR {
     pub mip: u32,
     pub mtinst: u32,
     pub mtval2: u32,
+    /* Supervisor Trap Setup, sstatus, sie and sip are views into their machine registers */
+    pub stvec: u32,
+    pub scounteren: u32,
+    /* Supervisor Trap Handling */
+    pub sscratch: u32,
+    pub sepc: u32,
+    pub scause: u32,
+    pub stval: u32,
+    /* Supervisor Protection and Translation */
+    pub satp: u32,
+    /* Machine Memory Protection */
+    pub pmp: Pmp,
+    /* Machine Counter/Timers, hpm counters and events are indexed from 3 */
+    pub mcycle: u64,
+    pub minstret: u64,
+    pub mhpmcounter: [u64; 32],
+    pub mhpmevent: [u32; 32],
+    pub mcountinhibit: u32,
+    /* Shadow of mtime of the platform timer, read through the time CSR */
+    pub time: u64,
+    /* Counters written by the current instruction, they skip their increment */
+    counters_written: u32,
+    /* Floating-Point Control and Status Register, frm and fflags are views into it */
+    pub fcsr: u32,
 }
 
 impl CSR {
-    pub fn read(&self, index: u32) -> u32 {
-        match index {
+    /// Value of the counter with the given number, 0 is cycle, 1 time and 2 instret
+    fn counter(&self, number: u32) -> u64 {
+        match number {
+            0 => self.mcycle,
+            1 => self.time,
+            2 => self.minstret,
+            n => self.mhpmcounter[n as usize],
+        }
+    }
+
+    fn counter_mut(&mut self, number: u32) -> &mut u64 {
+        self.counters_written |= 1 << number;
+        match number {
+            0 => &mut self.mcycle,
+            2 => &mut self.minstret,
+            n => &mut self.mhpmcounter[n as usize],
+        }
+    }
+
+    /// Advances the counters by one step of the execution loop.
+    ///
+    /// `retired` tells whether the instruction completed without an exception, `events` is a
+    /// set of `Event::mask` bits of what happened during its execution.
+    pub fn count(&mut self, retired: bool, events: u32) {
+        let active = !self.mcountinhibit & !self.counters_written;
+        if active & 0b001 != 0 {
+            self.mcycle = self.mcycle.wrapping_add(1);
+        }
+        if retired && active & 0b100 != 0 {
+            self.minstret = self.minstret.wrapping_add(1);
+        }
+        for n in 3..32 {
+            let event = self.mhpmevent[n];
+            if active & (1 << n) != 0 && event != 0 && events & (1 << event) != 0 {
+                self.mhpmcounter[n] = self.mhpmcounter[n].wrapping_add(1);
+            }
+        }
+        self.counters_written = 0;
+    }
+
+    /// Value of `mstatus`, SD summarizes whether FS is Dirty
+    fn status(&self) -> u32 {
+        if self.mstatus & MSTATUS_FS == FS_DIRTY {
+            self.mstatus | MSTATUS_SD
+        } else {
+            self.mstatus
+        }
+    }
+
+    /// Marks the floating-point state as modified, in `mstatus.FS`
+    pub fn fp_written(&mut self) {
+        self.mstatus |= FS_DIRTY;
+    }
+
+    pub fn read(&self, index: u32) -> Result<u32, Exception> {
+        let value = match index {
+            0x001 => self.fcsr & 0b1_1111,
+            0x002 => (self.fcsr >> 5) & 0b111,
+            0x003 => self.fcsr,
             0xF11 => self.mvendorid,
             0xF12 => self.marchid,
             0xF13 => self.mimpid,
             0xF14 => self.mhartid,
             0xF15 => self.mconfigptr,
-            0x300 => self.mstatus,
+            0x100 => self.status() & SSTATUS_MASK,
+            0x104 => self.mie & self.mideleg,
+            0x105 => self.stvec,
+            0x106 => self.scounteren,
+            0x140 => self.sscratch,
+            0x141 => self.sepc,
+            0x142 => self.scause,
+            0x143 => self.stval,
+            0x144 => self.mip & self.mideleg,
+            0x180 => self.satp,
+            0x300 => self.status(),
             0x301 => self.misa,
             0x302 => self.medeleg,
             0x303 => self.mideleg,
@@ -50,52 +226,120 @@ impl CSR {
             0x344 => self.mip,
             0x34A => self.mtinst,
             0x34B => self.mtval2,
+            0xB00 | 0xB02..=0xB1F | 0xC00..=0xC1F => self.counter(index & 0x1F) as u32,
+            0xB80 | 0xB82..=0xB9F | 0xC80..=0xC9F => (self.counter(index & 0x1F) >> 32) as u32,
+            0x320 => self.mcountinhibit,
+            0x323..=0x33F => self.mhpmevent[(index & 0x1F) as usize],
+            0x3A0..=0x3A3 => self.pmp.read_cfg((index & 0xF) as usize),
+            0x3B0..=0x3BF => self.pmp.read_addr((index & 0xF) as usize),
+            /* Entries beyond the implemented ones are hardwired to zero */
+            0x3A4..=0x3AF | 0x3C0..=0x3EF => 0,
             _ => {
-                todo!();
+                return Err(Exception::IllegalInstruction(0));
             }
-        }
+        };
+        Ok(value)
     }
 
-    pub fn write(&mut self, index: u32, value: u32) {
+    pub fn write(&mut self, index: u32, value: u32) -> Result<(), Exception> {
         match index {
-            0xF11 | 0xF12 | 0xF13 | 0xF14 | 0xF15 => {
-                panic!("Attempt to write to read-only CSR!");
+            0x001 => {
+                self.fcsr = (self.fcsr & !0b1_1111) | (value & 0b1_1111);
+                self.fp_written();
+            }
+            0x002 => {
+                self.fcsr = (self.fcsr & 0b1_1111) | ((value & 0b111) << 5);
+                self.fp_written();
+            }
+            0x003 => {
+                self.fcsr = value & 0b1111_1111;
+                self.fp_written();
+            }
+            0xF11..=0xF15 | 0xC00..=0xC1F | 0xC80..=0xC9F => {
+                /* Writes to read-only CSRs are illegal */
+                return Err(Exception::IllegalInstruction(0));
+            }
+            0x100 => {
+                /* SD is read-only, it follows FS */
+                let writable = SSTATUS_MASK & !MSTATUS_SD;
+                self.mstatus = (self.mstatus & !writable) | (value & writable);
+            }
+            0x104 => {
+                let writable = self.mideleg;
+                self.mie = (self.mie & !writable) | (value & writable);
+            }
+            0x105 => {
+                self.stvec = tvec(value);
+            }
+            0x106 => {
+                self.scounteren = value;
+            }
+            0x140 => {
+                self.sscratch = value;
+            }
+            0x141 => {
+                self.sepc = value;
+            }
+            0x142 => {
+                self.scause = value;
+            }
+            0x143 => {
+                self.stval = value;
+            }
+            0x144 => {
+                /* Only the software interrupt can be raised or cleared from supervisor mode */
+                let writable = Interrupt::SupervisorSoftware.mask() & self.mideleg;
+                self.mip = (self.mip & !writable) | (value & writable);
+            }
+            0x180 => {
+                /* WARL / ASIDs are not implemented, the field reads as 0 */
+                self.satp = value & !(0x1FF << 22);
             }
             0x300 => {
-                println!("Ingoring write of {value:X} into mstatus");
-                self.mstatus = 0;
+                /* WARL / the reserved privilege level 2 leaves MPP unchanged */
+                let mpp = if Privilege::from_bits((value & MSTATUS_MPP) >> 11).is_some() {
+                    value & MSTATUS_MPP
+                } else {
+                    self.mstatus & MSTATUS_MPP
+                };
+                let writable = MSTATUS_SIE
+                    | MSTATUS_MIE
+                    | MSTATUS_SPIE
+                    | MSTATUS_MPIE
+                    | MSTATUS_SPP
+                    | MSTATUS_FS
+                    | MSTATUS_MPRV
+                    | MSTATUS_SUM
+                    | MSTATUS_MXR
+                    | MSTATUS_TVM
+                    | MSTATUS_TW
+                    | MSTATUS_TSR;
+                self.mstatus = (value & writable) | mpp;
             }
             0x301 => {
                 /* WARL / zero indicates misa is not implemented */
                 self.misa = 0;
             }
             0x302 => {
-                println!("Ingoring write of {value:X} into medeleg");
-                self.medeleg = 0;
+                /* WARL / only exceptions supervisor mode can raise are delegable */
+                self.medeleg = value & DELEGABLE_EXCEPTIONS;
             }
             0x303 => {
-                println!("Ingoring write of {value:X} into mideleg");
-                self.mideleg = 0;
+                /* WARL / only the supervisor interrupts are delegable */
+                self.mideleg = value & interrupts(Interrupt::is_supervisor);
             }
             0x304 => {
-                println!("Ingoring write of {value:X} into mie");
-                self.mie = 0;
+                /* WARL / only the bits of implemented interrupts are writable */
+                self.mie = interrupts(|_| true) & value;
             }
             0x305 => {
-                if value % 4 != 0 {
-                    assert!(
-                        value % 4 == 0,
-                        "mtvec value not 4-byte aligned or mode other than Direct selected"
-                    );
-                }
-                self.mtvec = value;
+                self.mtvec = tvec(value);
             }
             0x306 => {
-                println!("Ingoring write of {value:X} into mcounteren");
-                self.mcounteren = 0;
+                self.mcounteren = value;
             }
             0x310 => {
-                println!("Ingoring write of {value:X} into mstatush");
+                /* WARL / only little-endian is implemented, SBE and MBE are hardwired to 0 */
                 self.mstatush = 0;
             }
             0x340 => {
@@ -108,33 +352,168 @@ impl CSR {
                 self.mcause = value;
             }
             0x343 => {
-                println!("Ingoring write of {value:X} into mtval");
-                self.mtval = 0;
+                self.mtval = value;
             }
             0x344 => {
-                println!("Ingoring write of {value:X} into mip");
-                self.mip = 0;
+                /* The machine interrupt bits are read-only, they reflect their sources */
+                let writable = interrupts(Interrupt::is_supervisor);
+                self.mip = (self.mip & !writable) | (value & writable);
             }
             0x34A => {
-                println!("Ingoring write of {value:X} into mtinst");
-                self.mtinst = 0;
+                self.mtinst = value;
             }
             0x34B => {
-                println!("Ingoring write of {value:X} into mtval2");
-                self.mtval2 = 0;
+                self.mtval2 = value;
+            }
+            0xB00 | 0xB02..=0xB1F => {
+                let counter = self.counter_mut(index & 0x1F);
+                *counter = (*counter & !0xFFFF_FFFF) | u64::from(value);
+            }
+            0xB80 | 0xB82..=0xB9F => {
+                let counter = self.counter_mut(index & 0x1F);
+                *counter = (*counter & 0xFFFF_FFFF) | (u64::from(value) << 32);
+            }
+            0x320 => {
+                /* WARL / time can't be inhibited */
+                self.mcountinhibit = value & !0b10;
             }
+            0x323..=0x33F => {
+                /* WARL / unknown events read back as 0, which counts nothing */
+                let event = if value <= Event::Trap as u32 {
+                    value
+                } else {
+                    0
+                };
+                self.mhpmevent[(index & 0x1F) as usize] = event;
+            }
+            0x3A0..=0x3A3 => {
+                self.pmp.write_cfg((index & 0xF) as usize, value);
+            }
+            0x3B0..=0x3BF => {
+                self.pmp.write_addr((index & 0xF) as usize, value);
+            }
+            0x3A4..=0x3AF | 0x3C0..=0x3EF => {}
             _ => {
-                todo!();
+                return Err(Exception::IllegalInstruction(0));
             }
         }
+        Ok(())
     }
 }
 
-#[derive(Default)]
+/// WARL value of `mtvec` and `stvec`, Direct (0) and Vectored (1) mode are implemented
+/// and the reserved modes fall back to Direct
+fn tvec(value: u32) -> u32 {
+    if value & 0b11 == 0b01 {
+        value
+    } else {
+        value & !0b11
+    }
+}
+
+/// ABI names of the integer registers
+pub const REGISTER_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+/// ABI names of the floating-point registers
+pub const FP_REGISTER_NAMES: [&str; 32] = [
+    "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7", "fs0", "fs1", "fa0", "fa1", "fa2",
+    "fa3", "fa4", "fa5", "fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7", "fs8", "fs9",
+    "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+];
+
+/// Name of the CSR with the given number, `None` for CSRs this machine doesn't know of
+pub fn csr_name(index: u32) -> Option<String> {
+    let name = match index {
+        0x001 => "fflags",
+        0x002 => "frm",
+        0x003 => "fcsr",
+        0xF11 => "mvendorid",
+        0xF12 => "marchid",
+        0xF13 => "mimpid",
+        0xF14 => "mhartid",
+        0xF15 => "mconfigptr",
+        0x100 => "sstatus",
+        0x104 => "sie",
+        0x105 => "stvec",
+        0x106 => "scounteren",
+        0x140 => "sscratch",
+        0x141 => "sepc",
+        0x142 => "scause",
+        0x143 => "stval",
+        0x144 => "sip",
+        0x180 => "satp",
+        0x300 => "mstatus",
+        0x301 => "misa",
+        0x302 => "medeleg",
+        0x303 => "mideleg",
+        0x304 => "mie",
+        0x305 => "mtvec",
+        0x306 => "mcounteren",
+        0x310 => "mstatush",
+        0x320 => "mcountinhibit",
+        0x340 => "mscratch",
+        0x341 => "mepc",
+        0x342 => "mcause",
+        0x343 => "mtval",
+        0x344 => "mip",
+        0x34A => "mtinst",
+        0x34B => "mtval2",
+        0xB00 => "mcycle",
+        0xB02 => "minstret",
+        0xB80 => "mcycleh",
+        0xB82 => "minstreth",
+        0xC00 => "cycle",
+        0xC01 => "time",
+        0xC02 => "instret",
+        0xC80 => "cycleh",
+        0xC81 => "timeh",
+        0xC82 => "instreth",
+        0x323..=0x33F => return Some(format!("mhpmevent{}", index & 0x1F)),
+        0xB03..=0xB1F => return Some(format!("mhpmcounter{}", index & 0x1F)),
+        0xB83..=0xB9F => return Some(format!("mhpmcounter{}h", index & 0x1F)),
+        0xC03..=0xC1F => return Some(format!("hpmcounter{}", index & 0x1F)),
+        0xC83..=0xC9F => return Some(format!("hpmcounter{}h", index & 0x1F)),
+        0x3A0..=0x3AF => return Some(format!("pmpcfg{}", index & 0xF)),
+        0x3B0..=0x3EF => return Some(format!("pmpaddr{}", index - 0x3B0)),
+        _ => return None,
+    };
+    Some(name.to_string())
+}
+
 pub struct RegisterFile {
     regs: [u32; 32],
+    /* Floating-point registers, wide enough for the D extension */
+    fregs: [u64; 32],
     pub csr: CSR,
     pub pc: u32,
+    /* Privilege level the hart currently runs in, it starts in machine mode */
+    pub privilege: Privilege,
+    /* Cached address translations, flushed by SFENCE.VMA */
+    pub tlb: Tlb,
+    /* Address reserved by the last LR.W, SC.W only succeeds on a matching reservation */
+    pub reservation: Option<u32>,
+}
+
+impl Default for RegisterFile {
+    fn default() -> Self {
+        Self {
+            regs: [0; 32],
+            fregs: [0; 32],
+            /* The FPU starts out enabled, programs can use it without setting up mstatus */
+            csr: CSR {
+                mstatus: FS_INITIAL,
+                ..CSR::default()
+            },
+            pc: 0,
+            privilege: Privilege::default(),
+            tlb: Tlb::default(),
+            reservation: None,
+        }
+    }
 }
 
 impl RegisterFile {
@@ -147,87 +526,172 @@ impl RegisterFile {
             self.regs[index] = value;
         }
     }
+
+    /// Reads a single-precision value, anything not properly NaN-boxed reads as canonical NaN
+    pub fn read_f32(&self, index: Rindex) -> u32 {
+        let value = self.fregs[index];
+        if value >> 32 == 0xFFFF_FFFF {
+            value as u32
+        } else {
+            0x7FC0_0000
+        }
+    }
+
+    /// Writes a single-precision value, NaN-boxed into the upper half
+    pub fn write_f32(&mut self, index: Rindex, value: u32) {
+        self.fregs[index] = 0xFFFF_FFFF_0000_0000 | u64::from(value);
+        self.csr.fp_written();
+    }
+
+    /// Reads a double-precision value, which is also the raw contents of the register
+    pub fn read_f64(&self, index: Rindex) -> u64 {
+        self.fregs[index]
+    }
+
+    pub fn write_f64(&mut self, index: Rindex, value: u64) {
+        self.fregs[index] = value;
+        self.csr.fp_written();
+    }
+
+    /// Privilege level loads and stores are performed with.
+    ///
+    /// With `mstatus.MPRV` set, machine mode accesses memory as if it ran in the mode in
+    /// MPP, which lets a trap handler access memory on behalf of the interrupted mode.
+    pub fn data_privilege(&self) -> Privilege {
+        if self.privilege == Privilege::Machine && self.csr.mstatus & MSTATUS_MPRV != 0 {
+            Privilege::from_bits((self.csr.mstatus & MSTATUS_MPP) >> 11).unwrap()
+        } else {
+            self.privilege
+        }
+    }
 }
 
-pub struct Memory {
-    pub io_base: usize,
-    pub io_len: usize,
-    pub ram_base: usize,
-    pub ram: Vec<u8>,
-    pub rom_base: usize,
+/* Memory map of the machine */
+pub const ROM_BASE: usize = 0x2000_0000;
+pub const IO_BASE: usize = 0x6000_0000;
+pub const RAM_BASE: usize = 0x8000_0000;
+
+/// ROM and RAM grow to fit the segments of an ELF file up to this size
+const MAX_REGION_SIZE: usize = 256 << 20;
+
+/// Contents of ROM and RAM as loaded from a program, the bus of the machine is built around it.
+pub struct Image {
     pub rom: Vec<u8>,
+    pub ram: Vec<u8>,
+    pub entry: u32,
 }
 
-impl Memory {
-    pub fn default_rom(rom: Vec<u8>) -> Self {
+impl Image {
+    /// A flat binary, loaded to the start of RAM where execution begins
+    pub fn flat(ram: Vec<u8>) -> Self {
         Self {
-            io_base: 0x6000_0000,
-            io_len: 0x01,
-            ram_base: 0x8000_0000,
-            ram: [0; 4096].to_vec(),
-            rom_base: 0x2000_0000,
-            rom,
+            rom: [0; 4096].to_vec(),
+            ram,
+            entry: RAM_BASE as u32,
         }
     }
 
-    pub fn default_ram(ram: Vec<u8>) -> Self {
-        Self {
-            io_base: 0x6000_0000,
-            io_len: 0x01,
-            ram_base: 0x8000_0000,
-            ram,
-            rom_base: 0x2000_0000,
+    /// Loads an ELF32 RISC-V executable.
+    ///
+    /// Every `PT_LOAD` segment is copied to its physical address, either into ROM or RAM.
+    /// Both regions grow to fit the segments placed in them, up to `MAX_REGION_SIZE`. The
+    /// part of a segment not backed by the file (`.bss`) stays zero.
+    pub fn from_elf(elf_data: &[u8]) -> anyhow::Result<Self> {
+        let file = ElfBytes::<LittleEndian>::minimal_parse(elf_data)?;
+        ensure!(file.ehdr.class == Class::ELF32, "Not an ELF32 file");
+        ensure!(
+            file.ehdr.e_machine == abi::EM_RISCV,
+            "Not a RISC-V ELF file"
+        );
+        ensure!(
+            file.ehdr.e_type == abi::ET_EXEC,
+            "Not an executable ELF file"
+        );
+
+        let mut image = Self {
             rom: [0; 4096].to_vec(),
+            ram: [0; 4096].to_vec(),
+            entry: u32::try_from(file.ehdr.e_entry)?,
+        };
+        let segments = file
+            .segments()
+            .ok_or(anyhow!("ELF file has no program headers"))?;
+        for phdr in segments.iter().filter(|phdr| phdr.p_type == abi::PT_LOAD) {
+            let data = file.segment_data(&phdr)?;
+            let addr = usize::try_from(phdr.p_paddr)?;
+            let size = usize::try_from(phdr.p_memsz)?;
+            image.load_segment(addr, size, data)?;
         }
-    }
 
-    fn is_io(&self, addr: usize) -> bool {
-        self.io_base <= addr && addr < self.io_base + self.io_len
+        Ok(image)
     }
 
-    fn is_ram(&self, addr: usize) -> bool {
-        self.ram_base <= addr && addr < self.ram_base + self.ram.len()
-    }
-
-    fn is_rom(&self, addr: usize) -> bool {
-        self.rom_base <= addr && addr < self.rom_base + self.rom.len()
-    }
+    fn load_segment(&mut self, addr: usize, size: usize, data: &[u8]) -> anyhow::Result<()> {
+        ensure!(
+            data.len() <= size,
+            "Segment at 0x{addr:X} is larger in file than in memory"
+        );
+        let outside = || anyhow!("Segment at 0x{addr:X} is outside the memory map");
+        let end = addr.checked_add(size).ok_or_else(outside)?;
+        let (base, region) = if RAM_BASE <= addr {
+            (RAM_BASE, &mut self.ram)
+        } else if ROM_BASE <= addr && end <= IO_BASE {
+            (ROM_BASE, &mut self.rom)
+        } else {
+            return Err(outside());
+        };
+        ensure!(
+            end - base <= MAX_REGION_SIZE,
+            "Segment at 0x{addr:X} ends beyond the {} MiB its memory region can grow to",
+            MAX_REGION_SIZE >> 20
+        );
 
-    pub fn read_byte(&self, addr: usize) -> u32 {
-        if self.is_ram(addr) {
-            let index = addr - self.ram_base;
-            return u32::from(self.ram[index]);
-        }
-        if self.is_rom(addr) {
-            let index = addr - self.rom_base;
-            return u32::from(self.rom[index]);
+        let offset = addr - base;
+        if region.len() < offset + size {
+            region.resize(offset + size, 0);
         }
-        panic!("Memory access outside memory map: 0x{addr:X}");
+        region[offset..offset + data.len()].copy_from_slice(data);
+        region[offset + data.len()..offset + size].fill(0);
+        Ok(())
     }
-    pub fn read_halfword(&self, index: usize) -> u32 {
-        (self.read_byte(index + 1) << 8) + self.read_byte(index)
-    }
-    pub fn read_word(&self, index: usize) -> u32 {
-        (self.read_halfword(index + 2) << 16) + self.read_halfword(index)
+
+    /// Builds a bus with the ROM, the print port writing to `output` and the RAM of the image
+    pub fn into_bus(self, output: Box<dyn Write>) -> anyhow::Result<Bus> {
+        let mut bus = Bus::default();
+        bus.attach("rom", ROM_BASE, self.rom.len(), Memory::rom(self.rom))?;
+        bus.attach("print", IO_BASE, 1, PrintPort::new(output))?;
+        bus.attach("ram", RAM_BASE, self.ram.len(), Memory::ram(self.ram))?;
+        Ok(bus)
     }
-    pub fn write_byte(&mut self, addr: usize, value: u32) {
-        if self.is_ram(addr) {
-            let index = addr - self.ram_base;
-            self.ram[index] = (value & 0xFF) as u8;
-            return;
-        }
-        if self.is_io(addr) {
-            print!("{:}", char::from_u32(value).unwrap());
-            return;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments_grow_their_region_within_limits() {
+        let mut image = Image::flat(Vec::new());
+        image.load_segment(RAM_BASE + 0x100, 8, &[1, 2]).unwrap();
+        assert_eq!(
+            image.ram,
+            [&[0; 0x100][..], &[1, 2, 0, 0, 0, 0, 0, 0]].concat()
+        );
+        image.load_segment(ROM_BASE, 2, &[3]).unwrap();
+        assert_eq!(image.rom[..2], [3, 0]);
+
+        let errors = [
+            (RAM_BASE + MAX_REGION_SIZE - 4, 8, "ends beyond the 256 MiB"),
+            (0xFFFF_F000, 0x1000, "ends beyond the 256 MiB"),
+            (usize::MAX, 2, "outside the memory map"),
+            (IO_BASE - 4, 8, "outside the memory map"),
+            (0x1000, 4, "outside the memory map"),
+            (RAM_BASE, 1, "larger in file than in memory"),
+        ];
+        for (addr, size, error) in errors {
+            let result = image.load_segment(addr, size, &[0; 2]);
+            let message = result.unwrap_err().to_string();
+            assert!(message.contains(error), "0x{addr:X}: {message}");
         }
-        panic!("Memory access outside memory map: 0x{addr:X}");
-    }
-    pub fn write_halfword(&mut self, index: usize, value: u32) {
-        self.write_byte(index, value);
-        self.write_byte(index + 1, value >> 8);
-    }
-    pub fn write_word(&mut self, index: usize, value: u32) {
-        self.write_halfword(index, value);
-        self.write_halfword(index + 2, value >> 16);
     }
 }
@@ -0,0 +1,114 @@
+/// Synchronous exceptions raised by the guest.
+///
+/// The payload is the value that is reported in `mtval` when the trap is taken,
+/// e.g. the faulting address or the bits of an illegal instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    InstructionAddressMisaligned(u32),
+    InstructionAccessFault(u32),
+    IllegalInstruction(u32),
+    Breakpoint(u32),
+    LoadAddressMisaligned(u32),
+    LoadAccessFault(u32),
+    StoreAddressMisaligned(u32),
+    StoreAccessFault(u32),
+    EnvironmentCallFromUMode,
+    EnvironmentCallFromSMode,
+    EnvironmentCallFromMMode,
+    InstructionPageFault(u32),
+    LoadPageFault(u32),
+    StorePageFault(u32),
+}
+
+impl Exception {
+    /// Exception code as written into `mcause`
+    pub fn cause(&self) -> u32 {
+        match self {
+            Self::InstructionAddressMisaligned(_) => 0,
+            Self::InstructionAccessFault(_) => 1,
+            Self::IllegalInstruction(_) => 2,
+            Self::Breakpoint(_) => 3,
+            Self::LoadAddressMisaligned(_) => 4,
+            Self::LoadAccessFault(_) => 5,
+            Self::StoreAddressMisaligned(_) => 6,
+            Self::StoreAccessFault(_) => 7,
+            Self::EnvironmentCallFromUMode => 8,
+            Self::EnvironmentCallFromSMode => 9,
+            Self::EnvironmentCallFromMMode => 11,
+            Self::InstructionPageFault(_) => 12,
+            Self::LoadPageFault(_) => 13,
+            Self::StorePageFault(_) => 15,
+        }
+    }
+
+    /// Trap value as written into `mtval`
+    pub fn value(&self) -> u32 {
+        match *self {
+            Self::InstructionAddressMisaligned(value)
+            | Self::InstructionAccessFault(value)
+            | Self::IllegalInstruction(value)
+            | Self::Breakpoint(value)
+            | Self::LoadAddressMisaligned(value)
+            | Self::LoadAccessFault(value)
+            | Self::StoreAddressMisaligned(value)
+            | Self::StoreAccessFault(value)
+            | Self::InstructionPageFault(value)
+            | Self::LoadPageFault(value)
+            | Self::StorePageFault(value) => value,
+            Self::EnvironmentCallFromUMode
+            | Self::EnvironmentCallFromSMode
+            | Self::EnvironmentCallFromMMode => 0,
+        }
+    }
+}
+
+/// Asynchronous interrupts, pending ones are flagged in `mip` by their sources.
+///
+/// The machine interrupts are raised by devices, the supervisor ones are set by software
+/// in `mip`, usually by the machine mode firmware on behalf of the platform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    SupervisorSoftware,
+    MachineSoftware,
+    SupervisorTimer,
+    MachineTimer,
+    SupervisorExternal,
+    MachineExternal,
+}
+
+impl Interrupt {
+    /// Interrupts in the order they are taken when several are pending at once
+    pub const PRIORITY: [Self; 6] = [
+        Self::MachineExternal,
+        Self::MachineSoftware,
+        Self::MachineTimer,
+        Self::SupervisorExternal,
+        Self::SupervisorSoftware,
+        Self::SupervisorTimer,
+    ];
+
+    /// Exception code as written into `mcause`, without the interrupt bit
+    pub fn cause(&self) -> u32 {
+        match self {
+            Self::SupervisorSoftware => 1,
+            Self::MachineSoftware => 3,
+            Self::SupervisorTimer => 5,
+            Self::MachineTimer => 7,
+            Self::SupervisorExternal => 9,
+            Self::MachineExternal => 11,
+        }
+    }
+
+    /// Whether the interrupt targets supervisor mode, only those can be delegated
+    pub fn is_supervisor(&self) -> bool {
+        matches!(
+            self,
+            Self::SupervisorSoftware | Self::SupervisorTimer | Self::SupervisorExternal
+        )
+    }
+
+    /// Bit of the interrupt in `mip` and `mie`
+    pub fn mask(&self) -> u32 {
+        1 << self.cause()
+    }
+}
@@ -0,0 +1,370 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use crate::bus::{AccessFault, Device, Size};
+
+/* Register offsets, the divisor latch replaces RBR/THR and IER while LCR.DLAB is set */
+const RBR_THR_DLL: usize = 0;
+const IER_DLM: usize = 1;
+const IIR_FCR: usize = 2;
+const LCR: usize = 3;
+const MCR: usize = 4;
+const LSR: usize = 5;
+const MSR: usize = 6;
+const SCR: usize = 7;
+
+/// Size of the address range taken by the UART
+pub const UART_LEN: usize = 0x100;
+
+const FIFO_DEPTH: usize = 16;
+
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+const IER_TX_EMPTY: u8 = 1 << 1;
+
+const IIR_NONE: u8 = 0b0001;
+const IIR_TX_EMPTY: u8 = 0b0010;
+const IIR_RX_AVAILABLE: u8 = 0b0100;
+const IIR_FIFO_ENABLED: u8 = 0b1100_0000;
+
+const FCR_FIFO_ENABLE: u8 = 1 << 0;
+const FCR_RX_RESET: u8 = 1 << 1;
+
+const LCR_DLAB: u8 = 1 << 7;
+
+const MCR_LOOPBACK: u8 = 1 << 4;
+
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_OVERRUN: u8 = 1 << 1;
+const LSR_THR_EMPTY: u8 = 1 << 5;
+const LSR_TX_EMPTY: u8 = 1 << 6;
+
+/// Carrier detect, data set ready and clear to send, the host end is always there
+const MSR_CONNECTED: u8 = 0b1011_0000;
+
+/// NS16550A compatible UART with byte-wide registers.
+///
+/// Transmission is instantaneous, the transmitter is always empty. Received bytes come
+/// from the host through a channel and are moved into the receive FIFO as it has room.
+pub struct Uart {
+    ier: u8,
+    fcr: u8,
+    lcr: u8,
+    mcr: u8,
+    scr: u8,
+    dll: u8,
+    dlm: u8,
+    overrun: bool,
+    /* The THR empty interrupt is cleared by reading IIR or writing THR */
+    tx_empty_pending: bool,
+    rx: VecDeque<u8>,
+    input: Receiver<u8>,
+    output: Box<dyn Write>,
+}
+
+impl Uart {
+    pub fn new(input: Receiver<u8>, output: Box<dyn Write>) -> Self {
+        Self {
+            ier: 0,
+            fcr: 0,
+            lcr: 0,
+            mcr: 0,
+            scr: 0,
+            dll: 0,
+            dlm: 0,
+            overrun: false,
+            tx_empty_pending: false,
+            rx: VecDeque::with_capacity(FIFO_DEPTH),
+            input,
+            output,
+        }
+    }
+
+    /// Queues a byte received from the host, it is lost if the receive FIFO is full
+    pub fn receive(&mut self, byte: u8) {
+        if self.rx.len() < FIFO_DEPTH {
+            self.rx.push_back(byte);
+        } else {
+            self.overrun = true;
+        }
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        if self.mcr & MCR_LOOPBACK != 0 {
+            self.receive(byte);
+        } else {
+            /* A broken host stdout is no concern of the guest */
+            let _ = self.output.write_all(&[byte]);
+            let _ = self.output.flush();
+        }
+        self.tx_empty_pending = true;
+    }
+
+    fn iir(&self) -> u8 {
+        let fifo = if self.fcr & FCR_FIFO_ENABLE != 0 {
+            IIR_FIFO_ENABLED
+        } else {
+            0
+        };
+        let id = if self.ier & IER_RX_AVAILABLE != 0 && !self.rx.is_empty() {
+            IIR_RX_AVAILABLE
+        } else if self.ier & IER_TX_EMPTY != 0 && self.tx_empty_pending {
+            IIR_TX_EMPTY
+        } else {
+            IIR_NONE
+        };
+        fifo | id
+    }
+
+    fn lsr(&self) -> u8 {
+        let mut lsr = LSR_THR_EMPTY | LSR_TX_EMPTY;
+        if !self.rx.is_empty() {
+            lsr |= LSR_DATA_READY;
+        }
+        if self.overrun {
+            lsr |= LSR_OVERRUN;
+        }
+        lsr
+    }
+
+    fn dlab(&self) -> bool {
+        self.lcr & LCR_DLAB != 0
+    }
+}
+
+impl Device for Uart {
+    fn read(&mut self, offset: usize, size: Size) -> Result<u32, AccessFault> {
+        if size != Size::Byte {
+            return Err(AccessFault);
+        }
+        let value = match offset {
+            RBR_THR_DLL if self.dlab() => self.dll,
+            RBR_THR_DLL => self.rx.pop_front().unwrap_or(0),
+            IER_DLM if self.dlab() => self.dlm,
+            IER_DLM => self.ier,
+            IIR_FCR => {
+                let iir = self.iir();
+                if iir & 0b1111 == IIR_TX_EMPTY {
+                    self.tx_empty_pending = false;
+                }
+                iir
+            }
+            LSR => {
+                let lsr = self.lsr();
+                self.overrun = false;
+                lsr
+            }
+            _ => self.peek(offset, size).ok_or(AccessFault)? as u8,
+        };
+        Ok(u32::from(value))
+    }
+
+    fn write(&mut self, offset: usize, size: Size, value: u32) -> Result<(), AccessFault> {
+        if size != Size::Byte {
+            return Err(AccessFault);
+        }
+        let value = value as u8;
+        match offset {
+            RBR_THR_DLL if self.dlab() => self.dll = value,
+            RBR_THR_DLL => self.transmit(value),
+            IER_DLM if self.dlab() => self.dlm = value,
+            IER_DLM => {
+                /* Enabling the THR empty interrupt raises it right away, THR is always empty */
+                if value & IER_TX_EMPTY != 0 && self.ier & IER_TX_EMPTY == 0 {
+                    self.tx_empty_pending = true;
+                }
+                self.ier = value & 0b1111;
+            }
+            IIR_FCR => {
+                if value & FCR_RX_RESET != 0 {
+                    self.rx.clear();
+                }
+                self.fcr = value & !0b110;
+            }
+            LCR => self.lcr = value,
+            MCR => self.mcr = value & 0b1_1111,
+            SCR => self.scr = value,
+            /* LSR and MSR are read-only */
+            LSR | MSR => {}
+            _ => return Err(AccessFault),
+        }
+        Ok(())
+    }
+
+    fn peek(&self, offset: usize, size: Size) -> Option<u32> {
+        if size != Size::Byte {
+            return None;
+        }
+        let value = match offset {
+            RBR_THR_DLL if self.dlab() => self.dll,
+            RBR_THR_DLL => self.rx.front().copied().unwrap_or(0),
+            IER_DLM if self.dlab() => self.dlm,
+            IER_DLM => self.ier,
+            IIR_FCR => self.iir(),
+            LCR => self.lcr,
+            MCR => self.mcr,
+            LSR => self.lsr(),
+            MSR => MSR_CONNECTED,
+            SCR => self.scr,
+            _ => return None,
+        };
+        Some(u32::from(value))
+    }
+
+    fn tick(&mut self, _retired: bool) {
+        while self.rx.len() < FIFO_DEPTH {
+            match self.input.try_recv() {
+                Ok(byte) => self.rx.push_back(byte),
+                Err(_) => break,
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.iir() & IIR_NONE == 0
+    }
+}
+
+/// Reads host stdin on a background thread, the bytes arrive on the returned channel
+pub fn stdin_input() -> Receiver<u8> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for byte in io::stdin().lock().bytes() {
+            match byte {
+                Ok(byte) if sender.send(byte).is_ok() => {}
+                _ => break,
+            }
+        }
+    });
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::mpsc::Sender;
+
+    use super::*;
+
+    /// Collects what the UART transmits to the host
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn uart() -> (Uart, Sender<u8>, Output) {
+        let (sender, input) = mpsc::channel();
+        let output = Output::default();
+        (Uart::new(input, Box::new(output.clone())), sender, output)
+    }
+
+    fn read(uart: &mut Uart, offset: usize) -> u8 {
+        uart.read(offset, Size::Byte).unwrap() as u8
+    }
+
+    #[test]
+    fn line_status_follows_the_receive_fifo() {
+        let (mut uart, input, output) = uart();
+        assert_eq!(read(&mut uart, LSR), LSR_THR_EMPTY | LSR_TX_EMPTY);
+
+        input.send(b'h').unwrap();
+        input.send(b'i').unwrap();
+        uart.tick(true);
+        assert_eq!(read(&mut uart, LSR) & LSR_DATA_READY, LSR_DATA_READY);
+        assert_eq!(read(&mut uart, RBR_THR_DLL), b'h');
+        assert_eq!(read(&mut uart, RBR_THR_DLL), b'i');
+        assert_eq!(read(&mut uart, LSR) & LSR_DATA_READY, 0);
+
+        uart.write(RBR_THR_DLL, Size::Byte, u32::from(b'!'))
+            .unwrap();
+        assert_eq!(*output.0.borrow(), b"!");
+    }
+
+    #[test]
+    fn overrun_is_reported_once() {
+        let (mut uart, _, _) = uart();
+        for byte in 0..=FIFO_DEPTH as u8 {
+            uart.receive(byte);
+        }
+        assert_eq!(read(&mut uart, LSR) & LSR_OVERRUN, LSR_OVERRUN);
+        assert_eq!(read(&mut uart, LSR) & LSR_OVERRUN, 0);
+        /* The byte that didn't fit is lost */
+        let received: Vec<u8> = (0..FIFO_DEPTH)
+            .map(|_| read(&mut uart, RBR_THR_DLL))
+            .collect();
+        assert_eq!(received, (0..FIFO_DEPTH as u8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn receive_interrupt_needs_ier() {
+        let (mut uart, _, _) = uart();
+        uart.receive(b'x');
+        assert!(!uart.irq());
+        assert_eq!(read(&mut uart, IIR_FCR), IIR_NONE);
+
+        uart.write(IER_DLM, Size::Byte, u32::from(IER_RX_AVAILABLE))
+            .unwrap();
+        assert_eq!(read(&mut uart, IER_DLM), IER_RX_AVAILABLE);
+        assert!(uart.irq());
+        assert_eq!(read(&mut uart, IIR_FCR), IIR_RX_AVAILABLE);
+        read(&mut uart, RBR_THR_DLL);
+        assert!(!uart.irq());
+    }
+
+    #[test]
+    fn transmitter_empty_interrupt_is_cleared_by_iir_and_raised_by_thr() {
+        let (mut uart, _, _) = uart();
+        /* THR is always empty, so enabling the interrupt raises it */
+        uart.write(IER_DLM, Size::Byte, u32::from(IER_TX_EMPTY))
+            .unwrap();
+        assert!(uart.irq());
+        assert_eq!(read(&mut uart, IIR_FCR), IIR_TX_EMPTY);
+        assert!(!uart.irq());
+        assert_eq!(read(&mut uart, IIR_FCR), IIR_NONE);
+
+        uart.write(RBR_THR_DLL, Size::Byte, u32::from(b'a'))
+            .unwrap();
+        assert!(uart.irq());
+        /* Peeking, as the debugger does, leaves it pending */
+        assert_eq!(
+            uart.peek(IIR_FCR, Size::Byte),
+            Some(u32::from(IIR_TX_EMPTY))
+        );
+        assert!(uart.irq());
+    }
+
+    #[test]
+    fn divisor_latch_replaces_data_and_ier() {
+        let (mut uart, _, output) = uart();
+        uart.write(LCR, Size::Byte, u32::from(LCR_DLAB)).unwrap();
+        uart.write(RBR_THR_DLL, Size::Byte, 0x0C).unwrap();
+        uart.write(IER_DLM, Size::Byte, 0x01).unwrap();
+        uart.write(LCR, Size::Byte, 0x03).unwrap();
+        assert_eq!(read(&mut uart, IER_DLM), 0);
+        assert!(output.0.borrow().is_empty());
+
+        uart.write(LCR, Size::Byte, u32::from(LCR_DLAB | 0x03))
+            .unwrap();
+        assert_eq!(read(&mut uart, RBR_THR_DLL), 0x0C);
+        assert_eq!(read(&mut uart, IER_DLM), 0x01);
+    }
+
+    #[test]
+    fn registers_only_take_byte_accesses() {
+        let (mut uart, _, _) = uart();
+        assert_eq!(uart.read(LSR, Size::Word), Err(AccessFault));
+        assert_eq!(uart.write(SCR, Size::Halfword, 0), Err(AccessFault));
+        assert_eq!(uart.write(8, Size::Byte, 0), Err(AccessFault));
+    }
+}
@@ -1,13 +1,19 @@
-use crate::decoder::decode;
-use crate::system::{Memory, RegisterFile};
+use crate::bus::Bus;
+use crate::console::Console;
+use crate::debugger::Debugger;
+use crate::decoder::{decode, instruction_length};
+use crate::executer::fetch;
+use crate::symbols::Symbols;
+use crate::system::RegisterFile;
 use std::iter::Map;
 
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use tui::{
     backend::{Backend, CrosstermBackend},
     layout::{Alignment, Constraint, Direction, Layout},
     style::{Color, Modifier, Style},
-    text::Span,
-    widgets::{Block, BorderType, Borders, Cell, List, ListItem, ListState, Row, Table},
+    text::{Span, Spans},
+    widgets::{Block, BorderType, Borders, Cell, List, ListItem, ListState, Paragraph, Row, Table},
     Frame, Terminal,
 };
 
@@ -15,10 +21,21 @@ pub struct ViewState {
     register_table: Vec<Vec<String>>,
     list_state: ListState,
     instruction_list: Vec<String>,
+    console: Console,
+    /// Symbols of the program, for labels and breakpoints
+    pub symbols: Symbols,
+    /* Lines the I/O pane is scrolled up from the end of the console output */
+    scroll: usize,
+    /// Keystrokes go to the guest instead of the debugger
+    pub input_mode: bool,
+    /// Command line being typed, if any
+    pub command: Option<String>,
+    /// Message shown in the status line
+    pub status: String,
 }
 
 impl ViewState {
-    pub fn new() -> ViewState {
+    pub fn new(console: Console, symbols: Symbols) -> ViewState {
         ViewState {
             register_table: vec![
                 vec![
@@ -70,11 +87,26 @@ impl ViewState {
                     "x3: 0x00000000".to_string(),
                 ],
             ],
-            instruction_list: vec!["0x00000000: NOP".to_string(); 20],
+            instruction_list: vec![" 0x00000000: nop".to_string(); 20],
             list_state: ListState::default(),
+            console,
+            symbols,
+            scroll: 0,
+            input_mode: false,
+            command: None,
+            status: String::new(),
         }
     }
 
+    pub fn scroll_up(&mut self, lines: usize) {
+        let max = self.console.lines().len().saturating_sub(1);
+        self.scroll = (self.scroll + lines).min(max);
+    }
+
+    pub fn scroll_down(&mut self, lines: usize) {
+        self.scroll = self.scroll.saturating_sub(lines);
+    }
+
     fn prepare_register_table(&mut self, rf: &RegisterFile) {
         for k in 0..8 {
             for n in 0..4 {
@@ -86,21 +118,36 @@ impl ViewState {
         }
     }
 
-    fn prepare_instruction_list(&mut self, rf: &RegisterFile, mem: &Memory) {
+    fn prepare_instruction_list(&mut self, rf: &RegisterFile, bus: &Bus, debugger: &Debugger) {
         self.instruction_list
             .truncate(self.instruction_list.len() / 2);
 
-        for n in 0..11 {
-            let inst = decode(mem.read_word((rf.pc + n * 4) as usize));
-            if inst.is_ok() {
-                self.instruction_list
-                    .push(format!("0x{:08X}: {:?}", rf.pc + n * 4, inst.unwrap()));
+        let mut addr = rf.pc;
+        for _ in 0..11 {
+            /* Breakpoints are marked in front of the address */
+            let marker = if debugger.has_breakpoint(addr) {
+                '*'
             } else {
-                self.instruction_list.push(format!(
-                    "0x{:08X}: {:08X}",
-                    rf.pc + n * 4,
-                    mem.read_word((rf.pc + n * 4) as usize)
-                ));
+                ' '
+            };
+            match fetch(rf, bus, addr) {
+                Ok(raw) => {
+                    match decode(raw) {
+                        Ok(inst) => self.instruction_list.push(format!(
+                            "{marker}0x{addr:08X}: {}",
+                            inst.at(addr, &self.symbols)
+                        )),
+                        Err(_) => self
+                            .instruction_list
+                            .push(format!("{marker}0x{addr:08X}: {raw:08X}")),
+                    }
+                    addr += instruction_length(raw);
+                }
+                Err(_) => {
+                    self.instruction_list
+                        .push(format!("{marker}0x{addr:08X}: ????????"));
+                    addr += 4;
+                }
             }
         }
         while self.instruction_list.len() > 20 {
@@ -109,7 +156,13 @@ impl ViewState {
         self.list_state.select(Some(9));
     }
 
-    pub fn ui<B: Backend>(&mut self, f: &mut Frame<B>, rf: &RegisterFile, mem: &Memory) {
+    pub fn ui<B: Backend>(
+        &mut self,
+        f: &mut Frame<B>,
+        rf: &RegisterFile,
+        bus: &Bus,
+        debugger: &Debugger,
+    ) {
         let size = f.size();
 
         let block = Block::default()
@@ -119,22 +172,32 @@ impl ViewState {
             .border_type(BorderType::Rounded);
         f.render_widget(block, size);
 
-        let chunks = Layout::default()
-            .direction(Direction::Horizontal)
+        let main_chunks = Layout::default()
+            .direction(Direction::Vertical)
             .margin(1)
-            .constraints([Constraint::Percentage(25), Constraint::Percentage(75)].as_ref())
+            .constraints([Constraint::Min(0), Constraint::Length(1)].as_ref())
             .split(f.size());
 
+        let chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(35), Constraint::Percentage(65)].as_ref())
+            .split(main_chunks[0]);
+
         let right_chunks = Layout::default()
             .direction(Direction::Vertical)
             .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
             .split(chunks[1]);
 
+        /* The title names the function the guest is in, if the program has symbols */
+        let title = match self.symbols.label(rf.pc) {
+            Some(label) => format!("PC: {label}"),
+            None => "PC:\tInstruction".to_string(),
+        };
         let instruction_listing = Block::default()
             .borders(Borders::ALL)
-            .title(vec![Span::from("PC:\tInstruction")]);
+            .title(vec![Span::from(title)]);
 
-        self.prepare_instruction_list(rf, mem);
+        self.prepare_instruction_list(rf, bus, debugger);
         let items: Vec<ListItem> = self
             .instruction_list
             .iter()
@@ -167,10 +230,66 @@ impl ViewState {
             ]);
         f.render_widget(t, right_chunks[0]);
 
+        let title = if self.input_mode {
+            "I/O (input, Ctrl-] to leave)"
+        } else if self.scroll > 0 {
+            "I/O (scrolled, PgDn to follow)"
+        } else {
+            "I/O"
+        };
         let right_block_down = Block::default()
             .borders(Borders::ALL)
-            .title(vec![Span::from("I/O")])
+            .title(vec![Span::from(title)])
             .title_alignment(Alignment::Right);
-        f.render_widget(right_block_down, right_chunks[1]);
+        let height = usize::from(right_chunks[1].height.saturating_sub(2));
+        let lines = self.console.lines();
+        let end = lines.len() - self.scroll.min(lines.len());
+        let text: Vec<Spans> = lines[end.saturating_sub(height)..end]
+            .iter()
+            .map(|line| Spans::from(line.as_str()))
+            .collect();
+        let console = Paragraph::new(text).block(right_block_down);
+        f.render_widget(console, right_chunks[1]);
+
+        let status = match &self.command {
+            Some(command) => format!(":{command}"),
+            None if self.status.is_empty() => {
+                "s: step  c: continue  b: breakpoint  :: command  i: input  q: quit".to_string()
+            }
+            None => self.status.clone(),
+        };
+        f.render_widget(Paragraph::new(status), main_chunks[1]);
+    }
+}
+
+/// Whether `key` is Ctrl-], which leaves input mode
+pub fn leaves_input(key: KeyEvent) -> bool {
+    /* crossterm reports the control codes 0x1C to 0x1F as Ctrl-4 to Ctrl-7 */
+    key.modifiers.contains(KeyModifiers::CONTROL)
+        && matches!(key.code, KeyCode::Char(']') | KeyCode::Char('5'))
+}
+
+/// Bytes a serial terminal sends for `key`, empty for keys it has no encoding for
+pub fn key_bytes(key: KeyEvent) -> Vec<u8> {
+    match key.code {
+        KeyCode::Char(c @ '4'..='7') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            vec![c as u8 - b'4' + 0x1C]
+        }
+        KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            vec![(c.to_ascii_uppercase() as u8) & 0x1F]
+        }
+        KeyCode::Char(c) => c.to_string().into_bytes(),
+        KeyCode::Enter => vec![b'\r'],
+        KeyCode::Backspace => vec![0x7F],
+        KeyCode::Tab => vec![b'\t'],
+        KeyCode::Esc => vec![0x1B],
+        KeyCode::Up => b"\x1b[A".to_vec(),
+        KeyCode::Down => b"\x1b[B".to_vec(),
+        KeyCode::Right => b"\x1b[C".to_vec(),
+        KeyCode::Left => b"\x1b[D".to_vec(),
+        KeyCode::Home => b"\x1b[H".to_vec(),
+        KeyCode::End => b"\x1b[F".to_vec(),
+        KeyCode::Delete => b"\x1b[3~".to_vec(),
+        _ => Vec::new(),
     }
 }
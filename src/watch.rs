@@ -0,0 +1,341 @@
+use std::fmt;
+
+use crate::bus::Size;
+use crate::parse_address;
+
+/// Accesses a watchpoint stops at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    /// Writes that change the value in memory
+    Change,
+    /// Reads and writes
+    Access,
+}
+
+/// Comparison of a condition, the accessed value is on the left
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compare {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Compare {
+    fn apply(self, left: u32, right: u32) -> bool {
+        match self {
+            Self::Eq => left == right,
+            Self::Ne => left != right,
+            Self::Lt => left < right,
+            Self::Le => left <= right,
+            Self::Gt => left > right,
+            Self::Ge => left >= right,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+        }
+    }
+}
+
+/// A bus access as seen by the watchpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    /// `old` is the value before the write, if the device allows peeking at it
+    Write {
+        old: Option<u32>,
+    },
+}
+
+/// Stops the guest when an address range is accessed.
+///
+/// Values are those of the watched range if it is 1, 2 or 4 bytes long, so a byte store
+/// into a watched word is compared as the whole word. Longer ranges use the value of the
+/// access instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub kind: WatchKind,
+    pub addr: usize,
+    pub len: usize,
+    /// Only stop if the value of the access compares true against this one
+    pub condition: Option<(Compare, u32)>,
+}
+
+impl Watchpoint {
+    /// Whether an access of `size` bytes at `addr` touches the watched range
+    pub fn overlaps(&self, addr: usize, size: Size) -> bool {
+        addr < self.addr.saturating_add(self.len) && self.addr < addr.saturating_add(size.bytes())
+    }
+
+    /// Size of the watched range if it can be read in a single access
+    pub fn size(&self) -> Option<Size> {
+        match self.len {
+            1 => Some(Size::Byte),
+            2 => Some(Size::Halfword),
+            4 => Some(Size::Word),
+            _ => None,
+        }
+    }
+
+    /// Whether an overlapping access that leaves `value` in the watched range hits
+    pub fn hits(&self, access: Access, value: u32) -> bool {
+        let kind = match (self.kind, access) {
+            (WatchKind::Access, _)
+            | (WatchKind::Read, Access::Read)
+            | (WatchKind::Write, Access::Write { .. }) => true,
+            (WatchKind::Change, Access::Write { old }) => old != Some(value),
+            _ => false,
+        };
+        let condition = self
+            .condition
+            .is_none_or(|(compare, right)| compare.apply(value, right));
+        kind && condition
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::Change => "change",
+            WatchKind::Access => "access",
+        };
+        write!(f, "{kind} 0x{:08X}+{}", self.addr, self.len)?;
+        if let Some((compare, value)) = self.condition {
+            write!(f, " {} 0x{value:08X}", compare.symbol())?;
+        }
+        Ok(())
+    }
+}
+
+/// A watchpoint that was hit, reported once the instruction that hit it completed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub watchpoint: Watchpoint,
+    /// Address of the access that hit
+    pub addr: usize,
+    pub access: Access,
+    /// Value the watchpoint saw after the access
+    pub value: u32,
+    /// Address and bits of the instruction that made the access, filled in by the debugger
+    pub pc: u32,
+    pub instruction: Option<u32>,
+}
+
+/// Parses `KIND ADDR[+LEN] [OP VALUE]`, e.g. `change 0x80001000+4 == 0x2A`
+pub fn parse_watchpoint(spec: &str) -> Result<Watchpoint, String> {
+    let words: Vec<&str> = spec.split_whitespace().collect();
+    let (kind, range, condition) = match words.as_slice() {
+        [kind, range] => (kind, range, None),
+        [kind, range, compare, value] => (kind, range, Some((compare, value))),
+        _ => return Err(format!("Expected KIND ADDR[+LEN] [OP VALUE], got: {spec}")),
+    };
+    let kind = match *kind {
+        "r" | "read" => WatchKind::Read,
+        "w" | "write" => WatchKind::Write,
+        "c" | "change" => WatchKind::Change,
+        "a" | "access" => WatchKind::Access,
+        _ => return Err(format!("Unknown watchpoint kind: {kind}")),
+    };
+    let (addr, len) = range.split_once('+').unwrap_or((range, "1"));
+    let addr = parse_address(addr).map_err(|_| format!("Invalid address: {addr}"))?;
+    let len = parse_address(len).map_err(|_| format!("Invalid length: {len}"))?;
+    if len == 0 {
+        return Err("Watchpoint has no length".to_string());
+    }
+    if addr
+        .checked_add(len - 1)
+        .is_none_or(|last| last > u32::MAX as usize)
+    {
+        return Err(format!(
+            "Watchpoint wraps around the address space: {range}"
+        ));
+    }
+    let condition = match condition {
+        None => None,
+        Some((compare, value)) => {
+            let compare = match *compare {
+                "==" => Compare::Eq,
+                "!=" => Compare::Ne,
+                "<" => Compare::Lt,
+                "<=" => Compare::Le,
+                ">" => Compare::Gt,
+                ">=" => Compare::Ge,
+                _ => return Err(format!("Unknown comparison: {compare}")),
+            };
+            let value = parse_address(value)
+                .ok()
+                .and_then(|value| u32::try_from(value).ok())
+                .ok_or_else(|| format!("Invalid value: {value}"))?;
+            Some((compare, value))
+        }
+    };
+    Ok(Watchpoint {
+        kind,
+        addr,
+        len,
+        condition,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watchpoint(kind: WatchKind, addr: usize, len: usize) -> Watchpoint {
+        Watchpoint {
+            kind,
+            addr,
+            len,
+            condition: None,
+        }
+    }
+
+    #[test]
+    fn parses_kind_range_and_condition() {
+        let cases = [
+            ("r 0x1000", WatchKind::Read, 0x1000, 1, None),
+            ("write 4096+4", WatchKind::Write, 0x1000, 4, None),
+            (
+                "c 0x80001000+0x10",
+                WatchKind::Change,
+                0x8000_1000,
+                16,
+                None,
+            ),
+            (
+                "access 0x20+2 == 0x2A",
+                WatchKind::Access,
+                0x20,
+                2,
+                Some((Compare::Eq, 0x2A)),
+            ),
+            (
+                "w 0xFFFFFFFC+4 >= 4294967295",
+                WatchKind::Write,
+                0xFFFF_FFFC,
+                4,
+                Some((Compare::Ge, u32::MAX)),
+            ),
+        ];
+        for (spec, kind, addr, len, condition) in cases {
+            let expected = Watchpoint {
+                condition,
+                ..watchpoint(kind, addr, len)
+            };
+            assert_eq!(parse_watchpoint(spec), Ok(expected), "{spec}");
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_watchpoints() {
+        let cases = [
+            ("r", "Expected KIND ADDR[+LEN] [OP VALUE], got: r"),
+            (
+                "r 0x10 ==",
+                "Expected KIND ADDR[+LEN] [OP VALUE], got: r 0x10 ==",
+            ),
+            ("x 0x10", "Unknown watchpoint kind: x"),
+            ("r zero", "Invalid address: zero"),
+            ("r 0x10+many", "Invalid length: many"),
+            ("r 0x10+0", "Watchpoint has no length"),
+            (
+                "r 0xFFFFFFFF+2",
+                "Watchpoint wraps around the address space: 0xFFFFFFFF+2",
+            ),
+            ("r 0x10 =~ 1", "Unknown comparison: =~"),
+            ("r 0x10 == one", "Invalid value: one"),
+            ("r 0x10 == 0x100000000", "Invalid value: 0x100000000"),
+        ];
+        for (spec, error) in cases {
+            assert_eq!(parse_watchpoint(spec), Err(error.to_string()), "{spec}");
+        }
+    }
+
+    #[test]
+    fn overlaps_accesses_touching_the_range() {
+        let word = watchpoint(WatchKind::Access, 0x1000, 4);
+        let cases = [
+            (0xFFC, Size::Word, false),
+            (0xFFD, Size::Word, true),
+            (0x1000, Size::Byte, true),
+            (0x1002, Size::Halfword, true),
+            (0x1003, Size::Word, true),
+            (0x1004, Size::Byte, false),
+        ];
+        for (addr, size, overlaps) in cases {
+            assert_eq!(word.overlaps(addr, size), overlaps, "0x{addr:X} {size:?}");
+        }
+        /* Adding a huge length or an access at the top doesn't overflow */
+        let huge = watchpoint(WatchKind::Access, 0x1000, usize::MAX);
+        assert!(huge.overlaps(0x8000_0000, Size::Word));
+        assert!(!huge.overlaps(0xFFC, Size::Word));
+        assert!(!word.overlaps(usize::MAX - 1, Size::Word));
+    }
+
+    #[test]
+    fn kinds_select_accesses() {
+        let read = Access::Read;
+        let write = Access::Write { old: Some(1) };
+        let same = Access::Write { old: Some(2) };
+        let unknown = Access::Write { old: None };
+        let cases = [
+            (WatchKind::Read, [true, false, false, false]),
+            (WatchKind::Write, [false, true, true, true]),
+            (WatchKind::Change, [false, true, false, true]),
+            (WatchKind::Access, [true, true, true, true]),
+        ];
+        for (kind, hits) in cases {
+            let watchpoint = watchpoint(kind, 0x1000, 4);
+            for (access, hit) in [read, write, same, unknown].into_iter().zip(hits) {
+                assert_eq!(watchpoint.hits(access, 2), hit, "{kind:?} {access:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn conditions_compare_the_value_unsigned() {
+        let cases = [
+            (Compare::Eq, [false, true, false]),
+            (Compare::Ne, [true, false, true]),
+            (Compare::Lt, [true, false, false]),
+            (Compare::Le, [true, true, false]),
+            (Compare::Gt, [false, false, true]),
+            (Compare::Ge, [false, true, true]),
+        ];
+        for (compare, hits) in cases {
+            let watchpoint = Watchpoint {
+                condition: Some((compare, 5)),
+                ..watchpoint(WatchKind::Read, 0x1000, 4)
+            };
+            for (value, hit) in [4, 5, u32::MAX].into_iter().zip(hits) {
+                assert_eq!(
+                    watchpoint.hits(Access::Read, value),
+                    hit,
+                    "{compare:?} {value}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn displays_like_it_is_parsed() {
+        let watchpoint = parse_watchpoint("c 0x80001000+4 != 0x2A").unwrap();
+        assert_eq!(watchpoint.to_string(), "change 0x80001000+4 != 0x0000002A");
+        assert_eq!(watchpoint.size(), Some(Size::Word));
+        assert_eq!(parse_watchpoint("r 0x10+3").unwrap().size(), None);
+    }
+}
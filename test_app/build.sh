@@ -1,2 +1 @@
-riscv64-unknown-elf-gcc test.c -O1 -fPIE -ffreestanding -nostdlib -fno-builtin -march=rv32i -mabi=ilp32 -T link.ld -o test.elf
-riscv64-unknown-elf-objcopy --strip-debug -O binary test.elf test.hex
\ No newline at end of file
+riscv64-unknown-elf-gcc test.c -O1 -fPIE -ffreestanding -nostdlib -fno-builtin -march=rv32i -mabi=ilp32 -T link.ld -o test.elf
\ No newline at end of file
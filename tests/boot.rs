@@ -0,0 +1,93 @@
+//! Boots the stub kernel of `tests/boot` with the built-in SBI firmware and checks its
+//! console output.
+//!
+//! This is not Linux, no RV32 Linux kernel is part of the tests. The stub only follows
+//! the boot protocol of Linux: it finds the command line and the initrd in the device
+//! tree, enables Sv32 paging, takes a timer interrupt through the SBI and runs `/init`
+//! from the initrd in user mode, which shows a shell prompt. The images are checked in,
+//! `tests/boot/build.sh` rebuilds them.
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A boot that runs longer than this is stuck
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+#[test]
+fn stub_kernel_boots_to_shell_prompt() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/boot");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rv"))
+        .arg("--kernel")
+        .arg(dir.join("stub.bin"))
+        .arg("--initrd")
+        .arg(dir.join("initrd.bin"))
+        .args(["--bootargs", "console=ttyS0 rdinit=/init"])
+        .arg("--headless")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start rv");
+
+    let start = Instant::now();
+    while child.try_wait().expect("Failed to wait for rv").is_none() {
+        if start.elapsed() > TIMEOUT {
+            child.kill().expect("Failed to kill rv");
+            panic!("Stub kernel did not shut down within {TIMEOUT:?}");
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let output = child.wait_with_output().expect("Failed to wait for rv");
+    let console = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "Boot failed: {console}{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    for line in [
+        "Booting the rv test kernel on hart 0\n",
+        "SBI specification v2.0\n",
+        "Kernel command line: console=ttyS0 rdinit=/init\n",
+        "Timer interrupt received\n",
+        "Welcome to the rv test initramfs\n\n/ # ",
+    ] {
+        assert!(console.contains(line), "Missing {line:?} in: {console}");
+    }
+}
+
+#[test]
+fn headless_kernel_run_ends_without_a_test_result() {
+    /* Resets with reason "system failure", which no test convention applies to */
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let source = dir.join("reset.s");
+    let kernel = dir.join("reset.bin");
+    fs::write(
+        &source,
+        "li a7, 0x53525354\nli a6, 0\nli a0, 0\nli a1, 1\necall\n",
+    )
+    .unwrap();
+    let rv = || Command::new(env!("CARGO_BIN_EXE_rv"));
+    let status = rv()
+        .arg("asm")
+        .arg(&source)
+        .arg("--output")
+        .arg(&kernel)
+        .status()
+        .expect("Failed to start rv");
+    assert!(status.success());
+
+    let output = rv()
+        .arg("--kernel")
+        .arg(&kernel)
+        .arg("--headless")
+        .output()
+        .expect("Failed to start rv");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
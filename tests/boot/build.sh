@@ -0,0 +1,22 @@
+#!/bin/sh
+# Rebuilds the checked-in stub kernel and its initrd from their sources.
+#
+# Needs llvm-mc, llvm-objcopy and an ld.lld compatible linker, rust-lld of the Rust
+# toolchain works:
+#   LD="$(rustc --print sysroot)/lib/rustlib/x86_64-unknown-linux-gnu/bin/rust-lld -flavor gnu" ./build.sh
+set -e
+
+cd "$(dirname "$0")"
+LD=${LD:-ld.lld}
+
+llvm-mc -triple=riscv32 -mattr=+a -filetype=obj stub.s -o stub.o
+${LD} -T link.ld stub.o -o stub.elf
+llvm-objcopy -O binary stub.elf stub.bin
+rm stub.o stub.elf
+printf "Built stub.bin\n"
+
+llvm-mc -triple=riscv32 -filetype=obj init.s -o init.o
+${LD} -Ttext=0x10000 init.o -o init.elf
+llvm-objcopy -O binary init.elf initrd.bin
+rm init.o init.elf
+printf "Built initrd.bin\n"
@@ -0,0 +1,23 @@
+# /init of the initrd, a flat binary the stub kernel runs in user mode at 0x10000.
+#
+# It greets and shows a shell prompt through the write system call, then exits.
+
+    .text
+    .globl _start
+_start:
+    li a0, 1
+    la a1, banner
+    la a2, banner_end
+    sub a2, a2, a1
+    li a7, 64
+    ecall
+
+    li a0, 0
+    li a7, 93
+    ecall
+1:
+    j 1b
+
+banner:
+    .ascii "\nWelcome to the rv test initramfs\n\n/ # "
+banner_end:
@@ -0,0 +1,549 @@
+# A stub kernel booted by `rv --kernel`, it is not Linux.
+#
+# It only follows the boot protocol of RV32 Linux: entered in supervisor mode by the SBI firmware with the
+# hart ID in a0 and the device tree in a1, it reads the command line and the initrd from
+# /chosen, enables Sv32 paging to run at 0xC0000000, takes a timer interrupt through the
+# SBI and runs /init, the flat binary in the initrd, in user mode at 0x10000.
+
+.equ PAGE_OFFSET, 0xC0000000
+.equ UART_BASE, 0x10000000
+.equ USER_BASE, 0x10000
+.equ USER_STACK, 0x12000
+
+.equ PTE_V, 0x01
+.equ PTE_R, 0x02
+.equ PTE_W, 0x04
+.equ PTE_X, 0x08
+.equ PTE_U, 0x10
+
+.equ SSTATUS_SIE, 0x2
+.equ SSTATUS_SPP, 0x100
+.equ SSTATUS_SUM, 0x40000
+.equ SIE_STIE, 0x20
+
+.equ SBI_EXT_BASE, 0x10
+.equ SBI_EXT_TIME, 0x54494D45
+.equ SBI_EXT_SRST, 0x53525354
+.equ SBI_EXT_DBCN, 0x4442434E
+
+.equ FDT_MAGIC, 0xD00DFEED
+.equ FDT_BEGIN_NODE, 1
+.equ FDT_END_NODE, 2
+.equ FDT_PROP, 3
+.equ FDT_END, 9
+
+    .section .text.head
+# The header of a RISC-V Linux Image, which `rv --kernel` expects
+    .globl _start
+_start:
+    j entry
+    .word 0
+    .quad 0
+    .quad _end - _start
+    .quad 0
+    .word 2
+    .word 0
+    .quad 0
+    .ascii "RISCV\0\0\0"
+    .ascii "RSC\x05"
+    .word 0
+
+    .text
+entry:
+    la sp, stack_top
+    mv s0, a1
+    bnez a0, hang
+
+    li a7, SBI_EXT_BASE
+    li a6, 3
+    li a0, SBI_EXT_DBCN
+    ecall
+    beqz a1, fail
+    la a0, booting
+    call sbi_puts
+
+    li a7, SBI_EXT_BASE
+    li a6, 0
+    ecall
+    mv s1, a1
+    la a0, sbi_version
+    call sbi_puts
+    srli a0, s1, 24
+    call sbi_putdigit
+    li a0, '.'
+    call sbi_putchar
+    andi a0, s1, 0xFF
+    call sbi_putdigit
+    li a0, '\n'
+    call sbi_putchar
+
+    call parse_dtb
+    call map_kernel
+
+    # Enable paging and continue at the same instruction in the high mapping
+    la t0, root_table
+    srli t0, t0, 12
+    li t1, 0x80000000
+    or t0, t0, t1
+    sfence.vma
+    csrw satp, t0
+    sfence.vma
+    la t0, virtual
+    la t1, _start
+    li t2, PAGE_OFFSET
+    sub t2, t2, t1
+    add t0, t0, t2
+    add sp, sp, t2
+    jr t0
+
+virtual:
+    la t0, trap
+    csrw stvec, t0
+    csrw sscratch, zero
+    la a0, paging
+    call puts
+
+    la a0, command_line
+    call puts
+    la t0, bootargs
+    lw a0, 0(t0)
+    call puts
+    li a0, '\n'
+    call putc
+
+    # Wait for one timer interrupt, the SBI passes the machine timer on
+    li t0, SIE_STIE
+    csrs sie, t0
+    csrr a0, time
+    addi a0, a0, 1000
+    li a1, 0
+    li a7, SBI_EXT_TIME
+    li a6, 0
+    ecall
+    csrsi sstatus, SSTATUS_SIE
+1:
+    wfi
+    la t0, ticks
+    lw t0, 0(t0)
+    beqz t0, 1b
+    csrci sstatus, SSTATUS_SIE
+    la a0, timer
+    call puts
+
+    la t0, counter
+    li t1, 5
+    amoadd.w t2, t1, (t0)
+    li t1, 37
+    bne t2, t1, fail
+    lr.w t1, (t0)
+    addi t1, t1, 1
+    sc.w t2, t1, (t0)
+    bnez t2, fail
+    lw t1, 0(t0)
+    li t2, 43
+    bne t1, t2, fail
+    la a0, atomics
+    call puts
+
+    # Run /init, the system calls write to the UART from user memory
+    la a0, run_init
+    call puts
+    li t0, SSTATUS_SUM
+    csrs sstatus, t0
+    li t0, SSTATUS_SPP
+    csrc sstatus, t0
+    li t0, USER_BASE
+    csrw sepc, t0
+    csrw sscratch, sp
+    li sp, USER_STACK
+    sret
+
+hang:
+    wfi
+    j hang
+
+# Builds the page table: the kernel at PAGE_OFFSET and where it was loaded, the UART, the
+# device tree and the initrd at their physical addresses and /init at USER_BASE
+map_kernel:
+    la t0, root_table
+    la t1, _start
+    srli t1, t1, 12
+    slli t1, t1, 10
+    ori t1, t1, PTE_V | PTE_R | PTE_W | PTE_X
+    li t2, PAGE_OFFSET >> 22
+    slli t2, t2, 2
+    add t2, t0, t2
+    sw t1, 0(t2)
+    la t2, _start
+    srli t2, t2, 22
+    slli t2, t2, 2
+    add t2, t0, t2
+    sw t1, 0(t2)
+
+    li a0, UART_BASE
+    mv t3, ra
+    call map_megapage
+    mv a0, s0
+    call map_megapage
+    la t1, initrd_start
+    lw a0, 0(t1)
+    beqz a0, fail
+    call map_megapage
+    mv ra, t3
+
+    la t1, user_table
+    srli t1, t1, 12
+    slli t1, t1, 10
+    ori t1, t1, PTE_V
+    sw t1, 0(t0)
+    la t0, user_table
+    la t1, initrd_start
+    lw t1, 0(t1)
+    srli t1, t1, 12
+    slli t1, t1, 10
+    ori t1, t1, PTE_V | PTE_R | PTE_X | PTE_U
+    sw t1, ((USER_BASE >> 12) * 4)(t0)
+    la t1, user_stack
+    srli t1, t1, 12
+    slli t1, t1, 10
+    ori t1, t1, PTE_V | PTE_R | PTE_W | PTE_U
+    sw t1, ((USER_STACK >> 12) * 4 - 4)(t0)
+    ret
+
+# Maps the megapage containing a0 to itself, t0 holds the root table
+map_megapage:
+    srli a0, a0, 22
+    slli a1, a0, 20
+    ori a1, a1, PTE_V | PTE_R | PTE_W
+    slli a0, a0, 2
+    add a0, t0, a0
+    sw a1, 0(a0)
+    ret
+
+# Finds the command line and the initrd in /chosen of the device tree at s0
+parse_dtb:
+    mv s11, ra
+    mv a0, s0
+    call be32
+    li t0, FDT_MAGIC
+    bne a0, t0, fail
+    addi a0, s0, 8
+    call be32
+    add s2, s0, a0
+    addi a0, s0, 12
+    call be32
+    add s3, s0, a0
+    li s4, 0
+    li s5, 0
+
+parse_token:
+    mv a0, s2
+    call be32
+    addi s2, s2, 4
+    li t0, FDT_BEGIN_NODE
+    beq a0, t0, parse_begin
+    li t0, FDT_END_NODE
+    beq a0, t0, parse_end
+    li t0, FDT_PROP
+    beq a0, t0, parse_prop
+    li t0, FDT_END
+    beq a0, t0, parse_done
+    j parse_token
+
+parse_begin:
+    addi s4, s4, 1
+    li t0, 2
+    bne s4, t0, 1f
+    mv a0, s2
+    la a1, chosen
+    call strcmp
+    seqz s5, a0
+1:
+    mv a0, s2
+    call strlen
+    addi a0, a0, 4
+    andi a0, a0, -4
+    add s2, s2, a0
+    j parse_token
+
+parse_end:
+    addi s4, s4, -1
+    li s5, 0
+    j parse_token
+
+parse_prop:
+    mv a0, s2
+    call be32
+    mv s6, a0
+    addi a0, s2, 4
+    call be32
+    add s7, s3, a0
+    addi s8, s2, 8
+    addi s2, s6, 3
+    andi s2, s2, -4
+    add s2, s2, s8
+    beqz s5, parse_token
+
+    mv a0, s7
+    la a1, prop_bootargs
+    call strcmp
+    bnez a0, 1f
+    la t0, bootargs
+    sw s8, 0(t0)
+1:
+    mv a0, s7
+    la a1, prop_initrd_start
+    call strcmp
+    bnez a0, 1f
+    mv a0, s8
+    call be32
+    la t0, initrd_start
+    sw a0, 0(t0)
+1:
+    j parse_token
+
+parse_done:
+    la t0, bootargs
+    lw t0, 0(t0)
+    beqz t0, fail
+    mv ra, s11
+    ret
+
+# Reads the big-endian word at a0
+be32:
+    lbu t0, 0(a0)
+    lbu t1, 1(a0)
+    lbu t2, 2(a0)
+    lbu a0, 3(a0)
+    slli t0, t0, 24
+    slli t1, t1, 16
+    slli t2, t2, 8
+    or a0, a0, t0
+    or a0, a0, t1
+    or a0, a0, t2
+    ret
+
+# Compares the strings at a0 and a1, zero if they are equal
+strcmp:
+    lbu t0, 0(a0)
+    lbu t1, 0(a1)
+    sub t2, t0, t1
+    bnez t2, 1f
+    beqz t0, 1f
+    addi a0, a0, 1
+    addi a1, a1, 1
+    j strcmp
+1:
+    mv a0, t2
+    ret
+
+strlen:
+    mv t0, a0
+1:
+    lbu t1, 0(t0)
+    beqz t1, 2f
+    addi t0, t0, 1
+    j 1b
+2:
+    sub a0, t0, a0
+    ret
+
+# Writes the string at a0 through the debug console of the SBI
+sbi_puts:
+    mv t3, ra
+    mv a1, a0
+    call strlen
+    li a2, 0
+    li a7, SBI_EXT_DBCN
+    li a6, 0
+    ecall
+    mv ra, t3
+    ret
+
+sbi_putdigit:
+    addi a0, a0, '0'
+sbi_putchar:
+    li a7, 1
+    ecall
+    ret
+
+# Writes the string at a0 to the UART
+puts:
+    mv t3, a0
+1:
+    lbu a0, 0(t3)
+    beqz a0, 2f
+    mv t4, ra
+    call putc
+    mv ra, t4
+    addi t3, t3, 1
+    j 1b
+2:
+    ret
+
+putc:
+    li t0, UART_BASE
+1:
+    lbu t1, 5(t0)
+    andi t1, t1, 0x20
+    beqz t1, 1b
+    sb a0, 0(t0)
+    ret
+
+# Stops the machine with a failure, through the legacy console that works with or
+# without paging
+fail:
+    la t3, failed
+1:
+    lbu a0, 0(t3)
+    beqz a0, 2f
+    li a7, 1
+    ecall
+    addi t3, t3, 1
+    j 1b
+2:
+    li a0, 0
+    li a1, 1
+    li a7, SBI_EXT_SRST
+    li a6, 0
+    ecall
+    j hang
+
+    .p2align 2
+trap:
+    csrrw sp, sscratch, sp
+    bnez sp, 1f
+    csrrw sp, sscratch, sp
+1:
+    addi sp, sp, -48
+    sw ra, 0(sp)
+    sw t0, 4(sp)
+    sw t1, 8(sp)
+    sw t2, 12(sp)
+    sw t3, 16(sp)
+    sw t4, 20(sp)
+    sw a0, 24(sp)
+    sw a1, 28(sp)
+    sw a2, 32(sp)
+    sw a6, 36(sp)
+    sw a7, 40(sp)
+
+    csrr t0, scause
+    bltz t0, interrupt
+    li t1, 8
+    bne t0, t1, fail
+    csrr t0, sepc
+    addi t0, t0, 4
+    csrw sepc, t0
+    li t1, 64
+    beq a7, t1, sys_write
+    li t1, 93
+    beq a7, t1, sys_exit
+    j fail
+
+interrupt:
+    slli t0, t0, 1
+    li t1, 5 << 1
+    bne t0, t1, fail
+    li a0, -1
+    li a1, -1
+    li a7, SBI_EXT_TIME
+    li a6, 0
+    ecall
+    la t0, ticks
+    lw t1, 0(t0)
+    addi t1, t1, 1
+    sw t1, 0(t0)
+    j trap_return
+
+sys_write:
+    mv t3, a1
+    add t4, a1, a2
+1:
+    beq t3, t4, trap_return
+    lbu a0, 0(t3)
+    call putc
+    addi t3, t3, 1
+    j 1b
+
+sys_exit:
+    bnez a0, fail
+    la a0, exited
+    call puts
+    li a0, 0
+    li a1, 0
+    li a7, SBI_EXT_SRST
+    li a6, 0
+    ecall
+    j hang
+
+trap_return:
+    csrr t1, sstatus
+    andi t1, t1, SSTATUS_SPP
+    lw ra, 0(sp)
+    lw t0, 4(sp)
+    lw t2, 12(sp)
+    lw t3, 16(sp)
+    lw t4, 20(sp)
+    lw a0, 24(sp)
+    lw a1, 28(sp)
+    lw a2, 32(sp)
+    lw a6, 36(sp)
+    lw a7, 40(sp)
+    bnez t1, 1f
+    lw t1, 8(sp)
+    addi sp, sp, 48
+    csrrw sp, sscratch, sp
+    sret
+1:
+    lw t1, 8(sp)
+    addi sp, sp, 48
+    sret
+
+    .section .rodata
+booting:
+    .asciz "Booting the rv test kernel on hart 0\n"
+sbi_version:
+    .asciz "SBI specification v"
+paging:
+    .asciz "Sv32 paging enabled\n"
+command_line:
+    .asciz "Kernel command line: "
+timer:
+    .asciz "Timer interrupt received\n"
+atomics:
+    .asciz "Atomics work\n"
+run_init:
+    .asciz "Run /init as init process\n"
+exited:
+    .asciz "\ninit exited, shutting down\n"
+failed:
+    .asciz "\nKernel panic\n"
+chosen:
+    .asciz "chosen"
+prop_bootargs:
+    .asciz "bootargs"
+prop_initrd_start:
+    .asciz "linux,initrd-start"
+
+    .data
+    .p2align 2
+bootargs:
+    .word 0
+initrd_start:
+    .word 0
+ticks:
+    .word 0
+counter:
+    .word 37
+
+    .bss
+    .p2align 12
+root_table:
+    .zero 4096
+user_table:
+    .zero 4096
+user_stack:
+    .zero 4096
+    .zero 4096
+stack_top:
@@ -2,19 +2,19 @@
 set -e
 
 export XLEN=32
-export RISCV_GCC_OPTS="-I$(pwd)/include -I$(pwd)/src/env -march=rv32im_zifencei -mabi=ilp32 -static -mcmodel=medany -fvisibility=hidden -nostdlib -nostartfiles"
+export RISCV_GCC_OPTS="-I$(pwd)/include -I$(pwd)/src/env -march=rv32imafdc_zifencei -mabi=ilp32 -static -mcmodel=medany -fvisibility=hidden -nostdlib -nostartfiles"
 
 banner() {
 	printf "##\n# %s\n##\n\n" "${1}"
 }
 
 banner "Build tests"
-make -C src/isa XLEN=${XLEN} rv32ui rv32um
+make -C src/isa XLEN=${XLEN} rv32ui rv32um rv32ua rv32uc rv32uf rv32ud
 
 banner "Run tests"
 
 exit=0
-for file in src/isa/rv32ui-p-* src/isa/rv32um-p-*; do
+for file in src/isa/rv32ui-p-* src/isa/rv32um-p-* src/isa/rv32ua-p-* src/isa/rv32uc-p-* src/isa/rv32uf-p-* src/isa/rv32ud-p-*; do
 	[ -f "${file}" -a -x "${file}" ] || continue
 
 	name=${file##*/}
@@ -25,9 +25,7 @@ for file in src/isa/rv32ui-p-* src/isa/rv32um-p-*; do
 		continue
 	fi
 
-	riscv64-unknown-elf-objcopy --strip-debug -O binary ${file} ./test.hex
-
-	ret=0; ../target/debug/rv --file "./test.hex" --headless > /dev/null || ret=$?
+	ret=0; ../target/debug/rv --file "${file}" --headless > /dev/null || ret=$?
 	if [ "${ret}" -ne 0 ]; then
 		exit=1
 		printf "FAIL\n"
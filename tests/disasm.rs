@@ -0,0 +1,28 @@
+//! Runs `rv disasm` like a shell pipeline would.
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+#[test]
+fn closed_pipe_ends_the_listing_quietly() {
+    /* Far more lines than fit into the buffer of a pipe */
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("disasm-pipe.bin");
+    fs::write(&path, [0x13, 0x00, 0x00, 0x00].repeat(0x10000)).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rv"))
+        .arg("disasm")
+        .arg(&path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start rv");
+    /* Like `rv disasm | head -0`, the reader goes away before reading anything */
+    drop(child.stdout.take());
+    let output = child.wait_with_output().expect("Failed to wait for rv");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(output.stderr.is_empty());
+}
@@ -0,0 +1,155 @@
+//! Runs `tests/gdb/counter.s` under the GDB stub and speaks the remote serial protocol to
+//! it over a loopback TCP connection, as GDB would.
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The stub takes this long at most to listen or to answer a packet
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/* Addresses in counter.s */
+const LOOP: u32 = 0x8000_0008;
+const STORE: u32 = 0x8000_000C;
+const COUNT: u32 = 0x8000_0014;
+
+struct Gdb {
+    stream: TcpStream,
+    child: Child,
+}
+
+impl Gdb {
+    /// Starts rv and connects to its GDB stub
+    fn start() -> Self {
+        /* A port nobody listens on, rv binds it right after it is freed here */
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("Failed to find a free port")
+            .port();
+        let program = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/gdb/counter.s");
+        let child = Command::new(env!("CARGO_BIN_EXE_rv"))
+            .arg("--file")
+            .arg(program)
+            .args(["--gdb", &format!("127.0.0.1:{port}")])
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed to start rv");
+
+        let start = Instant::now();
+        let stream = loop {
+            match TcpStream::connect(("127.0.0.1", port)) {
+                Ok(stream) => break stream,
+                Err(_) if start.elapsed() < TIMEOUT => thread::sleep(Duration::from_millis(10)),
+                Err(error) => panic!("Failed to connect to rv: {error}"),
+            }
+        };
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+        Self { stream, child }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.stream
+            .read_exact(&mut byte)
+            .expect("rv did not answer");
+        byte[0]
+    }
+
+    /// Sends a packet and returns the reply, both acknowledged
+    fn request(&mut self, data: &str) -> String {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.stream, "${data}#{checksum:02x}").unwrap();
+        assert_eq!(self.read_byte(), b'+', "{data} was not acknowledged");
+
+        while self.read_byte() != b'$' {}
+        let mut reply = Vec::new();
+        loop {
+            match self.read_byte() {
+                b'#' => break,
+                byte => reply.push(byte),
+            }
+        }
+        let digits = [self.read_byte(), self.read_byte()];
+        let expected = u8::from_str_radix(std::str::from_utf8(&digits).unwrap(), 16).unwrap();
+        assert_eq!(
+            expected,
+            reply.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)),
+            "Reply to {data} has a wrong checksum"
+        );
+        self.stream.write_all(b"+").unwrap();
+        String::from_utf8(reply).unwrap()
+    }
+}
+
+impl Drop for Gdb {
+    /// Doesn't leave rv waiting for GDB when the test fails
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+    }
+}
+
+/// Hex digits of a register or word as GDB sees them, little-endian
+fn hex(value: u32) -> String {
+    value
+        .to_le_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[test]
+fn gdb_controls_the_guest() {
+    let mut gdb = Gdb::start();
+
+    assert!(gdb.request("qSupported:swbreak+").contains("swbreak+"));
+    assert_eq!(gdb.request("?"), "T05");
+
+    /* x0 to x31 and the pc */
+    let registers = gdb.request("g");
+    assert_eq!(registers.len(), 33 * 8);
+    assert_eq!(&registers[32 * 8..], hex(0x8000_0000));
+
+    assert_eq!(
+        gdb.request(&format!("M{COUNT:x},4:{}", hex(0xDEAD_BEEF))),
+        "OK"
+    );
+    assert_eq!(gdb.request(&format!("m{COUNT:x},4")), hex(0xDEAD_BEEF));
+
+    /* Continues to the loop, t0 was set to 0 and counted up once when it gets there again */
+    assert_eq!(gdb.request(&format!("Z0,{LOOP:x},4")), "OK");
+    assert_eq!(gdb.request("c"), "T05swbreak:;");
+    assert_eq!(gdb.request("p20"), hex(LOOP));
+    assert_eq!(gdb.request("c"), "T05swbreak:;");
+    assert_eq!(gdb.request("p5"), hex(1));
+    assert_eq!(gdb.request(&format!("m{COUNT:x},4")), hex(1));
+
+    assert_eq!(gdb.request("s"), "T05");
+    assert_eq!(gdb.request("p20"), hex(STORE));
+    assert_eq!(gdb.request("p5"), hex(2));
+
+    /* The store of the step that follows hits the watchpoint */
+    assert_eq!(gdb.request(&format!("z0,{LOOP:x},4")), "OK");
+    assert_eq!(gdb.request(&format!("Z2,{COUNT:x},4")), "OK");
+    assert_eq!(gdb.request("c"), format!("T05watch:{COUNT:x};"));
+    assert_eq!(gdb.request(&format!("m{COUNT:x},4")), hex(2));
+    assert_eq!(gdb.request(&format!("z2,{COUNT:x},4")), "OK");
+
+    /* GDB doesn't wait for a reply to a kill */
+    write!(gdb.stream, "$k#6b").unwrap();
+    let start = Instant::now();
+    let status = loop {
+        match gdb.child.try_wait().expect("Failed to wait for rv") {
+            Some(status) => break status,
+            None if start.elapsed() < TIMEOUT => thread::sleep(Duration::from_millis(10)),
+            None => {
+                gdb.child.kill().unwrap();
+                panic!("rv did not exit after k");
+            }
+        }
+    };
+    assert!(status.success());
+}
@@ -0,0 +1,12 @@
+# Target of the GDB test, counts in t0 and stores the count to `count`.
+# The test relies on the addresses, every instruction is 4 bytes long.
+
+_start:
+    addi t0, zero, 0
+    auipc t1, 0
+loop:
+    addi t0, t0, 1
+    sw t0, 16(t1)
+    j loop
+count:
+    .word 0
@@ -0,0 +1,76 @@
+//! Runs the ISA test images of `tests/isa` headless and checks that each one passes.
+//!
+//! The images are checked in, `tests/isa/build.sh` rebuilds them from the sources next to
+//! them, so running the tests needs no RISC-V toolchain. These are rv's own tests, not the
+//! riscv-tests suite: their test cases were written for rv and only borrow the layout and
+//! exit convention of riscv-tests. The riscv-tests suite itself is built and run by
+//! `tests/build.sh`, which needs its submodule and a RISC-V gcc.
+
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A test image that runs longer than this is stuck, e.g. in a trap loop
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+fn run(name: &str) {
+    let image = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/isa")
+        .join(format!("{name}.elf"));
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rv"))
+        .arg("--file")
+        .arg(&image)
+        .arg("--headless")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start rv");
+
+    let start = Instant::now();
+    while child.try_wait().expect("Failed to wait for rv").is_none() {
+        if start.elapsed() > TIMEOUT {
+            child.kill().expect("Failed to kill rv");
+            panic!("{name} did not finish within {TIMEOUT:?}");
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let output = child.wait_with_output().expect("Failed to wait for rv");
+    assert!(
+        output.status.success(),
+        "{name} failed: {}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn rv32ui() {
+    run("rv32ui");
+}
+
+#[test]
+fn rv32um() {
+    run("rv32um");
+}
+
+#[test]
+fn rv32ua() {
+    run("rv32ua");
+}
+
+#[test]
+fn rv32uc() {
+    run("rv32uc");
+}
+
+#[test]
+fn rv32uf() {
+    run("rv32uf");
+}
+
+#[test]
+fn rv32ud() {
+    run("rv32ud");
+}
@@ -0,0 +1,25 @@
+#!/bin/sh
+# Rebuilds the checked-in test images from their sources.
+#
+# Needs llvm-mc and an ld.lld compatible linker, rust-lld of the Rust toolchain works:
+#   LD="$(rustc --print sysroot)/lib/rustlib/x86_64-unknown-linux-gnu/bin/rust-lld -flavor gnu" ./build.sh
+set -e
+
+cd "$(dirname "$0")"
+LD=${LD:-ld.lld}
+
+build() {
+	name=$1
+	mattr=$2
+	llvm-mc -triple=riscv32 -mattr="${mattr}" -filetype=obj "${name}.s" -o "${name}.o"
+	${LD} -T link.ld "${name}.o" -o "${name}.elf"
+	rm "${name}.o"
+	printf "Built %s\n" "${name}.elf"
+}
+
+build rv32ui ""
+build rv32um +m
+build rv32ua +a
+build rv32uc +c,+f,+d
+build rv32uf +f
+build rv32ud +f,+d
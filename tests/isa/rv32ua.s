@@ -0,0 +1,149 @@
+# RV32A atomic instructions
+
+.include "env.inc"
+
+RVTEST_CODE_BEGIN
+
+#-----------------------------------------------------------------------
+# Atomic memory operations
+#-----------------------------------------------------------------------
+
+    TEST_AMO_OP 2, amoswap.w, 0xfffff800, 0x80000000, 0xfffff800
+    TEST_AMO_OP 3, amoswap.w, 0x00000000, 0x7ffff800, 0x00000000
+    TEST_AMO_OP 4, amoswap.w.aq, 0x12345678, 0x00000000, 0x12345678
+
+    TEST_AMO_OP 5, amoadd.w, 0x7ffff800, 0x80000000, 0xfffff800
+    TEST_AMO_OP 6, amoadd.w, 0x00000000, 0xffffffff, 0x00000001
+    TEST_AMO_OP 7, amoadd.w.rl, 0x80000000, 0x7fffffff, 0x00000001
+
+    TEST_AMO_OP 8, amoxor.w, 0x7ffff800, 0x80000000, 0xfffff800
+    TEST_AMO_OP 9, amoxor.w, 0xc0000001, 0x7ffff800, 0xbffff801
+    TEST_AMO_OP 10, amoxor.w.aqrl, 0x00000000, 0x5a5a5a5a, 0x5a5a5a5a
+
+    TEST_AMO_OP 11, amoand.w, 0x80000000, 0x80000000, 0xfffff800
+    TEST_AMO_OP 12, amoand.w, 0x80000000, 0x80000000, 0x80000000
+    TEST_AMO_OP 13, amoand.w, 0x00000000, 0x7ffff800, 0x80000000
+
+    TEST_AMO_OP 14, amoor.w, 0xfffff800, 0x80000000, 0xfffff800
+    TEST_AMO_OP 15, amoor.w, 0x80000001, 0x80000000, 0x00000001
+    TEST_AMO_OP 16, amoor.w, 0xffffffff, 0x0f0f0f0f, 0xf0f0f0f0
+
+    TEST_AMO_OP 17, amomin.w, 0x80000000, 0x80000000, 0xfffff800
+    TEST_AMO_OP 18, amomin.w, 0xffffffff, 0x00000000, 0xffffffff
+    TEST_AMO_OP 19, amomin.w, 0x00000001, 0x00000001, 0x7fffffff
+
+    TEST_AMO_OP 20, amomax.w, 0xfffff800, 0x80000000, 0xfffff800
+    TEST_AMO_OP 21, amomax.w, 0x00000001, 0x00000001, 0xffffffff
+    TEST_AMO_OP 22, amomax.w, 0x7fffffff, 0x80000000, 0x7fffffff
+
+    TEST_AMO_OP 23, amominu.w, 0x80000000, 0x80000000, 0xfffff800
+    TEST_AMO_OP 24, amominu.w, 0x00000000, 0xffffffff, 0x00000000
+    TEST_AMO_OP 25, amominu.w, 0x00000001, 0x00000001, 0xffffffff
+
+    TEST_AMO_OP 26, amomaxu.w, 0xfffff800, 0x80000000, 0xfffff800
+    TEST_AMO_OP 27, amomaxu.w, 0xffffffff, 0xffffffff, 0x00000000
+    TEST_AMO_OP 28, amomaxu.w, 0x80000000, 0x7fffffff, 0x80000000
+
+    TEST_CASE 29
+    # rd may be the same register as the operand
+    la x1, amo_operand
+    li x2, 5
+    sw x2, 0(x1)
+    li x3, 3
+    amoadd.w x3, x3, (x1)
+    ASSERT_EQ x3, x2
+    lw x4, 0(x1)
+    li x5, 8
+    ASSERT_EQ x4, x5
+
+    TEST_CASE 30
+    # The old value isn't written to x0
+    la x1, amo_operand
+    li x2, 7
+    amoswap.w x0, x2, (x1)
+    ASSERT_EQ x0, zero
+    lw x4, 0(x1)
+    ASSERT_EQ x4, x2
+
+#-----------------------------------------------------------------------
+# lr.w, sc.w
+#-----------------------------------------------------------------------
+
+    TEST_CASE 31
+    # Without a reservation the store conditional fails and writes nothing
+    la x1, amo_operand
+    sw zero, 0(x1)
+    li x2, 1
+    sc.w x3, x2, (x1)
+    li x4, 1
+    ASSERT_EQ x3, x4
+    lw x5, 0(x1)
+    ASSERT_EQ x5, zero
+
+    TEST_CASE 32
+    la x1, amo_operand
+    li x2, 0x1234
+    sw x2, 0(x1)
+    lr.w x3, (x1)
+    ASSERT_EQ x3, x2
+    li x4, 0x5678
+    sc.w x5, x4, (x1)
+    ASSERT_EQ x5, zero
+    lw x6, 0(x1)
+    ASSERT_EQ x6, x4
+
+    TEST_CASE 33
+    # A successful store conditional consumes the reservation
+    la x1, amo_operand
+    lr.w x3, (x1)
+    sc.w x5, x3, (x1)
+    ASSERT_EQ x5, zero
+    sc.w x5, x3, (x1)
+    li x4, 1
+    ASSERT_EQ x5, x4
+
+    TEST_CASE 34
+    # A store conditional to another address fails
+    la x1, amo_operand
+    lr.w x3, 0(x1)
+    addi x2, x1, 4
+    sc.w.rl x5, x3, (x2)
+    li x4, 1
+    ASSERT_EQ x5, x4
+
+    TEST_CASE 35
+    # The usual retry loop, counting to 100
+    la x1, amo_operand
+    sw zero, 0(x1)
+    li x4, 100
+1:  lr.w.aq x2, (x1)
+    addi x2, x2, 1
+    sc.w.rl x3, x2, (x1)
+    bnez x3, 1b
+    bne x2, x4, 1b
+    lw x5, 0(x1)
+    ASSERT_EQ x5, x4
+
+#-----------------------------------------------------------------------
+# Misaligned atomics trap
+#-----------------------------------------------------------------------
+
+    TEST_CASE 36
+    la t0, 1f
+    csrw mtvec, t0
+    la x1, amo_operand + 2
+    amoadd.w x2, x2, (x1)
+    j fail
+    .align 2
+1:  csrr t0, mcause
+    li t1, 6
+    ASSERT_EQ t0, t1
+    la t0, trap_vector
+    csrw mtvec, t0
+
+RVTEST_CODE_END
+
+    .data
+    .align 3
+amo_operand:
+    .word 0, 0
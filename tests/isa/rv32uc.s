@@ -0,0 +1,268 @@
+# RV32C compressed instructions
+#
+# The compressed instructions are written out explicitly, llvm-mc also compresses
+# the instructions of the test environment where it can.
+
+.include "env.inc"
+
+.macro CHECK reg, result
+    li x7, \result
+    ASSERT_EQ \reg, x7
+.endm
+
+RVTEST_CODE_BEGIN
+
+    la sp, stack
+
+#-----------------------------------------------------------------------
+# Stack pointer based instructions
+#-----------------------------------------------------------------------
+
+    TEST_CASE 2
+    c.addi4spn a0, sp, 1020
+    sub a0, a0, sp
+    CHECK a0, 1020
+
+    TEST_CASE 3
+    c.addi4spn s1, sp, 4
+    sub s1, s1, sp
+    CHECK s1, 4
+
+    TEST_CASE 4
+    mv s0, sp
+    c.addi16sp sp, -512
+    sub a0, s0, sp
+    CHECK a0, 512
+    c.addi16sp sp, 496
+    c.addi16sp sp, 16
+    ASSERT_EQ sp, s0
+
+    TEST_CASE 5
+    li a0, 0x12345678
+    c.swsp a0, 12(sp)
+    c.lwsp a1, 12(sp)
+    CHECK a1, 0x12345678
+    lw a2, 12(sp)
+    CHECK a2, 0x12345678
+
+    TEST_CASE 6
+    li ra, 0xfedcba98
+    c.swsp ra, 252(sp)
+    c.lwsp t6, 252(sp)
+    CHECK t6, 0xfedcba98
+
+#-----------------------------------------------------------------------
+# Register based loads and stores
+#-----------------------------------------------------------------------
+
+    TEST_CASE 7
+    mv s0, sp
+    li a1, 0x0badcafe
+    c.sw a1, 124(s0)
+    c.lw a2, 124(s0)
+    CHECK a2, 0x0badcafe
+
+    TEST_CASE 8
+    mv a5, sp
+    li s1, -1
+    c.sw s1, 0(a5)
+    c.lw a4, 0(a5)
+    CHECK a4, -1
+
+#-----------------------------------------------------------------------
+# Constants and immediate arithmetic
+#-----------------------------------------------------------------------
+
+    TEST_CASE 9
+    c.li a0, 31
+    CHECK a0, 31
+    c.li a0, -32
+    CHECK a0, -32
+
+    TEST_CASE 10
+    c.lui s0, 0xfffe1
+    CHECK s0, 0xfffe1000
+    c.lui s0, 1
+    CHECK s0, 0x1000
+    c.lui s0, 31
+    CHECK s0, 0x1f000
+
+    TEST_CASE 11
+    c.li a0, 1
+    c.addi a0, -32
+    CHECK a0, -31
+    c.addi a0, 31
+    CHECK a0, 0
+
+    TEST_CASE 12
+    c.nop
+    c.nop
+
+    TEST_CASE 13
+    li s0, 0x80000001
+    c.srli s0, 31
+    CHECK s0, 1
+    li s1, 0x80000001
+    c.srli s1, 1
+    CHECK s1, 0x40000000
+
+    TEST_CASE 14
+    li a3, 0x80000001
+    c.srai a3, 31
+    CHECK a3, -1
+    li a4, 0x80000000
+    c.srai a4, 1
+    CHECK a4, 0xc0000000
+
+    TEST_CASE 15
+    li a5, 0x12345678
+    c.andi a5, -16
+    CHECK a5, 0x12345670
+    li a5, 0x12345678
+    c.andi a5, 15
+    CHECK a5, 8
+
+    TEST_CASE 16
+    li t0, 0x01234567
+    c.slli t0, 4
+    CHECK t0, 0x12345670
+    li t1, 3
+    c.slli t1, 31
+    CHECK t1, 0x80000000
+
+#-----------------------------------------------------------------------
+# Register arithmetic
+#-----------------------------------------------------------------------
+
+    TEST_CASE 17
+    li s0, 20
+    li s1, 6
+    c.sub s0, s1
+    CHECK s0, 14
+
+    TEST_CASE 18
+    li a0, 0xff00ff00
+    li a1, 0x0ff00ff0
+    c.xor a0, a1
+    CHECK a0, 0xf0f0f0f0
+
+    TEST_CASE 19
+    li a2, 0xff00ff00
+    li a3, 0x0ff00ff0
+    c.or a2, a3
+    CHECK a2, 0xfff0fff0
+
+    TEST_CASE 20
+    li a4, 0xff00ff00
+    li a5, 0x0ff00ff0
+    c.and a4, a5
+    CHECK a4, 0x0f000f00
+
+    TEST_CASE 21
+    li t0, 0x7fffffff
+    c.mv t1, t0
+    CHECK t1, 0x7fffffff
+
+    TEST_CASE 22
+    li s2, 1
+    li s3, 0xffffffff
+    c.add s2, s3
+    CHECK s2, 0
+
+#-----------------------------------------------------------------------
+# Jumps and branches
+#-----------------------------------------------------------------------
+
+    TEST_CASE 23
+    c.j 1f
+    j fail
+1:
+
+    TEST_CASE 24
+    li ra, 0
+    c.jal 1f
+2:  j fail
+1:  la t0, 2b
+    ASSERT_EQ ra, t0
+
+    TEST_CASE 25
+    la t0, 1f
+    c.jr t0
+    j fail
+1:
+
+    TEST_CASE 26
+    li ra, 0
+    la t0, 1f
+    c.jalr t0
+2:  j fail
+1:  la t1, 2b
+    ASSERT_EQ ra, t1
+
+    TEST_CASE 27
+    li s0, 0
+    c.beqz s0, 1f
+    j fail
+1:  li s0, 1
+    c.beqz s0, fail
+
+    TEST_CASE 28
+    li s1, -1
+    c.bnez s1, 1f
+    j fail
+1:  li s1, 0
+    c.bnez s1, fail
+
+#-----------------------------------------------------------------------
+# Floating point loads and stores
+#-----------------------------------------------------------------------
+
+    TEST_CASE 29
+    mv a0, sp
+    li a1, 0x3f800000
+    sw a1, 8(a0)
+    c.flw fa2, 8(a0)
+    c.fsw fa2, 16(a0)
+    lw a2, 16(a0)
+    CHECK a2, 0x3f800000
+
+    TEST_CASE 30
+    li a1, 0x40490fdb
+    sw a1, 4(sp)
+    c.flwsp ft0, 4(sp)
+    c.fswsp ft0, 8(sp)
+    lw a2, 8(sp)
+    CHECK a2, 0x40490fdb
+
+    TEST_CASE 31
+    mv s1, sp
+    li a1, 0x54442d18
+    li a2, 0x400921fb
+    sw a1, 32(s1)
+    sw a2, 36(s1)
+    c.fld fs0, 32(s1)
+    c.fsd fs0, 40(s1)
+    lw a3, 40(s1)
+    lw a4, 44(s1)
+    ASSERT_EQ a1, a3
+    ASSERT_EQ a2, a4
+
+    TEST_CASE 32
+    li a1, 0x11111111
+    li a2, 0x22222222
+    sw a1, 0(sp)
+    sw a2, 4(sp)
+    c.fldsp fa5, 0(sp)
+    c.fsdsp fa5, 504(sp)
+    lw a3, 504(sp)
+    lw a4, 508(sp)
+    ASSERT_EQ a1, a3
+    ASSERT_EQ a2, a4
+
+RVTEST_CODE_END
+
+    .data
+    .align 4
+    .zero 1024
+stack:
+    .zero 1024
@@ -0,0 +1,231 @@
+# RV32D double-precision floating point
+#
+# fflags are checked after every test case: 0x10 invalid, 0x08 divide by zero,
+# 0x04 overflow, 0x02 underflow and 0x01 inexact.
+
+.include "env.inc"
+
+RVTEST_CODE_BEGIN
+
+#-----------------------------------------------------------------------
+# Arithmetic
+#-----------------------------------------------------------------------
+
+    TEST_FP_OP2_D 2, fadd.d, 0, 3.5, 2.5, 1.0
+    TEST_FP_OP2_D 3, fadd.d, 1, -1234.0, -1235.1, 1.1
+    TEST_FP_OP2_D 4, fadd.d, 1, 3.14159266, 3.14159265, 0.00000001
+    TEST_FP_OP2_D_QNAN 5, fadd.d, 0x10, inf, -inf
+
+    TEST_FP_OP2_D 6, fsub.d, 0, 1.5, 2.5, 1.0
+    TEST_FP_OP2_D 7, fsub.d, 1, -1234.0, -1235.1, -1.1
+    TEST_FP_OP2_D 8, fsub.d, 1, 3.1415926400000001, 3.14159265, 0.00000001
+    TEST_FP_OP2_D_QNAN 9, fsub.d, 0x10, inf, inf
+
+    TEST_FP_OP2_D 10, fmul.d, 0, 2.5, 2.5, 1.0
+    TEST_FP_OP2_D 11, fmul.d, 1, 1358.61, -1235.1, -1.1
+    TEST_FP_OP2_D 12, fmul.d, 1, 3.14159265e-8, 3.14159265, 0.00000001
+    TEST_FP_OP2_D 13, fmul.d, 0x05, inf, 1.0e300, 1.0e300
+    TEST_FP_OP2_D_QNAN 14, fmul.d, 0x10, inf, 0.0
+
+    TEST_FP_OP2_D 15, fdiv.d, 1, 1.1557273520668288, 3.14159265, 2.71828182
+    TEST_FP_OP2_D 16, fdiv.d, 1, -0.9991093838555584, -1234, 1235.1
+    TEST_FP_OP2_D 17, fdiv.d, 0, 3.14159265, 3.14159265, 1.0
+    TEST_FP_OP2_D 18, fdiv.d, 0x08, inf, 1.0, 0.0
+    TEST_FP_OP2_D_QNAN 19, fdiv.d, 0x10, 0.0, 0.0
+
+    TEST_FP_OP1_D 20, fsqrt.d, 1, 1.7724538498928541, 3.14159265
+    TEST_FP_OP1_D 21, fsqrt.d, 0, 100, 10000
+    TEST_FP_OP1_D 22, fsqrt.d, 1, 13.076696830622021, 171.0
+    TEST_FP_OP1_D 23, fsqrt.d, 1, 0.00040099251863345283320230749383, 1.60795e-7
+
+#-----------------------------------------------------------------------
+# Fused multiply-add
+#-----------------------------------------------------------------------
+
+    TEST_FP_OP3_D 24, fmadd.d, 0, 3.5, 1.0, 2.5, 1.0
+    TEST_FP_OP3_D 25, fmadd.d, 1, 1236.1999999999999, -1.0, -1235.1, 1.1
+    TEST_FP_OP3_D 26, fmadd.d, 0, -12.0, 2.0, -5.0, -2.0
+
+    TEST_FP_OP3_D 27, fnmadd.d, 0, -3.5, 1.0, 2.5, 1.0
+    TEST_FP_OP3_D 28, fnmadd.d, 1, -1236.1999999999999, -1.0, -1235.1, 1.1
+    TEST_FP_OP3_D 29, fnmadd.d, 0, 12.0, 2.0, -5.0, -2.0
+
+    TEST_FP_OP3_D 30, fmsub.d, 0, 1.5, 1.0, 2.5, 1.0
+    TEST_FP_OP3_D 31, fmsub.d, 1, 1234, -1.0, -1235.1, 1.1
+    TEST_FP_OP3_D 32, fmsub.d, 0, -8.0, 2.0, -5.0, -2.0
+
+    TEST_FP_OP3_D 33, fnmsub.d, 0, -1.5, 1.0, 2.5, 1.0
+    TEST_FP_OP3_D 34, fnmsub.d, 1, -1234, -1.0, -1235.1, 1.1
+    TEST_FP_OP3_D 35, fnmsub.d, 0, 8.0, 2.0, -5.0, -2.0
+
+#-----------------------------------------------------------------------
+# Sign injection, minimum and maximum
+#-----------------------------------------------------------------------
+
+    TEST_FP_OP2_D 36, fsgnj.d, 0, -6.3, 6.3, -1.0
+    TEST_FP_OP2_D 37, fsgnj.d, 0, 6.3, -6.3, 1.0
+    TEST_FP_OP2_D 38, fsgnjn.d, 0, 6.3, 6.3, -1.0
+    TEST_FP_OP2_D 39, fsgnjn.d, 0, -6.3, -6.3, 1.0
+    TEST_FP_OP2_D 40, fsgnjx.d, 0, -6.3, 6.3, -1.0
+    TEST_FP_OP2_D 41, fsgnjx.d, 0, 6.3, -6.3, -1.0
+
+    TEST_FP_OP2_D 42, fmin.d, 0, 1.0, 2.5, 1.0
+    TEST_FP_OP2_D 43, fmin.d, 0, -1235.1, -1235.1, 1.1
+    TEST_FP_OP2_D 44, fmin.d, 0, 1.1, nan, 1.1
+    TEST_FP_OP2_D 45, fmin.d, 0, -0.0, -0.0, 0.0
+    TEST_FP_OP2_D_QNAN 46, fmin.d, 0, nan, nan
+
+    TEST_FP_OP2_D 47, fmax.d, 0, 2.5, 2.5, 1.0
+    TEST_FP_OP2_D 48, fmax.d, 0, 1.1, -1235.1, 1.1
+    TEST_FP_OP2_D 49, fmax.d, 0, 1.1, nan, 1.1
+    TEST_FP_OP2_D 50, fmax.d, 0, 0.0, -0.0, 0.0
+
+#-----------------------------------------------------------------------
+# Comparisons
+#-----------------------------------------------------------------------
+
+    TEST_FP_CMP_OP_D 51, feq.d, 0, 1, -1.36, -1.36
+    TEST_FP_CMP_OP_D 52, fle.d, 0, 1, -1.36, -1.36
+    TEST_FP_CMP_OP_D 53, flt.d, 0, 0, -1.36, -1.36
+    TEST_FP_CMP_OP_D 54, feq.d, 0, 0, -1.37, -1.36
+    TEST_FP_CMP_OP_D 55, fle.d, 0, 1, -1.37, -1.36
+    TEST_FP_CMP_OP_D 56, flt.d, 0, 1, -1.37, -1.36
+
+    TEST_FP_CMP_OP_D 57, feq.d, 0, 0, nan, 0
+    TEST_FP_CMP_OP_D 58, flt.d, 0x10, 0, nan, 0
+    TEST_FP_CMP_OP_D 59, fle.d, 0x10, 0, nan, 0
+
+#-----------------------------------------------------------------------
+# Conversions to and from integers
+#-----------------------------------------------------------------------
+
+    TEST_FP_INT_OP_D 60, fcvt.w.d, 0x01, -1, -1.1, rtz
+    TEST_FP_INT_OP_D 61, fcvt.w.d, 0x00, -1, -1.0, rtz
+    TEST_FP_INT_OP_D 62, fcvt.w.d, 0x01, 0, -0.9, rtz
+    TEST_FP_INT_OP_D 63, fcvt.w.d, 0x01, 1, 1.1, rtz
+    TEST_FP_INT_OP_D 64, fcvt.w.d, 0x10, 0x80000000, -3e9, rtz
+    TEST_FP_INT_OP_D 65, fcvt.w.d, 0x10, 0x7fffffff, 3e9, rtz
+    TEST_FP_INT_OP_D 66, fcvt.w.d, 0x00, 0x7fffffff, 2147483647.0, rtz
+    TEST_FP_INT_OP_D 67, fcvt.w.d, 0x01, 0x7fffffff, 2147483647.5, rtz
+    TEST_FP_INT_OP_D 68, fcvt.w.d, 0x10, 0x7fffffff, 2147483647.5, rne
+    TEST_FP_INT_OP_D 69, fcvt.w.d, 0x10, 0x7fffffff, nan, rtz
+    TEST_FP_INT_OP_D 70, fcvt.w.d, 0x01, -2, -2.5, rne
+    TEST_FP_INT_OP_D 71, fcvt.w.d, 0x01, -3, -2.5, rmm
+
+    TEST_FP_INT_OP_D 72, fcvt.wu.d, 0x10, 0, -3.0, rtz
+    TEST_FP_INT_OP_D 73, fcvt.wu.d, 0x01, 0, -0.9, rtz
+    TEST_FP_INT_OP_D 74, fcvt.wu.d, 0x01, 1, 1.1, rtz
+    TEST_FP_INT_OP_D 75, fcvt.wu.d, 0x00, 3000000000, 3e9, rtz
+    TEST_FP_INT_OP_D 76, fcvt.wu.d, 0x00, 0xffffffff, 4294967295.0, rtz
+    TEST_FP_INT_OP_D 77, fcvt.wu.d, 0x10, 0xffffffff, 4294967296.0, rtz
+
+    # Every 32 bit integer is exact as a double
+    TEST_INT_FP_OP_D 78, fcvt.d.w, 0x00, 2.0, 2
+    TEST_INT_FP_OP_D 79, fcvt.d.w, 0x00, -2.0, -2
+    TEST_INT_FP_OP_D 80, fcvt.d.w, 0x00, 2147483647.0, 0x7fffffff
+    TEST_INT_FP_OP_D 81, fcvt.d.w, 0x00, -2147483648.0, 0x80000000
+
+    TEST_INT_FP_OP_D 82, fcvt.d.wu, 0x00, 2.0, 2
+    TEST_INT_FP_OP_D 83, fcvt.d.wu, 0x00, 4294967294.0, -2
+    TEST_INT_FP_OP_D 84, fcvt.d.wu, 0x00, 2147483648.0, 0x80000000
+
+#-----------------------------------------------------------------------
+# Conversions between single and double precision
+#-----------------------------------------------------------------------
+
+    TEST_CASE 85
+    # 1.1 loses precision on the way to single and is NaN-boxed
+    la a0, tdat
+    fld f0, 0(a0)
+    fcvt.s.d f3, f0
+    fsd f3, 16(a0)
+    lw a1, 16(a0)
+    li a2, 0x3f8ccccd
+    ASSERT_EQ a1, a2
+    lw a1, 20(a0)
+    li a2, -1
+    ASSERT_EQ a1, a2
+    CHECK_FFLAGS 0x01
+
+    TEST_CASE 86
+    # Widening is exact
+    li a1, 0x3f8ccccd
+    fmv.w.x f0, a1
+    fcvt.d.s f3, f0
+    la a0, tdat
+    fsd f3, 16(a0)
+    lw a1, 16(a0)
+    li a2, 0xa0000000
+    ASSERT_EQ a1, a2
+    lw a1, 20(a0)
+    li a2, 0x3ff19999
+    ASSERT_EQ a1, a2
+    CHECK_FFLAGS 0
+
+    TEST_CASE 87
+    # Overflowing the single-precision range
+    la a0, tdat
+    fld f0, 8(a0)
+    fcvt.s.d f3, f0
+    fmv.x.w a1, f3
+    li a2, 0x7f800000
+    ASSERT_EQ a1, a2
+    CHECK_FFLAGS 0x05
+
+    TEST_CASE 88
+    # A NaN-boxed single is not a valid double
+    li a1, 0x3f800000
+    fmv.w.x f0, a1
+    fcvt.s.d f3, f0
+    fmv.x.w a1, f3
+    li a2, 0x7fc00000
+    ASSERT_EQ a1, a2
+    CHECK_FFLAGS 0
+
+    TEST_CASE 89
+    # A double is not a NaN-boxed single
+    la a0, tdat
+    fld f0, 0(a0)
+    fadd.s f3, f0, f0
+    fmv.x.w a1, f3
+    li a2, 0x7fc00000
+    ASSERT_EQ a1, a2
+    CHECK_FFLAGS 0
+
+#-----------------------------------------------------------------------
+# fclass
+#-----------------------------------------------------------------------
+
+    TEST_FCLASS_D 90, 1 << 0, 0xfff00000, 0x00000000
+    TEST_FCLASS_D 91, 1 << 1, 0xbff00000, 0x00000000
+    TEST_FCLASS_D 92, 1 << 2, 0x800fffff, 0xffffffff
+    TEST_FCLASS_D 93, 1 << 3, 0x80000000, 0x00000000
+    TEST_FCLASS_D 94, 1 << 4, 0x00000000, 0x00000000
+    TEST_FCLASS_D 95, 1 << 5, 0x000fffff, 0xffffffff
+    TEST_FCLASS_D 96, 1 << 6, 0x3ff00000, 0x00000000
+    TEST_FCLASS_D 97, 1 << 7, 0x7ff00000, 0x00000000
+    TEST_FCLASS_D 98, 1 << 8, 0x7ff00000, 0x00000001
+    TEST_FCLASS_D 99, 1 << 9, 0x7ff80000, 0x00000000
+
+#-----------------------------------------------------------------------
+# Loads and stores
+#-----------------------------------------------------------------------
+
+    TEST_CASE 100
+    la a0, tdat
+    fld f31, 8(a0)
+    fsd f31, 24(a0)
+    lw a1, 24(a0)
+    lw a2, 8(a0)
+    ASSERT_EQ a1, a2
+    lw a1, 28(a0)
+    lw a2, 12(a0)
+    ASSERT_EQ a1, a2
+
+RVTEST_CODE_END
+
+    .data
+    .align 3
+tdat:
+    .double 1.1, 1.0e300
+    .double 0, 0
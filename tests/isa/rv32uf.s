@@ -0,0 +1,284 @@
+# RV32F single-precision floating point
+#
+# fflags are checked after every test case: 0x10 invalid, 0x08 divide by zero,
+# 0x04 overflow, 0x02 underflow and 0x01 inexact.
+
+.include "env.inc"
+
+RVTEST_CODE_BEGIN
+
+#-----------------------------------------------------------------------
+# Arithmetic
+#-----------------------------------------------------------------------
+
+    TEST_FP_OP2_S 2, fadd.s, 0, 3.5, 2.5, 1.0
+    TEST_FP_OP2_S 3, fadd.s, 1, -1234.0, -1235.1, 1.1
+    TEST_FP_OP2_S 4, fadd.s, 1, 3.14159265, 3.14159265, 0.00000001
+    TEST_FP_OP2_S 5, fadd.s, 0, inf, inf, 1.0
+    TEST_FP_OP2_S_QNAN 6, fadd.s, 0x10, inf, -inf
+
+    TEST_FP_OP2_S 7, fsub.s, 0, 1.5, 2.5, 1.0
+    TEST_FP_OP2_S 8, fsub.s, 1, -1234.0, -1235.1, -1.1
+    TEST_FP_OP2_S 9, fsub.s, 1, 3.14159265, 3.14159265, 0.00000001
+    TEST_FP_OP2_S 10, fsub.s, 0, -0.0, -0.0, 0.0
+    TEST_FP_OP2_S_QNAN 11, fsub.s, 0x10, inf, inf
+
+    TEST_FP_OP2_S 12, fmul.s, 0, 2.5, 2.5, 1.0
+    TEST_FP_OP2_S 13, fmul.s, 1, 1358.61, -1235.1, -1.1
+    TEST_FP_OP2_S 14, fmul.s, 1, 3.14159265e-8, 3.14159265, 0.00000001
+    TEST_FP_OP2_S 15, fmul.s, 0, -0.0, -0.0, 2.0
+    TEST_FP_OP2_S 16, fmul.s, 0x05, inf, 1.0e30, 1.0e30
+    TEST_FP_OP2_S_QNAN 17, fmul.s, 0x10, inf, 0.0
+
+    TEST_FP_OP2_S 18, fdiv.s, 1, 1.1557273520668288, 3.14159265, 2.71828182
+    TEST_FP_OP2_S 19, fdiv.s, 1, -0.9991093838555584, -1234, 1235.1
+    TEST_FP_OP2_S 20, fdiv.s, 0, 3.14159265, 3.14159265, 1.0
+    TEST_FP_OP2_S 21, fdiv.s, 0x08, -inf, -1.0, 0.0
+    TEST_FP_OP2_S_QNAN 22, fdiv.s, 0x10, 0.0, 0.0
+
+    TEST_FP_OP1_S 23, fsqrt.s, 1, 1.7724538498928541, 3.14159265
+    TEST_FP_OP1_S 24, fsqrt.s, 0, 100, 10000
+    TEST_FP_OP1_S 25, fsqrt.s, 0, -0.0, -0.0
+    TEST_FP_OP1_S 26, fsqrt.s, 1, 13.076696, 171.0
+
+    TEST_CASE 27
+    # The square root of a negative number is invalid
+    li a0, 0xbf800000
+    fmv.w.x f0, a0
+    fsqrt.s f3, f0
+    fmv.x.w a3, f3
+    li a4, 0x7fc00000
+    ASSERT_EQ a3, a4
+    CHECK_FFLAGS 0x10
+
+#-----------------------------------------------------------------------
+# Fused multiply-add
+#-----------------------------------------------------------------------
+
+    TEST_FP_OP3_S 28, fmadd.s, 0, 3.5, 1.0, 2.5, 1.0
+    TEST_FP_OP3_S 29, fmadd.s, 1, 1236.2, -1.0, -1235.1, 1.1
+    TEST_FP_OP3_S 30, fmadd.s, 0, -12.0, 2.0, -5.0, -2.0
+
+    TEST_FP_OP3_S 31, fnmadd.s, 0, -3.5, 1.0, 2.5, 1.0
+    TEST_FP_OP3_S 32, fnmadd.s, 1, -1236.2, -1.0, -1235.1, 1.1
+    TEST_FP_OP3_S 33, fnmadd.s, 0, 12.0, 2.0, -5.0, -2.0
+
+    TEST_FP_OP3_S 34, fmsub.s, 0, 1.5, 1.0, 2.5, 1.0
+    TEST_FP_OP3_S 35, fmsub.s, 1, 1234, -1.0, -1235.1, 1.1
+    TEST_FP_OP3_S 36, fmsub.s, 0, -8.0, 2.0, -5.0, -2.0
+
+    TEST_FP_OP3_S 37, fnmsub.s, 0, -1.5, 1.0, 2.5, 1.0
+    TEST_FP_OP3_S 38, fnmsub.s, 1, -1234, -1.0, -1235.1, 1.1
+    TEST_FP_OP3_S 39, fnmsub.s, 0, 8.0, 2.0, -5.0, -2.0
+
+    TEST_CASE 40
+    # The product isn't rounded before the addition
+    li a0, 0x3f800001
+    fmv.w.x f0, a0
+    li a0, 0xbf800002
+    fmv.w.x f2, a0
+    fmadd.s f3, f0, f0, f2
+    fmv.x.w a3, f3
+    li a4, 0x28800000
+    ASSERT_EQ a3, a4
+    CHECK_FFLAGS 0
+
+#-----------------------------------------------------------------------
+# Sign injection, minimum and maximum
+#-----------------------------------------------------------------------
+
+    TEST_FP_OP2_S 41, fsgnj.s, 0, -6.3, 6.3, -1.0
+    TEST_FP_OP2_S 42, fsgnj.s, 0, 6.3, -6.3, 1.0
+    TEST_FP_OP2_S 43, fsgnjn.s, 0, 6.3, 6.3, -1.0
+    TEST_FP_OP2_S 44, fsgnjn.s, 0, -6.3, -6.3, 1.0
+    TEST_FP_OP2_S 45, fsgnjx.s, 0, -6.3, 6.3, -1.0
+    TEST_FP_OP2_S 46, fsgnjx.s, 0, 6.3, -6.3, -1.0
+    TEST_FP_OP2_S 47, fsgnjx.s, 0, -6.3, -6.3, 1.0
+
+    TEST_FP_OP2_S 48, fmin.s, 0, 1.0, 2.5, 1.0
+    TEST_FP_OP2_S 49, fmin.s, 0, -1235.1, -1235.1, 1.1
+    TEST_FP_OP2_S 50, fmin.s, 0, -1235.1, 1.1, -1235.1
+    TEST_FP_OP2_S 51, fmin.s, 0, 1.1, nan, 1.1
+    TEST_FP_OP2_S 52, fmin.s, 0, -0.0, -0.0, 0.0
+    TEST_FP_OP2_S 53, fmin.s, 0, -0.0, 0.0, -0.0
+    TEST_FP_OP2_S_QNAN 54, fmin.s, 0, nan, nan
+
+    TEST_FP_OP2_S 55, fmax.s, 0, 2.5, 2.5, 1.0
+    TEST_FP_OP2_S 56, fmax.s, 0, 1.1, -1235.1, 1.1
+    TEST_FP_OP2_S 57, fmax.s, 0, 1.1, nan, 1.1
+    TEST_FP_OP2_S 58, fmax.s, 0, 0.0, -0.0, 0.0
+    TEST_FP_OP2_S 59, fmax.s, 0, 0.0, 0.0, -0.0
+
+    TEST_CASE 60
+    # A signaling NaN operand is invalid even though the other operand is returned
+    li a0, 0x7f800001
+    fmv.w.x f0, a0
+    li a0, 0x3f800000
+    fmv.w.x f1, a0
+    fmin.s f3, f0, f1
+    fmv.x.w a3, f3
+    ASSERT_EQ a3, a0
+    CHECK_FFLAGS 0x10
+
+#-----------------------------------------------------------------------
+# Comparisons
+#-----------------------------------------------------------------------
+
+    TEST_FP_CMP_OP_S 61, feq.s, 0, 1, -1.36, -1.36
+    TEST_FP_CMP_OP_S 62, fle.s, 0, 1, -1.36, -1.36
+    TEST_FP_CMP_OP_S 63, flt.s, 0, 0, -1.36, -1.36
+    TEST_FP_CMP_OP_S 64, feq.s, 0, 0, -1.37, -1.36
+    TEST_FP_CMP_OP_S 65, fle.s, 0, 1, -1.37, -1.36
+    TEST_FP_CMP_OP_S 66, flt.s, 0, 1, -1.37, -1.36
+    TEST_FP_CMP_OP_S 67, feq.s, 0, 1, -0.0, 0.0
+    TEST_FP_CMP_OP_S 68, flt.s, 0, 0, -0.0, 0.0
+
+    # Only the ordered comparisons are invalid on quiet NaN operands
+    TEST_FP_CMP_OP_S 69, feq.s, 0, 0, nan, 0
+    TEST_FP_CMP_OP_S 70, feq.s, 0, 0, nan, nan
+    TEST_FP_CMP_OP_S 71, flt.s, 0x10, 0, nan, 0
+    TEST_FP_CMP_OP_S 72, fle.s, 0x10, 0, nan, 0
+
+#-----------------------------------------------------------------------
+# Conversions
+#-----------------------------------------------------------------------
+
+    TEST_FP_INT_OP_S 73, fcvt.w.s, 0x01, -1, -1.1, rtz
+    TEST_FP_INT_OP_S 74, fcvt.w.s, 0x00, -1, -1.0, rtz
+    TEST_FP_INT_OP_S 75, fcvt.w.s, 0x01, 0, -0.9, rtz
+    TEST_FP_INT_OP_S 76, fcvt.w.s, 0x01, 0, 0.9, rtz
+    TEST_FP_INT_OP_S 77, fcvt.w.s, 0x00, 1, 1.0, rtz
+    TEST_FP_INT_OP_S 78, fcvt.w.s, 0x01, 1, 1.1, rtz
+    TEST_FP_INT_OP_S 79, fcvt.w.s, 0x10, 0x80000000, -3e9, rtz
+    TEST_FP_INT_OP_S 80, fcvt.w.s, 0x10, 0x7fffffff, 3e9, rtz
+    TEST_FP_INT_OP_S 81, fcvt.w.s, 0x10, 0x7fffffff, nan, rtz
+    TEST_FP_INT_OP_S 82, fcvt.w.s, 0x10, 0x7fffffff, inf, rtz
+    TEST_FP_INT_OP_S 83, fcvt.w.s, 0x10, 0x80000000, -inf, rtz
+
+    TEST_FP_INT_OP_S 84, fcvt.w.s, 0x01, 2, 2.5, rne
+    TEST_FP_INT_OP_S 85, fcvt.w.s, 0x01, 4, 3.5, rne
+    TEST_FP_INT_OP_S 86, fcvt.w.s, 0x01, 3, 2.5, rmm
+    TEST_FP_INT_OP_S 87, fcvt.w.s, 0x01, -3, -2.5, rdn
+    TEST_FP_INT_OP_S 88, fcvt.w.s, 0x01, -2, -2.5, rup
+    TEST_FP_INT_OP_S 89, fcvt.w.s, 0x01, 3, 2.1, rup
+
+    TEST_FP_INT_OP_S 90, fcvt.wu.s, 0x10, 0, -3.0, rtz
+    TEST_FP_INT_OP_S 91, fcvt.wu.s, 0x10, 0, -1.0, rtz
+    TEST_FP_INT_OP_S 92, fcvt.wu.s, 0x01, 0, -0.9, rtz
+    TEST_FP_INT_OP_S 93, fcvt.wu.s, 0x01, 0, 0.9, rtz
+    TEST_FP_INT_OP_S 94, fcvt.wu.s, 0x00, 1, 1.0, rtz
+    TEST_FP_INT_OP_S 95, fcvt.wu.s, 0x01, 1, 1.1, rtz
+    TEST_FP_INT_OP_S 96, fcvt.wu.s, 0x10, 0, -3e9, rtz
+    TEST_FP_INT_OP_S 97, fcvt.wu.s, 0x00, 3000000000, 3e9, rtz
+    TEST_FP_INT_OP_S 98, fcvt.wu.s, 0x10, 0xffffffff, 5e9, rtz
+    TEST_FP_INT_OP_S 99, fcvt.wu.s, 0x10, 0xffffffff, nan, rtz
+
+    TEST_INT_FP_OP_S 100, fcvt.s.w, 0x00, 2.0, 2
+    TEST_INT_FP_OP_S 101, fcvt.s.w, 0x00, -2.0, -2
+    TEST_INT_FP_OP_S 102, fcvt.s.w, 0x00, -2147483648.0, 0x80000000
+    TEST_INT_FP_OP_S 103, fcvt.s.w, 0x01, 2147483648.0, 0x7fffffff
+
+    TEST_INT_FP_OP_S 104, fcvt.s.wu, 0x00, 2.0, 2
+    TEST_INT_FP_OP_S 105, fcvt.s.wu, 0x01, 4294967296.0, -2
+    TEST_INT_FP_OP_S 106, fcvt.s.wu, 0x00, 2147483648.0, 0x80000000
+
+#-----------------------------------------------------------------------
+# fclass
+#-----------------------------------------------------------------------
+
+    TEST_FCLASS_S 107, 1 << 0, 0xff800000
+    TEST_FCLASS_S 108, 1 << 1, 0xbf800000
+    TEST_FCLASS_S 109, 1 << 2, 0x807fffff
+    TEST_FCLASS_S 110, 1 << 3, 0x80000000
+    TEST_FCLASS_S 111, 1 << 4, 0x00000000
+    TEST_FCLASS_S 112, 1 << 5, 0x007fffff
+    TEST_FCLASS_S 113, 1 << 6, 0x3f800000
+    TEST_FCLASS_S 114, 1 << 7, 0x7f800000
+    TEST_FCLASS_S 115, 1 << 8, 0x7f800001
+    TEST_FCLASS_S 116, 1 << 9, 0x7fc00000
+
+#-----------------------------------------------------------------------
+# Moves, loads and stores
+#-----------------------------------------------------------------------
+
+    TEST_CASE 117
+    # Moves copy the bits unchanged, even of a signaling NaN
+    li a0, 0x7f800001
+    fmv.w.x f0, a0
+    fmv.x.w a1, f0
+    ASSERT_EQ a0, a1
+    li a0, 0xdeadbeef
+    fmv.w.x f31, a0
+    fmv.x.w a1, f31
+    ASSERT_EQ a0, a1
+    CHECK_FFLAGS 0
+
+    TEST_CASE 118
+    la a0, tdat
+    flw f0, 4(a0)
+    fsw f0, 20(a0)
+    lw a1, 20(a0)
+    lw a2, 4(a0)
+    ASSERT_EQ a1, a2
+    flw f1, 12(a0)
+    fsw f1, -4(a0)
+    lw a1, -4(a0)
+    lw a2, 12(a0)
+    ASSERT_EQ a1, a2
+
+#-----------------------------------------------------------------------
+# Floating point CSRs
+#-----------------------------------------------------------------------
+
+    TEST_CASE 119
+    # fcsr holds frm above fflags, its upper bits are zero
+    li a0, -1
+    csrw fcsr, a0
+    csrr a1, fcsr
+    li a2, 0xff
+    ASSERT_EQ a1, a2
+    csrr a1, frm
+    li a2, 7
+    ASSERT_EQ a1, a2
+    csrr a1, fflags
+    li a2, 0x1f
+    ASSERT_EQ a1, a2
+    csrwi fflags, 0
+    csrr a1, fcsr
+    li a2, 0xe0
+    ASSERT_EQ a1, a2
+    csrw fcsr, zero
+
+    TEST_CASE 120
+    # Dynamic rounding uses frm
+    li a0, 0xbf8ccccd
+    fmv.w.x f0, a0
+    fsrmi a1, 2
+    ASSERT_EQ a1, zero
+    fcvt.w.s a2, f0
+    li a3, -2
+    ASSERT_EQ a2, a3
+    fsrmi 3
+    fcvt.w.s a2, f0
+    li a3, -1
+    ASSERT_EQ a2, a3
+    fsrmi 0
+    CHECK_FFLAGS 0x01
+
+    TEST_CASE 121
+    # Flags accrue until they are cleared
+    li a0, 0x3f800000
+    fmv.w.x f0, a0
+    fmv.w.x f1, zero
+    fdiv.s f2, f0, f1
+    fsqrt.s f2, f2
+    fsub.s f2, f2, f2
+    CHECK_FFLAGS 0x18
+
+RVTEST_CODE_END
+
+    .data
+    .align 2
+    .word 0
+tdat:
+    .word 0xbf800000, 0x40000000, 0x40400000, 0xc0800000, 0xdeadbeef, 0xcafebabe
@@ -0,0 +1,396 @@
+# RV32I base integer instructions
+
+.include "env.inc"
+
+RVTEST_CODE_BEGIN
+
+#-----------------------------------------------------------------------
+# add, sub
+#-----------------------------------------------------------------------
+
+    TEST_RR_OP 2, add, 0x00000000, 0x00000000, 0x00000000
+    TEST_RR_OP 3, add, 0x00000002, 0x00000001, 0x00000001
+    TEST_RR_OP 4, add, 0x0000000a, 0x00000003, 0x00000007
+    TEST_RR_OP 5, add, 0xffff8000, 0x00000000, 0xffff8000
+    TEST_RR_OP 6, add, 0x80000000, 0x80000000, 0x00000000
+    TEST_RR_OP 7, add, 0x7fff8000, 0x80000000, 0xffff8000
+    TEST_RR_OP 8, add, 0x80007ffe, 0x7fffffff, 0x00007fff
+    TEST_RR_OP 9, add, 0x7fffffff, 0x7fffffff, 0x00000000
+    TEST_RR_OP 10, add, 0x80000000, 0x7fffffff, 0x00000001
+    TEST_RR_OP 11, add, 0xfffffffe, 0xffffffff, 0xffffffff
+    TEST_RR_OP 12, add, 0x00000000, 0xffffffff, 0x00000001
+    TEST_RR_SRC1_EQ_DEST 13, add, 24, 13, 11
+    TEST_RR_SRC2_EQ_DEST 14, add, 25, 14, 11
+    TEST_RR_SRC12_EQ_DEST 15, add, 26, 13
+    TEST_RR_ZEROSRC1 16, add, 15, 15
+    TEST_RR_ZEROSRC2 17, add, 32, 32
+    TEST_RR_ZERODEST 18, add, 16, 30
+
+    TEST_RR_OP 19, sub, 0x00000000, 0x00000000, 0x00000000
+    TEST_RR_OP 20, sub, 0x00000000, 0x00000001, 0x00000001
+    TEST_RR_OP 21, sub, 0xfffffffc, 0x00000003, 0x00000007
+    TEST_RR_OP 22, sub, 0x00008000, 0x00000000, 0xffff8000
+    TEST_RR_OP 23, sub, 0x80008000, 0x80000000, 0xffff8000
+    TEST_RR_OP 24, sub, 0x80008000, 0x7fffffff, 0xffff7fff
+    TEST_RR_OP 25, sub, 0x7fffffff, 0x80000000, 0x00000001
+    TEST_RR_OP 26, sub, 0x00000001, 0x00000000, 0xffffffff
+    TEST_RR_SRC1_EQ_DEST 27, sub, 2, 13, 11
+    TEST_RR_SRC2_EQ_DEST 28, sub, 3, 14, 11
+    TEST_RR_SRC12_EQ_DEST 29, sub, 0, 13
+    TEST_RR_ZEROSRC1 30, sub, 0xfffffff1, 15
+    TEST_RR_ZEROSRC2 31, sub, 32, 32
+    TEST_RR_ZERODEST 32, sub, 16, 30
+
+#-----------------------------------------------------------------------
+# addi, slti, sltiu, xori, ori, andi
+#-----------------------------------------------------------------------
+
+    TEST_IMM_OP 33, addi, 0x00000000, 0x00000000, 0x000
+    TEST_IMM_OP 34, addi, 0x0000000a, 0x00000003, 0x007
+    TEST_IMM_OP 35, addi, 0xfffff800, 0x00000000, -0x800
+    TEST_IMM_OP 36, addi, 0x000007ff, 0x00000000, 0x7ff
+    TEST_IMM_OP 37, addi, 0x7ffff800, 0x80000000, -0x800
+    TEST_IMM_OP 38, addi, 0x800007fe, 0x7fffffff, 0x7ff
+    TEST_IMM_OP 39, addi, 0xfffffffe, 0xffffffff, -1
+    TEST_IMM_OP 40, addi, 0x80000000, 0x7fffffff, 1
+    TEST_IMM_SRC1_EQ_DEST 41, addi, 24, 13, 11
+    TEST_IMM_ZEROSRC1 42, addi, 32, 32
+    TEST_IMM_ZERODEST 43, addi, 33, 50
+
+    TEST_IMM_OP 44, slti, 0, 0x00000000, 0x000
+    TEST_IMM_OP 45, slti, 1, 0x00000002, 0x003
+    TEST_IMM_OP 46, slti, 0, 0x00000007, 0x003
+    TEST_IMM_OP 47, slti, 1, 0x80000000, 0x000
+    TEST_IMM_OP 48, slti, 0, 0x7fffffff, -0x800
+    TEST_IMM_OP 49, slti, 1, 0xffffffff, 0x001
+    TEST_IMM_OP 50, slti, 0, 0x00000000, -1
+    TEST_IMM_SRC1_EQ_DEST 51, slti, 1, 11, 13
+    TEST_IMM_ZEROSRC1 52, slti, 0, -1
+
+    TEST_IMM_OP 53, sltiu, 0, 0x00000000, 0x000
+    TEST_IMM_OP 54, sltiu, 1, 0x00000002, 0x003
+    TEST_IMM_OP 55, sltiu, 0, 0x80000000, 0x000
+    TEST_IMM_OP 56, sltiu, 1, 0x80000000, -0x800
+    TEST_IMM_OP 57, sltiu, 1, 0x00000000, -1
+    TEST_IMM_OP 58, sltiu, 0, 0xffffffff, -1
+    TEST_IMM_OP 59, sltiu, 0, 0x00000001, 0x001
+    TEST_IMM_ZEROSRC1 60, sltiu, 1, 0x7ff
+
+    TEST_IMM_OP 61, xori, 0xff00f00f, 0x00ff0f00, -0x0f1
+    TEST_IMM_OP 62, xori, 0x0ff00f00, 0x0ff00ff0, 0x0f0
+    TEST_IMM_OP 63, xori, 0x00ff0ff0, 0x00ff08ff, 0x70f
+    TEST_IMM_OP 64, xori, 0xf00ff0ff, 0xf00ff00f, 0x0f0
+    TEST_IMM_SRC1_EQ_DEST 65, xori, 0xff00f00f, 0xff00f700, 0x70f
+    TEST_IMM_ZEROSRC1 66, xori, 0x0f0, 0x0f0
+
+    TEST_IMM_OP 67, ori, 0xffffff0f, 0xff00ff00, -0x0f1
+    TEST_IMM_OP 68, ori, 0x0ff00ff0, 0x0ff00ff0, 0x0f0
+    TEST_IMM_OP 69, ori, 0x00ff07ff, 0x00ff00ff, 0x70f
+    TEST_IMM_OP 70, ori, 0xf00ff0ff, 0xf00ff00f, 0x0f0
+    TEST_IMM_ZEROSRC1 71, ori, 0x0f0, 0x0f0
+
+    TEST_IMM_OP 72, andi, 0xff00ff00, 0xff00ff00, -0x0f1
+    TEST_IMM_OP 73, andi, 0x000000f0, 0x0ff00ff0, 0x0f0
+    TEST_IMM_OP 74, andi, 0x0000000f, 0x00ff00ff, 0x70f
+    TEST_IMM_OP 75, andi, 0x00000000, 0xf00ff00f, 0x0f0
+    TEST_IMM_ZEROSRC1 76, andi, 0, 0x0f0
+    TEST_IMM_ZERODEST 77, andi, 0x00ff00ff, 0x70f
+
+#-----------------------------------------------------------------------
+# slt, sltu, xor, or, and
+#-----------------------------------------------------------------------
+
+    TEST_RR_OP 78, slt, 0, 0x00000000, 0x00000000
+    TEST_RR_OP 79, slt, 1, 0x00000003, 0x00000007
+    TEST_RR_OP 80, slt, 0, 0x00000007, 0x00000003
+    TEST_RR_OP 81, slt, 1, 0x80000000, 0x00000000
+    TEST_RR_OP 82, slt, 0, 0x7fffffff, 0x80000000
+    TEST_RR_OP 83, slt, 1, 0xffffffff, 0x00000001
+    TEST_RR_OP 84, slt, 0, 0x00000001, 0xffffffff
+    TEST_RR_ZEROSRC1 85, slt, 0, -1
+    TEST_RR_ZEROSRC2 86, slt, 1, -1
+
+    TEST_RR_OP 87, sltu, 0, 0x00000000, 0x00000000
+    TEST_RR_OP 88, sltu, 1, 0x00000003, 0x00000007
+    TEST_RR_OP 89, sltu, 0, 0x80000000, 0x00000000
+    TEST_RR_OP 90, sltu, 1, 0x7fffffff, 0x80000000
+    TEST_RR_OP 91, sltu, 0, 0xffffffff, 0x00000001
+    TEST_RR_OP 92, sltu, 1, 0x00000001, 0xffffffff
+    TEST_RR_ZEROSRC1 93, sltu, 1, -1
+    TEST_RR_ZEROSRC2 94, sltu, 0, -1
+
+    TEST_RR_OP 95, xor, 0xf00ff00f, 0xff00ff00, 0x0f0f0f0f
+    TEST_RR_OP 96, xor, 0xff00ff00, 0x0ff00ff0, 0xf0f0f0f0
+    TEST_RR_OP 97, xor, 0x0ff00ff0, 0x00ff00ff, 0x0f0f0f0f
+    TEST_RR_SRC12_EQ_DEST 98, xor, 0, 0xff00ff00
+    TEST_RR_ZEROSRC1 99, xor, 0xff00ff00, 0xff00ff00
+
+    TEST_RR_OP 100, or, 0xff0fff0f, 0xff00ff00, 0x0f0f0f0f
+    TEST_RR_OP 101, or, 0xfff0fff0, 0x0ff00ff0, 0xf0f0f0f0
+    TEST_RR_OP 102, or, 0x0fff0fff, 0x00ff00ff, 0x0f0f0f0f
+    TEST_RR_SRC12_EQ_DEST 103, or, 0xff00ff00, 0xff00ff00
+
+    TEST_RR_OP 104, and, 0x0f000f00, 0xff00ff00, 0x0f0f0f0f
+    TEST_RR_OP 105, and, 0x00f000f0, 0x0ff00ff0, 0xf0f0f0f0
+    TEST_RR_OP 106, and, 0x000f000f, 0x00ff00ff, 0x0f0f0f0f
+    TEST_RR_ZEROSRC2 107, and, 0, 0xff00ff00
+
+#-----------------------------------------------------------------------
+# Shifts
+#-----------------------------------------------------------------------
+
+    TEST_IMM_OP 108, slli, 0x00000001, 0x00000001, 0
+    TEST_IMM_OP 109, slli, 0x00000080, 0x00000001, 7
+    TEST_IMM_OP 110, slli, 0x80000000, 0x00000001, 31
+    TEST_IMM_OP 111, slli, 0x42424242, 0x21212121, 1
+    TEST_IMM_OP 112, slli, 0x90909080, 0x21212121, 7
+    TEST_IMM_OP 113, slli, 0x80000000, 0x21212121, 31
+
+    TEST_IMM_OP 114, srli, 0x80000000, 0x80000000, 0
+    TEST_IMM_OP 115, srli, 0x01000000, 0x80000000, 7
+    TEST_IMM_OP 116, srli, 0x00000001, 0x80000000, 31
+    TEST_IMM_OP 117, srli, 0x10909090, 0x21212121, 1
+    TEST_IMM_OP 118, srli, 0x00000000, 0x21212121, 31
+
+    TEST_IMM_OP 119, srai, 0x00000000, 0x00000000, 0
+    TEST_IMM_OP 120, srai, 0x80000000, 0x80000000, 0
+    TEST_IMM_OP 121, srai, 0xff000000, 0x80000000, 7
+    TEST_IMM_OP 122, srai, 0xffffffff, 0x80000000, 31
+    TEST_IMM_OP 123, srai, 0x7fffffff, 0x7fffffff, 0
+    TEST_IMM_OP 124, srai, 0x00ffffff, 0x7fffffff, 7
+    TEST_IMM_OP 125, srai, 0xc0c0c0c0, 0x81818181, 1
+    TEST_IMM_OP 126, srai, 0xffffffff, 0x81818181, 31
+    TEST_IMM_SRC1_EQ_DEST 127, srai, 0xff000000, 0x80000000, 7
+
+    TEST_RR_OP 128, sll, 0x00000001, 0x00000001, 0
+    TEST_RR_OP 129, sll, 0x00004000, 0x00000001, 14
+    TEST_RR_OP 130, sll, 0x80000000, 0x00000001, 31
+    TEST_RR_OP 131, sll, 0x42424242, 0x21212121, 0xffffffe1
+    TEST_RR_OP 132, sll, 0x80000000, 0x21212121, 0xffffffff
+
+    TEST_RR_OP 133, srl, 0x80000000, 0x80000000, 0
+    TEST_RR_OP 134, srl, 0x00020000, 0x80000000, 14
+    TEST_RR_OP 135, srl, 0x00000001, 0x80000000, 31
+    TEST_RR_OP 136, srl, 0x10909090, 0x21212121, 0xffffffe1
+    TEST_RR_OP 137, srl, 0x00000000, 0x21212121, 0xffffffff
+
+    TEST_RR_OP 138, sra, 0x80000000, 0x80000000, 0
+    TEST_RR_OP 139, sra, 0xfffe0000, 0x80000000, 14
+    TEST_RR_OP 140, sra, 0xffffffff, 0x80000000, 31
+    TEST_RR_OP 141, sra, 0x0001ffff, 0x7fffffff, 14
+    TEST_RR_OP 142, sra, 0xc0c0c0c0, 0x81818181, 0xffffffe1
+    TEST_RR_OP 143, sra, 0xffffffff, 0x81818181, 0xffffffff
+    TEST_RR_SRC2_EQ_DEST 144, sra, 0xfffe0000, 0x80000000, 14
+
+#-----------------------------------------------------------------------
+# lui, auipc
+#-----------------------------------------------------------------------
+
+    TEST_CASE 145
+    lui x1, 0x00000
+    ASSERT_EQ x1, zero
+    lui x1, 0xfffff
+    sra x1, x1, 1
+    li x7, 0xfffff800
+    ASSERT_EQ x1, x7
+    lui x1, 0x7ffff
+    li x7, 0x7ffff000
+    ASSERT_EQ x1, x7
+    lui x0, 0x80000
+    ASSERT_EQ x0, zero
+
+    TEST_CASE 146
+1:  auipc a0, 1
+    la a1, 1b
+    sub a0, a0, a1
+    li a2, 0x1000
+    ASSERT_EQ a0, a2
+2:  auipc a0, 0xfffff
+    la a1, 2b
+    sub a0, a0, a1
+    li a2, -0x1000
+    ASSERT_EQ a0, a2
+
+#-----------------------------------------------------------------------
+# Branches
+#-----------------------------------------------------------------------
+
+    TEST_BR2_OP_TAKEN 147, beq, 0, 0
+    TEST_BR2_OP_TAKEN 148, beq, -1, -1
+    TEST_BR2_OP_NOTTAKEN 149, beq, 0, 1
+    TEST_BR2_OP_NOTTAKEN 150, beq, -1, 1
+
+    TEST_BR2_OP_TAKEN 151, bne, 0, 1
+    TEST_BR2_OP_TAKEN 152, bne, -1, 1
+    TEST_BR2_OP_NOTTAKEN 153, bne, 0, 0
+    TEST_BR2_OP_NOTTAKEN 154, bne, -1, -1
+
+    TEST_BR2_OP_TAKEN 155, blt, 0, 1
+    TEST_BR2_OP_TAKEN 156, blt, -1, 1
+    TEST_BR2_OP_TAKEN 157, blt, -2, -1
+    TEST_BR2_OP_NOTTAKEN 158, blt, 1, 0
+    TEST_BR2_OP_NOTTAKEN 159, blt, 1, -1
+    TEST_BR2_OP_NOTTAKEN 160, blt, -1, -2
+
+    TEST_BR2_OP_TAKEN 161, bge, 0, 0
+    TEST_BR2_OP_TAKEN 162, bge, 1, 0
+    TEST_BR2_OP_TAKEN 163, bge, 1, -1
+    TEST_BR2_OP_TAKEN 164, bge, -1, -2
+    TEST_BR2_OP_NOTTAKEN 165, bge, 0, 1
+    TEST_BR2_OP_NOTTAKEN 166, bge, -1, 1
+    TEST_BR2_OP_NOTTAKEN 167, bge, -2, -1
+
+    TEST_BR2_OP_TAKEN 168, bltu, 0x00000000, 0x00000001
+    TEST_BR2_OP_TAKEN 169, bltu, 0xfffffffe, 0xffffffff
+    TEST_BR2_OP_TAKEN 170, bltu, 0x00000000, 0xffffffff
+    TEST_BR2_OP_NOTTAKEN 171, bltu, 0x00000001, 0x00000000
+    TEST_BR2_OP_NOTTAKEN 172, bltu, 0xffffffff, 0xfffffffe
+    TEST_BR2_OP_NOTTAKEN 173, bltu, 0x80000000, 0x7fffffff
+
+    TEST_BR2_OP_TAKEN 174, bgeu, 0x00000000, 0x00000000
+    TEST_BR2_OP_TAKEN 175, bgeu, 0xffffffff, 0xffffffff
+    TEST_BR2_OP_TAKEN 176, bgeu, 0xffffffff, 0x00000000
+    TEST_BR2_OP_TAKEN 177, bgeu, 0x80000000, 0x7fffffff
+    TEST_BR2_OP_NOTTAKEN 178, bgeu, 0x00000000, 0x00000001
+    TEST_BR2_OP_NOTTAKEN 179, bgeu, 0xfffffffe, 0xffffffff
+    TEST_BR2_OP_NOTTAKEN 180, bgeu, 0x7fffffff, 0x80000000
+
+#-----------------------------------------------------------------------
+# jal, jalr
+#-----------------------------------------------------------------------
+
+    TEST_CASE 181
+    li ra, 0
+    jal x4, 1f
+2:  j fail
+1:  la x2, 2b
+    ASSERT_EQ x2, x4
+
+    TEST_CASE 182
+    li t0, 0
+    la t1, 1f
+    jalr t0, 0(t1)
+2:  j fail
+1:  la t1, 2b
+    ASSERT_EQ t0, t1
+
+    TEST_CASE 183
+    # The lowest bit of the target is cleared, rd may be rs1
+    la t0, 1f - 3
+    jalr t0, 4(t0)
+2:  j fail
+    j fail
+1:  la t1, 2b
+    ASSERT_EQ t0, t1
+
+    TEST_CASE 184
+    la t0, 1f
+    jalr x0, 4(t0)
+1:  j fail
+    nop
+
+#-----------------------------------------------------------------------
+# Loads
+#-----------------------------------------------------------------------
+
+    TEST_LD_OP 185, lb, 0xffffffff, 0, tdat_b
+    TEST_LD_OP 186, lb, 0x00000000, 1, tdat_b
+    TEST_LD_OP 187, lb, 0xfffffff0, 2, tdat_b
+    TEST_LD_OP 188, lb, 0x0000000f, 3, tdat_b
+    TEST_LD_OP 189, lb, 0xffffffff, -3, tdat_b + 3
+
+    TEST_LD_OP 190, lbu, 0x000000ff, 0, tdat_b
+    TEST_LD_OP 191, lbu, 0x00000000, 1, tdat_b
+    TEST_LD_OP 192, lbu, 0x000000f0, 2, tdat_b
+    TEST_LD_OP 193, lbu, 0x0000000f, 3, tdat_b
+    TEST_LD_OP 194, lbu, 0x000000ff, -3, tdat_b + 3
+
+    TEST_LD_OP 195, lh, 0x000000ff, 0, tdat_h
+    TEST_LD_OP 196, lh, 0xffffff00, 2, tdat_h
+    TEST_LD_OP 197, lh, 0x00000ff0, 4, tdat_h
+    TEST_LD_OP 198, lh, 0xfffff00f, 6, tdat_h
+    TEST_LD_OP 199, lh, 0x00000ff0, -2, tdat_h + 6
+
+    TEST_LD_OP 200, lhu, 0x000000ff, 0, tdat_h
+    TEST_LD_OP 201, lhu, 0x0000ff00, 2, tdat_h
+    TEST_LD_OP 202, lhu, 0x00000ff0, 4, tdat_h
+    TEST_LD_OP 203, lhu, 0x0000f00f, 6, tdat_h
+    TEST_LD_OP 204, lhu, 0x0000ff00, -4, tdat_h + 6
+
+    TEST_LD_OP 205, lw, 0x00ff00ff, 0, tdat_w
+    TEST_LD_OP 206, lw, 0xff00ff00, 4, tdat_w
+    TEST_LD_OP 207, lw, 0x0ff00ff0, 8, tdat_w
+    TEST_LD_OP 208, lw, 0xf00ff00f, 12, tdat_w
+    TEST_LD_OP 209, lw, 0x0ff00ff0, -4, tdat_w + 12
+
+    TEST_CASE 210
+    # A load into x0 has no effect
+    la t0, tdat_w
+    lw x0, 0(t0)
+    ASSERT_EQ x0, zero
+
+#-----------------------------------------------------------------------
+# Stores
+#-----------------------------------------------------------------------
+
+    TEST_ST_OP 211, lb, sb, 0xffffffaa, 0, tdat_s
+    TEST_ST_OP 212, lb, sb, 0x00000000, 1, tdat_s
+    TEST_ST_OP 213, lh, sh, 0xffffefa0, 2, tdat_s
+    TEST_ST_OP 214, lb, sb, 0x0000000a, 3, tdat_s
+    TEST_ST_OP 215, lb, sb, 0xffffffaa, -3, tdat_s + 3
+
+    TEST_ST_OP 216, lh, sh, 0x000000aa, 0, tdat_s
+    TEST_ST_OP 217, lh, sh, 0xffffaa00, 2, tdat_s
+    TEST_ST_OP 218, lh, sh, 0xffffa00a, 6, tdat_s
+    TEST_ST_OP 219, lh, sh, 0x000000aa, -6, tdat_s + 6
+
+    TEST_ST_OP 220, lw, sw, 0x00aa00aa, 0, tdat_s
+    TEST_ST_OP 221, lw, sw, 0xaa00aa00, 4, tdat_s
+    TEST_ST_OP 222, lw, sw, 0x0aa00aa0, 8, tdat_s
+    TEST_ST_OP 223, lw, sw, 0xa00aa00a, 12, tdat_s
+    TEST_ST_OP 224, lw, sw, 0x12345678, -12, tdat_s + 12
+
+    TEST_CASE 225
+    # Narrow stores leave the rest of the word alone
+    la t0, tdat_s
+    li t1, 0x11223344
+    sw t1, 0(t0)
+    li t1, 0xff
+    sb t1, 1(t0)
+    li t1, 0xabcd
+    sh t1, 2(t0)
+    lw t2, 0(t0)
+    li t3, 0xabcdff44
+    ASSERT_EQ t2, t3
+
+#-----------------------------------------------------------------------
+# fence, x0
+#-----------------------------------------------------------------------
+
+    TEST_CASE 226
+    fence
+    fence rw, rw
+    fence.tso
+    li t0, 1
+    ASSERT_EQ t0, t0
+
+    TEST_CASE 227
+    addi x0, x0, 5
+    lui x0, 0x12345
+    auipc x0, 0x1
+    jal x0, 1f
+1:  ASSERT_EQ x0, zero
+
+RVTEST_CODE_END
+
+    .data
+tdat_b:
+    .byte 0xff, 0x00, 0xf0, 0x0f
+    .align 1
+tdat_h:
+    .half 0x00ff, 0xff00, 0x0ff0, 0xf00f
+    .align 2
+tdat_w:
+    .word 0x00ff00ff, 0xff00ff00, 0x0ff00ff0, 0xf00ff00f
+tdat_s:
+    .word 0xdeadbeef, 0xdeadbeef, 0xdeadbeef, 0xdeadbeef
@@ -0,0 +1,117 @@
+# RV32M multiplication and division
+
+.include "env.inc"
+
+RVTEST_CODE_BEGIN
+
+#-----------------------------------------------------------------------
+# mul, mulh, mulhsu, mulhu
+#-----------------------------------------------------------------------
+
+    TEST_RR_OP 2, mul, 0x00000000, 0x00000000, 0x00000000
+    TEST_RR_OP 3, mul, 0x00000001, 0x00000001, 0x00000001
+    TEST_RR_OP 4, mul, 0x00000015, 0x00000003, 0x00000007
+    TEST_RR_OP 5, mul, 0x00000000, 0x00000000, 0xffff8000
+    TEST_RR_OP 6, mul, 0x00000000, 0x80000000, 0x00000000
+    TEST_RR_OP 7, mul, 0x00000000, 0x80000000, 0xffff8000
+    TEST_RR_OP 8, mul, 0x0000ff7f, 0xaaaaaaab, 0x0002fe7d
+    TEST_RR_OP 9, mul, 0x0000ff7f, 0x0002fe7d, 0xaaaaaaab
+    TEST_RR_OP 10, mul, 0x00000001, 0xffffffff, 0xffffffff
+    TEST_RR_OP 11, mul, 0xffffffff, 0xffffffff, 0x00000001
+    TEST_RR_SRC1_EQ_DEST 12, mul, 143, 13, 11
+    TEST_RR_SRC2_EQ_DEST 13, mul, 154, 14, 11
+    TEST_RR_SRC12_EQ_DEST 14, mul, 169, 13
+    TEST_RR_ZEROSRC1 15, mul, 0, 31
+    TEST_RR_ZEROSRC2 16, mul, 0, 32
+    TEST_RR_ZERODEST 17, mul, 33, 34
+
+    TEST_RR_OP 18, mulh, 0x00000000, 0x00000000, 0x00000000
+    TEST_RR_OP 19, mulh, 0x00000000, 0x00000001, 0x00000001
+    TEST_RR_OP 20, mulh, 0x00000000, 0x00000003, 0x00000007
+    TEST_RR_OP 21, mulh, 0x00000000, 0x00000000, 0xffff8000
+    TEST_RR_OP 22, mulh, 0x00000000, 0x80000000, 0x00000000
+    TEST_RR_OP 23, mulh, 0x00004000, 0x80000000, 0xffff8000
+    TEST_RR_OP 24, mulh, 0x40000000, 0x80000000, 0x80000000
+    TEST_RR_OP 25, mulh, 0xffff0081, 0xaaaaaaab, 0x0002fe7d
+    TEST_RR_OP 26, mulh, 0xffff0081, 0x0002fe7d, 0xaaaaaaab
+    TEST_RR_OP 27, mulh, 0x00000000, 0xffffffff, 0xffffffff
+    TEST_RR_OP 28, mulh, 0xffffffff, 0xffffffff, 0x00000001
+    TEST_RR_OP 29, mulh, 0xc0000000, 0x80000000, 0x7fffffff
+    TEST_RR_SRC12_EQ_DEST 30, mulh, 0x3fffffff, 0x7fffffff
+    TEST_RR_ZEROSRC1 31, mulh, 0, 31
+
+    TEST_RR_OP 32, mulhsu, 0x00000000, 0x00000000, 0x00000000
+    TEST_RR_OP 33, mulhsu, 0x00000000, 0x00000001, 0x00000001
+    TEST_RR_OP 34, mulhsu, 0x00000000, 0x00000003, 0x00000007
+    TEST_RR_OP 35, mulhsu, 0x00000000, 0x00000000, 0xffff8000
+    TEST_RR_OP 36, mulhsu, 0x00000000, 0x80000000, 0x00000000
+    TEST_RR_OP 37, mulhsu, 0x80004000, 0x80000000, 0xffff8000
+    TEST_RR_OP 38, mulhsu, 0xffff0081, 0xaaaaaaab, 0x0002fe7d
+    TEST_RR_OP 39, mulhsu, 0x0001fefe, 0x0002fe7d, 0xaaaaaaab
+    TEST_RR_OP 40, mulhsu, 0xffffffff, 0xffffffff, 0xffffffff
+    TEST_RR_OP 41, mulhsu, 0xffffffff, 0xffffffff, 0x00000001
+    TEST_RR_OP 42, mulhsu, 0x7ffffffe, 0x7fffffff, 0xffffffff
+    TEST_RR_ZEROSRC2 43, mulhsu, 0, -1
+
+    TEST_RR_OP 44, mulhu, 0x00000000, 0x00000000, 0x00000000
+    TEST_RR_OP 45, mulhu, 0x00000000, 0x00000001, 0x00000001
+    TEST_RR_OP 46, mulhu, 0x00000000, 0x00000003, 0x00000007
+    TEST_RR_OP 47, mulhu, 0x00000000, 0x00000000, 0xffff8000
+    TEST_RR_OP 48, mulhu, 0x00000000, 0x80000000, 0x00000000
+    TEST_RR_OP 49, mulhu, 0x7fffc000, 0x80000000, 0xffff8000
+    TEST_RR_OP 50, mulhu, 0x0001fefe, 0xaaaaaaab, 0x0002fe7d
+    TEST_RR_OP 51, mulhu, 0x0001fefe, 0x0002fe7d, 0xaaaaaaab
+    TEST_RR_OP 52, mulhu, 0xfffffffe, 0xffffffff, 0xffffffff
+    TEST_RR_OP 53, mulhu, 0x00000000, 0xffffffff, 0x00000001
+    TEST_RR_SRC12_EQ_DEST 54, mulhu, 0xfffffffe, 0xffffffff
+
+#-----------------------------------------------------------------------
+# div, divu, rem, remu
+#-----------------------------------------------------------------------
+
+    TEST_RR_OP 55, div, 3, 20, 6
+    TEST_RR_OP 56, div, -3, -20, 6
+    TEST_RR_OP 57, div, -3, 20, -6
+    TEST_RR_OP 58, div, 3, -20, -6
+    TEST_RR_OP 59, div, 0, 1, 2
+    TEST_RR_OP 60, div, 0, -1, 2
+    TEST_RR_OP 61, div, 0x80000000, 0x80000000, 1
+    TEST_RR_OP 62, div, 0x80000000, 0x80000000, -1
+    TEST_RR_OP 63, div, -1, 0x80000000, 0
+    TEST_RR_OP 64, div, -1, 1, 0
+    TEST_RR_OP 65, div, -1, 0, 0
+    TEST_RR_SRC1_EQ_DEST 66, div, 3, 20, 6
+    TEST_RR_ZERODEST 67, div, 20, 0
+
+    TEST_RR_OP 68, divu, 3, 20, 6
+    TEST_RR_OP 69, divu, 715827879, -20, 6
+    TEST_RR_OP 70, divu, 0, 20, -6
+    TEST_RR_OP 71, divu, 0, -20, -6
+    TEST_RR_OP 72, divu, 0x80000000, 0x80000000, 1
+    TEST_RR_OP 73, divu, 0, 0x80000000, -1
+    TEST_RR_OP 74, divu, -1, 0x80000000, 0
+    TEST_RR_OP 75, divu, -1, 1, 0
+    TEST_RR_OP 76, divu, -1, 0, 0
+
+    TEST_RR_OP 77, rem, 2, 20, 6
+    TEST_RR_OP 78, rem, -2, -20, 6
+    TEST_RR_OP 79, rem, 2, 20, -6
+    TEST_RR_OP 80, rem, -2, -20, -6
+    TEST_RR_OP 81, rem, 0, 0x80000000, 1
+    TEST_RR_OP 82, rem, 0, 0x80000000, -1
+    TEST_RR_OP 83, rem, 0x80000000, 0x80000000, 0
+    TEST_RR_OP 84, rem, 1, 1, 0
+    TEST_RR_OP 85, rem, 0, 0, 0
+    TEST_RR_SRC2_EQ_DEST 86, rem, -2, -20, 6
+
+    TEST_RR_OP 87, remu, 2, 20, 6
+    TEST_RR_OP 88, remu, 2, -20, 6
+    TEST_RR_OP 89, remu, 20, 20, -6
+    TEST_RR_OP 90, remu, -20, -20, -6
+    TEST_RR_OP 91, remu, 0, 0x80000000, 1
+    TEST_RR_OP 92, remu, 0x80000000, 0x80000000, -1
+    TEST_RR_OP 93, remu, 0x80000000, 0x80000000, 0
+    TEST_RR_OP 94, remu, 1, 1, 0
+    TEST_RR_OP 95, remu, 0, 0, 0
+
+RVTEST_CODE_END